@@ -1,31 +1,139 @@
-use monitor_common::core::{AudioClip, HitSound};
-use std::collections::HashMap;
+use monitor_common::core::{AudioClip, HitSound, Time, time_f64};
+use std::collections::{HashMap, VecDeque};
+use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
-use web_sys::{AudioBuffer, AudioBufferSourceNode, AudioContext};
+use web_sys::{
+    AnalyserNode, AudioBuffer, AudioBufferSourceNode, AudioContext, AudioContextState,
+    AudioScheduledSourceNode, GainNode, MediaStreamAudioDestinationNode,
+};
+
+/// Gain nodes routing music and hitsounds through a shared master gain
+struct Mixer {
+    master: GainNode,
+    music: GainNode,
+    hitsound: GainNode,
+    /// Sits between the music gain and the master gain, so visualizations
+    /// follow the music volume but ignore hitsounds
+    analyser: AnalyserNode,
+}
+
+impl Mixer {
+    fn new(ctx: &AudioContext) -> Result<Self, JsValue> {
+        let base_ctx: &web_sys::BaseAudioContext = ctx.as_ref();
+        let master = ctx.create_gain()?;
+        master.connect_with_audio_node(&base_ctx.destination())?;
+        let analyser = ctx.create_analyser()?;
+        analyser.connect_with_audio_node(&master)?;
+        let music = ctx.create_gain()?;
+        music.connect_with_audio_node(&analyser)?;
+        let hitsound = ctx.create_gain()?;
+        hitsound.connect_with_audio_node(&master)?;
+        Ok(Self {
+            master,
+            music,
+            hitsound,
+            analyser,
+        })
+    }
+}
 
 pub struct AudioEngine {
     ctx: AudioContext,
+    mixer: Mixer,
     music_buffer: Option<AudioBuffer>,
-    music_source: Option<AudioBufferSourceNode>,
+    music_source: Option<MusicVoice>,
     hitsound_buffers: HashMap<HitSound, AudioBuffer>,
-    start_time: f64, // context.currentTime when play started
-    offset: f32,     // chart offset
+    start_time: f64, // context.currentTime at which audio position 0 plays
+    offset: Time,    // chart offset
+    rate: f64,       // music playback rate
+    /// Output latency compensation in seconds. Audio is scheduled this much
+    /// later than the chart clock; negative values schedule music earlier.
+    latency: f64,
+    metronome: Option<Metronome>,
+    /// Metronome beep, created on first use
+    click: Option<AudioBuffer>,
+    /// Length of the music fade on play, pause and seek, in seconds
+    fade_time: f64,
+    /// Playing or scheduled hitsound sources with their start and end
+    /// times, oldest first
+    voices: VecDeque<(AudioBufferSourceNode, f64, f64)>,
+    /// Start time of the latest hitsounds and their kinds, for deduping
+    /// hits landing on the same instant
+    tick: (f64, Vec<HitSound>),
+}
+
+/// Music fade length unless configured otherwise, short enough to only
+/// remove clicks
+const DEFAULT_FADE_TIME: f64 = 0.05;
+
+/// A playing music source with its own fade gain, so a fading out source
+/// can overlap the next one on seeks
+struct MusicVoice {
+    source: AudioBufferSourceNode,
+    fade: GainNode,
+}
+
+/// Hitsound sources allowed to play at once; the oldest is cut beyond this
+const MAX_VOICES: usize = 32;
+/// Hitsounds starting at the same instant, duplicates excluded
+const MAX_VOICES_PER_TICK: usize = 8;
+
+/// How far ahead of the chart clock autoplay hitsounds are scheduled, in
+/// seconds of audio. Covers frame jitter without reacting late to seeks.
+pub const HITSOUND_LOOKAHEAD: f32 = 0.1;
+
+/// How far ahead metronome clicks are scheduled, in seconds
+const METRONOME_LOOKAHEAD: f64 = 0.5;
+
+/// Calibration metronome. Clicks are scheduled on the audio clock and taps
+/// are compared against the beats they were meant to land on.
+struct Metronome {
+    /// Context time of the first beat
+    origin: f64,
+    interval: f64,
+    /// Index of the next beat to schedule
+    next_beat: u64,
+    /// Tap delays relative to the nearest beat, in seconds
+    taps: Vec<f64>,
 }
 
+impl Metronome {
+    /// Suggested latency: the current one minus the median tap delay
+    fn suggestion(&self, latency: f64) -> Option<f64> {
+        if self.taps.is_empty() {
+            return None;
+        }
+        let mut taps = self.taps.clone();
+        taps.sort_by(|a, b| a.total_cmp(b));
+        Some(latency - taps[taps.len() / 2])
+    }
+}
+
+/// Supported playback rates
+pub const PLAYBACK_RATE_RANGE: (f32, f32) = (0.5, 2.0);
+
 impl AudioEngine {
     pub fn new() -> Result<Self, JsValue> {
         let ctx = AudioContext::new()?;
         Ok(Self {
+            mixer: Mixer::new(&ctx)?,
             ctx,
             music_buffer: None,
             music_source: None,
+            fade_time: DEFAULT_FADE_TIME,
             hitsound_buffers: HashMap::new(),
             start_time: 0.0,
             offset: 0.0,
+            rate: 1.0,
+            latency: 0.0,
+            metronome: None,
+            click: None,
+            voices: VecDeque::new(),
+            tick: (0.0, Vec::new()),
         })
     }
 
-    pub fn set_music(&mut self, clip: &AudioClip) -> Result<(), JsValue> {
+    fn buffer_of(&self, clip: &AudioClip) -> Result<AudioBuffer, JsValue> {
         let buffer = self.ctx.create_buffer(
             clip.channel_count as u32,
             (clip.samples.len() / clip.channel_count as usize) as u32,
@@ -40,80 +148,353 @@ impl AudioEngine {
             }
             buffer.copy_to_channel(&channel_data, channel as i32)?;
         }
+        Ok(buffer)
+    }
 
-        self.music_buffer = Some(buffer);
-        Ok(())
+    /// Decodes compressed audio (mp3, ogg, wav...) with the browser's own
+    /// decoder
+    pub async fn decode(&self, data: &js_sys::ArrayBuffer) -> Result<AudioBuffer, JsValue> {
+        let promise = self.ctx.decode_audio_data(data)?;
+        wasm_bindgen_futures::JsFuture::from(promise)
+            .await?
+            .dyn_into()
     }
 
-    pub fn set_hitsound(&mut self, kind: HitSound, clip: &AudioClip) -> Result<(), JsValue> {
-        let buffer = self.ctx.create_buffer(
-            clip.channel_count as u32,
-            (clip.samples.len() / clip.channel_count as usize) as u32,
-            clip.sample_rate as f32,
-        )?;
+    pub fn set_music(&mut self, clip: &AudioClip) -> Result<(), JsValue> {
+        self.music_buffer = Some(self.buffer_of(clip)?);
+        Ok(())
+    }
 
-        for channel in 0..clip.channel_count {
-            let mut channel_data =
-                Vec::with_capacity(clip.samples.len() / clip.channel_count as usize);
-            for i in (channel as usize..clip.samples.len()).step_by(clip.channel_count as usize) {
-                channel_data.push(clip.samples[i]);
-            }
-            buffer.copy_to_channel(&channel_data, channel as i32)?;
-        }
+    pub fn set_music_buffer(&mut self, buffer: AudioBuffer) {
+        self.music_buffer = Some(buffer);
+    }
 
+    pub fn set_hitsound(&mut self, kind: HitSound, clip: &AudioClip) -> Result<(), JsValue> {
+        let buffer = self.buffer_of(clip)?;
         self.hitsound_buffers.insert(kind, buffer);
         Ok(())
     }
 
-    pub fn play(&mut self, start_time: f32) -> Result<(), JsValue> {
+    pub fn set_hitsound_buffer(&mut self, kind: HitSound, buffer: AudioBuffer) {
+        self.hitsound_buffers.insert(kind, buffer);
+    }
+
+    pub fn play(&mut self, start_time: Time) -> Result<(), JsValue> {
         let current = self.ctx.current_time();
         // Audio starts at start_time + offset
-        let audio_start_pos = start_time + self.offset;
-        self.start_time = current - audio_start_pos as f64;
+        let audio_start_pos = time_f64(start_time + self.offset);
+        self.start_time = current - audio_start_pos / self.rate;
 
         if let Some(buffer) = &self.music_buffer {
             let source = self.ctx.create_buffer_source()?;
             source.set_buffer(Some(buffer));
-            // Explicitly cast to BaseAudioContext to access destination()
-            let base_ctx: &web_sys::BaseAudioContext = self.ctx.as_ref();
-            source.connect_with_audio_node(&base_ctx.destination())?;
+            source.playback_rate().set_value(self.rate as f32);
+            let fade = self.ctx.create_gain()?;
+            fade.connect_with_audio_node(&self.mixer.music)?;
+            source.connect_with_audio_node(&fade)?;
+            let gain = fade.gain();
+            gain.set_value_at_time(0.0, current)?;
+            gain.linear_ramp_to_value_at_time(1.0, current + self.fade_time)?;
 
-            if audio_start_pos >= 0.0 {
-                source.start_with_when_and_grain_offset(current, audio_start_pos as f64)?;
+            // Buffer position due now once latency compensation is applied
+            let buffer_pos = audio_start_pos - self.latency * self.rate;
+            if buffer_pos >= 0.0 {
+                source.start_with_when_and_grain_offset(current, buffer_pos)?;
             } else {
                 // Future start
-                source.start_with_when(current - audio_start_pos as f64)?;
+                source.start_with_when(current - buffer_pos / self.rate)?;
             }
 
-            self.music_source = Some(source);
+            self.music_source = Some(MusicVoice { source, fade });
         }
         let _ = self.ctx.resume()?;
         Ok(())
     }
 
     pub fn pause(&mut self) -> Result<(), JsValue> {
-        if let Some(source) = self.music_source.take() {
-            let _ = source.stop_with_when(0.0);
+        if let Some(MusicVoice { source, fade }) = self.music_source.take() {
+            let now = self.ctx.current_time();
+            let gain = fade.gain();
+            let _ = gain.cancel_scheduled_values(now);
+            let _ = gain.set_value_at_time(gain.value(), now);
+            let _ = gain.linear_ramp_to_value_at_time(0.0, now + self.fade_time);
+            let _ = AudioScheduledSourceNode::stop_with_when(&source, now + self.fade_time);
         }
+        self.cancel_scheduled_hitsounds();
         Ok(())
     }
 
-    pub fn play_hitsound(&self, kind: &HitSound) -> Result<(), JsValue> {
-        if let Some(buffer) = self.hitsound_buffers.get(kind) {
+    /// Plays a hitsound now. Hitsounds fire on the chart clock, so negative
+    /// latency cannot move them earlier; see `schedule_hitsound`.
+    pub fn play_hitsound(&mut self, kind: &HitSound) -> Result<(), JsValue> {
+        let when = self.ctx.current_time() + self.latency.max(0.0);
+        self.start_voice(kind, when)
+    }
+
+    /// Schedules a hitsound to land exactly on chart time `time` of the
+    /// playing music.
+    pub fn schedule_hitsound(&mut self, kind: &HitSound, time: Time) -> Result<(), JsValue> {
+        let when = self.start_time + self.latency + time_f64(time + self.offset) / self.rate;
+        self.start_voice(kind, when.max(self.ctx.current_time()))
+    }
+
+    /// Stops hitsounds scheduled ahead that have not started yet.
+    pub fn cancel_scheduled_hitsounds(&mut self) {
+        let now = self.ctx.current_time();
+        self.voices.retain(|(source, start, _)| {
+            if *start > now {
+                let _ = AudioScheduledSourceNode::stop_with_when(source, 0.0);
+                false
+            } else {
+                true
+            }
+        });
+        self.tick = (0.0, Vec::new());
+    }
+
+    /// Starts a hitsound voice at `when`. Identical hitsounds at the same
+    /// instant are merged and the number of voices is capped, so dense
+    /// sections don't crackle or pile up sources.
+    fn start_voice(&mut self, kind: &HitSound, when: f64) -> Result<(), JsValue> {
+        let Some(buffer) = self.hitsound_buffers.get(kind) else {
+            return Ok(());
+        };
+        if self.tick.0 != when {
+            self.tick = (when, Vec::new());
+        }
+        if self.tick.1.contains(kind) || self.tick.1.len() >= MAX_VOICES_PER_TICK {
+            return Ok(());
+        }
+        self.tick.1.push(kind.clone());
+
+        let now = self.ctx.current_time();
+        while self.voices.front().is_some_and(|(_, _, end)| *end <= now) {
+            self.voices.pop_front();
+        }
+        if self.voices.len() >= MAX_VOICES
+            && let Some((oldest, _, _)) = self.voices.pop_front()
+        {
+            let _ = AudioScheduledSourceNode::stop_with_when(&oldest, 0.0);
+        }
+
+        let source = self.ctx.create_buffer_source()?;
+        source.set_buffer(Some(buffer));
+        source.connect_with_audio_node(&self.mixer.hitsound)?;
+        source.start_with_when(when)?;
+        self.voices
+            .push_back((source, when, when + buffer.duration()));
+        Ok(())
+    }
+
+    /// Sets the output latency compensation. Positive values delay audio
+    /// (for slow displays), negative ones play music earlier (for slow audio
+    /// outputs such as Bluetooth). Restart playback to apply it to music
+    /// that is already playing.
+    pub fn set_latency(&mut self, latency: f64) {
+        self.latency = latency;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.music_source.is_some()
+    }
+
+    fn click(&mut self) -> Result<AudioBuffer, JsValue> {
+        if let Some(click) = &self.click {
+            return Ok(click.clone());
+        }
+        let sample_rate = self.ctx.sample_rate();
+        // 30ms decaying 1kHz beep
+        let len = (sample_rate * 0.03) as usize;
+        let samples: Vec<f32> = (0..len)
+            .map(|i| {
+                let t = i as f32 / sample_rate;
+                (t * 1000.0 * std::f32::consts::TAU).sin() * (-t * 150.0).exp()
+            })
+            .collect();
+        let click = self.ctx.create_buffer(1, len as u32, sample_rate)?;
+        click.copy_to_channel(&samples, 0)?;
+        self.click = Some(click.clone());
+        Ok(click)
+    }
+
+    /// Schedules a metronome click on chart time `time` of the playing
+    /// music, pitched up for the first beat of a bar. Cancelled along with
+    /// the hitsounds.
+    pub fn schedule_beat(&mut self, time: Time, bar: bool) -> Result<(), JsValue> {
+        let click = self.click()?;
+        let when = self.start_time + self.latency + time_f64(time + self.offset) / self.rate;
+        let now = self.ctx.current_time();
+        if when < now {
+            return Ok(());
+        }
+        let pitch = if bar { 1.5 } else { 1.0 };
+        let source = self.ctx.create_buffer_source()?;
+        source.set_buffer(Some(&click));
+        source.playback_rate().set_value(pitch);
+        source.connect_with_audio_node(&self.mixer.master)?;
+        source.start_with_when(when)?;
+        self.voices
+            .push_back((source, when, when + click.duration() / pitch as f64));
+        Ok(())
+    }
+
+    /// Starts the calibration metronome at `bpm`, discarding previous taps.
+    pub fn start_metronome(&mut self, bpm: f32) -> Result<(), JsValue> {
+        self.click()?;
+        self.metronome = Some(Metronome {
+            origin: self.ctx.current_time() + METRONOME_LOOKAHEAD,
+            interval: 60.0 / bpm.max(1.0) as f64,
+            next_beat: 0,
+            taps: Vec::new(),
+        });
+        let _ = self.ctx.resume()?;
+        Ok(())
+    }
+
+    /// Schedules upcoming metronome clicks, call once per frame.
+    pub fn tick_metronome(&mut self) -> Result<(), JsValue> {
+        let (Some(metronome), Some(click)) = (&mut self.metronome, &self.click) else {
+            return Ok(());
+        };
+        let horizon = self.ctx.current_time() + METRONOME_LOOKAHEAD;
+        loop {
+            let beat = metronome.origin + metronome.next_beat as f64 * metronome.interval;
+            if beat > horizon {
+                break;
+            }
             let source = self.ctx.create_buffer_source()?;
-            source.set_buffer(Some(buffer));
-            let base_ctx: &web_sys::BaseAudioContext = self.ctx.as_ref();
-            source.connect_with_audio_node(&base_ctx.destination())?;
-            source.start()?;
+            source.set_buffer(Some(click));
+            source.connect_with_audio_node(&self.mixer.master)?;
+            source.start_with_when((beat + self.latency).max(0.0))?;
+            metronome.next_beat += 1;
         }
         Ok(())
     }
 
-    pub fn get_time(&self) -> f32 {
-        (self.ctx.current_time() - self.start_time) as f32 - self.offset
+    /// Records a tap, returning the suggested latency so far.
+    pub fn metronome_tap(&mut self) -> Option<f64> {
+        let metronome = self.metronome.as_mut()?;
+        let since = self.ctx.current_time() - metronome.origin;
+        let nearest = (since / metronome.interval).round();
+        if nearest >= 0.0 {
+            metronome.taps.push(since - nearest * metronome.interval);
+        }
+        metronome.suggestion(self.latency)
+    }
+
+    /// Stops the metronome, returning the suggested latency if any taps
+    /// were recorded.
+    pub fn stop_metronome(&mut self) -> Option<f64> {
+        self.metronome.take()?.suggestion(self.latency)
+    }
+
+    /// Sets the music fade applied on play, pause and seek, in seconds.
+    pub fn set_fade_time(&mut self, seconds: f64) {
+        self.fade_time = seconds.max(0.0);
+    }
+
+    /// Volumes are linear gains, 1.0 being unchanged
+    pub fn set_master_volume(&self, volume: f32) {
+        self.mixer.master.gain().set_value(volume.max(0.0));
+    }
+
+    pub fn set_music_volume(&self, volume: f32) {
+        self.mixer.music.gain().set_value(volume.max(0.0));
     }
 
-    pub fn set_offset(&mut self, offset: f32) {
+    pub fn set_hitsound_volume(&self, volume: f32) {
+        self.mixer.hitsound.gain().set_value(volume.max(0.0));
+    }
+
+    /// Sets the analyser FFT size, a power of two in 32..=32768. Returns
+    /// the resulting number of frequency bins.
+    pub fn set_analyser_fft_size(&self, size: u32) -> u32 {
+        let size = size.clamp(32, 32768).next_power_of_two();
+        self.mixer.analyser.set_fft_size(size);
+        self.mixer.analyser.frequency_bin_count()
+    }
+
+    /// Music spectrum in decibels scaled to 0..=255, one byte per bin
+    pub fn frequency_data(&self) -> Vec<u8> {
+        let analyser = &self.mixer.analyser;
+        let mut data = vec![0; analyser.frequency_bin_count() as usize];
+        analyser.get_byte_frequency_data(&mut data);
+        data
+    }
+
+    /// Music waveform with 128 as silence, `fft_size` samples
+    pub fn waveform_data(&self) -> Vec<u8> {
+        let analyser = &self.mixer.analyser;
+        let mut data = vec![0; analyser.fft_size() as usize];
+        analyser.get_byte_time_domain_data(&mut data);
+        data
+    }
+
+    /// Chart time on screen now. Follows the scheduled music, which plays
+    /// `latency` seconds behind it.
+    pub fn get_time(&self) -> Time {
+        ((self.ctx.current_time() - self.start_time) * self.rate) as Time - self.offset
+    }
+
+    /// Changes the music speed, keeping the current position. Buffer sources
+    /// cannot preserve pitch, so the music is pitched along with the rate.
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        let rate = rate.clamp(PLAYBACK_RATE_RANGE.0, PLAYBACK_RATE_RANGE.1) as f64;
+        let current = self.ctx.current_time();
+        let position = (current - self.start_time) * self.rate;
+        self.rate = rate;
+        self.start_time = current - position / rate;
+        self.cancel_scheduled_hitsounds();
+        if let Some(MusicVoice { source, .. }) = &self.music_source {
+            source.playback_rate().set_value(rate as f32);
+        }
+    }
+
+    pub fn playback_rate(&self) -> f32 {
+        self.rate as f32
+    }
+
+    pub fn set_offset(&mut self, offset: Time) {
         self.offset = offset;
     }
+
+    /// Routes all output to the device with `device_id` from
+    /// `enumerateDevices()`, or the system default for an empty id. The
+    /// returned promise settles once the switch is done. Fails right away in
+    /// browsers without `AudioContext.setSinkId`.
+    pub fn set_output_device(&self, device_id: &str) -> Result<js_sys::Promise, JsValue> {
+        let set_sink_id = js_sys::Reflect::get(&self.ctx, &JsValue::from_str("setSinkId"))?
+            .dyn_into::<js_sys::Function>()
+            .map_err(|_| JsValue::from_str("Output device selection is not supported"))?;
+        set_sink_id
+            .call1(&self.ctx, &JsValue::from_str(device_id))?
+            .dyn_into()
+    }
+
+    /// Stops the music and suspends the audio context to save power.
+    /// `play` resumes it.
+    pub fn suspend(&mut self) -> Result<(), JsValue> {
+        self.pause()?;
+        let _ = self.ctx.suspend()?;
+        Ok(())
+    }
+
+    /// Creates a stream carrying everything played, for recording.
+    pub fn connect_recording_output(&self) -> Result<MediaStreamAudioDestinationNode, JsValue> {
+        let output = self.ctx.create_media_stream_destination()?;
+        self.mixer.master.connect_with_audio_node(&output)?;
+        Ok(output)
+    }
+
+    pub fn disconnect_recording_output(&self, output: &MediaStreamAudioDestinationNode) {
+        let _ = self.mixer.master.disconnect_with_audio_node(output);
+    }
+
+    pub fn state(&self) -> AudioContextState {
+        self.ctx.state()
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.ctx.sample_rate()
+    }
 }
@@ -0,0 +1,196 @@
+use crate::audio::AudioEngine;
+use crate::renderer::GlContext;
+use serde::Serialize;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use web_sys::{RequestInit, RequestMode, Response, WebGl2RenderingContext};
+
+// Minimal module using a v128 instruction, validates only if SIMD is available
+const SIMD_PROBE: [u8; 31] = [
+    0, 97, 115, 109, 1, 0, 0, 0, 1, 5, 1, 96, 0, 1, 123, 3, 2, 1, 0, 10, 10, 1, 8, 0, 65, 0, 253,
+    15, 253, 98, 11,
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    pub webgl: WebGlReport,
+    pub audio: AudioReport,
+    pub wasm: WasmReport,
+    pub memory: MemoryReport,
+    pub network: NetworkReport,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebGlReport {
+    pub context_lost: bool,
+    pub version: Option<String>,
+    pub vendor: Option<String>,
+    pub renderer: Option<String>,
+    pub shading_language: Option<String>,
+    pub max_texture_size: Option<u32>,
+    pub max_renderbuffer_size: Option<u32>,
+    pub max_samples: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioReport {
+    pub state: String,
+    pub sample_rate: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WasmReport {
+    /// Whether the browser can run SIMD modules
+    pub simd_supported: bool,
+    /// Whether this build was compiled with SIMD
+    pub simd_enabled: bool,
+    /// Whether SharedArrayBuffer is usable (requires cross-origin isolation)
+    pub threads_supported: bool,
+    /// Whether this build was compiled with atomics
+    pub threads_enabled: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryReport {
+    pub wasm_memory_bytes: f64,
+    pub js_heap_used_bytes: Option<f64>,
+    pub js_heap_limit_bytes: Option<f64>,
+    pub device_memory_gb: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkReport {
+    pub url: String,
+    pub reachable: bool,
+    pub status: Option<u16>,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+fn js_get(target: &JsValue, key: &str) -> Option<JsValue> {
+    js_sys::Reflect::get(target, &JsValue::from_str(key))
+        .ok()
+        .filter(|v| !v.is_undefined() && !v.is_null())
+}
+
+fn webgl(ctx: &GlContext) -> WebGlReport {
+    let gl = &ctx.gl;
+    let string = |param| gl.get_parameter(param).ok().and_then(|v| v.as_string());
+    let number = |param| {
+        gl.get_parameter(param)
+            .ok()
+            .and_then(|v| v.as_f64())
+            .map(|v| v as u32)
+    };
+    WebGlReport {
+        context_lost: gl.is_context_lost(),
+        version: string(WebGl2RenderingContext::VERSION),
+        vendor: string(WebGl2RenderingContext::VENDOR),
+        renderer: string(WebGl2RenderingContext::RENDERER),
+        shading_language: string(WebGl2RenderingContext::SHADING_LANGUAGE_VERSION),
+        max_texture_size: number(WebGl2RenderingContext::MAX_TEXTURE_SIZE),
+        max_renderbuffer_size: number(WebGl2RenderingContext::MAX_RENDERBUFFER_SIZE),
+        max_samples: number(WebGl2RenderingContext::MAX_SAMPLES),
+    }
+}
+
+fn wasm() -> WasmReport {
+    let global = js_sys::global();
+    let simd_supported =
+        js_sys::WebAssembly::validate(&js_sys::Uint8Array::from(&SIMD_PROBE[..])).unwrap_or(false);
+    let threads_supported = js_get(&global, "SharedArrayBuffer").is_some()
+        && js_get(&global, "crossOriginIsolated")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+    WasmReport {
+        simd_supported,
+        simd_enabled: cfg!(target_feature = "simd128"),
+        threads_supported,
+        threads_enabled: cfg!(target_feature = "atomics"),
+    }
+}
+
+fn memory() -> MemoryReport {
+    let wasm_memory_bytes = wasm_bindgen::memory()
+        .dyn_into::<js_sys::WebAssembly::Memory>()
+        .map(|m| {
+            m.buffer()
+                .unchecked_into::<js_sys::ArrayBuffer>()
+                .byte_length() as f64
+        })
+        .unwrap_or(0.0);
+    let global = js_sys::global();
+    // Chromium-only, absent elsewhere
+    let heap = js_get(&global, "performance").and_then(|p| js_get(&p, "memory"));
+    let heap_value = |key| heap.as_ref().and_then(|h| js_get(h, key)?.as_f64());
+    MemoryReport {
+        wasm_memory_bytes,
+        js_heap_used_bytes: heap_value("usedJSHeapSize"),
+        js_heap_limit_bytes: heap_value("jsHeapSizeLimit"),
+        device_memory_gb: js_get(&global, "navigator")
+            .and_then(|n| js_get(&n, "deviceMemory"))
+            .and_then(|v| v.as_f64()),
+    }
+}
+
+async fn network(url: String) -> NetworkReport {
+    let mut report = NetworkReport {
+        url,
+        reachable: false,
+        status: None,
+        latency_ms: None,
+        error: None,
+    };
+    let Some(window) = web_sys::window() else {
+        report.error = Some("no window".to_string());
+        return report;
+    };
+    let performance = window.performance();
+    let start = performance.as_ref().map(|p| p.now());
+
+    let init = RequestInit::new();
+    init.set_method("GET");
+    // Opaque responses are enough to tell the host is reachable
+    init.set_mode(RequestMode::NoCors);
+    let result =
+        wasm_bindgen_futures::JsFuture::from(window.fetch_with_str_and_init(&report.url, &init))
+            .await;
+
+    match result {
+        Ok(resp) => {
+            report.reachable = true;
+            if let Ok(resp) = resp.dyn_into::<Response>() {
+                // Opaque (cross-origin) responses report status 0
+                report.status = Some(resp.status()).filter(|&s| s != 0);
+            }
+            if let (Some(p), Some(start)) = (performance, start) {
+                report.latency_ms = Some(p.now() - start);
+            }
+        }
+        Err(e) => {
+            report.error = Some(e.as_string().unwrap_or_else(|| format!("{:?}", e)));
+        }
+    }
+    report
+}
+
+/// Gathers environment information useful for troubleshooting a monitor
+/// that does not play correctly.
+pub async fn run(ctx: &GlContext, audio: &AudioEngine, url: String) -> DiagnosticsReport {
+    DiagnosticsReport {
+        webgl: webgl(ctx),
+        audio: AudioReport {
+            state: format!("{:?}", audio.state()).to_lowercase(),
+            sample_rate: audio.sample_rate(),
+        },
+        wasm: wasm(),
+        memory: memory(),
+        network: network(url).await,
+    }
+}
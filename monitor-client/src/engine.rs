@@ -1,14 +1,35 @@
+mod beat;
+pub use beat::{BeatGrid, is_bar};
+
 mod chart;
-pub use chart::ChartRenderer;
+pub use chart::{ChartRenderer, note_hitsound};
+
+mod debug;
+pub use debug::{DebugOverlay, DebugRect};
+
+mod hud;
+pub use hud::Hud;
 
 mod judge;
 pub use judge::JudgeEventKind;
 
 mod line;
-pub use line::draw_line;
+pub use line::{draw_line, draw_line_notes};
+
+mod player;
+pub use player::PlayerOverlay;
+
+mod popup;
+pub use popup::JudgePopups;
 
 mod note;
-pub use note::{RenderConfig, draw_note};
+pub use note::{NoteFilter, RenderConfig, draw_note};
+
+mod stats;
+pub use stats::{FrameInfo, StatsOverlay};
 
 mod resource;
-pub use resource::{Resource, ResourcePack};
+pub use resource::{HitFxStyle, Resource, ResourcePack};
+
+mod video;
+pub use video::VideoLayer;
@@ -0,0 +1,77 @@
+use crate::renderer::{IDENTITY, Renderer};
+use monitor_common::core::{BpmList, Color, Time, time_f32};
+
+/// Seconds of chart time shown before and after the playhead
+const PAST: Time = 0.5;
+const FUTURE: Time = 2.0;
+/// Beats per bar; Phira charts carry no time signature
+const BEATS_PER_BAR: i32 = 4;
+/// Strip height as a fraction of the screen height
+const HEIGHT: f32 = 0.06;
+const BEAT_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.5);
+const BAR_COLOR: Color = Color::new(1.0, 0.85, 0.3, 0.9);
+const PLAYHEAD_COLOR: Color = Color::new(1.0, 0.3, 0.3, 1.0);
+
+/// Whether `beat` starts a bar
+pub fn is_bar(beat: i32) -> bool {
+    beat.rem_euclid(BEATS_PER_BAR) == 0
+}
+
+/// Timeline strip along the bottom of the screen with a tick per beat and
+/// a taller one per bar, for checking a chart's BPM against the music.
+#[derive(Default)]
+pub struct BeatGrid {
+    pub enabled: bool,
+}
+
+impl BeatGrid {
+    pub fn draw(&self, bpm: &mut BpmList, time: Time, renderer: &mut Renderer) {
+        if !self.enabled {
+            return;
+        }
+        let (width, height) = (renderer.context.width, renderer.context.height);
+        if width == 0 || height == 0 {
+            return;
+        }
+        // World units per canvas pixel, see the projection in ChartPlayer::render
+        let px = 2.0 / width as f32;
+        let bottom = -(height as f32 * px / 2.0);
+        let strip = height as f32 * HEIGHT * px;
+        let x_at = |t: Time| -1.0 + time_f32((t - time + PAST) / (PAST + FUTURE)) * 2.0;
+
+        // Particle drawing leaves no program bound
+        renderer.begin_frame();
+        renderer.draw_rect(-1.0, bottom, 2.0, strip, 0.0, 0.0, 0.0, 0.5, &IDENTITY);
+        for (beat, t) in bpm.beats_between(time - PAST, time + FUTURE) {
+            let (color, h) = if is_bar(beat) {
+                (BAR_COLOR, strip)
+            } else {
+                (BEAT_COLOR, strip * 0.5)
+            };
+            renderer.draw_rect(
+                x_at(t) - px,
+                bottom,
+                2.0 * px,
+                h,
+                color.r,
+                color.g,
+                color.b,
+                color.a,
+                &IDENTITY,
+            );
+        }
+        let c = PLAYHEAD_COLOR;
+        renderer.draw_rect(
+            x_at(time) - 1.5 * px,
+            bottom,
+            3.0 * px,
+            strip,
+            c.r,
+            c.g,
+            c.b,
+            c.a,
+            &IDENTITY,
+        );
+        renderer.flush();
+    }
+}
@@ -1,29 +1,52 @@
 use crate::engine::judge::{JudgeEvent, JudgeEventKind};
-use crate::engine::{Resource, draw_line};
+use crate::engine::{Hud, NoteFilter, Resource, draw_line, draw_line_notes};
 use crate::renderer::Renderer;
-use monitor_common::core::{Chart, ChartInfo, JudgeStatus, Judgement, Matrix, NoteKind, Vector};
+use monitor_common::core::{
+    Chart, ChartInfo, HitSound, JudgeStatus, Judgement, Matrix, Note, NoteKind, Point, Time,
+    Vector, time_f32,
+};
+use monitor_common::score::ScoreCounter;
 use nalgebra::{Matrix3, Rotation2};
-use std::f32::consts::PI;
+use wasm_bindgen::JsValue;
 
-const HOLD_PARTICLE_INTERVAL: f32 = 0.15;
+const HOLD_PARTICLE_INTERVAL: Time = 0.15;
+
+/// The hitsound a note plays, falling back to its kind's default
+pub fn note_hitsound(note: &Note) -> HitSound {
+    note.hitsound.clone().unwrap_or(match note.kind {
+        NoteKind::Click => HitSound::Click,
+        NoteKind::Drag => HitSound::Drag,
+        NoteKind::Flick => HitSound::Flick,
+        _ => HitSound::Click,
+    })
+}
 
 pub struct ChartRenderer {
     pub info: ChartInfo,
     pub chart: Chart,
-    pub time: f32, // Seconds
+    pub time: Time, // Seconds
     pub world_matrices: Vec<Option<Matrix>>,
     pub autoplay: bool,
+    /// Flip the chart horizontally: line positions and rotations and note
+    /// X offsets are mirrored, textures are not
+    pub mirror: bool,
+    pub filter: NoteFilter,
+    pub score: ScoreCounter,
 }
 
 impl ChartRenderer {
     pub fn new(info: ChartInfo, chart: Chart) -> Self {
         let n = chart.lines.len();
+        let score = ScoreCounter::new(chart.note_count() as u32);
         Self {
             info,
             chart,
             time: 0.0,
             world_matrices: vec![None; n],
             autoplay: true,
+            mirror: false,
+            filter: NoteFilter::default(),
+            score,
         }
     }
 
@@ -45,8 +68,12 @@ impl ChartRenderer {
             return matrix;
         }
         let line = &self.chart.lines[line_index];
-        let translation = self.fetch_pos(line_index);
-        let rot = line.object.rotation.now_opt().unwrap_or(0.0);
+        let mut translation = self.fetch_pos(line_index);
+        let mut rot = line.object.rotation.now_opt().unwrap_or(0.0);
+        if self.mirror {
+            translation.x = -translation.x;
+            rot = -rot;
+        }
         let rotation = Rotation2::new(rot.to_radians());
 
         let mut transform = Matrix3::identity();
@@ -58,8 +85,8 @@ impl ChartRenderer {
         transform
     }
 
-    pub fn update(&mut self, res: &mut Resource, time: f32) {
-        let dt = time - self.time;
+    pub fn update(&mut self, res: &mut Resource, time: Time) {
+        let dt = time_f32(time - self.time);
         self.time = time;
         res.time = time;
         res.dt = dt;
@@ -96,7 +123,7 @@ impl ChartRenderer {
                             match &note.kind {
                                 NoteKind::Hold { .. } => {
                                     note.judge =
-                                        JudgeStatus::Hold(true, t, 0.0, false, f32::INFINITY);
+                                        JudgeStatus::Hold(true, t, 0.0, false, Time::INFINITY);
                                     events.push(JudgeEvent {
                                         kind: JudgeEventKind::HoldStart,
                                         line_idx,
@@ -115,6 +142,11 @@ impl ChartRenderer {
                         } else if !self.autoplay && t - note.time > 0.22 {
                             // Miss (LIMIT_BAD)
                             note.judge = JudgeStatus::Judged;
+                            events.push(JudgeEvent {
+                                kind: JudgeEventKind::Judged(Judgement::Miss),
+                                line_idx,
+                                note_idx,
+                            });
                         }
                     }
                     JudgeStatus::Hold(perfect, at, diff, pre_judge, up_time) => {
@@ -159,23 +191,84 @@ impl ChartRenderer {
             }
         }
 
+        for event in &events {
+            match event.kind {
+                JudgeEventKind::Judged(j) | JudgeEventKind::HoldComplete(j) => self.score.push(j),
+                _ => {}
+            }
+        }
+
         events
     }
 
-    pub fn render(&mut self, res: &mut Resource, renderer: &mut Renderer) {
-        for &i in &self.chart.order {
-            let line = &self.chart.lines[i];
-            let world_matrix = self.world_matrices[i].unwrap_or(Matrix::identity());
-            draw_line(
-                res,
-                line,
-                self.info.line_length,
+    /// Hitsounds of the real notes with time in `(from, to]`, as autoplay
+    /// will hit them.
+    pub fn hitsounds_between(&self, from: Time, to: Time) -> Vec<(HitSound, Time)> {
+        self.chart
+            .lines
+            .iter()
+            .flat_map(|line| &line.notes)
+            .filter(|note| !note.fake && note.time > from && note.time <= to)
+            .map(|note| (note_hitsound(note), note.time))
+            .collect()
+    }
+
+    /// Draws background videos behind the chart, keeping them in sync with
+    /// chart time, the playback state and rate.
+    pub fn render_videos(
+        &self,
+        res: &mut Resource,
+        renderer: &mut Renderer,
+        playing: bool,
+        rate: f32,
+    ) {
+        for (video, layer) in self.chart.extra.videos.iter().zip(&mut res.videos) {
+            layer.draw(
+                video,
+                self.time,
+                playing,
+                rate as f64,
+                res.aspect_ratio,
                 renderer,
-                i,
-                &self.chart.settings,
-                world_matrix,
             );
         }
+    }
+
+    /// Draws lines in z-order. Each z-index layer draws all its lines before
+    /// their notes, so notes stay above lines of the same layer while higher
+    /// layers can still cover them.
+    pub fn render(&mut self, res: &mut Resource, renderer: &mut Renderer) {
+        let lines = &self.chart.lines;
+        for layer in self
+            .chart
+            .order
+            .chunk_by(|&a, &b| lines[a].z_index == lines[b].z_index)
+        {
+            for &i in layer.iter().filter(|&&i| self.filter.shows_line(i)) {
+                let world_matrix = self.world_matrices[i].unwrap_or(Matrix::identity());
+                draw_line(
+                    res,
+                    &lines[i],
+                    self.info.line_length,
+                    renderer,
+                    i,
+                    &self.chart.settings,
+                    world_matrix,
+                );
+            }
+            for &i in layer.iter().filter(|&&i| self.filter.shows_line(i)) {
+                let world_matrix = self.world_matrices[i].unwrap_or(Matrix::identity());
+                draw_line_notes(
+                    res,
+                    &lines[i],
+                    renderer,
+                    &self.chart.settings,
+                    world_matrix,
+                    self.mirror,
+                    &self.filter,
+                );
+            }
+        }
 
         // Flush lines before drawing particles to avoid state leaks
         renderer.flush();
@@ -184,18 +277,54 @@ impl ChartRenderer {
         }
     }
 
+    fn hit_transform(&self, event: &JudgeEvent) -> Matrix {
+        let note = &self.chart.lines[event.line_idx].notes[event.note_idx];
+        let line_matrix = self.world_matrices[event.line_idx].unwrap_or(Matrix::identity());
+
+        // Note x position relative to line
+        let mut note_x = note.object.translation.x.now_opt().unwrap_or(0.0);
+        if self.mirror {
+            note_x = -note_x;
+        }
+        let note_offset = Matrix3::new_translation(&Vector::new(note_x, 0.0));
+        line_matrix * note_offset
+    }
+
+    /// World position where the note of `event` meets its judge line.
+    pub fn hit_position(&self, event: &JudgeEvent) -> Vector {
+        let pt = self.hit_transform(event).transform_point(&Point::origin());
+        Vector::new(pt.x, pt.y)
+    }
+
+    /// Applies the chart's shader effects active at the current time.
+    /// Non-global effects only cover the chart, global ones the UI as well.
+    pub fn render_effects(&self, renderer: &mut Renderer, global: bool) {
+        for effect in &self.chart.extra.effects {
+            if effect.global == global && effect.active(self.time) {
+                renderer.apply_effect(effect, time_f32(self.time));
+            }
+        }
+    }
+
+    pub fn render_hud(&self, hud: &mut Hud, renderer: &mut Renderer) -> Result<(), JsValue> {
+        if !hud.enabled {
+            return Ok(());
+        }
+        hud.draw(renderer, &self.info, &self.score, self.autoplay)
+    }
+
     /// Emit particles for judge events. Must be called after `update_judges()`
     /// and before `render()` so particles appear on the correct frame.
     pub fn emit_particles(&self, res: &mut Resource, events: &[JudgeEvent]) {
         for event in events {
-            let color = match &event.kind {
+            let (color, judgement) = match &event.kind {
                 JudgeEventKind::Judged(j)
                 | JudgeEventKind::HoldTick(j)
                 | JudgeEventKind::HoldComplete(j) => {
                     if let Some(info) = res.res_pack.as_ref().map(|p| &p.info) {
                         match j {
-                            Judgement::Perfect => info.fx_perfect(),
-                            Judgement::Good => info.fx_good(),
+                            Judgement::Perfect => (info.fx_perfect(), *j),
+                            Judgement::Good => (info.fx_good(), *j),
                             _ => continue, // Bad/Miss — no particle
                         }
                     } else {
@@ -205,17 +334,14 @@ impl ChartRenderer {
                 JudgeEventKind::HoldStart => continue, // No particle on hold start
             };
 
-            let note = &self.chart.lines[event.line_idx].notes[event.note_idx];
-            let line_matrix = self.world_matrices[event.line_idx].unwrap_or(Matrix::identity());
-
-            // Note x position relative to line
-            let note_x = note.object.translation.x.now_opt().unwrap_or(0.0);
-            let note_offset = Matrix3::new_translation(&Vector::new(note_x, 0.0));
-
-            let rotation = if note.above { 0.0 } else { PI };
+            let line = &self.chart.lines[event.line_idx];
+            let mut rotation = line.notes[event.note_idx].rotation(line);
+            if self.mirror {
+                rotation = -rotation;
+            }
 
-            res.with_model(line_matrix * note_offset, |res| {
-                res.emit_at_origin(rotation, color);
+            res.with_model(self.hit_transform(event), |res| {
+                res.emit_at_origin(rotation, color, judgement);
             });
         }
     }
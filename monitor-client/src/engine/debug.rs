@@ -0,0 +1,168 @@
+use crate::engine::ChartRenderer;
+use crate::engine::resource::Resource;
+use crate::renderer::text::{Glyph, SpriteFont};
+use crate::renderer::{IDENTITY, Label, Renderer};
+use monitor_common::core::{Color, Point};
+use wasm_bindgen::prelude::*;
+
+/// Printable ASCII, rasterized once into a monospace glyph strip
+const CHARSET: &str = " !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+/// Outline width in canvas pixels
+const OUTLINE_PX: f32 = 1.5;
+const LINE_COLOR: Color = Color::new(1.0, 0.3, 0.3, 1.0);
+const NOTE_COLOR: Color = Color::new(0.3, 1.0, 0.4, 0.9);
+
+/// A note as drawn this frame: model matrix and its rect in model space
+pub struct DebugRect {
+    pub model: [f32; 16],
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+/// Draws judge line indices, heights and control values, and note bounding
+/// boxes, for comparing the renderer against Phira.
+pub struct DebugOverlay {
+    pub enabled: bool,
+    /// Canvas height the font was sized for
+    height: u32,
+    label: Label,
+    font: Option<SpriteFont>,
+}
+
+impl DebugOverlay {
+    pub fn new(renderer: &Renderer) -> Result<Self, JsValue> {
+        Ok(Self {
+            enabled: false,
+            height: 0,
+            label: Label::new(&renderer.context)?,
+            font: None,
+        })
+    }
+
+    /// Glyphs for the current canvas height, rasterized on first use
+    fn font(&mut self, renderer: &mut Renderer) -> Result<&SpriteFont, JsValue> {
+        let height = renderer.context.height;
+        if self.font.is_none() || self.height != height {
+            self.height = height;
+            // Re-rasterizing binds textures behind the batcher's back
+            renderer.flush();
+            self.label
+                .set_font(&format!("{}px monospace", (height as f32 * 0.02).round()));
+            self.label.set_text(&renderer.context, CHARSET)?;
+            renderer.batcher.invalidate_texture_cache();
+
+            let texture = self.label.texture.clone();
+            let (tex_w, tex_h) = (texture.width as f32, texture.height as f32);
+            // The label pads the text by a pixel on the left and right
+            let advance = (tex_w - 2.0) / CHARSET.len() as f32;
+            let mut font = SpriteFont::new(texture, tex_h);
+            for (i, c) in CHARSET.chars().enumerate() {
+                font.add_glyph(
+                    c,
+                    Glyph {
+                        x: (1.0 + i as f32 * advance) / tex_w,
+                        y: 0.0,
+                        w: advance / tex_w,
+                        h: 1.0,
+                        advance,
+                    },
+                );
+            }
+            self.font = Some(font);
+        }
+        Ok(self.font.as_ref().unwrap())
+    }
+
+    fn outline(renderer: &mut Renderer, rect: &DebugRect, t: f32, color: Color) {
+        let DebugRect { model, x, y, w, h } = rect;
+        let (x, y, w, h) = (*x, *y, *w, *h);
+        for (x, y, w, h) in [
+            (x, y, w, t),
+            (x, y + h - t, w, t),
+            (x, y, t, h),
+            (x + w - t, y, t, h),
+        ] {
+            renderer.draw_rect(x, y, w, h, color.r, color.g, color.b, color.a, model);
+        }
+    }
+
+    /// Draws the overlay from the note rects collected in `res` while the
+    /// chart was drawn, and starts collecting for the next frame.
+    pub fn draw(
+        &mut self,
+        chart: &ChartRenderer,
+        res: &mut Resource,
+        renderer: &mut Renderer,
+    ) -> Result<(), JsValue> {
+        if !self.enabled {
+            res.debug_rects = None;
+            return Ok(());
+        }
+        let rects = res.debug_rects.replace(Vec::new()).unwrap_or_default();
+        let (width, height) = (renderer.context.width, renderer.context.height);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        // World units per canvas pixel, see the projection in ChartPlayer::render
+        let px = 2.0 / width as f32;
+        let t = OUTLINE_PX * px;
+
+        let font = self.font(renderer)?.clone();
+        // Particle drawing leaves no program bound
+        renderer.begin_frame();
+
+        for rect in &rects {
+            Self::outline(renderer, rect, t, NOTE_COLOR);
+        }
+
+        let size = font.line_height * px;
+        for (i, line) in chart.chart.lines.iter().enumerate() {
+            if !chart.filter.shows_line(i) {
+                continue;
+            }
+            let Some(world) = chart.world_matrices[i] else {
+                continue;
+            };
+            let origin = world.transform_point(&Point::origin());
+            let marker = DebugRect {
+                model: IDENTITY,
+                x: origin.x - 4.0 * t,
+                y: origin.y - 4.0 * t,
+                w: 8.0 * t,
+                h: 8.0 * t,
+            };
+            Self::outline(renderer, &marker, t, LINE_COLOR);
+
+            let ctrl = &line.ctrl_obj;
+            let text = [
+                format!("#{} h={:.2}", i, line.height.now_opt().unwrap_or(0.0)),
+                format!(
+                    "a={:.2} s={:.2} p={:.2} y={:.2}",
+                    ctrl.alpha.now_opt().unwrap_or(1.0),
+                    ctrl.size.now_opt().unwrap_or(1.0),
+                    ctrl.pos.now_opt().unwrap_or(0.0),
+                    ctrl.y.now_opt().unwrap_or(0.0),
+                ),
+            ];
+            for (row, text) in text.iter().enumerate() {
+                font.draw_text_color(
+                    renderer,
+                    text,
+                    origin.x + 6.0 * t,
+                    origin.y - size * (row as f32 + 1.0),
+                    size,
+                    0.0,
+                    LINE_COLOR.r,
+                    LINE_COLOR.g,
+                    LINE_COLOR.b,
+                    LINE_COLOR.a,
+                    &IDENTITY,
+                );
+            }
+        }
+        renderer.flush();
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::renderer::{IDENTITY, Label, Renderer};
+use monitor_common::core::ChartInfo;
+use monitor_common::score::ScoreCounter;
+use wasm_bindgen::prelude::*;
+
+/// Combo is only shown from this value on, like Phira
+const MIN_COMBO_SHOWN: u32 = 3;
+
+/// Score / combo / accuracy overlay drawn on top of the chart.
+pub struct Hud {
+    pub enabled: bool,
+    /// Canvas height the fonts were sized for
+    height: u32,
+    combo: Label,
+    combo_caption: Label,
+    score: Label,
+    accuracy: Label,
+    name: Label,
+    level: Label,
+}
+
+impl Hud {
+    pub fn new(renderer: &Renderer) -> Result<Self, JsValue> {
+        let ctx = &renderer.context;
+        Ok(Self {
+            enabled: true,
+            height: 0,
+            combo: Label::new(ctx)?,
+            combo_caption: Label::new(ctx)?,
+            score: Label::new(ctx)?,
+            accuracy: Label::new(ctx)?,
+            name: Label::new(ctx)?,
+            level: Label::new(ctx)?,
+        })
+    }
+
+    fn update_fonts(&mut self, height: u32) {
+        if self.height == height {
+            return;
+        }
+        self.height = height;
+        let font = |ratio: f32, weight: &str| {
+            format!(
+                "{} {}px sans-serif",
+                weight,
+                (height as f32 * ratio).round()
+            )
+        };
+        self.combo.set_font(&font(0.06, "bold"));
+        self.score.set_font(&font(0.045, "bold"));
+        self.combo_caption.set_font(&font(0.022, "bold"));
+        self.accuracy.set_font(&font(0.025, "normal"));
+        self.name.set_font(&font(0.03, "normal"));
+        self.level.set_font(&font(0.03, "normal"));
+    }
+
+    pub fn draw(
+        &mut self,
+        renderer: &mut Renderer,
+        info: &ChartInfo,
+        score: &ScoreCounter,
+        autoplay: bool,
+    ) -> Result<(), JsValue> {
+        let (width, height) = (renderer.context.width, renderer.context.height);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        self.update_fonts(height);
+
+        // Re-rasterizing binds textures behind the batcher's back
+        renderer.flush();
+        let ctx = &renderer.context;
+        let show_combo = score.combo >= MIN_COMBO_SHOWN;
+        if show_combo {
+            self.combo.set_text(ctx, &score.combo.to_string())?;
+            self.combo_caption
+                .set_text(ctx, if autoplay { "AUTOPLAY" } else { "COMBO" })?;
+        }
+        self.score.set_text(ctx, &format!("{:07}", score.score()))?;
+        self.accuracy
+            .set_text(ctx, &format!("{:.2}%", score.real_time_accuracy() * 100.))?;
+        self.name.set_text(ctx, &info.name)?;
+        self.level.set_text(ctx, &info.level)?;
+        renderer.batcher.invalidate_texture_cache();
+        // Particle drawing leaves no program bound
+        renderer.begin_frame();
+
+        // World units per canvas pixel, see the projection in ChartPlayer::render
+        let px = 2.0 / width as f32;
+        let top = height as f32 * px / 2.0;
+        let margin = height as f32 * 0.03 * px;
+
+        if show_combo {
+            let y = top - margin;
+            let combo_h = draw_label(renderer, &self.combo, px, 0.0, y, 0.5, 1.0);
+            draw_label(
+                renderer,
+                &self.combo_caption,
+                px,
+                0.0,
+                y - combo_h,
+                0.5,
+                1.0,
+            );
+        }
+        let score_h = draw_label(
+            renderer,
+            &self.score,
+            px,
+            1.0 - margin,
+            top - margin,
+            1.0,
+            1.0,
+        );
+        draw_label(
+            renderer,
+            &self.accuracy,
+            px,
+            1.0 - margin,
+            top - margin - score_h,
+            1.0,
+            1.0,
+        );
+        draw_label(
+            renderer,
+            &self.name,
+            px,
+            -1.0 + margin,
+            -top + margin,
+            0.0,
+            0.0,
+        );
+        draw_label(
+            renderer,
+            &self.level,
+            px,
+            1.0 - margin,
+            -top + margin,
+            1.0,
+            0.0,
+        );
+
+        renderer.flush();
+        Ok(())
+    }
+}
+
+/// Draws `label` anchored at (x, y); `align_x`/`align_y` pick the anchor
+/// within the label (0 = left/bottom, 1 = right/top). Returns its height.
+fn draw_label(
+    renderer: &mut Renderer,
+    label: &Label,
+    px: f32,
+    x: f32,
+    y: f32,
+    align_x: f32,
+    align_y: f32,
+) -> f32 {
+    let w = label.texture.width as f32 * px;
+    let h = label.texture.height as f32 * px;
+    renderer.set_texture(&label.texture);
+    renderer.draw_texture_rect(
+        x - w * align_x,
+        y - h * align_y,
+        w,
+        h,
+        0.0,
+        0.0,
+        1.0,
+        1.0,
+        1.0,
+        1.0,
+        1.0,
+        1.0,
+        &IDENTITY,
+    );
+    h
+}
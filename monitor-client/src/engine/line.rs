@@ -1,7 +1,161 @@
-use crate::engine::{RenderConfig, Resource, draw_note};
-use crate::renderer::Renderer;
-use monitor_common::core::{ChartSettings, JudgeLine, JudgeLineKind, Matrix, Vector};
+use crate::engine::{NoteFilter, RenderConfig, Resource, draw_note};
+use crate::renderer::{IDENTITY, RenderTarget, Renderer, Texture};
+use monitor_common::core::{
+    ChartSettings, Color, JudgeLine, JudgeLineKind, Matrix, Vector, colors,
+};
+use std::collections::hash_map::Entry;
+use web_sys::WebGl2RenderingContext;
 
+/// World units per RPE pixel, the RPE canvas being 1350 wide
+const RPE_UNIT: f32 = 2.0 / 1350.0;
+
+/// World size of a textured line. The proxy folds `RPE_UNIT` into the
+/// scale events, so a line without them is drawn at the texture's
+/// pixel size on the RPE canvas.
+fn texture_line_size(line: &JudgeLine, texture: &Texture) -> (f32, f32) {
+    let scale_x = line.object.scale.x.now_opt().unwrap_or(RPE_UNIT);
+    let scale_y = line.object.scale.y.now_opt().unwrap_or(RPE_UNIT);
+    (
+        scale_x * texture.width as f32,
+        scale_y * texture.height as f32,
+    )
+}
+
+/// Paint pass for `JudgeLineKind::Paint` lines.
+///
+/// Strokes accumulate in a per-line offscreen target that persists across
+/// frames. A positive paint value stamps a brush of that size (in RPE units)
+/// at the line origin; a negative value stops painting and fades the canvas
+/// out at `-value` per second. The target is then composited over the screen.
+fn draw_paint(
+    res: &mut Resource,
+    line_index: usize,
+    value: f32,
+    color: Color,
+    renderer: &mut Renderer,
+) {
+    let (width, height) = (renderer.context.width, renderer.context.height);
+    renderer.flush();
+    if res
+        .paint_targets
+        .get(&line_index)
+        .is_some_and(|t| t.texture.width != width || t.texture.height != height)
+    {
+        // Canvas was resized, the old strokes no longer line up
+        let target = res.paint_targets.remove(&line_index).unwrap();
+        target.delete(&renderer.context);
+    }
+    let model = res.get_gl_matrix();
+    let half_h = 1.0 / res.aspect_ratio;
+    let dt = res.dt;
+    let target = match res.paint_targets.entry(line_index) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let target = RenderTarget::new(&renderer.context, width, height);
+            // Creating the target rebinds TEXTURE_2D behind the batcher's back
+            renderer.batcher.invalidate_texture_cache();
+            match target {
+                Ok(target) => entry.insert(target),
+                Err(_) => return,
+            }
+        }
+    };
+
+    target.bind(&renderer.context);
+    if value > 0.0 {
+        let size = value * RPE_UNIT;
+        renderer.draw_rect(
+            -size / 2.0,
+            -size / 2.0,
+            size,
+            size,
+            color.r,
+            color.g,
+            color.b,
+            color.a,
+            &model,
+        );
+    } else if value < 0.0 && dt > 0.0 {
+        // dst *= 1 - fade
+        let fade = (-value * dt).min(1.0);
+        let gl = &renderer.context.gl;
+        gl.blend_func(
+            WebGl2RenderingContext::ZERO,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+        renderer.draw_rect(
+            -1.0,
+            -half_h,
+            2.0,
+            2.0 * half_h,
+            0.0,
+            0.0,
+            0.0,
+            fade,
+            &IDENTITY,
+        );
+        renderer.flush();
+        renderer.context.gl.blend_func(
+            WebGl2RenderingContext::SRC_ALPHA,
+            WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+        );
+    }
+    renderer.flush();
+    RenderTarget::unbind(&renderer.context);
+
+    // Framebuffer rows start at the bottom, hence the flipped v range
+    renderer.set_texture(&target.texture);
+    renderer.draw_texture_rect(
+        -1.0,
+        -half_h,
+        2.0,
+        2.0 * half_h,
+        0.0,
+        1.0,
+        1.0,
+        -1.0,
+        1.0,
+        1.0,
+        1.0,
+        1.0,
+        &IDENTITY,
+    );
+}
+
+/// Applies the PE alpha extension (negative alpha values as control codes).
+/// Returns `None` if neither the line nor its notes should be drawn,
+/// otherwise whether notes below the line are drawn.
+fn line_visibility(line: &JudgeLine, settings: &ChartSettings) -> Option<bool> {
+    // TODO: support attach_ui
+    if line.attach_ui.is_some() {
+        return None;
+    }
+    let alpha = line.object.alpha.now_opt().unwrap_or(1.0);
+    let mut draw_below = line.show_below;
+    let mut _appear_before = f32::INFINITY;
+
+    if alpha < 0.0 {
+        if !settings.pe_alpha_extension {
+            return None;
+        }
+        let w = (-alpha).floor() as u32;
+        match w {
+            1 => {
+                return None;
+            }
+            2 => {
+                draw_below = false;
+            }
+            w if (100..1000).contains(&w) => {
+                _appear_before = (w as f32 - 100.) / 10.;
+            }
+            _ => {}
+        }
+    }
+    Some(draw_below)
+}
+
+/// Draws the line itself, without its notes.
 pub fn draw_line(
     res: &mut Resource,
     line: &JudgeLine,
@@ -11,42 +165,16 @@ pub fn draw_line(
     settings: &ChartSettings,
     world_matrix: Matrix,
 ) {
-    // TODO: support attach_ui
-    if let Some(_) = &line.attach_ui {
+    if line_visibility(line, settings).is_none() {
         return;
     }
     res.with_model(world_matrix, |res| {
         let alpha = line.object.alpha.now_opt().unwrap_or(1.0);
 
-        // PE Alpha Extension Logic (Negative Alpha)
-        let mut draw_below = line.show_below;
-        let mut _appear_before = f32::INFINITY;
-
-        if alpha < 0.0 {
-            if !settings.pe_alpha_extension {
-                return;
-            }
-            let w = (-alpha).floor() as u32;
-            match w {
-                1 => {
-                    return;
-                }
-                2 => {
-                    draw_below = false;
-                }
-                w if (100..1000).contains(&w) => {
-                    _appear_before = (w as f32 - 100.) / 10.;
-                }
-                _ => {}
-            }
-        }
-
-        let color = line.color.now_opt().unwrap_or(monitor_common::core::Color {
-            r: 1.0,
-            g: 1.0,
-            b: 1.0,
-            a: 1.0,
-        });
+        // colorEvents tint every line kind; negative PE alpha values are
+        // control codes handled above and must not leak into blending
+        let mut color = line.color.now_opt().unwrap_or(colors::WHITE);
+        color.a *= alpha.max(0.0);
 
         match &line.kind {
             JudgeLineKind::Normal => {
@@ -61,18 +189,13 @@ pub fn draw_line(
                     color.r,
                     color.g,
                     color.b,
-                    alpha * color.a,
+                    color.a,
                     &res.get_gl_matrix(),
                 );
             }
             JudgeLineKind::Texture(_, _) => {
                 if let Some(texture) = res.line_textures.get(&line_index) {
-                    let scale_x = line.object.scale.x.now_opt().unwrap_or(1.0);
-                    let scale_y = line.object.scale.y.now_opt().unwrap_or(1.0);
-
-                    // Note: RPE scale (2/1350) is already included in the animation scale from the proxy
-                    let w = scale_x * (texture.width as f32);
-                    let h = scale_y * (texture.height as f32);
+                    let (w, h) = texture_line_size(line, texture);
 
                     renderer.set_texture(texture);
                     renderer.draw_texture_rect(
@@ -87,36 +210,17 @@ pub fn draw_line(
                         color.r,
                         color.g,
                         color.b,
-                        alpha * color.a,
+                        color.a,
                         &res.get_gl_matrix(),
                     );
                 }
             }
-            JudgeLineKind::TextureGif(_, gif, _) => {
+            JudgeLineKind::TextureGif(progress, gif, _) => {
                 if let Some(frames) = res.line_gif_textures.get(&line_index) {
-                    let time = res.time * 1000.0; // convert to ms
-                    let total_time = gif.total_time as f32;
-                    let current_time = if total_time > 0.0 {
-                        time % total_time
-                    } else {
-                        0.0
-                    };
-
-                    let mut frame_index = 0;
-                    for (i, (frame_time, _)) in gif.frames.iter().enumerate() {
-                        if (*frame_time as f32) > current_time {
-                            break;
-                        }
-                        frame_index = i;
-                    }
+                    let frame_index = gif.frame_at_progress(progress.now_opt().unwrap_or(0.0));
 
                     if let Some(texture) = frames.get(frame_index) {
-                        let scale_x = line.object.scale.x.now_opt().unwrap_or(1.0);
-                        let scale_y = line.object.scale.y.now_opt().unwrap_or(1.0);
-
-                        // Note: RPE scale (2/1350) is already included in the animation scale from the proxy
-                        let w = scale_x * (texture.width as f32);
-                        let h = scale_y * (texture.height as f32);
+                        let (w, h) = texture_line_size(line, texture);
 
                         renderer.set_texture(texture);
                         renderer.draw_texture_rect(
@@ -131,7 +235,7 @@ pub fn draw_line(
                             color.r,
                             color.g,
                             color.b,
-                            alpha * color.a,
+                            color.a,
                             &res.get_gl_matrix(),
                         );
                     }
@@ -140,37 +244,63 @@ pub fn draw_line(
             JudgeLineKind::Text(anim) => {
                 if let Some(font) = &res.font {
                     let text = anim.now_opt().unwrap_or_default();
-                    let rpe_scale = 2.0 / 1350.0;
 
-                    font.draw_text(
+                    font.draw_text_color(
                         renderer,
                         &text,
                         0.0,
                         0.0,
-                        60.0 * rpe_scale,
+                        60.0 * RPE_UNIT,
                         0.5,
+                        color.r,
+                        color.g,
+                        color.b,
+                        color.a,
                         &res.get_gl_matrix(),
                     );
                 }
             }
-            JudgeLineKind::Paint(_) => {
-                // TODO: Implement Paint rendering
+            JudgeLineKind::Paint(anim) => {
+                let value = anim.now_opt().unwrap_or(0.0);
+                draw_paint(res, line_index, value, color, renderer);
             }
         }
+    });
+}
 
+/// Draws the notes attached to the line.
+pub fn draw_line_notes(
+    res: &mut Resource,
+    line: &JudgeLine,
+    renderer: &mut Renderer,
+    settings: &ChartSettings,
+    world_matrix: Matrix,
+    mirror: bool,
+    filter: &NoteFilter,
+) {
+    let Some(draw_below) = line_visibility(line, settings) else {
+        return;
+    };
+    res.with_model(world_matrix, |res| {
         let height_val = line.height.now_opt().unwrap_or(0.0);
 
         let config = RenderConfig {
             line_height: height_val,
             aspect_ratio: res.aspect_ratio,
             note_width: res.note_width * res.note_scale,
-            draw_below: draw_below,
-            alpha: line.ctrl_obj.alpha.now_opt().unwrap_or(1.0),
+            draw_below,
+            mirror,
+            incline_sin: line.incline.now_opt().unwrap_or(0.0).to_radians().sin(),
+            ctrl: &line.ctrl_obj,
         };
 
         // Draw notes
         // Pass 1: Above notes
-        for note in line.notes.iter().filter(|n| n.above) {
+        for note in line
+            .notes
+            .iter()
+            .filter(|n| n.above && filter.shows_note(n))
+        {
             draw_note(res, note, line, &config, renderer);
         }
 
@@ -178,7 +308,11 @@ pub fn draw_line(
         res.with_model(
             Matrix::identity().append_nonuniform_scaling(&Vector::new(1.0, -1.0)),
             |res| {
-                for note in line.notes.iter().filter(|n| !n.above) {
+                for note in line
+                    .notes
+                    .iter()
+                    .filter(|n| !n.above && filter.shows_note(n))
+                {
                     draw_note(res, note, line, &config, renderer);
                 }
             },
@@ -1,14 +1,73 @@
+use crate::engine::DebugRect;
 use crate::engine::resource::Resource;
 use crate::renderer::{Renderer, Texture};
-use monitor_common::core::{JudgeLine, JudgeStatus, Note, NoteKind};
+use monitor_common::core::{CtrlObject, CtrlValues, JudgeLine, JudgeStatus, Note, NoteKind, Time};
+use monitor_common::parse::rpe::RPE_HEIGHT;
 use nalgebra::{Matrix3, Vector2};
 
-pub struct RenderConfig {
+/// How long notes stay drawn past their time when the line hides notes
+/// below it, as in prpr
+const FADEOUT_TIME: Time = 0.16;
+
+pub struct RenderConfig<'a> {
     pub line_height: f32,
     pub aspect_ratio: f32,
     pub note_width: f32,
     pub draw_below: bool,
-    pub alpha: f32,
+    /// The line's control events, evaluated per note
+    pub ctrl: &'a CtrlObject,
+    /// Negate note X offsets, see `ChartRenderer::mirror`
+    pub mirror: bool,
+    /// Sine of the line's incline angle
+    pub incline_sin: f32,
+}
+
+impl RenderConfig<'_> {
+    /// Control values for `note`, keyed by its distance from the line in
+    /// RPE pixels
+    fn ctrl_at(&self, note: &Note) -> CtrlValues {
+        self.ctrl
+            .eval_at((note.height - self.line_height) * RPE_HEIGHT / 2.0)
+    }
+
+    /// X offset of a note drawn `y` above the line. Inclined lines pull
+    /// notes towards the center the farther they are, like Phira's
+    /// `incline_val`.
+    fn note_x(&self, note: &Note, ctrl: &CtrlValues, y: f32) -> f32 {
+        let mut x = note.object.translation.x.now_opt().unwrap_or(0.0) * ctrl.pos;
+        x *= 1.0 - self.incline_sin * y * self.aspect_ratio / 2.0;
+        if self.mirror { -x } else { x }
+    }
+}
+
+/// What to leave out when drawing, for debugging charts. Judging, scoring
+/// and hitsounds are unaffected.
+#[derive(Clone, Default)]
+pub struct NoteFilter {
+    pub hide_fake: bool,
+    /// Bit per kind, see `NoteFilter::kind_bit`
+    pub hidden_kinds: u8,
+    /// Draw only this line and its notes
+    pub only_line: Option<usize>,
+}
+
+impl NoteFilter {
+    pub fn kind_bit(kind: &NoteKind) -> u8 {
+        match kind {
+            NoteKind::Click => 1,
+            NoteKind::Hold { .. } => 2,
+            NoteKind::Flick => 4,
+            NoteKind::Drag => 8,
+        }
+    }
+
+    pub fn shows_line(&self, index: usize) -> bool {
+        self.only_line.is_none_or(|only| only == index)
+    }
+
+    pub fn shows_note(&self, note: &Note) -> bool {
+        !(self.hide_fake && note.fake) && self.hidden_kinds & Self::kind_bit(&note.kind) == 0
+    }
 }
 
 pub fn draw_note(
@@ -30,8 +89,9 @@ pub fn draw_note(
         _ => {}
     }
 
+    let multiple_hint = note.multiple_hint && res.multiple_hint;
     let res_pack = res.res_pack.as_ref().unwrap();
-    let style_ref = if note.multiple_hint {
+    let style_ref = if multiple_hint {
         &res_pack.note_style_mh
     } else {
         &res_pack.note_style
@@ -39,7 +99,7 @@ pub fn draw_note(
 
     // Phira's double_hint scaling: multi-hint notes are wider by the ratio
     // of mh texture width to normal texture width (prpr note.rs L199-203)
-    let scale = if note.multiple_hint {
+    let scale = if multiple_hint {
         let ratio =
             res_pack.note_style_mh.click.width as f32 / res_pack.note_style.click.width as f32;
         config.note_width * ratio
@@ -57,19 +117,11 @@ pub fn draw_note(
         NoteKind::Flick => {
             draw_simple_note(res, note, style_ref.flick.clone(), scale, config, renderer);
         }
-        NoteKind::Hold {
-            end_time,
-            end_height,
-        } => {
+        NoteKind::Hold { end_height, .. } => {
             let head_rect = style_ref.hold_head_rect();
             let body_rect = style_ref.hold_body_rect();
             let tail_rect = style_ref.hold_tail_rect();
             let hold_tex = style_ref.hold.clone();
-            let alpha = if matches!(note.judge, JudgeStatus::Judged) {
-                0.5
-            } else {
-                1.0
-            };
 
             draw_hold_note(
                 res,
@@ -81,7 +133,7 @@ pub fn draw_note(
                 scale,
                 config,
                 renderer,
-                *end_time,
+                style_ref.hold_body.clone(),
                 *end_height,
             );
         }
@@ -96,30 +148,43 @@ fn draw_simple_note(
     config: &RenderConfig,
     renderer: &mut Renderer,
 ) {
-    let x = note.object.translation.x.now_opt().unwrap_or(0.0);
-
     let spd = note.speed;
     let line_height_val = config.line_height;
     let note_height_val = note.height;
 
     // Use (note - line) because coordinate system is Positive Up.
     // Future Note: note > line. Result Positive (Above).
-    let y_pos = (note_height_val - line_height_val) * spd / config.aspect_ratio;
+    let ctrl = config.ctrl_at(note);
+    let y_pos = (note_height_val - line_height_val) * spd / config.aspect_ratio * ctrl.y;
 
-    // If y_pos < 0, it means it's below the line (Past).
-    // If not drawing below, skip.
-    if !config.draw_below && y_pos < -0.001 {
+    // Below the line before its time, e.g. under a negative speed: hidden
+    // unless the line shows notes below it. Like prpr, notes that passed
+    // the line still show until they fade out.
+    if !config.draw_below
+        && (res.time - FADEOUT_TIME >= note.time || (note.time > res.time && y_pos < -0.001))
+    {
         return;
     }
 
+    let x = config.note_x(note, &ctrl, y_pos);
     let transform = Matrix3::new_translation(&Vector2::new(x, y_pos));
     res.with_model(transform, |res| {
         let obj_scale_x = note.object.scale.x.now_opt().unwrap_or(1.0);
 
-        let w = scale * 2.0 * obj_scale_x;
+        let w = scale * 2.0 * obj_scale_x * ctrl.size;
         // Adjust aspect ratio of texture
         let h = w * (texture.height as f32 / texture.width as f32);
-        let alpha = note.object.alpha.now_opt().unwrap_or(1.0) * config.alpha;
+        let alpha = note.object.alpha.now_opt().unwrap_or(1.0) * ctrl.alpha;
+        let model = res.get_gl_matrix();
+        if let Some(rects) = &mut res.debug_rects {
+            rects.push(DebugRect {
+                model,
+                x: -w / 2.0,
+                y: -h / 2.0,
+                w,
+                h,
+            });
+        }
 
         renderer.set_texture(&texture);
         renderer.draw_texture_rect(
@@ -150,7 +215,7 @@ fn draw_hold_note(
     scale: f32,
     config: &RenderConfig,
     renderer: &mut Renderer,
-    _end_time: f32,
+    body_texture: Option<Texture>,
     end_height: f32,
 ) {
     let spd = note.speed;
@@ -166,6 +231,10 @@ fn draw_hold_note(
     if raw_tail_y < 0.0 {
         return;
     }
+    // Coming from below the line, see `draw_simple_note`
+    if !config.draw_below && note.time > res.time && raw_head_y < -0.001 {
+        return;
+    }
 
     // For active Hold notes, clamp head to line position (head doesn't go below line)
     let clamped_head_y = if matches!(note.judge, JudgeStatus::Hold(..)) {
@@ -174,13 +243,15 @@ fn draw_hold_note(
         raw_head_y
     };
 
-    let x = note.object.translation.x.now_opt().unwrap_or(0.0);
+    // Holds take the control values at their head
+    let ctrl = config.ctrl_at(note);
+    let x = config.note_x(note, &ctrl, clamped_head_y);
     let transform = Matrix3::new_translation(&Vector2::new(x, 0.0));
     res.with_model(transform, |res| {
         let obj_scale_x = note.object.scale.x.now_opt().unwrap_or(1.0);
-        let width = scale * 2.0 * obj_scale_x;
+        let width = scale * 2.0 * obj_scale_x * ctrl.size;
         let alpha = note.object.alpha.now_opt().unwrap_or(1.0)
-            * config.alpha
+            * ctrl.alpha
             * if matches!(note.judge, JudgeStatus::Judged) {
                 0.5
             } else {
@@ -193,44 +264,45 @@ fn draw_hold_note(
         // y: bottom position of the part
         // h: height of the part
         // r: source rect (u, v, w, h)
-        let mut draw_part = |y: f32, h: f32, r: crate::engine::resource::Rect| {
-            if h <= 0.0001 {
-                return;
-            }
-            let mut draw_y = y;
-            let mut draw_h = h;
-            let mut draw_v = r.y;
-            let mut draw_vs = r.h;
-
-            // Clip bottom
-            if draw_y < 0.0 {
-                let diff = -draw_y;
-                if diff >= draw_h {
+        let draw_part =
+            |renderer: &mut Renderer, y: f32, h: f32, r: crate::engine::resource::Rect| {
+                if h <= 0.0001 {
                     return;
-                } // Fully clipped
-                draw_y = 0.0;
-                draw_h -= diff;
+                }
+                let mut draw_y = y;
+                let mut draw_h = h;
+                let mut draw_v = r.y;
+                let mut draw_vs = r.h;
 
-                draw_v += (diff / h) * draw_vs;
-                draw_vs *= draw_h / h;
-            }
+                // Clip bottom
+                if draw_y < 0.0 {
+                    let diff = -draw_y;
+                    if diff >= draw_h {
+                        return;
+                    } // Fully clipped
+                    draw_y = 0.0;
+                    draw_h -= diff;
 
-            renderer.draw_texture_rect(
-                -width / 2.0,
-                draw_y,
-                width,
-                draw_h,
-                r.x,
-                draw_v,
-                r.w,
-                draw_vs,
-                1.0,
-                1.0,
-                1.0,
-                alpha,
-                &res.get_gl_matrix(),
-            );
-        };
+                    draw_v += (diff / h) * draw_vs;
+                    draw_vs *= draw_h / h;
+                }
+
+                renderer.draw_texture_rect(
+                    -width / 2.0,
+                    draw_y,
+                    width,
+                    draw_h,
+                    r.x,
+                    draw_v,
+                    r.w,
+                    draw_vs,
+                    1.0,
+                    1.0,
+                    1.0,
+                    alpha,
+                    &res.get_gl_matrix(),
+                );
+            };
 
         // Aspect ratio of texture parts
         let tex_aspect = texture.height as f32 / texture.width as f32;
@@ -242,7 +314,8 @@ fn draw_hold_note(
         let head_y = clamped_head_y;
         let tail_y = raw_tail_y;
 
-        let is_compact = res.res_pack.as_ref().map_or(false, |p| p.info.hold_compact);
+        let is_compact = res.res_pack.as_ref().is_some_and(|p| p.info.hold_compact);
+        let is_repeat = res.res_pack.as_ref().is_some_and(|p| p.info.hold_repeat);
 
         let draw_head_y = head_y - if is_compact { head_h / 2.0 } else { head_h };
         let draw_tail_y = tail_y - if is_compact { tail_h / 2.0 } else { 0.0 };
@@ -252,11 +325,56 @@ fn draw_hold_note(
         let body_h = draw_tail_y - body_y;
 
         // Draw parts
-        draw_part(draw_head_y, head_h, head_rect);
+        draw_part(renderer, draw_head_y, head_h, head_rect);
         // Ensure body has positive height
         if body_h > 0.01 {
-            draw_part(body_y, body_h, body_rect);
+            if is_repeat {
+                // Tile the body at its natural aspect instead of stretching it,
+                // cropping the last tile from the top. A pre-cut body texture
+                // keeps the head and tail from bleeding into the seams.
+                let (body_rect, tile_h) = match &body_texture {
+                    Some(body) => {
+                        renderer.set_texture(body);
+                        let aspect = body.height as f32 / body.width as f32;
+                        (
+                            crate::engine::resource::Rect::new(0., 0., 1., 1.),
+                            width * aspect,
+                        )
+                    }
+                    None => (body_rect, width * (body_rect.h / body_rect.w) * tex_aspect),
+                };
+                let mut y = body_y;
+                while tile_h > 0.0001 && y < body_y + body_h {
+                    let h = tile_h.min(body_y + body_h - y);
+                    let frac = h / tile_h;
+                    let rect = crate::engine::resource::Rect::new(
+                        body_rect.x,
+                        body_rect.y + body_rect.h * (1.0 - frac),
+                        body_rect.w,
+                        body_rect.h * frac,
+                    );
+                    draw_part(renderer, y, h, rect);
+                    y += tile_h;
+                }
+                if body_texture.is_some() {
+                    renderer.set_texture(&texture);
+                }
+            } else {
+                draw_part(renderer, body_y, body_h, body_rect);
+            }
+        }
+        draw_part(renderer, draw_tail_y, tail_h, tail_rect);
+
+        let model = res.get_gl_matrix();
+        if let Some(rects) = &mut res.debug_rects {
+            let bottom = draw_head_y.max(0.0);
+            rects.push(DebugRect {
+                model,
+                x: -width / 2.0,
+                y: bottom,
+                w: width,
+                h: draw_tail_y + tail_h - bottom,
+            });
         }
-        draw_part(draw_tail_y, tail_h, tail_rect);
     });
 }
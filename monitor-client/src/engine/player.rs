@@ -0,0 +1,129 @@
+use crate::renderer::{IDENTITY, Label, Renderer, Texture};
+use wasm_bindgen::prelude::*;
+
+/// Player name, avatar and rank badge in the top left corner, so captures
+/// of a single canvas show whose play it is.
+pub struct PlayerOverlay {
+    /// Canvas height the fonts were sized for
+    height: u32,
+    name: Option<String>,
+    badge: Option<String>,
+    avatar: Option<Texture>,
+    name_label: Label,
+    badge_label: Label,
+}
+
+impl PlayerOverlay {
+    pub fn new(renderer: &Renderer) -> Result<Self, JsValue> {
+        let ctx = &renderer.context;
+        Ok(Self {
+            height: 0,
+            name: None,
+            badge: None,
+            avatar: None,
+            name_label: Label::new(ctx)?,
+            badge_label: Label::new(ctx)?,
+        })
+    }
+
+    /// Replaces the shown player; no name hides the overlay.
+    pub fn set(
+        &mut self,
+        renderer: &Renderer,
+        name: Option<String>,
+        badge: Option<String>,
+        avatar: Option<Texture>,
+    ) {
+        if let Some(old) = std::mem::replace(&mut self.avatar, avatar) {
+            renderer.context.gl.delete_texture(Some(&old.texture));
+        }
+        self.name = name;
+        self.badge = badge;
+    }
+
+    pub fn draw(&mut self, renderer: &mut Renderer) -> Result<(), JsValue> {
+        let Some(name) = &self.name else {
+            return Ok(());
+        };
+        let (width, height) = (renderer.context.width, renderer.context.height);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        if self.height != height {
+            self.height = height;
+            let px = |ratio: f32| (height as f32 * ratio).round();
+            self.name_label
+                .set_font(&format!("bold {}px sans-serif", px(0.03)));
+            self.badge_label
+                .set_font(&format!("normal {}px sans-serif", px(0.022)));
+        }
+
+        // Re-rasterizing binds textures behind the batcher's back
+        renderer.flush();
+        self.name_label.set_text(&renderer.context, name)?;
+        if let Some(badge) = &self.badge {
+            self.badge_label.set_text(&renderer.context, badge)?;
+        }
+        renderer.batcher.invalidate_texture_cache();
+        // Particle drawing leaves no program bound
+        renderer.begin_frame();
+
+        // World units per canvas pixel, see the projection in ChartPlayer::render
+        let px = 2.0 / width as f32;
+        let top = height as f32 * px / 2.0;
+        let margin = height as f32 * 0.03 * px;
+        let size = height as f32 * 0.06 * px;
+        let mut x = -1.0 + margin;
+
+        if let Some(avatar) = &self.avatar {
+            renderer.set_texture(avatar);
+            renderer.draw_texture_rect(
+                x,
+                top - margin - size,
+                size,
+                size,
+                0.0,
+                0.0,
+                1.0,
+                1.0,
+                1.0,
+                1.0,
+                1.0,
+                1.0,
+                &IDENTITY,
+            );
+            x += size + margin / 2.0;
+        }
+
+        let mut y = top - margin;
+        let mut labels = vec![&self.name_label];
+        if self.badge.is_some() {
+            labels.push(&self.badge_label);
+        }
+        for label in labels {
+            let (w, h) = (
+                label.texture.width as f32 * px,
+                label.texture.height as f32 * px,
+            );
+            renderer.set_texture(&label.texture);
+            renderer.draw_texture_rect(
+                x,
+                y - h,
+                w,
+                h,
+                0.0,
+                0.0,
+                1.0,
+                1.0,
+                1.0,
+                1.0,
+                1.0,
+                1.0,
+                &IDENTITY,
+            );
+            y -= h;
+        }
+        renderer.flush();
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::engine::Resource;
+use crate::renderer::{IDENTITY, Label, Renderer};
+use monitor_common::core::{Color, Judgement, Vector, colors};
+use wasm_bindgen::prelude::*;
+
+/// Seconds a popup stays on screen
+const POPUP_DURATION: f32 = 0.5;
+/// Seconds of the initial pop (scale-down) animation
+const POPUP_POP_TIME: f32 = 0.12;
+/// How far a popup drifts upwards over its lifetime, in world units
+const POPUP_RISE: f32 = 0.04;
+
+const BAD_COLOR: Color = Color::new(0.42, 0.23, 0.23, 1.0);
+
+struct Popup {
+    pos: Vector,
+    judgement: Judgement,
+    age: f32,
+}
+
+/// Floating "Perfect/Good/Bad/Miss" indicators shown where notes are judged.
+pub struct JudgePopups {
+    pub enabled: bool,
+    /// Canvas height the labels were rasterized for
+    height: u32,
+    labels: [Label; 4],
+    popups: Vec<Popup>,
+}
+
+impl JudgePopups {
+    pub fn new(renderer: &Renderer) -> Result<Self, JsValue> {
+        let ctx = &renderer.context;
+        Ok(Self {
+            enabled: true,
+            height: 0,
+            labels: [
+                Label::new(ctx)?,
+                Label::new(ctx)?,
+                Label::new(ctx)?,
+                Label::new(ctx)?,
+            ],
+            popups: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, pos: Vector, judgement: Judgement) {
+        if self.enabled {
+            self.popups.push(Popup {
+                pos,
+                judgement,
+                age: 0.0,
+            });
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.popups.clear();
+    }
+
+    fn color(res: &Resource, judgement: Judgement) -> Color {
+        let info = res.res_pack.as_ref().map(|p| &p.info);
+        match judgement {
+            Judgement::Perfect => info.map_or(colors::WHITE, |i| i.fx_perfect()),
+            Judgement::Good => info.map_or(colors::WHITE, |i| i.fx_good()),
+            Judgement::Bad => BAD_COLOR,
+            Judgement::Miss => colors::LIGHTGRAY,
+        }
+    }
+
+    pub fn draw(&mut self, renderer: &mut Renderer, res: &Resource) -> Result<(), JsValue> {
+        for popup in &mut self.popups {
+            popup.age += res.dt.max(0.0);
+        }
+        self.popups.retain(|p| p.age < POPUP_DURATION);
+        if self.popups.is_empty() {
+            return Ok(());
+        }
+
+        let (width, height) = (renderer.context.width, renderer.context.height);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        // Re-rasterizing binds textures behind the batcher's back
+        renderer.flush();
+        if self.height != height {
+            self.height = height;
+            let font = format!("bold {}px sans-serif", (height as f32 * 0.028).round());
+            for (label, text) in self
+                .labels
+                .iter_mut()
+                .zip(["Perfect", "Good", "Bad", "Miss"])
+            {
+                label.set_font(&font);
+                label.set_text(&renderer.context, text)?;
+            }
+            renderer.batcher.invalidate_texture_cache();
+        }
+        // Particle drawing leaves no program bound
+        renderer.begin_frame();
+
+        // World units per canvas pixel, see the projection in ChartPlayer::render
+        let px = 2.0 / width as f32;
+        for popup in &self.popups {
+            let t = popup.age / POPUP_DURATION;
+            let scale = 1.0 + 0.3 * (1.0 - popup.age / POPUP_POP_TIME).max(0.0);
+            let alpha = if t < 0.5 { 1.0 } else { (1.0 - t) * 2.0 };
+            let color = Self::color(res, popup.judgement);
+
+            let label = &self.labels[popup.judgement as usize];
+            let w = label.texture.width as f32 * px * scale;
+            let h = label.texture.height as f32 * px * scale;
+            let x = popup.pos.x;
+            let y = popup.pos.y + POPUP_RISE * t;
+            renderer.set_texture(&label.texture);
+            renderer.draw_texture_rect(
+                x - w / 2.0,
+                y - h / 2.0,
+                w,
+                h,
+                0.0,
+                0.0,
+                1.0,
+                1.0,
+                color.r,
+                color.g,
+                color.b,
+                color.a * alpha,
+                &IDENTITY,
+            );
+        }
+        renderer.flush();
+        Ok(())
+    }
+}
@@ -1,8 +1,14 @@
-use crate::renderer::Texture;
+use super::VideoLayer;
+use crate::renderer::{RenderTarget, Texture};
 use anyhow::Result;
-use monitor_common::core::{AudioClip, HitSound, HitSoundMap, Matrix, Point, Vector};
+use monitor_common::core::{
+    AudioClip, HitSound, HitSoundMap, Judgement, Matrix, Point, Time, Vector,
+};
+use monitor_common::respack;
+pub use monitor_common::respack::ResPackInfo;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
 
 #[derive(Deserialize, Clone, Copy)]
 pub struct Rect {
@@ -18,31 +24,6 @@ impl Rect {
     }
 }
 
-#[inline]
-fn default_scale() -> f32 {
-    1.
-}
-
-#[inline]
-fn default_duration() -> f32 {
-    0.5
-}
-
-#[inline]
-fn default_perfect() -> u32 {
-    0xe1ffec9f
-}
-
-#[inline]
-fn default_good() -> u32 {
-    0xebb4e1ff
-}
-
-#[inline]
-fn default_tinted() -> bool {
-    true
-}
-
 pub struct NoteStyle {
     pub click: Texture,
     pub hold: Texture,
@@ -88,63 +69,91 @@ impl NoteStyle {
     }
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ResPackInfo {
-    pub name: String,
-    pub author: String,
-    pub description: String,
-    pub hold_atlas: (u32, u32),
-    #[serde(rename = "holdAtlasMH")]
-    pub hold_atlas_mh: (u32, u32),
-    #[serde(default)]
-    pub hold_repeat: bool,
-    #[serde(default)]
-    pub hold_compact: bool,
-
-    pub hit_fx: (u32, u32),
-    #[serde(default = "default_duration")]
-    pub hit_fx_duration: f32,
-    #[serde(default = "default_scale")]
-    pub hit_fx_scale: f32,
-    #[serde(default)]
-    pub hit_fx_rotate: bool,
-    #[serde(default)]
-    pub hide_particles: bool,
-    #[serde(default = "default_tinted")]
-    pub hit_fx_tinted: bool,
+pub struct ResourcePack {
+    pub info: ResPackInfo,
+    pub note_style: NoteStyle,
+    pub note_style_mh: NoteStyle,
+    pub hit_fx: Texture,
+    pub font: Option<crate::renderer::text::SpriteFont>,
+    pub hitsounds: HitSoundMap,
+    /// Content hash of every file the pack was built from, keyed by file name.
+    pub hashes: HashMap<String, u64>,
+}
+
+const HITSOUND_FILES: [(HitSound, &str); 3] = [
+    (HitSound::Click, "click"),
+    (HitSound::Drag, "drag"),
+    (HitSound::Flick, "flick"),
+];
 
-    #[serde(default = "default_perfect")]
-    pub color_perfect: u32,
-    #[serde(default = "default_good")]
-    pub color_good: u32,
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
 }
 
-impl ResPackInfo {
-    pub fn fx_perfect(&self) -> monitor_common::core::Color {
-        if self.hit_fx_tinted {
-            monitor_common::core::Color::from_hex(self.color_perfect)
-        } else {
-            monitor_common::core::colors::WHITE
+fn parse_info(files: &HashMap<String, Vec<u8>>) -> Result<Option<ResPackInfo>> {
+    let Some(info_bytes) = files.get("info.yml") else {
+        return Ok(None);
+    };
+    let info_str = std::str::from_utf8(info_bytes)?;
+    Ok(Some(serde_yaml::from_str(info_str)?))
+}
+
+// Helper to load texture from bytes
+async fn load_tex(
+    ctx: &crate::renderer::GlContext,
+    files: &HashMap<String, Vec<u8>>,
+    name: &str,
+) -> Result<Texture, anyhow::Error> {
+    let bytes = files
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("Missing {}", name))?;
+    Ok(Texture::load_from_bytes(ctx, bytes)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to load texture {}: {:?}", name, e))?)
+}
+
+// Helper to load audio from bytes
+fn load_audio(files: &HashMap<String, Vec<u8>>, name: &str) -> Option<AudioClip> {
+    let exts = ["mp3", "ogg", "wav"];
+    for ext in exts {
+        let filename = format!("{}.{}", name, ext);
+        if let Some(bytes) = files.get(&filename) {
+            if let Ok(clip) = AudioClip::load_from_bytes(bytes, ext) {
+                return Some(clip);
+            }
         }
     }
+    None
+}
 
-    pub fn fx_good(&self) -> monitor_common::core::Color {
-        if self.hit_fx_tinted {
-            monitor_common::core::Color::from_hex(self.color_good)
-        } else {
-            monitor_common::core::colors::WHITE
-        }
+/// Pack files [`ResourcePack::update`] uploads as textures
+const TEXTURE_FILES: [&str; 10] = [
+    "click.png",
+    "hold.png",
+    "flick.png",
+    "drag.png",
+    "click_mh.png",
+    "hold_mh.png",
+    "flick_mh.png",
+    "drag_mh.png",
+    "hit_fx.png",
+    "font.png",
+];
+
+/// Deletes a replaced texture unless it is a sprite of a bundle's atlas,
+/// which the pack's other sprites still draw from
+fn delete_unshared(ctx: &crate::renderer::GlContext, tex: &Texture) {
+    if tex.region.is_none() {
+        ctx.gl.delete_texture(Some(&tex.texture));
     }
 }
 
-pub struct ResourcePack {
-    pub info: ResPackInfo,
-    pub note_style: NoteStyle,
-    pub note_style_mh: NoteStyle,
-    pub hit_fx: Texture,
-    pub font: Option<crate::renderer::text::SpriteFont>,
-    pub hitsounds: HitSoundMap,
+fn load_font(tex: Texture) -> crate::renderer::text::SpriteFont {
+    let mut font = crate::renderer::text::SpriteFont::new(tex, 60.0);
+    font.load_grid("0123456789.", 11, 1, 60.0, 60.0);
+    font
 }
 
 impl ResourcePack {
@@ -152,39 +161,7 @@ impl ResourcePack {
         ctx: &crate::renderer::GlContext,
         files: HashMap<String, Vec<u8>>,
     ) -> anyhow::Result<Self> {
-        let info_bytes = files
-            .get("info.yml")
-            .ok_or_else(|| anyhow::anyhow!("Missing info.yml"))?;
-        let info_str = String::from_utf8(info_bytes.clone())?;
-        let info: ResPackInfo = serde_yaml::from_str(&info_str)?;
-
-        // Helper to load texture from bytes
-        async fn load_tex(
-            ctx: &crate::renderer::GlContext,
-            files: &HashMap<String, Vec<u8>>,
-            name: &str,
-        ) -> Result<Texture, anyhow::Error> {
-            let bytes = files
-                .get(name)
-                .ok_or_else(|| anyhow::anyhow!("Missing {}", name))?;
-            Ok(Texture::load_from_bytes(ctx, bytes)
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to load texture {}: {:?}", name, e))?)
-        }
-
-        // Helper to load audio from bytes
-        fn load_audio(files: &HashMap<String, Vec<u8>>, name: &str) -> Option<AudioClip> {
-            let exts = ["mp3", "ogg", "wav"];
-            for ext in exts {
-                let filename = format!("{}.{}", name, ext);
-                if let Some(bytes) = files.get(&filename) {
-                    if let Ok(clip) = AudioClip::load_from_bytes(bytes, ext) {
-                        return Some(clip);
-                    }
-                }
-            }
-            None
-        }
+        let info = parse_info(&files)?.ok_or_else(|| anyhow::anyhow!("Missing info.yml"))?;
 
         let note_style = NoteStyle::new(
             load_tex(ctx, &files, "click.png").await?,
@@ -202,7 +179,8 @@ impl ResourcePack {
             info.hold_atlas_mh,
         );
 
-        // TODO: Handle hold_repeat body generation if needed
+        // hold_repeat bodies are tiled from the atlas at draw time, see
+        // draw_hold_note. Prepared bundles come with the bodies cut out.
 
         let hit_fx = load_tex(ctx, &files, "hit_fx.png")
             .await
@@ -212,24 +190,83 @@ impl ResourcePack {
             });
 
         let font = if files.contains_key("font.png") {
-            let tex = load_tex(ctx, &files, "font.png").await?;
-            let mut font = crate::renderer::text::SpriteFont::new(tex, 60.0);
-            font.load_grid("0123456789.", 11, 1, 60.0, 60.0);
-            Some(font)
+            Some(load_font(load_tex(ctx, &files, "font.png").await?))
         } else {
             None
         };
 
         let mut hitsounds = HashMap::new();
         web_sys::console::log_1(&"Loading hitsounds".into());
-        if let Some(clip) = load_audio(&files, "click") {
-            hitsounds.insert(HitSound::Click, clip);
-        }
-        if let Some(clip) = load_audio(&files, "drag") {
-            hitsounds.insert(HitSound::Drag, clip);
+        for (kind, name) in HITSOUND_FILES {
+            if let Some(clip) = load_audio(&files, name) {
+                hitsounds.insert(kind, clip);
+            }
         }
-        if let Some(clip) = load_audio(&files, "flick") {
-            hitsounds.insert(HitSound::Flick, clip);
+
+        let hashes = files
+            .iter()
+            .map(|(name, bytes)| (name.clone(), content_hash(bytes)))
+            .collect();
+
+        Ok(Self {
+            info,
+            note_style,
+            note_style_mh,
+            hit_fx,
+            font,
+            hitsounds,
+            hashes,
+        })
+    }
+
+    /// Loads a pack prepared by the proxy, see [`respack`]. The atlas is
+    /// uploaded once without decoding, every texture is a sprite of it and
+    /// hold bodies come pre-cut.
+    pub fn load_bundle(ctx: &crate::renderer::GlContext, bytes: &[u8]) -> anyhow::Result<Self> {
+        let respack::Bundle {
+            info,
+            atlas,
+            sprites,
+            sounds,
+        } = respack::decode(bytes)?;
+        let atlas_tex = Texture::from_rgba(ctx, atlas.width, atlas.height, &atlas.rgba)
+            .map_err(|e| anyhow::anyhow!("Failed to upload atlas: {:?}", e))?;
+        let tex = |name: &str| {
+            sprites
+                .get(name)
+                .map(|sprite| atlas_tex.sprite(sprite.width, sprite.height, sprite.uv(&atlas)))
+        };
+        let required = |name: &str| tex(name).ok_or_else(|| anyhow::anyhow!("Missing {}", name));
+
+        let mut note_style = NoteStyle::new(
+            required("click")?,
+            required("hold")?,
+            required("flick")?,
+            required("drag")?,
+            info.hold_atlas,
+        );
+        note_style.hold_body = tex("hold_body");
+        let mut note_style_mh = NoteStyle::new(
+            required("click_mh")?,
+            required("hold_mh")?,
+            required("flick_mh")?,
+            required("drag_mh")?,
+            info.hold_atlas_mh,
+        );
+        note_style_mh.hold_body = tex("hold_mh_body");
+
+        let hit_fx = match tex("hit_fx") {
+            Some(hit_fx) => hit_fx,
+            None => Texture::create_solid_color(ctx, 64, 64, [255, 255, 255, 255])
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?,
+        };
+        let font = tex("font").map(load_font);
+
+        let mut hitsounds = HashMap::new();
+        for (kind, name) in HITSOUND_FILES {
+            if let Some(clip) = load_audio(&sounds, name) {
+                hitsounds.insert(kind, clip);
+            }
         }
 
         Ok(Self {
@@ -239,13 +276,117 @@ impl ResourcePack {
             hit_fx,
             font,
             hitsounds,
+            // Bundles carry no source files, a later update replaces everything
+            hashes: HashMap::new(),
         })
     }
+
+    /// Applies a (possibly partial) set of pack files, re-uploading only the
+    /// entries whose content hash differs from the loaded one. Textures of
+    /// untouched entries are kept as-is. Returns the names of updated files.
+    ///
+    /// Everything is loaded before anything is replaced, so a failed update
+    /// leaves the pack as it was.
+    pub async fn update(
+        &mut self,
+        ctx: &crate::renderer::GlContext,
+        mut files: HashMap<String, Vec<u8>>,
+    ) -> anyhow::Result<Vec<String>> {
+        files.retain(|name, bytes| self.hashes.get(name) != Some(&content_hash(bytes)));
+        if files.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let info = parse_info(&files)?;
+        let mut textures = Vec::new();
+        for name in files.keys() {
+            if !TEXTURE_FILES.contains(&name.as_str()) {
+                continue;
+            }
+            match load_tex(ctx, &files, name).await {
+                Ok(tex) => textures.push((name.as_str(), tex)),
+                Err(e) => {
+                    for (_, tex) in textures {
+                        ctx.gl.delete_texture(Some(&tex.texture));
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        // Bodies cut by the proxy no longer match a new hold texture or atlas
+        if files.contains_key("info.yml") || files.contains_key("hold.png") {
+            self.note_style.hold_body = None;
+        }
+        if files.contains_key("info.yml") || files.contains_key("hold_mh.png") {
+            self.note_style_mh.hold_body = None;
+        }
+
+        if let Some(info) = info {
+            self.note_style.hold_atlas = info.hold_atlas;
+            self.note_style_mh.hold_atlas = info.hold_atlas_mh;
+            self.info = info;
+        }
+
+        for (name, tex) in textures {
+            if name == "font.png" {
+                if let Some(old) = self.font.replace(load_font(tex)) {
+                    delete_unshared(ctx, &old.texture);
+                }
+                continue;
+            }
+            let slot = match name {
+                "click.png" => &mut self.note_style.click,
+                "hold.png" => &mut self.note_style.hold,
+                "flick.png" => &mut self.note_style.flick,
+                "drag.png" => &mut self.note_style.drag,
+                "click_mh.png" => &mut self.note_style_mh.click,
+                "hold_mh.png" => &mut self.note_style_mh.hold,
+                "flick_mh.png" => &mut self.note_style_mh.flick,
+                "drag_mh.png" => &mut self.note_style_mh.drag,
+                "hit_fx.png" => &mut self.hit_fx,
+                _ => unreachable!("{name} is not in TEXTURE_FILES"),
+            };
+            let old = std::mem::replace(slot, tex);
+            delete_unshared(ctx, &old);
+        }
+
+        for (kind, name) in HITSOUND_FILES {
+            if let Some(clip) = load_audio(&files, name) {
+                self.hitsounds.insert(kind, clip);
+            }
+        }
+
+        let mut updated = Vec::with_capacity(files.len());
+        for (name, bytes) in files {
+            self.hashes.insert(name.clone(), content_hash(&bytes));
+            updated.push(name);
+        }
+        Ok(updated)
+    }
+}
+
+/// Per-judgement override of the hit effect style.
+#[derive(Clone, Copy)]
+pub struct HitFxStyle {
+    /// Multiplier applied on top of the pack's `hit_fx_scale`
+    pub scale: f32,
+    /// Effect lifetime in seconds, `None` keeps the pack's `hit_fx_duration`
+    pub duration: Option<f32>,
+}
+
+impl Default for HitFxStyle {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            duration: None,
+        }
+    }
 }
 
 pub struct Resource {
     pub model_stack: Vec<Matrix>,
-    pub time: f32,
+    pub time: Time,
     pub dt: f32,
     pub width: u32,
     pub height: u32,
@@ -253,14 +394,27 @@ pub struct Resource {
     pub aspect_ratio: f32,
     pub note_width: f32,
     pub note_scale: f32,
+    /// Draw simultaneous notes with the pack's `_mh` textures
+    pub multiple_hint: bool,
     pub line_textures: HashMap<usize, Texture>,
     pub line_gif_textures: HashMap<usize, Vec<Texture>>,
+    pub paint_targets: HashMap<usize, RenderTarget>,
+    /// One per `chart.extra.videos` entry
+    pub videos: Vec<VideoLayer>,
     pub emitter: Option<ParticleEmitter>,
+    /// Particle cap of each hit effect emitter. Lower it when many players
+    /// share a page to save GPU memory.
+    pub max_particles: usize,
+    pub hit_fx_styles: [HitFxStyle; 4],
     pub font: Option<crate::renderer::text::SpriteFont>,
+    /// Notes drawn this frame, collected only while the debug overlay is on
+    pub debug_rects: Option<Vec<crate::engine::DebugRect>>,
 }
 
 pub struct ParticleEmitter {
     pub scale: f32,
+    pub note_scale: f32,
+    pub duration: f32,
     pub emitter: crate::renderer::particle::Emitter,
     pub emitter_square: crate::renderer::particle::Emitter,
     pub hide_particles: bool,
@@ -272,6 +426,7 @@ impl ParticleEmitter {
         res_pack: &ResourcePack,
         scale: f32,
         hide_particles: bool,
+        max_particles: usize,
     ) -> Result<Self, String> {
         use crate::renderer::particle::{AtlasConfig, ColorCurve, Emitter, EmitterConfig};
         use monitor_common::core::colors;
@@ -287,6 +442,8 @@ impl ParticleEmitter {
 
         let mut res = Self {
             scale: res_pack.info.hit_fx_scale,
+            note_scale: scale,
+            duration: res_pack.info.hit_fx_duration,
             emitter: Emitter::new(
                 ctx,
                 EmitterConfig {
@@ -307,6 +464,7 @@ impl ParticleEmitter {
                     emitting: false,
                     colors_curve,
                     blend_mode: crate::renderer::particle::BlendMode::Alpha, // Changed to Alpha for debugging
+                    max_particles,
                     ..Default::default()
                 },
             )?,
@@ -324,6 +482,7 @@ impl ParticleEmitter {
                     linear_accel: -6. / 1.,
                     colors_curve,
                     blend_mode: crate::renderer::particle::BlendMode::Alpha,
+                    max_particles,
                     ..Default::default()
                 },
             )?,
@@ -333,7 +492,14 @@ impl ParticleEmitter {
         Ok(res)
     }
 
-    pub fn emit_at(&mut self, pt: Vector, rotation: f32, color: monitor_common::core::Color) {
+    pub fn emit_at(
+        &mut self,
+        pt: Vector,
+        rotation: f32,
+        color: monitor_common::core::Color,
+        style: HitFxStyle,
+    ) {
+        self.apply_style(style);
         self.emitter.config.initial_rotation = rotation;
         self.emitter.config.base_color = color;
         self.emitter.emit(pt, 1);
@@ -343,6 +509,20 @@ impl ParticleEmitter {
         }
     }
 
+    pub fn alive(&self) -> usize {
+        self.emitter.alive() + self.emitter_square.alive()
+    }
+
+    pub fn clear(&mut self) {
+        self.emitter.clear();
+        self.emitter_square.clear();
+    }
+
+    pub fn delete(self, ctx: &crate::renderer::GlContext) {
+        self.emitter.delete(ctx);
+        self.emitter_square.delete(ctx);
+    }
+
     pub fn draw(&mut self, renderer: &mut crate::renderer::Renderer, dt: f32) {
         self.emitter.draw(
             &renderer.context,
@@ -364,11 +544,21 @@ impl ParticleEmitter {
     }
 
     pub fn set_scale(&mut self, scale: f32) {
+        self.note_scale = scale;
+        self.emitter_square.config.initial_velocity = 2.5 * scale;
+        self.apply_style(HitFxStyle::default());
+    }
+
+    fn apply_style(&mut self, style: HitFxStyle) {
         let base_width = monitor_common::core::NOTE_WIDTH_RATIO_BASE * 2.0;
-        self.emitter.config.size = self.scale * scale * base_width;
+        let size = self.scale * self.note_scale * base_width * style.scale;
+        self.emitter.config.size = size;
         // Keep square size calculation from phira
-        self.emitter_square.config.size = self.scale * scale * base_width / 8.8;
-        self.emitter_square.config.initial_velocity = 2.5 * scale;
+        self.emitter_square.config.size = size / 8.8;
+
+        let duration = style.duration.unwrap_or(self.duration);
+        self.emitter.config.lifetime = duration;
+        self.emitter_square.config.lifetime = duration;
     }
 }
 
@@ -384,10 +574,16 @@ impl Resource {
             aspect_ratio: width as f32 / height as f32,
             note_width: monitor_common::core::NOTE_WIDTH_RATIO_BASE,
             note_scale: 1.0,
+            multiple_hint: true,
             line_textures: HashMap::new(),
             line_gif_textures: HashMap::new(),
+            paint_targets: HashMap::new(),
+            videos: Vec::new(),
             emitter: None,
+            max_particles: crate::renderer::particle::DEFAULT_MAX_PARTICLES,
+            hit_fx_styles: [HitFxStyle::default(); 4],
             font: None,
+            debug_rects: None,
         }
     }
 
@@ -437,6 +633,7 @@ impl Resource {
             hit_fx: crate::renderer::Texture::create_solid_color(ctx, 1, 1, [255, 255, 255, 255])?,
             font: None,
             hitsounds: HashMap::new(),
+            hashes: HashMap::new(),
         };
 
         self.set_pack(ctx, res_pack)?;
@@ -444,17 +641,62 @@ impl Resource {
         Ok(())
     }
 
+    /// Switches to `pack`. The current pack stays if its emitters cannot
+    /// be built.
     pub fn set_pack(
         &mut self,
         ctx: &crate::renderer::GlContext,
         pack: ResourcePack,
     ) -> Result<(), String> {
-        self.emitter = Some(ParticleEmitter::new(ctx, &pack, self.note_scale, false)?);
-        self.font = pack.font.clone();
+        self.apply_pack(ctx, &pack)?;
         self.res_pack = Some(pack);
         Ok(())
     }
 
+    /// Rebuilds the emitters and font of the current pack, after its
+    /// textures or `max_particles` changed. The old emitters stay if the
+    /// new ones cannot be built.
+    pub fn refresh_pack(&mut self, ctx: &crate::renderer::GlContext) -> Result<(), String> {
+        let Some(pack) = self.res_pack.take() else {
+            return Ok(());
+        };
+        let result = self.apply_pack(ctx, &pack);
+        self.res_pack = Some(pack);
+        result
+    }
+
+    fn apply_pack(
+        &mut self,
+        ctx: &crate::renderer::GlContext,
+        pack: &ResourcePack,
+    ) -> Result<(), String> {
+        let emitter = ParticleEmitter::new(ctx, pack, self.note_scale, false, self.max_particles)?;
+        if let Some(old) = self.emitter.replace(emitter) {
+            old.delete(ctx);
+        }
+        self.font = pack.font.clone();
+        Ok(())
+    }
+
+    /// Wipes the accumulated strokes of every paint line.
+    pub fn clear_paint(&self, ctx: &crate::renderer::GlContext) {
+        for target in self.paint_targets.values() {
+            target.clear(ctx);
+        }
+    }
+
+    pub fn release_paint(&mut self, ctx: &crate::renderer::GlContext) {
+        for (_, target) in self.paint_targets.drain() {
+            target.delete(ctx);
+        }
+    }
+
+    pub fn release_videos(&mut self, ctx: &crate::renderer::GlContext) {
+        for video in self.videos.drain(..) {
+            video.delete(ctx);
+        }
+    }
+
     pub fn set_scale(&mut self, scale: f32) {
         self.note_scale = scale;
         if let Some(emitter) = &mut self.emitter {
@@ -512,12 +754,26 @@ impl Resource {
         self.pop_model();
     }
 
-    pub fn emit_at_origin(&mut self, rotation: f32, color: monitor_common::core::Color) {
+    /// `rotation` is the note's rotation in degrees, only used when the pack
+    /// sets `hit_fx_rotate`.
+    pub fn emit_at_origin(
+        &mut self,
+        rotation: f32,
+        color: monitor_common::core::Color,
+        judgement: Judgement,
+    ) {
         let model = self.current_model();
+        let style = self.hit_fx_styles[judgement as usize];
+        // Lines rotate counter-clockwise, particles clockwise
+        let rotation = if self.res_pack.as_ref().is_some_and(|p| p.info.hit_fx_rotate) {
+            -rotation.to_radians()
+        } else {
+            0.0
+        };
         if let Some(emitter) = &mut self.emitter {
             let pt = model.transform_point(&Point::origin());
             let vec = Vector::new(pt.x, pt.y);
-            emitter.emit_at(vec, rotation, color);
+            emitter.emit_at(vec, rotation, color, style);
         }
     }
 }
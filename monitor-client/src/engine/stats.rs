@@ -0,0 +1,119 @@
+use crate::renderer::{IDENTITY, Label, Renderer};
+use wasm_bindgen::prelude::*;
+
+/// Milliseconds between text refreshes; re-rasterizing every frame would
+/// both flicker and skew the numbers being shown
+const REFRESH_INTERVAL: f64 = 250.0;
+/// Weight of the newest sample in the moving averages
+const SMOOTHING: f64 = 0.1;
+
+/// What happened in the current frame besides GPU work
+pub struct FrameInfo {
+    /// `performance.now()` when the frame started
+    pub started: f64,
+    pub particles: usize,
+    pub events: usize,
+}
+
+/// On-canvas frame time / draw call overlay for diagnosing slow machines.
+pub struct StatsOverlay {
+    pub enabled: bool,
+    /// Canvas height the font was sized for
+    height: u32,
+    label: Label,
+    last_frame: Option<f64>,
+    last_refresh: f64,
+    /// Moving averages in milliseconds
+    frame_interval: f64,
+    cpu_time: f64,
+}
+
+impl StatsOverlay {
+    pub fn new(renderer: &Renderer) -> Result<Self, JsValue> {
+        Ok(Self {
+            enabled: false,
+            height: 0,
+            label: Label::new(&renderer.context)?,
+            last_frame: None,
+            last_refresh: 0.0,
+            frame_interval: 0.0,
+            cpu_time: 0.0,
+        })
+    }
+
+    fn average(avg: f64, sample: f64) -> f64 {
+        if avg == 0.0 {
+            sample
+        } else {
+            avg + (sample - avg) * SMOOTHING
+        }
+    }
+
+    /// Draws the overlay. Should run last so the CPU time covers the frame.
+    pub fn draw(&mut self, renderer: &mut Renderer, frame: FrameInfo) -> Result<(), JsValue> {
+        if !self.enabled {
+            self.last_frame = None;
+            return Ok(());
+        }
+        let (width, height) = (renderer.context.width, renderer.context.height);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let now = web_sys::window()
+            .and_then(|w| w.performance())
+            .map_or(frame.started, |p| p.now());
+        if let Some(last) = self.last_frame {
+            self.frame_interval = Self::average(self.frame_interval, frame.started - last);
+        }
+        self.last_frame = Some(frame.started);
+        self.cpu_time = Self::average(self.cpu_time, now - frame.started);
+
+        // Re-rasterizing binds textures behind the batcher's back
+        renderer.flush();
+        if self.height != height {
+            self.height = height;
+            self.label
+                .set_font(&format!("{}px monospace", (height as f32 * 0.022).round()));
+            self.last_refresh = 0.0;
+        }
+        if now - self.last_refresh >= REFRESH_INTERVAL {
+            self.last_refresh = now;
+            let fps = if self.frame_interval > 0.0 {
+                1000.0 / self.frame_interval
+            } else {
+                0.0
+            };
+            let stats = renderer.last_stats;
+            let text = format!(
+                "{:.0} fps | frame {:.2} ms | cpu {:.2} ms | {} draws | {} flushes | {} particles | {} events",
+                fps,
+                self.frame_interval,
+                self.cpu_time,
+                stats.draw_calls,
+                stats.flushes,
+                frame.particles,
+                frame.events,
+            );
+            self.label.set_text(&renderer.context, &text)?;
+        }
+        renderer.batcher.invalidate_texture_cache();
+        // Particle drawing leaves no program bound
+        renderer.begin_frame();
+
+        // World units per canvas pixel, see the projection in ChartPlayer::render
+        let px = 2.0 / width as f32;
+        let top = height as f32 * px / 2.0;
+        let margin = height as f32 * 0.01 * px;
+        let w = self.label.texture.width as f32 * px;
+        let h = self.label.texture.height as f32 * px;
+        let (x, y) = (-1.0 + margin, top - margin - h);
+        renderer.draw_rect(x, y, w, h, 0.0, 0.0, 0.0, 0.5, &IDENTITY);
+        renderer.set_texture(&self.label.texture);
+        renderer.draw_texture_rect(
+            x, y, w, h, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, &IDENTITY,
+        );
+        renderer.flush();
+        Ok(())
+    }
+}
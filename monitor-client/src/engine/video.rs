@@ -0,0 +1,228 @@
+use crate::renderer::{GlContext, IDENTITY, Renderer, Texture};
+use monitor_common::core::{Time, Video, VideoScale, time_f64};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use web_sys::{HtmlMediaElement, HtmlVideoElement, WebGl2RenderingContext};
+
+/// Drift (seconds) tolerated while playing before the video is re-seeked
+const PLAYING_DRIFT: f64 = 0.2;
+/// Drift tolerated while paused, small enough to show the exact frame
+const PAUSED_DRIFT: f64 = 0.02;
+
+/// A chart background video decoded by the browser and sampled into a
+/// texture every frame.
+pub struct VideoLayer {
+    element: HtmlVideoElement,
+    url: String,
+    texture: Texture,
+    /// Whether a frame has been uploaded to `texture` yet
+    has_frame: bool,
+}
+
+fn mime_type(ext: &str) -> &'static str {
+    match ext {
+        "webm" => "video/webm",
+        "ogv" | "ogg" => "video/ogg",
+        "mov" => "video/quicktime",
+        _ => "video/mp4",
+    }
+}
+
+impl VideoLayer {
+    /// Creates the layer, taking the encoded bytes out of `video`.
+    pub fn new(ctx: &GlContext, video: &mut Video) -> Result<Self, JsValue> {
+        let data = std::mem::take(&mut video.data);
+        let array = js_sys::Uint8Array::from(&data[..]);
+        let blob_parts = js_sys::Array::new();
+        blob_parts.push(&array);
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type(mime_type(&video.ext));
+        let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&blob_parts, &options)?;
+        let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+        let document = web_sys::window()
+            .and_then(|w| w.document())
+            .ok_or("no document")?;
+        let element = document
+            .create_element("video")?
+            .dyn_into::<HtmlVideoElement>()?;
+        // Audio comes from the chart music, the video only provides frames
+        element.set_muted(true);
+        element.set_attribute("playsinline", "")?;
+        element.set_preload("auto");
+        element.set_src(&url);
+
+        Ok(Self {
+            element,
+            url,
+            texture: Texture::new(ctx)?,
+            has_frame: false,
+        })
+    }
+
+    fn media(&self) -> &HtmlMediaElement {
+        &self.element
+    }
+
+    /// Whether the video covers chart-relative time `t`
+    fn covers(&self, t: f64) -> bool {
+        let duration = self.media().duration();
+        t >= 0.0 && (duration.is_nan() || t < duration)
+    }
+
+    /// Keeps playback state, speed and position in line with the chart.
+    fn sync(&self, t: f64, playing: bool, rate: f64) {
+        let media = self.media();
+        if media.playback_rate() != rate {
+            media.set_playback_rate(rate);
+        }
+        if playing {
+            if media.paused() {
+                // Autoplay of muted videos is allowed, a rejection only
+                // means we keep showing the last frame
+                let _ = media.play();
+            }
+        } else if !media.paused() {
+            let _ = media.pause();
+        }
+        let drift = if playing { PLAYING_DRIFT } else { PAUSED_DRIFT };
+        if !media.seeking() && (media.current_time() - t).abs() > drift {
+            media.set_current_time(t);
+        }
+    }
+
+    /// Uploads the current frame, returning whether there is one to draw.
+    /// Binds TEXTURE_2D, so callers must flush batched draws first.
+    fn upload(&mut self, ctx: &GlContext) -> bool {
+        // HAVE_CURRENT_DATA
+        if self.media().ready_state() < 2 {
+            return self.has_frame;
+        }
+        let gl = &ctx.gl;
+        gl.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&self.texture.texture),
+        );
+        let uploaded = gl
+            .tex_image_2d_with_u32_and_u32_and_html_video_element(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RGBA as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                &self.element,
+            )
+            .is_ok();
+        if uploaded && !self.has_frame {
+            for (param, value) in [
+                (
+                    WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                    WebGl2RenderingContext::LINEAR,
+                ),
+                (
+                    WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+                    WebGl2RenderingContext::LINEAR,
+                ),
+                (
+                    WebGl2RenderingContext::TEXTURE_WRAP_S,
+                    WebGl2RenderingContext::CLAMP_TO_EDGE,
+                ),
+                (
+                    WebGl2RenderingContext::TEXTURE_WRAP_T,
+                    WebGl2RenderingContext::CLAMP_TO_EDGE,
+                ),
+            ] {
+                gl.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, param, value as i32);
+            }
+        }
+        if uploaded {
+            self.texture.width = self.element.video_width();
+            self.texture.height = self.element.video_height();
+            self.has_frame = true;
+        }
+        self.has_frame
+    }
+
+    /// Syncs the video to chart `time` and draws it behind the chart.
+    /// `rate` is the chart playback rate.
+    pub fn draw(
+        &mut self,
+        video: &Video,
+        time: Time,
+        playing: bool,
+        rate: f64,
+        aspect_ratio: f32,
+        renderer: &mut Renderer,
+    ) {
+        let t = time_f64(time - video.start_time);
+        if !self.covers(t) {
+            if !self.media().paused() {
+                let _ = self.media().pause();
+            }
+            return;
+        }
+        self.sync(t, playing, rate);
+
+        renderer.flush();
+        let has_frame = self.upload(&renderer.context);
+        renderer.batcher.invalidate_texture_cache();
+        if !has_frame || self.texture.width == 0 || self.texture.height == 0 {
+            return;
+        }
+
+        // Screen in world units, see the projection in ChartPlayer::render
+        let (screen_w, screen_h) = (2.0, 2.0 / aspect_ratio);
+        let (video_w, video_h) = (self.texture.width as f32, self.texture.height as f32);
+        let (w, h) = match video.scale {
+            VideoScale::CropCenter => {
+                let s = (screen_w / video_w).max(screen_h / video_h);
+                (video_w * s, video_h * s)
+            }
+            VideoScale::Inside => {
+                let s = (screen_w / video_w).min(screen_h / video_h);
+                (video_w * s, video_h * s)
+            }
+            VideoScale::Fit => (screen_w, screen_h),
+        };
+        let alpha = video.alpha.now_opt().unwrap_or(1.0);
+        let dim = video.dim.now_opt().unwrap_or(0.0);
+        renderer.set_texture(&self.texture);
+        renderer.draw_texture_rect(
+            -w / 2.0,
+            -h / 2.0,
+            w,
+            h,
+            0.0,
+            0.0,
+            1.0,
+            1.0,
+            1.0,
+            1.0,
+            1.0,
+            alpha,
+            &IDENTITY,
+        );
+        if dim > 0.0 {
+            renderer.draw_rect(
+                -w / 2.0,
+                -h / 2.0,
+                w,
+                h,
+                0.0,
+                0.0,
+                0.0,
+                dim * alpha,
+                &IDENTITY,
+            );
+        }
+    }
+
+    pub fn delete(self, ctx: &GlContext) {
+        let media = self.media();
+        let _ = media.pause();
+        let _ = media.remove_attribute("src");
+        media.load();
+        let _ = web_sys::Url::revoke_object_url(&self.url);
+        ctx.gl.delete_texture(Some(&self.texture.texture));
+    }
+}
@@ -0,0 +1,108 @@
+use monitor_common::core::{Anim, JudgeLine, Time, TweenFn, TweenId, Tweenable};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Line properties with keyframes, as accepted by `line_keyframes`
+pub const PROPERTIES: [&str; 13] = [
+    "x",
+    "y",
+    "rotation",
+    "alpha",
+    "scaleX",
+    "scaleY",
+    "height",
+    "incline",
+    "color",
+    "ctrlAlpha",
+    "ctrlSize",
+    "ctrlPos",
+    "ctrlY",
+];
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum TweenInfo {
+    Id {
+        id: TweenId,
+    },
+    Bezier {
+        p1: (f32, f32),
+        p2: (f32, f32),
+    },
+    /// Only the `start..end` part of easing `id` is used
+    Clamped {
+        id: TweenId,
+        start: f32,
+        end: f32,
+    },
+}
+
+#[derive(Serialize)]
+struct KeyframeInfo<'a, T> {
+    time: Time,
+    value: &'a T,
+    /// Easing towards the next keyframe
+    tween: TweenInfo,
+}
+
+/// Keyframes of each layer of `anim`, the values of all layers add up
+fn layers<T: Tweenable + Serialize>(anim: &Anim<T>) -> Vec<Vec<KeyframeInfo<'_, T>>> {
+    anim.tracks()
+        .map(|track| {
+            track
+                .keyframes
+                .iter()
+                .map(|k| KeyframeInfo {
+                    time: k.time,
+                    value: &k.value,
+                    tween: match &k.tween {
+                        TweenFn::TweenId(id) => TweenInfo::Id { id: *id },
+                        TweenFn::Bezier(bezier) => TweenInfo::Bezier {
+                            p1: bezier.p1,
+                            p2: bezier.p2,
+                        },
+                        TweenFn::Clamped(clamped) => TweenInfo::Clamped {
+                            id: clamped.0,
+                            start: clamped.1.start,
+                            end: clamped.1.end,
+                        },
+                    },
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn to_js<T: Serialize + ?Sized>(value: &T) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(value)
+        .map_err(|e| JsValue::from_str(&format!("Failed to serialize keyframes: {}", e)))
+}
+
+/// Keyframes of `property` (one of `PROPERTIES`) of `line` as an array of
+/// layers, each an array of `{ time, value, tween }`.
+pub fn line_keyframes(line: &JudgeLine, property: &str) -> Result<JsValue, JsValue> {
+    let ctrl = &line.ctrl_obj;
+    let anim = match property {
+        "x" => &line.object.translation.x,
+        "y" => &line.object.translation.y,
+        "rotation" => &line.object.rotation,
+        "alpha" => &line.object.alpha,
+        "scaleX" => &line.object.scale.x,
+        "scaleY" => &line.object.scale.y,
+        "height" => &line.height,
+        "incline" => &line.incline,
+        "color" => return to_js(&layers(&line.color)),
+        "ctrlAlpha" => &ctrl.alpha,
+        "ctrlSize" => &ctrl.size,
+        "ctrlPos" => &ctrl.pos,
+        "ctrlY" => &ctrl.y,
+        _ => {
+            return Err(JsValue::from_str(&format!(
+                "Unknown property {}, expected one of {}",
+                property,
+                PROPERTIES.join(", ")
+            )));
+        }
+    };
+    to_js(&layers(anim))
+}
@@ -1,12 +1,24 @@
-use crate::engine::{ChartRenderer, JudgeEventKind, Resource, ResourcePack};
-use crate::renderer::Texture;
-use monitor_common::core::{Chart, ChartInfo, HitSound, JudgeLineKind, JudgeStatus, NoteKind};
+use crate::engine::{
+    BeatGrid, ChartRenderer, DebugOverlay, FrameInfo, HitFxStyle, Hud, JudgeEventKind, JudgePopups,
+    NoteFilter, PlayerOverlay, Resource, ResourcePack, StatsOverlay, VideoLayer, is_bar,
+    note_hitsound,
+};
+use crate::renderer::{RenderSettings, Texture};
+use monitor_common::core::{
+    Chart, ChartInfo, JudgeLineKind, JudgeStatus, Judgement, NoteKind, Time, time_f64,
+    time_from_f32,
+};
+use monitor_common::parse::archive::parse_chart_zip;
+use monitor_common::payload;
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
 mod audio;
+mod diagnostics;
 mod engine;
+mod inspect;
 mod network;
+mod recorder;
 mod renderer;
 
 // For logging to JS console
@@ -26,10 +38,81 @@ pub struct ChartPlayer {
     renderer: renderer::Renderer,
     chart_renderer: ChartRenderer,
     resource: Resource,
+    hud: Hud,
+    popups: JudgePopups,
+    stats: StatsOverlay,
+    debug: DebugOverlay,
+    player: PlayerOverlay,
+    beat_grid: BeatGrid,
+    /// Whether a click plays on every beat of the chart
+    beat_metronome: bool,
     audio_engine: audio::AudioEngine,
     paused: bool,
-    current_time: f32,
+    current_time: Time,
     last_update_time: Option<f64>,
+    /// Chart time span `(from, until]` whose autoplay hitsounds have been
+    /// scheduled on the audio clock
+    hitsound_window: Option<(Time, Time)>,
+    /// Chart length in seconds, see `get_duration`
+    duration: Time,
+    /// Called with `(time, duration)` after every rendered frame
+    on_progress: Option<js_sys::Function>,
+    /// Chart time span `[start, end)` played over and over, see `set_loop`
+    loop_range: Option<(Time, Time)>,
+    /// Called with the score summary once every note has been judged
+    on_finish: Option<js_sys::Function>,
+    /// Whether `on_finish` already ran for the current run through the chart
+    finished: bool,
+    recorder: Option<recorder::Recorder>,
+    /// Whether the canvas can be seen, see `set_visible`
+    visible: bool,
+    suspend_audio_when_hidden: bool,
+    /// Frame rate cap, frames in between only advance the simulation
+    max_fps: Option<f64>,
+    /// `performance.now()` of the last drawn frame
+    last_frame: Option<f64>,
+    /// Chart time and `performance.now()` it was taken at, for keeping time
+    /// while the audio is suspended in the background
+    silent_clock: Option<(Time, f64)>,
+    /// Key sent with chart and audio requests, see `set_api_key`
+    api_key: Option<String>,
+}
+
+fn performance_now() -> f64 {
+    web_sys::window()
+        .and_then(|w| w.performance())
+        .map_or(0.0, |p| p.now())
+}
+
+/// Header the proxy reads API keys from when started with `--api-keys`
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Fetches `url` from the page's origin, sending `api_key` if there is one
+async fn fetch_array_buffer(
+    url: &str,
+    api_key: Option<&str>,
+) -> Result<js_sys::ArrayBuffer, JsValue> {
+    let window = web_sys::window().ok_or("no window")?;
+    let init = web_sys::RequestInit::new();
+    if let Some(key) = api_key {
+        let headers = web_sys::Headers::new()?;
+        headers.set(API_KEY_HEADER, key)?;
+        init.set_headers(&headers);
+    }
+    let resp_value =
+        wasm_bindgen_futures::JsFuture::from(window.fetch_with_str_and_init(url, &init)).await?;
+    let resp: web_sys::Response = resp_value.dyn_into()?;
+
+    if !resp.ok() {
+        return Err(JsValue::from_str(&format!(
+            "Fetch failed: {}",
+            resp.status_text()
+        )));
+    }
+
+    wasm_bindgen_futures::JsFuture::from(resp.array_buffer()?)
+        .await?
+        .dyn_into()
 }
 
 #[wasm_bindgen]
@@ -43,15 +126,76 @@ impl ChartPlayer {
         Ok(())
     }
 
+    /// Drops hitsounds scheduled ahead, for when the timeline changes.
+    fn reset_hitsound_schedule(&mut self) {
+        self.audio_engine.cancel_scheduled_hitsounds();
+        self.hitsound_window = None;
+    }
+
+    /// Schedules autoplay hitsounds and metronome beats up to
+    /// `HITSOUND_LOOKAHEAD` ahead, so they land on the music instead of on
+    /// the next frame.
+    fn schedule_hitsounds(&mut self) {
+        let autoplay = self.chart_renderer.autoplay;
+        if self.paused || !(autoplay || self.beat_metronome) || self.silent_clock.is_some() {
+            return;
+        }
+        let (from, until) = *self
+            .hitsound_window
+            .get_or_insert((self.current_time, self.current_time));
+        let mut horizon = self.current_time
+            + time_from_f32(audio::HITSOUND_LOOKAHEAD * self.audio_engine.playback_rate());
+        if let Some((_, end)) = self.loop_range {
+            // Notes past the loop end are never reached
+            horizon = horizon.min(end);
+        }
+        if horizon <= until {
+            return;
+        }
+        if autoplay {
+            for (kind, time) in self.chart_renderer.hitsounds_between(until, horizon) {
+                let _ = self.audio_engine.schedule_hitsound(&kind, time);
+            }
+        }
+        if self.beat_metronome {
+            let bpm = &mut self.chart_renderer.chart.bpm_list;
+            for (beat, time) in bpm.beats_between(until, horizon) {
+                let _ = self.audio_engine.schedule_beat(time, is_bar(beat));
+            }
+        }
+        self.hitsound_window = Some((from, horizon));
+    }
+
+    fn parse_render_settings(settings: JsValue) -> Result<RenderSettings, JsValue> {
+        if settings.is_undefined() || settings.is_null() {
+            return Ok(RenderSettings::default());
+        }
+        serde_wasm_bindgen::from_value(settings).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     #[wasm_bindgen(constructor)]
     pub fn new(canvas_id: String) -> Result<ChartPlayer, JsValue> {
+        Self::with_settings(canvas_id, JsValue::UNDEFINED)
+    }
+
+    /// Creates a player with render settings, e.g.
+    /// `{ antialias: false, msaaSamples: 4, fxaa: true, renderScale: 1.5 }`. Missing fields
+    /// keep their defaults.
+    pub fn with_settings(canvas_id: String, settings: JsValue) -> Result<ChartPlayer, JsValue> {
         console_error_panic_hook::set_once();
         console_log!("ChartPlayer Initialized on Canvas '{}'", canvas_id);
 
-        let renderer = renderer::Renderer::new(&canvas_id)?;
+        let settings = Self::parse_render_settings(settings)?;
+        let renderer = renderer::Renderer::new(&canvas_id, settings)?;
         let mut resource = Resource::new(renderer.context.width, renderer.context.height);
         resource.load_defaults(&renderer.context)?;
 
+        let hud = Hud::new(&renderer)?;
+        let popups = JudgePopups::new(&renderer)?;
+        let stats = StatsOverlay::new(&renderer)?;
+        let debug = DebugOverlay::new(&renderer)?;
+        let player = PlayerOverlay::new(&renderer)?;
+
         let info = ChartInfo::default();
         let chart = Chart::default();
 
@@ -59,30 +203,137 @@ impl ChartPlayer {
             renderer,
             chart_renderer: ChartRenderer::new(info, chart),
             resource,
+            hud,
+            popups,
+            stats,
+            debug,
+            player,
+            beat_grid: BeatGrid::default(),
+            beat_metronome: false,
             audio_engine: audio::AudioEngine::new()?,
             paused: true,
             current_time: 0.0,
             last_update_time: None,
+            hitsound_window: None,
+            duration: 0.0,
+            on_progress: None,
+            loop_range: None,
+            on_finish: None,
+            finished: false,
+            recorder: None,
+            visible: true,
+            suspend_audio_when_hidden: true,
+            max_fps: None,
+            last_frame: None,
+            silent_clock: None,
+            api_key: None,
         };
         player.sync_hitsounds()?;
         Ok(player)
     }
 
+    fn audio_suspended(&self) -> bool {
+        !self.visible && self.suspend_audio_when_hidden
+    }
+
+    /// Starts the music at the current time, or only the silent clock while
+    /// the audio is suspended.
+    fn start_audio(&mut self) -> Result<(), JsValue> {
+        if self.audio_suspended() {
+            self.silent_clock = Some((self.current_time, performance_now()));
+            Ok(())
+        } else {
+            self.silent_clock = None;
+            self.audio_engine.play(self.current_time)
+        }
+    }
+
+    /// Chart time while playing
+    fn playing_time(&self, now: f64) -> Time {
+        match self.silent_clock {
+            Some((time, at)) => {
+                time + ((now - at) / 1000.0 * self.audio_engine.playback_rate() as f64) as Time
+            }
+            None => self.audio_engine.get_time(),
+        }
+    }
+
+    fn set_background_state(&mut self, visible: bool, suspend: bool) -> Result<(), JsValue> {
+        let was_suspended = self.audio_suspended();
+        if !self.paused {
+            self.current_time = self.playing_time(performance_now());
+        }
+        self.visible = visible;
+        self.suspend_audio_when_hidden = suspend;
+        let suspended = self.audio_suspended();
+        if suspended == was_suspended {
+            return Ok(());
+        }
+        if !self.paused {
+            self.audio_engine.pause()?;
+            self.hitsound_window = None;
+            self.start_audio()?;
+        }
+        if suspended {
+            self.audio_engine.suspend()?;
+        }
+        Ok(())
+    }
+
     pub fn pause(&mut self) -> Result<(), JsValue> {
         self.paused = true;
         self.last_update_time = None;
+        self.hitsound_window = None;
+        self.silent_clock = None;
         self.audio_engine.pause()
     }
 
     pub fn resume(&mut self) -> Result<(), JsValue> {
         self.paused = false;
         self.last_update_time = None;
-        self.audio_engine.play(self.current_time)
+        self.reset_hitsound_schedule();
+        self.start_audio()
+    }
+
+    /// Tells the player whether its canvas can be seen, e.g. from a
+    /// `visibilitychange` listener or an `IntersectionObserver`. Hidden
+    /// players keep time and judge notes but skip all drawing, and suspend
+    /// their audio unless disabled with `set_suspend_audio_when_hidden`.
+    pub fn set_visible(&mut self, visible: bool) -> Result<(), JsValue> {
+        self.set_background_state(visible, self.suspend_audio_when_hidden)
     }
 
-    pub fn set_time(&mut self, time: f32) {
+    /// Whether hidden players suspend their audio (default true).
+    pub fn set_suspend_audio_when_hidden(&mut self, flag: bool) -> Result<(), JsValue> {
+        self.set_background_state(self.visible, flag)
+    }
+
+    /// Caps how often `render` draws, e.g. 30 for pages monitoring many
+    /// players. Calls in between only advance the simulation. `undefined`
+    /// removes the cap.
+    pub fn set_max_fps(&mut self, fps: Option<f64>) {
+        self.max_fps = fps.filter(|fps| *fps > 0.0);
+    }
+
+    /// API key sent with chart and audio requests, needed when the proxy
+    /// runs with `--api-keys`. `undefined` stops sending one.
+    pub fn set_api_key(&mut self, key: Option<String>) {
+        self.api_key = key.filter(|key| !key.is_empty());
+    }
+
+    /// Seeks to chart time `time`, restarting the music there if playing.
+    pub fn set_time(&mut self, time: f64) -> Result<(), JsValue> {
+        self.seek(time as Time)
+    }
+
+    fn seek(&mut self, time: Time) -> Result<(), JsValue> {
         self.current_time = time;
         self.last_update_time = None;
+        self.reset_hitsound_schedule();
+        if !self.paused {
+            self.audio_engine.pause()?;
+            self.start_audio()?;
+        }
 
         // Reset all judge states on seek
         for line in &mut self.chart_renderer.chart.lines {
@@ -91,105 +342,522 @@ impl ChartPlayer {
             }
         }
 
+        self.chart_renderer.score.reset();
+        self.finished = false;
+        self.popups.clear();
+
+        // Paint strokes and particles belong to the old timeline
+        self.resource.clear_paint(&self.renderer.context);
+        if let Some(emitter) = &mut self.resource.emitter {
+            emitter.clear();
+        }
+
         // Force update chart state immediately
         self.chart_renderer
             .update(&mut self.resource, self.current_time);
+        Ok(())
+    }
+
+    /// Plays chart time `start..end` over and over for practicing a section.
+    /// Judgements, score and particles reset every time playback jumps back.
+    pub fn set_loop(&mut self, start: f64, end: f64) -> Result<(), JsValue> {
+        if !start.is_finite() || !end.is_finite() || end <= start {
+            return Err(JsValue::from_str("Loop end must be after its start"));
+        }
+        let (start, end) = (start as Time, end as Time);
+        self.loop_range = Some((start, end));
+        if !(start..end).contains(&self.current_time) {
+            self.seek(start)?;
+        }
+        Ok(())
+    }
+
+    /// Stops looping, playback continues past the loop end.
+    pub fn clear_loop(&mut self) {
+        self.loop_range = None;
+    }
+
+    /// Current score, 0 to 1,000,000. Autoplay judges everything Perfect,
+    /// so it shows the theoretical maximum progress.
+    pub fn get_score(&self) -> u32 {
+        self.chart_renderer.score.score()
+    }
+
+    /// Accuracy over the notes judged so far, 0 to 1
+    pub fn get_accuracy(&self) -> f64 {
+        self.chart_renderer.score.real_time_accuracy()
+    }
+
+    pub fn get_combo(&self) -> u32 {
+        self.chart_renderer.score.combo
+    }
+
+    /// Score, accuracy, combo and judgement counts as one object, e.g. for
+    /// a results screen.
+    pub fn get_score_summary(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.chart_renderer.score.summary())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize score: {}", e)))
+    }
+
+    /// Sets a callback run with the score summary once all notes are
+    /// judged, or at the latest when playback passes the end of the chart.
+    /// Seeking back arms it again. Pass `undefined` to remove it.
+    pub fn set_on_finish(&mut self, callback: Option<js_sys::Function>) {
+        self.on_finish = callback;
+    }
+
+    /// Number of judge lines in the loaded chart
+    pub fn get_line_count(&self) -> usize {
+        self.chart_renderer.chart.lines.len()
+    }
+
+    /// Names of the line properties `get_line_keyframes` accepts
+    pub fn get_keyframe_properties(&self) -> Vec<String> {
+        inspect::PROPERTIES.iter().map(|p| p.to_string()).collect()
+    }
+
+    /// Keyframes of a line property for chart inspectors, as an array of
+    /// layers (their values add up) of `{ time, value, tween }`, where
+    /// `tween` is `{ kind: "id", id }`, `{ kind: "bezier", p1, p2 }` or
+    /// `{ kind: "clamped", id, start, end }`.
+    pub fn get_line_keyframes(&self, line: usize, property: &str) -> Result<JsValue, JsValue> {
+        let line = self
+            .chart_renderer
+            .chart
+            .lines
+            .get(line)
+            .ok_or_else(|| JsValue::from_str(&format!("No line {}", line)))?;
+        inspect::line_keyframes(line, property)
+    }
+
+    /// Current chart time in seconds
+    pub fn get_time(&self) -> f64 {
+        time_f64(self.current_time)
+    }
+
+    /// Chart length in seconds: until the music or the last note ends,
+    /// whichever is later.
+    pub fn get_duration(&self) -> f64 {
+        time_f64(self.duration)
+    }
+
+    /// Sets a callback run after every frame with the current time and the
+    /// duration, for driving a seek bar. Pass `undefined` to remove it.
+    pub fn set_on_progress(&mut self, callback: Option<js_sys::Function>) {
+        self.on_progress = callback;
     }
 
     pub fn set_autoplay(&mut self, flag: bool) {
         self.chart_renderer.autoplay = flag;
+        self.reset_hitsound_schedule();
+    }
+
+    /// Sets the playback speed (clamped to 0.5–2.0). Notes follow the music,
+    /// which changes pitch along with its speed.
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        let now = performance_now();
+        let time = self.playing_time(now);
+        self.audio_engine.set_playback_rate(rate);
+        self.hitsound_window = None;
+        if self.silent_clock.is_some() {
+            self.silent_clock = Some((time, now));
+        }
+    }
+
+    /// Sets the audio latency compensation in milliseconds. Positive values
+    /// delay audio, negative ones make music play ahead of the notes.
+    pub fn set_audio_offset_ms(&mut self, ms: f32) -> Result<(), JsValue> {
+        self.audio_engine.set_latency(ms as f64 / 1000.0);
+        if self.audio_engine.is_playing() {
+            self.audio_engine.pause()?;
+            self.hitsound_window = None;
+            self.audio_engine.play(self.current_time)?;
+        }
+        Ok(())
+    }
+
+    /// Starts a metronome for latency calibration. The user taps along with
+    /// `calibration_tap`; `stop_calibration` returns the suggested offset.
+    pub fn start_calibration(&mut self, bpm: f32) -> Result<(), JsValue> {
+        self.audio_engine.start_metronome(bpm)
+    }
+
+    /// Records a tap and returns the suggested offset in milliseconds so far.
+    pub fn calibration_tap(&mut self) -> Option<f32> {
+        self.audio_engine
+            .metronome_tap()
+            .map(|latency| (latency * 1000.0) as f32)
+    }
+
+    /// Stops the metronome and returns the suggested offset in milliseconds,
+    /// to be passed to `set_audio_offset_ms`.
+    pub fn stop_calibration(&mut self) -> Option<f32> {
+        self.audio_engine
+            .stop_metronome()
+            .map(|latency| (latency * 1000.0) as f32)
+    }
+
+    /// Sets the music fade on pause, resume and seek in milliseconds
+    /// (default 50, 0 disables it).
+    pub fn set_audio_fade_ms(&mut self, ms: f32) {
+        self.audio_engine.set_fade_time(ms as f64 / 1000.0);
+    }
+
+    /// Sets the overall volume (linear gain, 1.0 = unchanged).
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.audio_engine.set_master_volume(volume);
+    }
+
+    /// Sets the music volume, applied before the master volume.
+    pub fn set_music_volume(&mut self, volume: f32) {
+        self.audio_engine.set_music_volume(volume);
+    }
+
+    /// Sets the hitsound volume, applied before the master volume.
+    pub fn set_hitsound_volume(&mut self, volume: f32) {
+        self.audio_engine.set_hitsound_volume(volume);
+    }
+
+    /// Sets the FFT size of the music analyser (power of two, default 2048),
+    /// returning the number of frequency bins.
+    pub fn set_analyser_fft_size(&mut self, size: u32) -> u32 {
+        self.audio_engine.set_analyser_fft_size(size)
+    }
+
+    /// Current music spectrum for visualizations, one byte (0-255) per
+    /// frequency bin, low frequencies first.
+    pub fn get_frequency_data(&self) -> Vec<u8> {
+        self.audio_engine.frequency_data()
+    }
+
+    /// Current music waveform for visualizations, one byte per sample with
+    /// 128 as silence.
+    pub fn get_waveform_data(&self) -> Vec<u8> {
+        self.audio_engine.waveform_data()
+    }
+
+    /// Hides fake notes. Filters only change what is drawn, judging is
+    /// unaffected.
+    pub fn set_hide_fake_notes(&mut self, flag: bool) {
+        self.chart_renderer.filter.hide_fake = flag;
+    }
+
+    /// Hides or shows notes of `kind` ("click", "drag", "hold" or "flick").
+    pub fn set_note_kind_hidden(&mut self, kind: &str, hidden: bool) -> Result<(), JsValue> {
+        let kind = match kind.to_lowercase().as_str() {
+            "click" => NoteKind::Click,
+            "drag" => NoteKind::Drag,
+            "flick" => NoteKind::Flick,
+            "hold" => NoteKind::Hold {
+                end_time: 0.0,
+                end_height: 0.0,
+            },
+            _ => return Err(JsValue::from_str(&format!("Unknown note kind: {}", kind))),
+        };
+        let bit = NoteFilter::kind_bit(&kind);
+        let filter = &mut self.chart_renderer.filter;
+        if hidden {
+            filter.hidden_kinds |= bit;
+        } else {
+            filter.hidden_kinds &= !bit;
+        }
+        Ok(())
+    }
+
+    /// Draws only the judge line with `index` and its notes, or every line
+    /// for `undefined`. Reset when a chart is loaded.
+    pub fn set_solo_line(&mut self, index: Option<usize>) {
+        self.chart_renderer.filter.only_line = index;
+    }
+
+    /// Mirrors the chart horizontally.
+    pub fn set_mirror(&mut self, flag: bool) {
+        self.chart_renderer.mirror = flag;
+    }
+
+    /// Toggles the highlighted textures for notes sharing a time with
+    /// another note. Enabled by default.
+    pub fn set_multiple_hint(&mut self, flag: bool) {
+        self.resource.multiple_hint = flag;
+    }
+
+    /// Shows or hides the floating judgement indicators.
+    pub fn set_judge_popups(&mut self, flag: bool) {
+        self.popups.enabled = flag;
+        if !flag {
+            self.popups.clear();
+        }
+    }
+
+    /// Changes MSAA sample count / FXAA at runtime. The canvas `antialias`
+    /// attribute is fixed once the context exists and is ignored here.
+    pub fn set_render_settings(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let mut settings = Self::parse_render_settings(settings)?;
+        settings.antialias = self.renderer.settings.antialias;
+        self.renderer.apply_settings(settings)
+    }
+
+    /// Sets the render scale (0.5–2.0) applied on top of `devicePixelRatio`;
+    /// lower values trade sharpness for speed. Takes effect on the next
+    /// `resize`.
+    pub fn set_render_scale(&mut self, scale: f32) -> Result<(), JsValue> {
+        let mut settings = self.renderer.settings.clone();
+        settings.render_scale = scale;
+        self.renderer.apply_settings(settings)
+    }
+
+    /// Shows or hides the frame time / draw call overlay.
+    pub fn set_stats_overlay(&mut self, flag: bool) {
+        self.stats.enabled = flag;
+    }
+
+    /// Shows or hides judge line indices, heights and control values and
+    /// note bounding boxes.
+    pub fn set_debug_overlay(&mut self, flag: bool) {
+        self.debug.enabled = flag;
+    }
+
+    /// Shows whose play this is in the top left corner: the player's name,
+    /// an optional badge such as their rank, and their avatar image (e.g.
+    /// from the proxy's `/user/{id}/avatar`). Pass no name to hide it.
+    pub async fn set_player_info(
+        &mut self,
+        name: Option<String>,
+        badge: Option<String>,
+        avatar: Option<Vec<u8>>,
+    ) -> Result<(), JsValue> {
+        let avatar = match avatar {
+            Some(bytes) => Some(Texture::load_from_bytes(&self.renderer.context, &bytes).await?),
+            None => None,
+        };
+        self.player.set(&self.renderer, name, badge, avatar);
+        Ok(())
+    }
+
+    /// Shows or hides a timeline of the chart's beats and bars along the
+    /// bottom of the screen.
+    pub fn set_beat_grid(&mut self, flag: bool) {
+        self.beat_grid.enabled = flag;
+    }
+
+    /// Plays a click on every beat of the chart, higher on the first beat
+    /// of each bar, to check the chart's BPM and offset against the music.
+    pub fn set_beat_metronome(&mut self, flag: bool) {
+        self.beat_metronome = flag;
+        self.reset_hitsound_schedule();
+    }
+
+    /// Caps the live particles of each hit effect emitter (default 12000).
+    /// Rebuilds the emitters, dropping effects in flight.
+    pub fn set_max_particles(&mut self, count: usize) -> Result<(), JsValue> {
+        let previous = std::mem::replace(&mut self.resource.max_particles, count);
+        if let Err(e) = self.resource.refresh_pack(&self.renderer.context) {
+            self.resource.max_particles = previous;
+            return Err(JsValue::from_str(&format!("Failed to set pack: {}", e)));
+        }
+        Ok(())
+    }
+
+    /// Shows or hides the score / combo / accuracy overlay.
+    pub fn set_hud(&mut self, flag: bool) {
+        self.hud.enabled = flag;
+    }
+
+    /// Overrides the hit effect for one judgement (`"perfect"` or `"good"`).
+    /// `scale` multiplies the pack's effect scale and `duration` (seconds)
+    /// replaces its lifetime; pass `undefined` to restore the pack value.
+    pub fn set_hit_fx_style(
+        &mut self,
+        judgement: &str,
+        scale: Option<f32>,
+        duration: Option<f32>,
+    ) -> Result<(), JsValue> {
+        let judgement = match judgement {
+            "perfect" => Judgement::Perfect,
+            "good" => Judgement::Good,
+            _ => {
+                return Err(JsValue::from_str(&format!(
+                    "Unknown judgement: {}",
+                    judgement
+                )));
+            }
+        };
+        self.resource.hit_fx_styles[judgement as usize] = HitFxStyle {
+            scale: scale.unwrap_or(1.0),
+            duration,
+        };
+        Ok(())
     }
 
     pub fn render(&mut self) -> Result<(), JsValue> {
         let now = web_sys::window().unwrap().performance().unwrap().now();
+        // Leave a little slack so a cap matching the display rate skips nothing
+        let draw = self.visible
+            && self.max_fps.is_none_or(|fps| {
+                self.last_frame
+                    .is_none_or(|last| now - last >= 1000.0 / fps - 2.0)
+            });
 
         let mut dt = 0.0;
         if !self.paused {
-            self.current_time = self.audio_engine.get_time();
-            if let Some(last) = self.last_update_time {
-                dt = (now - last) as f32 / 1000.0;
+            self.current_time = self.playing_time(now);
+            if let Some((start, end)) = self.loop_range
+                && self.current_time >= end
+            {
+                self.seek(start)?;
+            }
+            // Skipped frames count towards the next drawn one
+            if draw {
+                if let Some(last) = self.last_update_time {
+                    dt = (now - last) as f32 / 1000.0;
+                }
+                self.last_update_time = Some(now);
             }
-            self.last_update_time = Some(now);
         }
         self.resource.dt = dt;
-
-        self.renderer.clear();
-        self.renderer.begin_frame();
-
-        let aspect = self.resource.aspect_ratio;
-        let y_scale = aspect;
-
-        self.renderer.set_projection(&[
-            1.0, 0.0, 0.0, 0.0, 0.0, y_scale, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
-        ]);
+        self.audio_engine.tick_metronome()?;
 
         self.chart_renderer
             .update(&mut self.resource, self.current_time);
 
+        self.schedule_hitsounds();
+
         // Judge update pass — produces events for hitsounds/particles
         let events = self.chart_renderer.update_judges(&self.resource);
 
         // Consume events: play hitsounds
         for event in &events {
             match &event.kind {
+                JudgeEventKind::Judged(Judgement::Miss) => {}
                 JudgeEventKind::Judged(_) | JudgeEventKind::HoldStart => {
                     let note =
                         &self.chart_renderer.chart.lines[event.line_idx].notes[event.note_idx];
-                    let hitsound = note.hitsound.clone().unwrap_or_else(|| match note.kind {
-                        NoteKind::Click => HitSound::Click,
-                        NoteKind::Drag => HitSound::Drag,
-                        NoteKind::Flick => HitSound::Flick,
-                        _ => HitSound::Click,
-                    });
-                    let _ = self.audio_engine.play_hitsound(&hitsound);
+                    let scheduled = self.chart_renderer.autoplay
+                        && self
+                            .hitsound_window
+                            .is_some_and(|(from, _)| note.time > from);
+                    if !scheduled && self.silent_clock.is_none() {
+                        let _ = self.audio_engine.play_hitsound(&note_hitsound(note));
+                    }
                 }
                 _ => {}
             }
         }
 
-        // Consume events: emit particles
-        self.chart_renderer
-            .emit_particles(&mut self.resource, &events);
+        // Notes can be left unjudged, e.g. charts without any
+        let ended = !self.paused && self.duration > 0.0 && self.current_time >= self.duration;
+        if !self.finished && (self.chart_renderer.score.finished() || ended) {
+            self.finished = true;
+            if let Some(callback) = &self.on_finish {
+                callback.call1(&JsValue::NULL, &self.get_score_summary()?)?;
+            }
+        }
 
-        self.chart_renderer
-            .render(&mut self.resource, &mut self.renderer);
-        self.renderer.flush();
+        if draw {
+            self.last_frame = Some(now);
+            self.renderer.clear();
+            self.renderer.begin_frame();
+
+            let aspect = self.resource.aspect_ratio;
+            let y_scale = aspect;
+
+            self.renderer.set_projection(&[
+                1.0, 0.0, 0.0, 0.0, 0.0, y_scale, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+            ]);
+
+            // Consume events: judgement popups
+            for event in &events {
+                if let JudgeEventKind::Judged(j) | JudgeEventKind::HoldComplete(j) = event.kind {
+                    self.popups.push(self.chart_renderer.hit_position(event), j);
+                }
+            }
+
+            // Consume events: emit particles
+            self.chart_renderer
+                .emit_particles(&mut self.resource, &events);
+
+            self.chart_renderer.render_videos(
+                &mut self.resource,
+                &mut self.renderer,
+                !self.paused,
+                self.audio_engine.playback_rate(),
+            );
+            self.chart_renderer
+                .render(&mut self.resource, &mut self.renderer);
+            self.chart_renderer
+                .render_effects(&mut self.renderer, false);
+            self.popups.draw(&mut self.renderer, &self.resource)?;
+            self.chart_renderer
+                .render_hud(&mut self.hud, &mut self.renderer)?;
+            self.player.draw(&mut self.renderer)?;
+            self.chart_renderer.render_effects(&mut self.renderer, true);
+            // After effects, so shaders don't distort it
+            self.debug
+                .draw(&self.chart_renderer, &mut self.resource, &mut self.renderer)?;
+            self.beat_grid.draw(
+                &mut self.chart_renderer.chart.bpm_list,
+                self.current_time,
+                &mut self.renderer,
+            );
+            let particles = self.resource.emitter.as_ref().map_or(0, |e| e.alive());
+            self.stats.draw(
+                &mut self.renderer,
+                FrameInfo {
+                    started: now,
+                    particles,
+                    events: events.len(),
+                },
+            )?;
+            self.renderer.end_frame();
+        }
+
+        if let Some(callback) = &self.on_progress {
+            callback.call2(
+                &JsValue::NULL,
+                &self.get_time().into(),
+                &self.get_duration().into(),
+            )?;
+        }
         Ok(())
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.renderer.resize(width, height);
+    /// Resizes to a canvas of `width` x `height` CSS pixels. The drawing
+    /// buffer follows `devicePixelRatio` times the configured render scale.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), JsValue> {
+        let dpr = web_sys::window().map_or(1.0, |w| w.device_pixel_ratio());
+        let (width, height) = self.renderer.settings.buffer_size(width, height, dpr);
+        self.renderer.resize(width, height)?;
         self.resource.width = width;
         self.resource.height = height;
         self.resource.aspect_ratio = width as f32 / height as f32;
+        Ok(())
     }
 
     pub async fn load_chart(&mut self, id: String) -> Result<JsValue, JsValue> {
-        let window = web_sys::window().ok_or("no window")?;
-        let resp_value =
-            wasm_bindgen_futures::JsFuture::from(window.fetch_with_str(&format!("/chart/{}", id)))
-                .await?;
-        let resp: web_sys::Response = resp_value.dyn_into()?;
-
-        if !resp.ok() {
-            return Err(JsValue::from_str(&format!(
-                "Fetch failed: {}",
-                resp.status_text()
-            )));
-        }
-
-        let array_buffer = wasm_bindgen_futures::JsFuture::from(resp.array_buffer()?).await?;
+        let array_buffer =
+            fetch_array_buffer(&format!("/chart/{}", id), self.api_key.as_deref()).await?;
         let uint8_array = js_sys::Uint8Array::new(&array_buffer);
         let vec = uint8_array.to_vec();
 
-        use bincode::Options;
-        let (info, mut chart): (ChartInfo, Chart) = bincode::options()
-            .with_varint_encoding()
-            .deserialize(&vec)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse chart: {}", e)))?;
+        let (info, chart) = payload::decode(&vec)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse chart: {:#}", e)))?;
+
+        self.set_chart(info, chart).await
+    }
+
+    /// Loads a chart zip (as downloaded from Phira or exported by an editor)
+    /// without going through the proxy. Returns the chart info like
+    /// `load_chart`.
+    pub async fn load_chart_from_zip(&mut self, bytes: Vec<u8>) -> Result<JsValue, JsValue> {
+        let (info, chart) = parse_chart_zip(bytes)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse chart: {:#}", e)))?;
+        self.set_chart(info, chart).await
+    }
 
-        chart.order = (0..chart.lines.len()).collect();
-        chart.order.sort_by_key(|&i| chart.lines[i].z_index);
+    /// Replaces the current chart, uploading its textures and audio.
+    async fn set_chart(&mut self, info: ChartInfo, mut chart: Chart) -> Result<JsValue, JsValue> {
+        chart.update_order();
 
         for line in &mut chart.lines {
             line.notes.sort_by(|a, b| {
@@ -203,6 +871,7 @@ impl ChartPlayer {
         let existing_pack = self.resource.res_pack.take();
         let renderer = &self.renderer;
         let mut resource = Resource::new(renderer.context.width, renderer.context.height);
+        resource.max_particles = self.resource.max_particles;
         resource.load_defaults(&renderer.context)?;
 
         if let Some(pack) = existing_pack {
@@ -236,20 +905,49 @@ impl ChartPlayer {
                 _ => {}
             }
         }
+        for video in &mut chart.extra.videos {
+            resource
+                .videos
+                .push(VideoLayer::new(&renderer.context, video)?);
+        }
+
+        // Custom effect shaders are cached by path, which is only unique per chart
+        self.renderer.effects.clear(&self.renderer.context);
+        self.renderer.require_offscreen(chart.extra.has_effects())?;
 
         let autoplay = self.chart_renderer.autoplay;
+        let mirror = self.chart_renderer.mirror;
+        let filter = self.chart_renderer.filter.clone();
         self.chart_renderer = ChartRenderer::new(info.clone(), chart);
         self.chart_renderer.autoplay = autoplay;
+        self.chart_renderer.mirror = mirror;
+        // Line indices refer to the old chart
+        self.chart_renderer.filter = NoteFilter {
+            only_line: None,
+            ..filter
+        };
+        self.resource.release_paint(&self.renderer.context);
+        self.resource.release_videos(&self.renderer.context);
+        if let Some(emitter) = self.resource.emitter.take() {
+            emitter.delete(&self.renderer.context);
+        }
+        resource.hit_fx_styles = self.resource.hit_fx_styles;
+        resource.multiple_hint = self.resource.multiple_hint;
         self.resource = resource;
+        self.popups.clear();
+        self.finished = false;
         self.current_time = 0.0;
         self.paused = true;
         self.last_update_time = None;
+        self.hitsound_window = None;
 
         // Load Audio into Engine
         self.audio_engine.pause()?;
         self.audio_engine
             .set_offset(self.chart_renderer.chart.offset);
 
+        self.duration = self.chart_renderer.chart.duration();
+
         if let Some(music) = &self.chart_renderer.chart.music {
             self.audio_engine.set_music(music)?;
         }
@@ -262,12 +960,32 @@ impl ChartPlayer {
             self.audio_engine.set_hitsound(kind.clone(), clip)?;
         }
 
+        // 3. Audio the proxy serves apart from the chart
+        let audio = self.chart_renderer.chart.audio.clone();
+        if let Some(url) = &audio.music {
+            match self.fetch_audio(url).await {
+                Ok(buffer) => self.audio_engine.set_music_buffer(buffer),
+                Err(e) => console_log!("Failed to load music: {:?}", e),
+            }
+        }
+        for (kind, url) in audio.hitsounds {
+            match self.fetch_audio(&url).await {
+                Ok(buffer) => self.audio_engine.set_hitsound_buffer(kind, buffer),
+                Err(e) => console_log!("Failed to load hitsound {:?}: {:?}", kind, e),
+            }
+        }
+
         serde_wasm_bindgen::to_value(&info)
             .map_err(|e| JsValue::from_str(&format!("Failed to serialize chart info: {}", e)))
     }
 
-    pub async fn load_resource_pack(&mut self, files: js_sys::Object) -> Result<(), JsValue> {
-        let entries = js_sys::Object::entries(&files);
+    async fn fetch_audio(&self, url: &str) -> Result<web_sys::AudioBuffer, JsValue> {
+        let data = fetch_array_buffer(url, self.api_key.as_deref()).await?;
+        self.audio_engine.decode(&data).await
+    }
+
+    fn read_file_map(files: &js_sys::Object) -> Result<HashMap<String, Vec<u8>>, JsValue> {
+        let entries = js_sys::Object::entries(files);
         let mut file_map = HashMap::new();
 
         for i in 0..entries.length() {
@@ -278,6 +996,11 @@ impl ChartPlayer {
             let uint8_array = js_sys::Uint8Array::new(&value);
             file_map.insert(key, uint8_array.to_vec());
         }
+        Ok(file_map)
+    }
+
+    pub async fn load_resource_pack(&mut self, files: js_sys::Object) -> Result<(), JsValue> {
+        let file_map = Self::read_file_map(&files)?;
 
         let res_pack = ResourcePack::load(&self.renderer.context, file_map)
             .await
@@ -291,4 +1014,139 @@ impl ChartPlayer {
 
         Ok(())
     }
+
+    /// Loads a resource pack bundle from the proxy's `/respack/prepare`,
+    /// skipping the per-texture image decoding of `load_resource_pack`.
+    pub fn load_prepared_resource_pack(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let res_pack = ResourcePack::load_bundle(&self.renderer.context, bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to load pack: {:?}", e)))?;
+
+        self.resource
+            .set_pack(&self.renderer.context, res_pack)
+            .map_err(|e| JsValue::from_str(&format!("Failed to set pack: {}", e)))?;
+
+        self.sync_hitsounds()?;
+
+        Ok(())
+    }
+
+    /// Updates the loaded resource pack in place, re-uploading only the files
+    /// whose content changed. Returns the names of the updated files.
+    pub async fn update_resource_pack(
+        &mut self,
+        files: js_sys::Object,
+    ) -> Result<js_sys::Array, JsValue> {
+        let file_map = Self::read_file_map(&files)?;
+
+        let pack = match &mut self.resource.res_pack {
+            Some(pack) if pack.info.name != "fallback" => pack,
+            _ => {
+                // Nothing to diff against, load the whole pack instead. The
+                // current pack stays until the new one is ready.
+                let names: js_sys::Array = file_map.keys().map(|k| JsValue::from_str(k)).collect();
+                let res_pack = ResourcePack::load(&self.renderer.context, file_map)
+                    .await
+                    .map_err(|e| JsValue::from_str(&format!("Failed to load pack: {:?}", e)))?;
+                self.resource
+                    .set_pack(&self.renderer.context, res_pack)
+                    .map_err(|e| JsValue::from_str(&format!("Failed to set pack: {}", e)))?;
+                self.sync_hitsounds()?;
+                return Ok(names);
+            }
+        };
+
+        // Loads everything before replacing anything, the pack is unchanged
+        // on failure
+        let updated = pack
+            .update(&self.renderer.context, file_map)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to update pack: {:?}", e)))?;
+
+        // Rebuild emitters/font so they pick up replaced textures
+        self.resource
+            .refresh_pack(&self.renderer.context)
+            .map_err(|e| JsValue::from_str(&format!("Failed to set pack: {}", e)))?;
+        self.sync_hitsounds()?;
+
+        console_log!("Resource pack updated: {} file(s) changed", updated.len());
+        Ok(updated.iter().map(|k| JsValue::from_str(k)).collect())
+    }
+
+    /// Sends audio to another output device, e.g. one kept apart from voice
+    /// chat. `device_id` comes from `navigator.mediaDevices.enumerateDevices()`;
+    /// an empty string selects the default device.
+    pub async fn set_output_device(&self, device_id: String) -> Result<(), JsValue> {
+        let promise = self.audio_engine.set_output_device(&device_id)?;
+        wasm_bindgen_futures::JsFuture::from(promise).await?;
+        Ok(())
+    }
+
+    /// Runs a self-test of the browser environment (WebGL, audio, WASM
+    /// features, memory) and checks that `url` is reachable. Defaults to the
+    /// proxy's room list endpoint.
+    pub async fn run_diagnostics(&self, url: Option<String>) -> Result<JsValue, JsValue> {
+        let url = url.unwrap_or_else(|| "/rooms/info".to_string());
+        let report = diagnostics::run(&self.renderer.context, &self.audio_engine, url).await;
+        serde_wasm_bindgen::to_value(&report)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize diagnostics: {}", e)))
+    }
+
+    /// Starts recording the canvas and the audio output, e.g. to export an
+    /// autoplay video. `fps` defaults to 60 and `bitrate` (video bits per
+    /// second) to 8 Mbps.
+    pub fn start_recording(
+        &mut self,
+        fps: Option<f64>,
+        bitrate: Option<u32>,
+    ) -> Result<(), JsValue> {
+        if self.recorder.is_some() {
+            return Err(JsValue::from_str("Already recording"));
+        }
+        let audio = self.audio_engine.connect_recording_output()?;
+        let recorder = recorder::Recorder::start(
+            &self.renderer.context.canvas,
+            audio.clone(),
+            fps.unwrap_or(60.0),
+            bitrate.unwrap_or(8_000_000),
+        );
+        if recorder.is_err() {
+            self.audio_engine.disconnect_recording_output(&audio);
+        }
+        self.recorder = Some(recorder?);
+        Ok(())
+    }
+
+    /// Stops recording and returns the video as a WebM `Blob`.
+    pub async fn stop_recording(&mut self) -> Result<web_sys::Blob, JsValue> {
+        let recorder = self.recorder.take().ok_or("Not recording")?;
+        self.audio_engine
+            .disconnect_recording_output(&recorder.audio);
+        recorder.stop().await
+    }
+
+    /// Renders the next frame and returns it as a PNG `Blob` of the whole
+    /// canvas at drawing buffer resolution.
+    pub async fn capture_frame(&mut self) -> Result<web_sys::Blob, JsValue> {
+        self.render()?;
+        // toBlob snapshots the canvas synchronously, so the frame is still
+        // there even without preserveDrawingBuffer
+        let canvas = self.renderer.context.canvas.clone();
+        let promise = js_sys::Promise::new(&mut |resolve, reject| {
+            let on_blob = {
+                let reject = reject.clone();
+                Closure::once_into_js(move |blob: Option<web_sys::Blob>| {
+                    let _ = match blob {
+                        Some(blob) => resolve.call1(&JsValue::NULL, &blob),
+                        None => reject.call1(&JsValue::NULL, &"Failed to encode frame".into()),
+                    };
+                })
+            };
+            if let Err(e) = canvas.to_blob_with_type(on_blob.unchecked_ref(), "image/png") {
+                let _ = reject.call1(&JsValue::NULL, &e);
+            }
+        });
+        wasm_bindgen_futures::JsFuture::from(promise)
+            .await?
+            .dyn_into()
+    }
 }
@@ -1,2 +1,121 @@
-// Basic Network module skeleton
-pub struct NetworkClient {}
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use web_sys::{EventSource, MessageEvent};
+
+/// Events `/rooms/listen` sends, each with a JSON object as data
+const ROOM_EVENTS: [&str; 9] = [
+    "create_room",
+    "update_room",
+    "join_room",
+    "leave_room",
+    "start_round",
+    "player_score",
+    "scoreboard",
+    "chat",
+    "presence",
+];
+
+/// Room events from the proxy's `/rooms/listen` stream, handed to JS as
+/// objects with the event name in `type`, e.g.
+/// `{ type: "join_room", room, user }`.
+#[wasm_bindgen]
+pub struct RoomEvents {
+    source: EventSource,
+    on_event: Option<Closure<dyn FnMut(MessageEvent)>>,
+}
+
+impl RoomEvents {
+    /// Opens `/rooms/listen` with `target` (`room` or `token`) in the query
+    fn connect(
+        target: Option<(&str, String)>,
+        api_key: Option<String>,
+    ) -> Result<RoomEvents, JsValue> {
+        let mut query = Vec::new();
+        if let Some((name, value)) = target {
+            query.push(format!(
+                "{name}={}",
+                String::from(js_sys::encode_uri_component(&value))
+            ));
+        }
+        if let Some(key) = api_key.filter(|key| !key.is_empty()) {
+            query.push(format!(
+                "api_key={}",
+                String::from(js_sys::encode_uri_component(&key))
+            ));
+        }
+        let mut url = String::from("/rooms/listen");
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query.join("&"));
+        }
+        Ok(Self {
+            source: EventSource::new(&url)?,
+            on_event: None,
+        })
+    }
+}
+
+#[wasm_bindgen]
+impl RoomEvents {
+    /// Connects to the page's proxy, counted as a viewer of `room` if
+    /// given. `api_key` is needed when the proxy runs with `--api-keys`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(room: Option<String>, api_key: Option<String>) -> Result<RoomEvents, JsValue> {
+        Self::connect(room.map(|room| ("room", room)), api_key)
+    }
+
+    /// Connects to the page's proxy as a monitor of the room `POST /rooms`
+    /// returned `token` for. Rooms the proxy hosts send their events only
+    /// to holders of their token.
+    #[wasm_bindgen(js_name = withToken)]
+    pub fn with_token(token: String, api_key: Option<String>) -> Result<RoomEvents, JsValue> {
+        Self::connect(Some(("token", token)), api_key)
+    }
+
+    /// Sets the callback run with every room event. Pass `undefined` to
+    /// remove it.
+    pub fn set_on_event(&mut self, callback: Option<js_sys::Function>) -> Result<(), JsValue> {
+        if let Some(old) = self.on_event.take() {
+            for name in ROOM_EVENTS {
+                self.source
+                    .remove_event_listener_with_callback(name, old.as_ref().unchecked_ref())?;
+            }
+        }
+        let Some(callback) = callback else {
+            return Ok(());
+        };
+        let on_event = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(data) = event.data().as_string() else {
+                return;
+            };
+            let Ok(object) = js_sys::JSON::parse(&data) else {
+                return;
+            };
+            let _ = js_sys::Reflect::set(&object, &"type".into(), &event.type_().into());
+            let _ = callback.call1(&JsValue::NULL, &object);
+        });
+        for name in ROOM_EVENTS {
+            self.source
+                .add_event_listener_with_callback(name, on_event.as_ref().unchecked_ref())?;
+        }
+        self.on_event = Some(on_event);
+        Ok(())
+    }
+
+    /// Whether the stream is connected. The browser reconnects on its own
+    /// after the connection drops.
+    pub fn is_open(&self) -> bool {
+        self.source.ready_state() == EventSource::OPEN
+    }
+
+    /// Closes the stream, no more events are delivered.
+    pub fn close(&self) {
+        self.source.close();
+    }
+}
+
+impl Drop for RoomEvents {
+    fn drop(&mut self) {
+        self.source.close();
+    }
+}
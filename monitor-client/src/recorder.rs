@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use web_sys::{
+    Blob, BlobEvent, BlobPropertyBag, HtmlCanvasElement, MediaRecorder, MediaRecorderOptions,
+    MediaStream, MediaStreamAudioDestinationNode, MediaStreamTrack,
+};
+
+/// Container formats to try, best first
+const MIME_TYPES: [&str; 3] = [
+    "video/webm;codecs=vp9,opus",
+    "video/webm;codecs=vp8,opus",
+    "video/webm",
+];
+/// Milliseconds of media per recorded chunk, so long recordings are not
+/// held by the encoder as one buffer
+const TIME_SLICE: i32 = 1000;
+
+/// Records the canvas together with the audio output into a WebM video.
+pub struct Recorder {
+    recorder: MediaRecorder,
+    stream: MediaStream,
+    /// Receives the audio output, disconnected again by the owner
+    pub audio: MediaStreamAudioDestinationNode,
+    chunks: Rc<RefCell<Vec<Blob>>>,
+    _on_data: Closure<dyn FnMut(BlobEvent)>,
+}
+
+impl Recorder {
+    /// Starts recording `canvas` at `fps` frames per second, with the audio
+    /// arriving at `audio`. `bitrate` is the video bitrate in bits per second.
+    pub fn start(
+        canvas: &HtmlCanvasElement,
+        audio: MediaStreamAudioDestinationNode,
+        fps: f64,
+        bitrate: u32,
+    ) -> Result<Self, JsValue> {
+        let mime_type = MIME_TYPES
+            .into_iter()
+            .find(|t| MediaRecorder::is_type_supported(t))
+            .ok_or("WebM recording is not supported")?;
+
+        let stream = canvas.capture_stream_with_frame_request_rate(fps)?;
+        for track in audio.stream().get_audio_tracks() {
+            stream.add_track(track.unchecked_ref());
+        }
+
+        let options = MediaRecorderOptions::new();
+        options.set_mime_type(mime_type);
+        options.set_video_bits_per_second(bitrate);
+        let recorder =
+            MediaRecorder::new_with_media_stream_and_media_recorder_options(&stream, &options)?;
+
+        let chunks = Rc::new(RefCell::new(Vec::new()));
+        let on_data = {
+            let chunks = chunks.clone();
+            Closure::<dyn FnMut(BlobEvent)>::new(move |event: BlobEvent| {
+                if let Some(blob) = event.data()
+                    && blob.size() > 0.0
+                {
+                    chunks.borrow_mut().push(blob);
+                }
+            })
+        };
+        recorder.set_ondataavailable(Some(on_data.as_ref().unchecked_ref()));
+        recorder.start_with_time_slice(TIME_SLICE)?;
+
+        Ok(Self {
+            recorder,
+            stream,
+            audio,
+            chunks,
+            _on_data: on_data,
+        })
+    }
+
+    /// Stops recording and returns the video once the last chunk is in.
+    pub async fn stop(self) -> Result<Blob, JsValue> {
+        let recorder = self.recorder.clone();
+        let promise = js_sys::Promise::new(&mut |resolve, reject| {
+            let on_stop = Closure::once_into_js(move || {
+                let _ = resolve.call0(&JsValue::NULL);
+            });
+            recorder.set_onstop(Some(on_stop.unchecked_ref()));
+            if let Err(e) = recorder.stop() {
+                let _ = reject.call1(&JsValue::NULL, &e);
+            }
+        });
+        let stopped = wasm_bindgen_futures::JsFuture::from(promise).await;
+        for track in self.stream.get_tracks() {
+            track.unchecked_into::<MediaStreamTrack>().stop();
+        }
+        stopped?;
+
+        let parts: js_sys::Array = self.chunks.borrow().iter().collect();
+        let options = BlobPropertyBag::new();
+        // The codec parameters are not part of the file type
+        options.set_type("video/webm");
+        Blob::new_with_blob_sequence_and_options(&parts, &options)
+    }
+}
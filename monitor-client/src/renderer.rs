@@ -1,20 +1,44 @@
+use monitor_common::core::Effect;
 use wasm_bindgen::prelude::*;
 
 mod batch;
 pub use batch::Batcher;
 
+mod effect;
+pub use effect::EffectRenderer;
+
+mod label;
+pub use label::Label;
+
 mod context;
 pub use context::GlContext;
 
+mod post;
+pub use post::{PostProcess, RENDER_SCALE_RANGE, RenderSettings};
+
 mod shader;
 pub use shader::ShaderManager;
 
+mod target;
+pub use target::RenderTarget;
+
 mod texture;
 pub use texture::Texture;
 
 pub mod particle;
 pub mod text;
 
+pub const IDENTITY: [f32; 16] = [
+    1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+];
+
+/// GPU work of one frame, for the stats overlay
+#[derive(Clone, Copy, Default)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    pub flushes: u32,
+}
+
 #[wasm_bindgen]
 pub struct Renderer {
     #[wasm_bindgen(skip)]
@@ -27,11 +51,24 @@ pub struct Renderer {
     pub white_texture: Texture,
     #[wasm_bindgen(skip)]
     pub projection: [f32; 16],
+    #[wasm_bindgen(skip)]
+    pub settings: RenderSettings,
+    #[wasm_bindgen(skip)]
+    pub post: Option<PostProcess>,
+    /// Keeps the offscreen pipeline alive regardless of settings, e.g. while
+    /// a chart with shader effects is loaded
+    #[wasm_bindgen(skip)]
+    pub offscreen_required: bool,
+    #[wasm_bindgen(skip)]
+    pub effects: EffectRenderer,
+    /// Stats of the last completed frame
+    #[wasm_bindgen(skip)]
+    pub last_stats: FrameStats,
 }
 
 impl Renderer {
-    pub fn new(canvas_id: &str) -> Result<Self, JsValue> {
-        let context = GlContext::new(canvas_id)?;
+    pub fn new(canvas_id: &str, settings: RenderSettings) -> Result<Self, JsValue> {
+        let context = GlContext::new(canvas_id, settings.antialias)?;
         let mut shader_manager = ShaderManager::new(&context);
         shader_manager.init_defaults(&context)?;
 
@@ -42,6 +79,7 @@ impl Renderer {
         );
 
         let batcher = Batcher::new(&context)?;
+        let effects = EffectRenderer::new(&context)?;
 
         // Create and bind default white texture to unit 0
         let white_texture = Texture::create_white_pixel(&context)?;
@@ -54,7 +92,13 @@ impl Renderer {
             projection: [
                 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
             ],
+            settings: RenderSettings::default(),
+            post: None,
+            offscreen_required: false,
+            effects,
+            last_stats: FrameStats::default(),
         };
+        renderer.apply_settings(settings)?;
         // Upload initial projection
         renderer.set_projection(&[
             1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
@@ -62,12 +106,79 @@ impl Renderer {
         Ok(renderer)
     }
 
-    pub fn clear(&self) {
+    /// Switches MSAA / FXAA. `antialias` only takes effect for new contexts.
+    pub fn apply_settings(&mut self, mut settings: RenderSettings) -> Result<(), JsValue> {
+        settings.render_scale = settings
+            .render_scale
+            .clamp(RENDER_SCALE_RANGE.0, RENDER_SCALE_RANGE.1);
+        if let Some(post) = self.post.take() {
+            post.delete(&self.context);
+        }
+        self.context.screen_framebuffer = None;
+        if settings.needs_post() || self.offscreen_required {
+            self.post = Some(PostProcess::new(&self.context, &settings)?);
+        }
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Starts a frame: redirects drawing offscreen when post-processing is on
+    /// and clears the target.
+    pub fn clear(&mut self) {
+        self.context.draw_calls.set(0);
+        self.batcher.flushes = 0;
+        if let Some(post) = &mut self.post {
+            post.begin(&mut self.context);
+        }
         self.context.clear(0.1, 0.1, 0.1, 1.0);
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
+    /// Finishes a frame, presenting the offscreen scene if there is one.
+    pub fn end_frame(&mut self) {
+        self.flush();
+        if let Some(post) = &mut self.post {
+            post.present(&mut self.context);
+            self.batcher.invalidate_texture_cache();
+        }
+        self.last_stats = FrameStats {
+            draw_calls: self.context.draw_calls.get(),
+            flushes: self.batcher.flushes,
+        };
+    }
+
+    /// Forces frames through the offscreen pipeline, as effect passes need
+    /// the scene in a texture.
+    pub fn require_offscreen(&mut self, flag: bool) -> Result<(), JsValue> {
+        if self.offscreen_required == flag {
+            return Ok(());
+        }
+        self.offscreen_required = flag;
+        self.apply_settings(self.settings.clone())
+    }
+
+    /// Runs a shader effect over everything drawn so far this frame.
+    pub fn apply_effect(&mut self, effect: &Effect, time: f32) {
+        let Some(post) = &mut self.post else {
+            return;
+        };
+        // A shader that failed to compile must not swap in an unwritten target
+        if !self.effects.prepare(&self.context, &effect.shader) {
+            return;
+        }
+        self.batcher.flush(&self.context);
+        let effects = &mut self.effects;
+        post.apply(&mut self.context, |ctx, scene| {
+            effects.draw(ctx, effect, scene, time)
+        });
+        self.batcher.invalidate_texture_cache();
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), JsValue> {
         self.context.resize(width, height);
+        if let Some(post) = &mut self.post {
+            post.resize(&self.context, width, height)?;
+        }
+        Ok(())
     }
 
     pub fn begin_frame(&mut self) {
@@ -3,19 +3,41 @@ use super::texture::Texture;
 use wasm_bindgen::prelude::*;
 use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlVertexArrayObject};
 
-const MAX_QUADS: usize = 10000;
 const VERTICES_PER_QUAD: usize = 4;
 const INDICES_PER_QUAD: usize = 6;
 const FLOATS_PER_VERTEX: usize = 8; // x, y, u, v, r, g, b, a
+/// Batch size the buffers start with
+const INITIAL_QUADS: usize = 1024;
+/// Largest batch addressable with u16 indices
+const MAX_QUADS: usize = (u16::MAX as usize + 1) / VERTICES_PER_QUAD;
+/// UVs of a texture drawn whole
+const FULL_REGION: [f32; 4] = [0., 0., 1., 1.];
+
+/// `0 1 2, 0 2 3` for every quad
+fn quad_indices(quads: usize) -> Vec<u16> {
+    (0..quads)
+        .flat_map(|i| {
+            let base = (i * VERTICES_PER_QUAD) as u16;
+            [base, base + 1, base + 2, base, base + 2, base + 3]
+        })
+        .collect()
+}
 
 pub struct Batcher {
     vertices: Vec<f32>,
-    _indices: Vec<u16>,
     vbo: WebGlBuffer,
-    _ebo: WebGlBuffer,
+    ebo: WebGlBuffer,
     vao: WebGlVertexArrayObject,
+    /// Quads the GPU buffers currently hold. Doubles whenever a frame fills
+    /// it, up to `MAX_QUADS`, so dense charts stop flushing mid-frame.
+    capacity: usize,
     index_count: i32,
     active_texture_id: Option<u32>,
+    /// Part of the bound texture UVs are mapped into, see
+    /// [`Texture::region`]
+    region: [f32; 4],
+    /// Flush requests since the frame started, including empty ones
+    pub flushes: u32,
 }
 
 impl Batcher {
@@ -27,14 +49,6 @@ impl Batcher {
         ctx.gl
             .bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&vbo));
 
-        // Allocate empty buffer
-        let size = (MAX_QUADS * VERTICES_PER_QUAD * FLOATS_PER_VERTEX * 4) as i32;
-        ctx.gl.buffer_data_with_i32(
-            WebGl2RenderingContext::ARRAY_BUFFER,
-            size,
-            WebGl2RenderingContext::DYNAMIC_DRAW,
-        );
-
         let ebo = ctx.gl.create_buffer().ok_or("failed to create EBO")?;
         ctx.gl
             .bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, Some(&ebo));
@@ -75,36 +89,59 @@ impl Batcher {
         );
         ctx.gl.enable_vertex_attrib_array(2);
 
-        // Pre-fill indices
-        let mut indices = Vec::with_capacity(MAX_QUADS * INDICES_PER_QUAD);
-        for i in 0..MAX_QUADS {
-            let base = (i * 4) as u16;
-            indices.push(base + 0);
-            indices.push(base + 1);
-            indices.push(base + 2);
-            indices.push(base + 0);
-            indices.push(base + 2);
-            indices.push(base + 3);
-        }
+        ctx.gl.bind_vertex_array(None);
 
+        let mut batcher = Self {
+            vertices: Vec::new(),
+            vbo,
+            ebo,
+            vao,
+            capacity: 0,
+            index_count: 0,
+            active_texture_id: None,
+            region: FULL_REGION,
+            flushes: 0,
+        };
+        batcher.grow(ctx, INITIAL_QUADS);
+        Ok(batcher)
+    }
+
+    /// Resizes the index buffer and vertex storage to hold `quads` quads.
+    /// The vertex buffer itself is reallocated on every flush.
+    fn grow(&mut self, ctx: &GlContext, quads: usize) {
+        self.capacity = quads.min(MAX_QUADS);
+        self.vertices.reserve_exact(
+            self.capacity * VERTICES_PER_QUAD * FLOATS_PER_VERTEX - self.vertices.len(),
+        );
+
+        let indices = quad_indices(self.capacity);
+        // The element buffer binding is part of the VAO state
+        ctx.gl.bind_vertex_array(Some(&self.vao));
+        ctx.gl.bind_buffer(
+            WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+            Some(&self.ebo),
+        );
         let indices_view = unsafe { js_sys::Uint16Array::view(&indices) };
         ctx.gl.buffer_data_with_array_buffer_view(
             WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
             &indices_view,
             WebGl2RenderingContext::STATIC_DRAW,
         );
-
         ctx.gl.bind_vertex_array(None);
+    }
 
-        Ok(Self {
-            vertices: Vec::with_capacity(MAX_QUADS * VERTICES_PER_QUAD * FLOATS_PER_VERTEX),
-            _indices: indices,
-            vbo,
-            _ebo: ebo,
-            vao,
-            index_count: 0,
-            active_texture_id: None,
-        })
+    /// Makes room for one more quad, growing the buffers before resorting
+    /// to a flush.
+    fn reserve_quad(&mut self, ctx: &GlContext) {
+        let quads = self.index_count as usize / INDICES_PER_QUAD;
+        if quads < self.capacity {
+            return;
+        }
+        if self.capacity < MAX_QUADS {
+            self.grow(ctx, self.capacity * 2);
+        } else {
+            self.flush(ctx);
+        }
     }
 
     pub fn set_texture(&mut self, ctx: &GlContext, texture: &Texture) {
@@ -114,6 +151,7 @@ impl Batcher {
             ctx.gl
                 .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture.texture));
         }
+        self.region = texture.region.unwrap_or(FULL_REGION);
     }
 
     pub fn invalidate_texture_cache(&mut self) {
@@ -133,9 +171,7 @@ impl Batcher {
         a: f32,
         model: &[f32; 16],
     ) {
-        if self.vertices.len() + VERTICES_PER_QUAD * FLOATS_PER_VERTEX > self.vertices.capacity() {
-            self.flush(ctx);
-        }
+        self.reserve_quad(ctx);
 
         let coords = [
             (x, y),         // 0
@@ -171,10 +207,10 @@ impl Batcher {
         a: f32,
         model: &[f32; 16],
     ) {
-        if self.vertices.len() + VERTICES_PER_QUAD * FLOATS_PER_VERTEX > self.vertices.capacity() {
-            self.flush(ctx);
-        }
+        self.reserve_quad(ctx);
 
+        let [ru, rv, rw, rh] = self.region;
+        let (u, v, uw, uh) = (ru + u * rw, rv + v * rh, uw * rw, uh * rh);
         let coords = [
             (x, y, u, v + uh),          // 0: Bottom-Left
             (x + w, y, u + uw, v + uh), // 1: Bottom-Right
@@ -194,6 +230,7 @@ impl Batcher {
     }
 
     pub fn flush(&mut self, ctx: &GlContext) {
+        self.flushes += 1;
         if self.index_count == 0 {
             return;
         }
@@ -202,6 +239,14 @@ impl Batcher {
         ctx.gl
             .bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.vbo));
 
+        // Orphan the previous storage so the driver can hand out a fresh
+        // allocation instead of waiting for draws still reading the old one
+        let size = (self.capacity * VERTICES_PER_QUAD * FLOATS_PER_VERTEX * 4) as i32;
+        ctx.gl.buffer_data_with_i32(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            size,
+            WebGl2RenderingContext::STREAM_DRAW,
+        );
         let vertices_view = unsafe { js_sys::Float32Array::view(&self.vertices) };
         ctx.gl.buffer_sub_data_with_i32_and_array_buffer_view(
             WebGl2RenderingContext::ARRAY_BUFFER,
@@ -209,6 +254,7 @@ impl Batcher {
             &vertices_view,
         );
 
+        ctx.count_draw();
         ctx.gl.draw_elements_with_i32(
             WebGl2RenderingContext::TRIANGLES,
             self.index_count,
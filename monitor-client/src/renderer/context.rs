@@ -1,14 +1,23 @@
+use std::cell::Cell;
 use wasm_bindgen::prelude::*;
-use web_sys::{HtmlCanvasElement, WebGl2RenderingContext, WebGlProgram, WebGlShader};
+use web_sys::{
+    HtmlCanvasElement, WebGl2RenderingContext, WebGlFramebuffer, WebGlProgram, WebGlShader,
+};
 
 pub struct GlContext {
     pub gl: WebGl2RenderingContext,
+    pub canvas: HtmlCanvasElement,
     pub width: u32,
     pub height: u32,
+    /// Framebuffer that stands in for the canvas, `None` for the canvas
+    /// itself. Set while a post-processing pass renders the scene offscreen.
+    pub screen_framebuffer: Option<WebGlFramebuffer>,
+    /// Draw calls issued since the frame started
+    pub draw_calls: Cell<u32>,
 }
 
 impl GlContext {
-    pub fn new(canvas_id: &str) -> Result<Self, JsValue> {
+    pub fn new(canvas_id: &str, antialias: bool) -> Result<Self, JsValue> {
         let window = web_sys::window().ok_or("no global `window` exists")?;
         let document = window
             .document()
@@ -18,8 +27,10 @@ impl GlContext {
             .ok_or(format!("canvas element '{}' not found", canvas_id))?
             .dyn_into::<HtmlCanvasElement>()?;
 
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"antialias".into(), &antialias.into())?;
         let gl = canvas
-            .get_context("webgl2")?
+            .get_context_with_context_options("webgl2", &options)?
             .ok_or("WebGL 2.0 not supported")?
             .dyn_into::<WebGl2RenderingContext>()?;
 
@@ -34,15 +45,35 @@ impl GlContext {
         let height = canvas.height();
         gl.viewport(0, 0, width as i32, height as i32);
 
-        Ok(Self { gl, width, height })
+        Ok(Self {
+            gl,
+            canvas,
+            width,
+            height,
+            screen_framebuffer: None,
+            draw_calls: Cell::new(0),
+        })
     }
 
+    /// Sets the drawing buffer size in physical pixels. The canvas backing
+    /// store is only touched when it changes, as that clears it.
     pub fn resize(&mut self, width: u32, height: u32) {
+        if self.canvas.width() != width {
+            self.canvas.set_width(width);
+        }
+        if self.canvas.height() != height {
+            self.canvas.set_height(height);
+        }
         self.width = width;
         self.height = height;
         self.gl.viewport(0, 0, width as i32, height as i32);
     }
 
+    /// Records a draw call for the stats overlay.
+    pub fn count_draw(&self) {
+        self.draw_calls.set(self.draw_calls.get() + 1);
+    }
+
     pub fn clear(&self, r: f32, g: f32, b: f32, a: f32) {
         self.gl.clear_color(r, g, b, a);
         self.gl.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
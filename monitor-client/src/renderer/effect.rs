@@ -0,0 +1,384 @@
+use super::context::GlContext;
+use super::texture::Texture;
+use monitor_common::core::{Effect, EffectShader, Uniform};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlVertexArrayObject};
+
+// Effect shaders follow Phira's conventions (GLSL ES 1.00, `uv`,
+// `screenTexture`, `screenSize`, `UVScale`, `time`) so chart-provided
+// shaders written for Phira compile unchanged. Uniform defaults are given as
+// `// %a, b, ...%` comments after the declaration.
+
+const EFFECT_VS: &str = r#"attribute vec2 position;
+varying lowp vec2 uv;
+
+void main() {
+    uv = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+"#;
+
+const EFFECT_PRELUDE: &str = r#"precision mediump float;
+varying lowp vec2 uv;
+uniform vec2 screenSize;
+uniform vec2 UVScale;
+uniform float time;
+uniform sampler2D screenTexture;
+"#;
+
+const CHROMATIC_FS: &str = r#"
+uniform float power; // %0.01%
+
+void main() {
+    vec2 dir = uv - vec2(0.5);
+    vec4 color = texture2D(screenTexture, uv);
+    gl_FragColor = vec4(
+        texture2D(screenTexture, uv + dir * power).r,
+        color.g,
+        texture2D(screenTexture, uv - dir * power).b,
+        color.a);
+}
+"#;
+
+const CIRCLE_BLUR_FS: &str = r#"
+uniform float size; // %10%
+
+void main() {
+    vec2 step = size / screenSize;
+    vec4 sum = vec4(0.0);
+    for (int i = 0; i < 8; i++) {
+        float angle = float(i) * 0.785398;
+        sum += texture2D(screenTexture, uv + vec2(cos(angle), sin(angle)) * step);
+        sum += texture2D(screenTexture, uv + vec2(cos(angle), sin(angle)) * step * 0.5);
+    }
+    gl_FragColor = sum / 16.0;
+}
+"#;
+
+const FISHEYE_FS: &str = r#"
+uniform float power; // %-0.1%
+
+void main() {
+    vec2 p = uv - vec2(0.5);
+    float d = length(p);
+    vec2 q = vec2(0.5) + p * (1.0 + power * d * d * 4.0);
+    gl_FragColor = texture2D(screenTexture, clamp(q, 0.0, 1.0));
+}
+"#;
+
+const GLITCH_FS: &str = r#"
+uniform float power; // %0.3%
+uniform float rate; // %0.6%
+uniform float speed; // %5.0%
+uniform float blockCount; // %30.5%
+uniform float colorRate; // %0.01%
+
+float rand(vec2 co) {
+    return fract(sin(dot(co, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main() {
+    float t = floor(time * speed * 60.0);
+    float block = floor(uv.y * blockCount);
+    float n = rand(vec2(block, t));
+    float shift = n < rate ? (rand(vec2(t, block)) - 0.5) * power * 0.2 : 0.0;
+    vec2 p = vec2(fract(uv.x + shift), uv.y);
+    vec4 color = texture2D(screenTexture, p);
+    color.r = texture2D(screenTexture, p + vec2(colorRate, 0.0)).r;
+    color.b = texture2D(screenTexture, p - vec2(colorRate, 0.0)).b;
+    gl_FragColor = color;
+}
+"#;
+
+const GRAYSCALE_FS: &str = r#"
+uniform float factor; // %1.0%
+
+void main() {
+    vec4 color = texture2D(screenTexture, uv);
+    float gray = dot(color.rgb, vec3(0.299, 0.587, 0.114));
+    gl_FragColor = vec4(mix(color.rgb, vec3(gray), factor), color.a);
+}
+"#;
+
+const NOISE_FS: &str = r#"
+uniform float seed; // %81.0%
+uniform float power; // %0.03%
+
+float rand(vec2 co) {
+    return fract(sin(dot(co + seed, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main() {
+    vec4 color = texture2D(screenTexture, uv);
+    float n = rand(uv + fract(time)) - 0.5;
+    gl_FragColor = vec4(color.rgb + n * power * 2.0, color.a);
+}
+"#;
+
+const PIXEL_FS: &str = r#"
+uniform float size; // %10.0%
+
+void main() {
+    vec2 block = max(size, 1.0) / screenSize;
+    vec2 p = (floor(uv / block) + 0.5) * block;
+    gl_FragColor = texture2D(screenTexture, p);
+}
+"#;
+
+const RADIAL_BLUR_FS: &str = r#"
+uniform float centerX; // %0.5%
+uniform float centerY; // %0.5%
+uniform float power; // %0.01%
+uniform float sampleCount; // %3%
+
+void main() {
+    vec2 dir = uv - vec2(centerX, centerY);
+    vec4 sum = vec4(0.0);
+    float count = 0.0;
+    for (int i = 0; i < 64; i++) {
+        if (float(i) >= sampleCount) break;
+        sum += texture2D(screenTexture, uv - dir * power * float(i));
+        count += 1.0;
+    }
+    gl_FragColor = sum / max(count, 1.0);
+}
+"#;
+
+const SHOCKWAVE_FS: &str = r#"
+uniform float progress; // %0.2%
+uniform float centerX; // %0.5%
+uniform float centerY; // %0.5%
+uniform float width; // %0.1%
+uniform float distortion; // %0.8%
+uniform float expand; // %10%
+
+void main() {
+    vec2 center = vec2(centerX, centerY);
+    vec2 aspect = vec2(screenSize.x / screenSize.y, 1.0);
+    float d = length((uv - center) * aspect);
+    vec2 p = uv;
+    if (d > progress - width && d < progress + width) {
+        float diff = (d - progress) / width;
+        float wave = 1.0 - pow(abs(diff), distortion);
+        p += normalize(uv - center) * diff * wave * width / expand;
+    }
+    gl_FragColor = texture2D(screenTexture, p);
+}
+"#;
+
+const VIGNETTE_FS: &str = r#"
+uniform vec4 color; // %0, 0, 0, 1%
+uniform float extend; // %0.25%
+uniform float radius; // %15%
+
+void main() {
+    vec2 p = uv * (1.0 - uv.yx);
+    float v = clamp(pow(p.x * p.y * radius, extend), 0.0, 1.0);
+    gl_FragColor = mix(color, texture2D(screenTexture, uv), v);
+}
+"#;
+
+fn builtin_shader(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "chromatic" => CHROMATIC_FS,
+        "circleBlur" => CIRCLE_BLUR_FS,
+        "fisheye" => FISHEYE_FS,
+        "glitch" => GLITCH_FS,
+        "grayscale" => GRAYSCALE_FS,
+        "noise" => NOISE_FS,
+        "pixel" => PIXEL_FS,
+        "radialBlur" => RADIAL_BLUR_FS,
+        "shockwave" => SHOCKWAVE_FS,
+        "vignette" => VIGNETTE_FS,
+        _ => return None,
+    })
+}
+
+/// Uniform defaults from `uniform <type> <name>; // %a, b%` declarations
+fn parse_defaults(source: &str) -> Vec<(String, Vec<f32>)> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let (decl, comment) = line.trim().split_once("//")?;
+            let name = decl
+                .strip_prefix("uniform")?
+                .trim()
+                .trim_end_matches(';')
+                .split_whitespace()
+                .last()?;
+            let values = comment.trim().strip_prefix('%')?.strip_suffix('%')?;
+            let values = values
+                .split(',')
+                .map(|v| v.trim().parse().ok())
+                .collect::<Option<Vec<f32>>>()?;
+            Some((name.to_string(), values))
+        })
+        .collect()
+}
+
+struct EffectProgram {
+    program: WebGlProgram,
+    defaults: Vec<(String, Vec<f32>)>,
+}
+
+/// Compiles and runs chart shader effects as full-screen passes.
+pub struct EffectRenderer {
+    vao: WebGlVertexArrayObject,
+    buffer: WebGlBuffer,
+    /// Keyed by shader name or custom shader path; `None` marks shaders that
+    /// failed to compile so they are not retried every frame
+    programs: HashMap<String, Option<EffectProgram>>,
+}
+
+impl EffectRenderer {
+    pub fn new(ctx: &GlContext) -> Result<Self, JsValue> {
+        let gl = &ctx.gl;
+        let vao = gl
+            .create_vertex_array()
+            .ok_or("failed to create vertex array")?;
+        let buffer = gl.create_buffer().ok_or("failed to create buffer")?;
+        gl.bind_vertex_array(Some(&vao));
+        gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+        // Single triangle covering the whole viewport
+        let vertices: [f32; 6] = [-1.0, -1.0, 3.0, -1.0, -1.0, 3.0];
+        unsafe {
+            let view = js_sys::Float32Array::view(&vertices);
+            gl.buffer_data_with_array_buffer_view(
+                WebGl2RenderingContext::ARRAY_BUFFER,
+                &view,
+                WebGl2RenderingContext::STATIC_DRAW,
+            );
+        }
+        gl.bind_vertex_array(None);
+        Ok(Self {
+            vao,
+            buffer,
+            programs: HashMap::new(),
+        })
+    }
+
+    fn compile(ctx: &GlContext, fragment: &str) -> Result<EffectProgram, String> {
+        let vert = ctx.create_shader(WebGl2RenderingContext::VERTEX_SHADER, EFFECT_VS)?;
+        let frag = ctx.create_shader(WebGl2RenderingContext::FRAGMENT_SHADER, fragment)?;
+        let program = ctx.create_program(&vert, &frag)?;
+        Ok(EffectProgram {
+            program,
+            defaults: parse_defaults(fragment),
+        })
+    }
+
+    fn program(&mut self, ctx: &GlContext, shader: &EffectShader) -> Option<&EffectProgram> {
+        let key = match shader {
+            EffectShader::Builtin(name) => name,
+            EffectShader::Custom { path, .. } => path,
+        };
+        self.programs
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let source = match shader {
+                    EffectShader::Builtin(name) => match builtin_shader(name) {
+                        Some(body) => format!("{}{}", EFFECT_PRELUDE, body),
+                        None => {
+                            web_sys::console::warn_1(
+                                &format!("Unknown effect shader '{}'", name).into(),
+                            );
+                            return None;
+                        }
+                    },
+                    EffectShader::Custom { source, .. } => source.clone(),
+                };
+                Self::compile(ctx, &source)
+                    .map_err(|e| {
+                        web_sys::console::warn_1(
+                            &format!("Failed to compile effect shader '{}': {}", key, e).into(),
+                        );
+                    })
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// Compiles the shader if needed, returning whether it is usable.
+    pub fn prepare(&mut self, ctx: &GlContext, shader: &EffectShader) -> bool {
+        self.program(ctx, shader).is_some()
+    }
+
+    /// Drops compiled programs, e.g. when another chart (with possibly
+    /// different custom shaders under the same paths) is loaded.
+    pub fn clear(&mut self, ctx: &GlContext) {
+        for program in self.programs.drain().filter_map(|(_, p)| p) {
+            ctx.gl.delete_program(Some(&program.program));
+        }
+    }
+
+    /// Draws `effect` applied to `source` into the currently bound framebuffer.
+    pub fn draw(&mut self, ctx: &GlContext, effect: &Effect, source: &Texture, time: f32) {
+        let (vao, buffer) = (self.vao.clone(), self.buffer.clone());
+        let Some(program) = self.program(ctx, &effect.shader) else {
+            return;
+        };
+        let gl = &ctx.gl;
+        let program_ref = &program.program;
+        gl.use_program(Some(program_ref));
+        let location = |name: &str| gl.get_uniform_location(program_ref, name);
+
+        for (name, values) in &program.defaults {
+            let loc = location(name);
+            match values.as_slice() {
+                [x] => gl.uniform1f(loc.as_ref(), *x),
+                [x, y] => gl.uniform2f(loc.as_ref(), *x, *y),
+                [x, y, z] => gl.uniform3f(loc.as_ref(), *x, *y, *z),
+                [x, y, z, w] => gl.uniform4f(loc.as_ref(), *x, *y, *z, *w),
+                _ => {}
+            }
+        }
+        for (name, uniform) in &effect.uniforms {
+            let loc = location(name);
+            match uniform {
+                Uniform::Float(anim) => gl.uniform1f(loc.as_ref(), anim.now()),
+                Uniform::Vec2(anim) => {
+                    let v = anim.now();
+                    gl.uniform2f(loc.as_ref(), v.x, v.y);
+                }
+                Uniform::Color(anim) => {
+                    let c = anim.now();
+                    gl.uniform4f(loc.as_ref(), c.r, c.g, c.b, c.a);
+                }
+            }
+        }
+        gl.uniform2f(
+            location("screenSize").as_ref(),
+            source.width as f32,
+            source.height as f32,
+        );
+        gl.uniform2f(location("UVScale").as_ref(), 1.0, 1.0);
+        gl.uniform1f(location("time").as_ref(), time);
+        gl.uniform1i(location("screenTexture").as_ref(), 0);
+
+        gl.active_texture(WebGl2RenderingContext::TEXTURE0);
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&source.texture));
+        // Attribute locations differ between programs, so point the
+        // shared triangle at whichever one `position` got
+        gl.bind_vertex_array(Some(&vao));
+        let position = gl.get_attrib_location(program_ref, "position");
+        if position >= 0 {
+            gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+            gl.enable_vertex_attrib_array(position as u32);
+            gl.vertex_attrib_pointer_with_i32(
+                position as u32,
+                2,
+                WebGl2RenderingContext::FLOAT,
+                false,
+                0,
+                0,
+            );
+        }
+        gl.disable(WebGl2RenderingContext::BLEND);
+        ctx.count_draw();
+        gl.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        gl.enable(WebGl2RenderingContext::BLEND);
+        gl.bind_vertex_array(None);
+        gl.use_program(None);
+    }
+}
@@ -0,0 +1,111 @@
+use super::context::GlContext;
+use super::texture::Texture;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, WebGl2RenderingContext};
+
+/// A line of text rasterized with the browser's 2D canvas into a texture.
+/// The texture is only re-uploaded when the text or font changes.
+pub struct Label {
+    canvas: HtmlCanvasElement,
+    ctx2d: CanvasRenderingContext2d,
+    font: String,
+    text: Option<String>,
+    pub texture: Texture,
+}
+
+impl Label {
+    pub fn new(ctx: &GlContext) -> Result<Self, JsValue> {
+        let document = web_sys::window()
+            .and_then(|w| w.document())
+            .ok_or("no document")?;
+        let canvas = document
+            .create_element("canvas")?
+            .dyn_into::<HtmlCanvasElement>()?;
+        let ctx2d = canvas
+            .get_context("2d")?
+            .ok_or("2D canvas not supported")?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        Ok(Self {
+            canvas,
+            ctx2d,
+            font: String::new(),
+            text: None,
+            texture: Texture::new(ctx)?,
+        })
+    }
+
+    /// Sets the CSS font used for rasterizing, e.g. `"bold 32px sans-serif"`.
+    pub fn set_font(&mut self, font: &str) {
+        if self.font != font {
+            self.font = font.to_string();
+            self.text = None;
+        }
+    }
+
+    /// Updates the text, returning whether the texture was re-uploaded.
+    /// Binds TEXTURE_2D when it does, so callers must flush/invalidate any
+    /// batched texture state first.
+    pub fn set_text(&mut self, ctx: &GlContext, text: &str) -> Result<bool, JsValue> {
+        if self.text.as_deref() == Some(text) {
+            return Ok(false);
+        }
+
+        self.ctx2d.set_font(&self.font);
+        let width = self.ctx2d.measure_text(text)?.width().ceil().max(1.0) as u32 + 2;
+        let height = font_px(&self.font).max(1.0).ceil() as u32 * 5 / 4 + 2;
+        // Resizing resets the 2D context state
+        self.canvas.set_width(width);
+        self.canvas.set_height(height);
+        self.ctx2d.set_font(&self.font);
+        self.ctx2d.set_text_baseline("middle");
+        self.ctx2d.set_fill_style_str("white");
+        self.ctx2d.fill_text(text, 1.0, height as f64 / 2.0)?;
+
+        let gl = &ctx.gl;
+        gl.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&self.texture.texture),
+        );
+        gl.tex_image_2d_with_u32_and_u32_and_html_canvas_element(
+            WebGl2RenderingContext::TEXTURE_2D,
+            0,
+            WebGl2RenderingContext::RGBA as i32,
+            WebGl2RenderingContext::RGBA,
+            WebGl2RenderingContext::UNSIGNED_BYTE,
+            &self.canvas,
+        )?;
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+            WebGl2RenderingContext::LINEAR as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+            WebGl2RenderingContext::LINEAR as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_WRAP_S,
+            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameteri(
+            WebGl2RenderingContext::TEXTURE_2D,
+            WebGl2RenderingContext::TEXTURE_WRAP_T,
+            WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+        );
+
+        self.texture.width = width;
+        self.texture.height = height;
+        self.text = Some(text.to_string());
+        Ok(true)
+    }
+}
+
+// Pixel size out of a CSS font shorthand, e.g. "bold 32px sans-serif" -> 32
+fn font_px(font: &str) -> f32 {
+    font.split_whitespace()
+        .find_map(|part| part.strip_suffix("px")?.parse().ok())
+        .unwrap_or(16.0)
+}
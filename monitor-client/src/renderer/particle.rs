@@ -4,6 +4,9 @@ use monitor_common::core::colors;
 use nalgebra::Vector2;
 use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlVertexArrayObject};
 
+/// Particle budget of an emitter unless configured otherwise
+pub const DEFAULT_MAX_PARTICLES: usize = 12000;
+
 #[derive(Clone, Copy, Debug)]
 pub enum EmissionShape {
     Point,
@@ -46,6 +49,35 @@ impl Default for ColorCurve {
     }
 }
 
+/// Piecewise linear multiplier over a particle's normalized lifetime, as in
+/// macroquad-particles. `points` are `(t, value)` pairs sorted by `t`;
+/// values outside the first/last point are clamped.
+#[derive(Clone, Debug, Default)]
+pub struct Curve {
+    pub points: Vec<(f32, f32)>,
+}
+
+impl Curve {
+    pub fn get(&self, t: f32) -> f32 {
+        let (Some(&first), Some(&last)) = (self.points.first(), self.points.last()) else {
+            return 1.0;
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        let i = self.points.partition_point(|p| p.0 <= t);
+        let (t0, v0) = self.points[i - 1];
+        let (t1, v1) = self.points[i];
+        if t1 - t0 <= f32::EPSILON {
+            return v1;
+        }
+        v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AtlasConfig {
     pub n: u16,
@@ -92,6 +124,10 @@ pub struct EmitterConfig {
     pub angular_damping: f32,
     pub size: f32,
     pub size_randomness: f32,
+    /// Multiplies `size` over the particle's lifetime
+    pub size_curve: Option<Curve>,
+    /// Multiplies the particle's speed over its lifetime
+    pub velocity_curve: Option<Curve>,
     pub texture: Option<Texture>,
     pub atlas: Option<AtlasConfig>,
     pub base_color: Color,
@@ -100,6 +136,8 @@ pub struct EmitterConfig {
     pub emitting: bool,
     pub one_shot: bool,
     pub blend_mode: BlendMode,
+    /// Live particle cap, also sizes the GPU instance buffer
+    pub max_particles: usize,
 }
 
 impl Default for EmitterConfig {
@@ -124,6 +162,8 @@ impl Default for EmitterConfig {
             angular_damping: 0.0,
             size: 10.0,
             size_randomness: 0.0,
+            size_curve: None,
+            velocity_curve: None,
             texture: None,
             atlas: None,
             base_color: colors::WHITE,
@@ -132,6 +172,7 @@ impl Default for EmitterConfig {
             emitting: true,
             one_shot: false,
             blend_mode: BlendMode::Alpha,
+            max_particles: DEFAULT_MAX_PARTICLES,
         }
     }
 }
@@ -159,6 +200,8 @@ pub struct Emitter {
     program: WebGlProgram,
     vao: WebGlVertexArrayObject,
     instance_buffer: WebGlBuffer,
+    /// Static quad vertices and indices, kept only to be deleted
+    static_buffers: [WebGlBuffer; 2],
 
     // Gpu data buffer (f32)
     gpu_data: Vec<f32>,
@@ -230,7 +273,7 @@ impl Emitter {
 
     pub fn new(ctx: &GlContext, config: EmitterConfig) -> Result<Self, String> {
         let gl = &ctx.gl;
-        let max_particles = 12000;
+        let max_particles = config.max_particles.max(1);
 
         // Compile Shader
         let vert = ctx.create_shader(WebGl2RenderingContext::VERTEX_SHADER, Self::SHADER_VS)?;
@@ -323,6 +366,7 @@ impl Emitter {
             program,
             vao,
             instance_buffer,
+            static_buffers: [quad_buffer, index_buffer],
             gpu_data: Vec::with_capacity(max_particles * 16),
             cpu_particles: Vec::with_capacity(max_particles),
             config,
@@ -334,6 +378,26 @@ impl Emitter {
         })
     }
 
+    /// Number of live particles
+    pub fn alive(&self) -> usize {
+        self.cpu_particles.len()
+    }
+
+    /// Removes all live particles
+    pub fn clear(&mut self) {
+        self.cpu_particles.clear();
+    }
+
+    pub fn delete(self, ctx: &GlContext) {
+        let gl = &ctx.gl;
+        gl.delete_vertex_array(Some(&self.vao));
+        gl.delete_buffer(Some(&self.instance_buffer));
+        for buffer in &self.static_buffers {
+            gl.delete_buffer(Some(buffer));
+        }
+        gl.delete_program(Some(&self.program));
+    }
+
     pub fn draw(
         &mut self,
         ctx: &GlContext,
@@ -415,6 +479,7 @@ impl Emitter {
 
         // Draw
         if !self.cpu_particles.is_empty() {
+            ctx.count_draw();
             gl.draw_elements_instanced_with_i32(
                 WebGl2RenderingContext::TRIANGLES,
                 6,
@@ -533,6 +598,12 @@ impl Emitter {
         self.gpu_data.clear();
 
         let config = &self.config;
+        // Frames are picked within the texture's sprite region if it has one
+        let [ru, rv, rw, rh] = config
+            .texture
+            .as_ref()
+            .and_then(|tex| tex.region)
+            .unwrap_or([0.0, 0.0, 1.0, 1.0]);
 
         let mut i = 0;
         while i < self.cpu_particles.len() {
@@ -542,7 +613,11 @@ impl Emitter {
             p.angular_velocity += p.angular_velocity * config.angular_accel * dt;
             p.angular_velocity *= 1.0 - config.angular_damping;
 
-            p.offset += p.velocity * dt;
+            let speed = config
+                .velocity_curve
+                .as_ref()
+                .map_or(1.0, |c| c.get(p.lived / p.lifetime));
+            p.offset += p.velocity * speed * dt;
             p.initial_rotation += p.angular_velocity * dt;
 
             p.lived += dt;
@@ -577,8 +652,7 @@ impl Emitter {
             };
 
             // Pos: x, y, rotation, size
-            // size is initial_size * curve(t) (ignoring curve for now)
-            let size = p.initial_size;
+            let size = p.initial_size * config.size_curve.as_ref().map_or(1.0, |c| c.get(t));
 
             // GPU Data Push
             // 3: inst_pos
@@ -606,15 +680,15 @@ impl Emitter {
 
                 let v_offset = y / atlas.m as f32;
 
-                self.gpu_data.push(x / atlas.n as f32); // inst_uv.x (u_offset)
-                self.gpu_data.push(v_offset); // inst_uv.y (v_offset)
-                self.gpu_data.push(1.0 / atlas.n as f32); // inst_uv.z (u_scale)
-                self.gpu_data.push(1.0 / atlas.m as f32); // inst_uv.w (v_scale)
+                self.gpu_data.push(ru + x / atlas.n as f32 * rw); // inst_uv.x (u_offset)
+                self.gpu_data.push(rv + v_offset * rh); // inst_uv.y (v_offset)
+                self.gpu_data.push(rw / atlas.n as f32); // inst_uv.z (u_scale)
+                self.gpu_data.push(rh / atlas.m as f32); // inst_uv.w (v_scale)
             } else {
-                self.gpu_data.push(0.0);
-                self.gpu_data.push(0.0);
-                self.gpu_data.push(1.0);
-                self.gpu_data.push(1.0);
+                self.gpu_data.push(ru);
+                self.gpu_data.push(rv);
+                self.gpu_data.push(rw);
+                self.gpu_data.push(rh);
             }
 
             // 5: inst_data (index, lifetime_progress, 0, 0)
@@ -0,0 +1,319 @@
+use super::context::GlContext;
+use super::target::RenderTarget;
+use super::texture::Texture;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer, WebGlProgram, WebGlRenderbuffer};
+
+/// Antialiasing configuration, passed from JS as a plain object.
+#[derive(Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RenderSettings {
+    /// Browser-side MSAA on the canvas itself. Only honored when the
+    /// context is created.
+    pub antialias: bool,
+    /// Sample count of the offscreen scene buffer, 0 disables it
+    pub msaa_samples: u32,
+    /// Run an FXAA pass when presenting the frame
+    pub fxaa: bool,
+    /// Drawing buffer size relative to the physical (DPR-scaled) canvas
+    /// size, clamped to `RENDER_SCALE_RANGE`
+    pub render_scale: f32,
+}
+
+pub const RENDER_SCALE_RANGE: (f32, f32) = (0.5, 2.0);
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            antialias: true,
+            msaa_samples: 0,
+            fxaa: false,
+            render_scale: 1.0,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Whether frames have to go through the offscreen pipeline
+    pub fn needs_post(&self) -> bool {
+        self.msaa_samples > 0 || self.fxaa
+    }
+
+    /// Drawing buffer size for a canvas of `css_width` x `css_height` CSS
+    /// pixels at the given device pixel ratio.
+    pub fn buffer_size(&self, css_width: u32, css_height: u32, dpr: f64) -> (u32, u32) {
+        let scale = dpr * self.render_scale as f64;
+        let size = |css: u32| ((css as f64 * scale).round() as u32).max(1);
+        (size(css_width), size(css_height))
+    }
+}
+
+const PRESENT_VS: &str = r#"#version 300 es
+out vec2 v_uv;
+
+void main() {
+    // Full-screen triangle, no vertex buffer needed
+    vec2 pos = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+    v_uv = pos;
+    gl_Position = vec4(pos * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+const PRESENT_FS: &str = r#"#version 300 es
+precision mediump float;
+
+in vec2 v_uv;
+
+uniform sampler2D u_texture;
+uniform vec2 u_texel;
+uniform bool u_fxaa;
+
+out vec4 out_color;
+
+const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+const float FXAA_SPAN_MAX = 8.0;
+
+void main() {
+    vec4 color = texture(u_texture, v_uv);
+    if (!u_fxaa) {
+        out_color = color;
+        return;
+    }
+
+    vec3 luma = vec3(0.299, 0.587, 0.114);
+    float lumaNW = dot(texture(u_texture, v_uv + vec2(-1.0, -1.0) * u_texel).rgb, luma);
+    float lumaNE = dot(texture(u_texture, v_uv + vec2(1.0, -1.0) * u_texel).rgb, luma);
+    float lumaSW = dot(texture(u_texture, v_uv + vec2(-1.0, 1.0) * u_texel).rgb, luma);
+    float lumaSE = dot(texture(u_texture, v_uv + vec2(1.0, 1.0) * u_texel).rgb, luma);
+    float lumaM = dot(color.rgb, luma);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 dir = vec2(
+        -((lumaNW + lumaNE) - (lumaSW + lumaSE)),
+        (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+    dir = clamp(dir * rcpDirMin, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * u_texel;
+
+    vec3 rgbA = 0.5 * (
+        texture(u_texture, v_uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        texture(u_texture, v_uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (
+        texture(u_texture, v_uv - dir * 0.5).rgb +
+        texture(u_texture, v_uv + dir * 0.5).rgb);
+    float lumaB = dot(rgbB, luma);
+    out_color = vec4((lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB, color.a);
+}
+"#;
+
+/// Multisampled color buffer that scene draws go to before being resolved.
+struct MsaaBuffer {
+    framebuffer: WebGlFramebuffer,
+    renderbuffer: WebGlRenderbuffer,
+}
+
+/// Offscreen scene rendering with optional MSAA resolve, ping-pong targets
+/// for effect passes and an FXAA present pass.
+pub struct PostProcess {
+    program: WebGlProgram,
+    samples: u32,
+    pub fxaa: bool,
+    msaa: Option<MsaaBuffer>,
+    targets: [RenderTarget; 2],
+    /// Target holding (or receiving) the scene
+    current: usize,
+    /// Whether the scene still sits in the multisampled buffer
+    in_msaa: bool,
+}
+
+impl PostProcess {
+    pub fn new(ctx: &GlContext, settings: &RenderSettings) -> Result<Self, JsValue> {
+        let vert = ctx.create_shader(WebGl2RenderingContext::VERTEX_SHADER, PRESENT_VS)?;
+        let frag = ctx.create_shader(WebGl2RenderingContext::FRAGMENT_SHADER, PRESENT_FS)?;
+        let program = ctx.create_program(&vert, &frag)?;
+
+        let max_samples = ctx
+            .gl
+            .get_parameter(WebGl2RenderingContext::MAX_SAMPLES)?
+            .as_f64()
+            .unwrap_or(0.0) as u32;
+        let samples = settings.msaa_samples.min(max_samples);
+
+        let (width, height) = (ctx.width.max(1), ctx.height.max(1));
+        let mut post = Self {
+            program,
+            samples,
+            fxaa: settings.fxaa,
+            msaa: None,
+            targets: [
+                RenderTarget::new(ctx, width, height)?,
+                RenderTarget::new(ctx, width, height)?,
+            ],
+            current: 0,
+            in_msaa: false,
+        };
+        post.msaa = post.create_msaa(ctx)?;
+        Ok(post)
+    }
+
+    fn create_msaa(&self, ctx: &GlContext) -> Result<Option<MsaaBuffer>, JsValue> {
+        if self.samples == 0 {
+            return Ok(None);
+        }
+        let gl = &ctx.gl;
+        let renderbuffer = gl
+            .create_renderbuffer()
+            .ok_or("failed to create renderbuffer")?;
+        gl.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, Some(&renderbuffer));
+        gl.renderbuffer_storage_multisample(
+            WebGl2RenderingContext::RENDERBUFFER,
+            self.samples as i32,
+            WebGl2RenderingContext::RGBA8,
+            self.targets[0].texture.width as i32,
+            self.targets[0].texture.height as i32,
+        );
+        let framebuffer = gl
+            .create_framebuffer()
+            .ok_or("failed to create framebuffer")?;
+        gl.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+        gl.framebuffer_renderbuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::RENDERBUFFER,
+            Some(&renderbuffer),
+        );
+        gl.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            ctx.screen_framebuffer.as_ref(),
+        );
+        Ok(Some(MsaaBuffer {
+            framebuffer,
+            renderbuffer,
+        }))
+    }
+
+    pub fn resize(&mut self, ctx: &GlContext, width: u32, height: u32) -> Result<(), JsValue> {
+        let size = &self.targets[0].texture;
+        if size.width == width && size.height == height {
+            return Ok(());
+        }
+        for target in &mut self.targets {
+            let new = RenderTarget::new(ctx, width.max(1), height.max(1))?;
+            std::mem::replace(target, new).delete(ctx);
+        }
+        if let Some(msaa) = self.msaa.take() {
+            ctx.gl.delete_framebuffer(Some(&msaa.framebuffer));
+            ctx.gl.delete_renderbuffer(Some(&msaa.renderbuffer));
+        }
+        self.msaa = self.create_msaa(ctx)?;
+        Ok(())
+    }
+
+    /// Points drawing (including `RenderTarget::unbind`) at `framebuffer`.
+    fn redirect(ctx: &mut GlContext, framebuffer: Option<&WebGlFramebuffer>) {
+        ctx.screen_framebuffer = framebuffer.cloned();
+        ctx.gl.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            ctx.screen_framebuffer.as_ref(),
+        );
+        ctx.gl.viewport(0, 0, ctx.width as i32, ctx.height as i32);
+    }
+
+    /// Redirects drawing into the scene buffer.
+    pub fn begin(&mut self, ctx: &mut GlContext) {
+        self.current = 0;
+        self.in_msaa = self.msaa.is_some();
+        let framebuffer = match &self.msaa {
+            Some(msaa) => &msaa.framebuffer,
+            None => &self.targets[0].framebuffer,
+        };
+        Self::redirect(ctx, Some(framebuffer));
+    }
+
+    /// Makes sure the scene is in `targets[current]`, where it can be sampled.
+    fn resolve(&mut self, ctx: &GlContext) {
+        let Some(msaa) = self.msaa.as_ref().filter(|_| self.in_msaa) else {
+            return;
+        };
+        let gl = &ctx.gl;
+        let target = &self.targets[self.current];
+        let (w, h) = (target.texture.width as i32, target.texture.height as i32);
+        gl.bind_framebuffer(
+            WebGl2RenderingContext::READ_FRAMEBUFFER,
+            Some(&msaa.framebuffer),
+        );
+        gl.bind_framebuffer(
+            WebGl2RenderingContext::DRAW_FRAMEBUFFER,
+            Some(&target.framebuffer),
+        );
+        gl.blit_framebuffer(
+            0,
+            0,
+            w,
+            h,
+            0,
+            0,
+            w,
+            h,
+            WebGl2RenderingContext::COLOR_BUFFER_BIT,
+            WebGl2RenderingContext::NEAREST,
+        );
+        self.in_msaa = false;
+    }
+
+    /// Runs a full-screen pass over the scene: `pass` gets the current scene
+    /// texture while the other target is bound, which then becomes the scene.
+    /// Drawing after this goes on in the single-sampled target.
+    pub fn apply(&mut self, ctx: &mut GlContext, pass: impl FnOnce(&GlContext, &Texture)) {
+        self.resolve(ctx);
+        let (src, dst) = (self.current, 1 - self.current);
+        Self::redirect(ctx, Some(&self.targets[dst].framebuffer));
+        pass(ctx, &self.targets[src].texture);
+        self.current = dst;
+    }
+
+    /// Resolves the scene buffer and draws it to the canvas.
+    pub fn present(&mut self, ctx: &mut GlContext) {
+        self.resolve(ctx);
+        Self::redirect(ctx, None);
+
+        let gl = &ctx.gl;
+        let texture = &self.targets[self.current].texture;
+        gl.use_program(Some(&self.program));
+        gl.bind_vertex_array(None);
+        gl.active_texture(WebGl2RenderingContext::TEXTURE0);
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture.texture));
+        gl.uniform1i(
+            gl.get_uniform_location(&self.program, "u_texture").as_ref(),
+            0,
+        );
+        gl.uniform2f(
+            gl.get_uniform_location(&self.program, "u_texel").as_ref(),
+            1.0 / texture.width as f32,
+            1.0 / texture.height as f32,
+        );
+        gl.uniform1i(
+            gl.get_uniform_location(&self.program, "u_fxaa").as_ref(),
+            self.fxaa as i32,
+        );
+        gl.disable(WebGl2RenderingContext::BLEND);
+        ctx.count_draw();
+        gl.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        gl.enable(WebGl2RenderingContext::BLEND);
+        gl.use_program(None);
+    }
+
+    pub fn delete(self, ctx: &GlContext) {
+        ctx.gl.delete_program(Some(&self.program));
+        if let Some(msaa) = self.msaa {
+            ctx.gl.delete_framebuffer(Some(&msaa.framebuffer));
+            ctx.gl.delete_renderbuffer(Some(&msaa.renderbuffer));
+        }
+        for target in self.targets {
+            target.delete(ctx);
+        }
+    }
+}
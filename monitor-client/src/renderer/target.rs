@@ -0,0 +1,102 @@
+use super::context::GlContext;
+use super::texture::Texture;
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer};
+
+/// An offscreen color buffer whose contents persist across frames.
+pub struct RenderTarget {
+    pub framebuffer: WebGlFramebuffer,
+    pub texture: Texture,
+}
+
+impl RenderTarget {
+    pub fn new(ctx: &GlContext, width: u32, height: u32) -> Result<Self, JsValue> {
+        let mut texture = Texture::new(ctx)?;
+        texture.width = width;
+        texture.height = height;
+
+        ctx.gl
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture.texture));
+        ctx.gl
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RGBA as i32,
+                width as i32,
+                height as i32,
+                0,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                None,
+            )?;
+        for (param, value) in [
+            (
+                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+        ] {
+            ctx.gl
+                .tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, param, value as i32);
+        }
+
+        let framebuffer = ctx
+            .gl
+            .create_framebuffer()
+            .ok_or("failed to create framebuffer")?;
+        ctx.gl
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+        ctx.gl.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::COLOR_ATTACHMENT0,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&texture.texture),
+            0,
+        );
+        let target = Self {
+            framebuffer,
+            texture,
+        };
+        target.clear(ctx);
+        Ok(target)
+    }
+
+    /// Redirects subsequent draws into this target.
+    pub fn bind(&self, ctx: &GlContext) {
+        ctx.gl
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&self.framebuffer));
+        ctx.gl
+            .viewport(0, 0, self.texture.width as i32, self.texture.height as i32);
+    }
+
+    /// Restores drawing to the canvas (or whatever currently stands in for it).
+    pub fn unbind(ctx: &GlContext) {
+        ctx.gl.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            ctx.screen_framebuffer.as_ref(),
+        );
+        ctx.gl.viewport(0, 0, ctx.width as i32, ctx.height as i32);
+    }
+
+    pub fn clear(&self, ctx: &GlContext) {
+        self.bind(ctx);
+        ctx.clear(0.0, 0.0, 0.0, 0.0);
+        Self::unbind(ctx);
+    }
+
+    pub fn delete(self, ctx: &GlContext) {
+        ctx.gl.delete_framebuffer(Some(&self.framebuffer));
+        ctx.gl.delete_texture(Some(&self.texture.texture));
+    }
+}
@@ -114,17 +114,4 @@ impl SpriteFont {
             }
         }
     }
-
-    pub fn draw_text(
-        &self,
-        renderer: &mut Renderer,
-        text: &str,
-        x: f32,
-        y: f32,
-        size: f32,
-        align: f32,
-        model: &[f32; 16],
-    ) {
-        self.draw_text_color(renderer, text, x, y, size, align, 1.0, 1.0, 1.0, 1.0, model);
-    }
 }
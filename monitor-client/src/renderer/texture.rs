@@ -13,6 +13,9 @@ pub struct Texture {
     pub width: u32,
     pub height: u32,
     pub id: u32,
+    /// `[u, v, width, height]` of a sprite in a texture it shares with
+    /// others, `None` when it has the texture to itself
+    pub region: Option<[f32; 4]>,
 }
 
 impl Texture {
@@ -27,9 +30,22 @@ impl Texture {
             width: 0,
             height: 0,
             id: Self::next_id(),
+            region: None,
         })
     }
 
+    /// The `width` x `height` sprite at `region` of this texture, drawn
+    /// from the same GL texture so sprites of an atlas batch together
+    pub fn sprite(&self, width: u32, height: u32, region: [f32; 4]) -> Self {
+        Self {
+            texture: self.texture.clone(),
+            width,
+            height,
+            id: self.id,
+            region: Some(region),
+        }
+    }
+
     pub fn create_white_pixel(ctx: &GlContext) -> Result<Self, JsValue> {
         Self::create_solid_color(ctx, 1, 1, [255, 255, 255, 255])
     }
@@ -79,6 +95,7 @@ impl Texture {
             width,
             height,
             id: Self::next_id(),
+            region: None,
         })
     }
 
@@ -152,6 +169,7 @@ impl Texture {
             width: image.width(),
             height: image.height(),
             id: Self::next_id(),
+            region: None,
         })
     }
 
@@ -169,4 +187,60 @@ impl Texture {
         web_sys::Url::revoke_object_url(&url)?;
         Ok(texture)
     }
+
+    /// Uploads already decoded pixels, rows top to bottom, sampled like
+    /// textures from [`Texture::load`]
+    pub fn from_rgba(
+        ctx: &GlContext,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+    ) -> Result<Texture, JsValue> {
+        let texture = ctx.gl.create_texture().ok_or("failed to create texture")?;
+        ctx.gl
+            .bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+        ctx.gl
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RGBA as i32,
+                width as i32,
+                height as i32,
+                0,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                Some(rgba),
+            )?;
+
+        for (param, value) in [
+            (
+                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+        ] {
+            ctx.gl
+                .tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, param, value as i32);
+        }
+        ctx.gl.generate_mipmap(WebGl2RenderingContext::TEXTURE_2D);
+
+        Ok(Texture {
+            texture,
+            width,
+            height,
+            id: Self::next_id(),
+            region: None,
+        })
+    }
 }
@@ -7,14 +7,21 @@ pub const HEIGHT_RATIO: f32 = 0.83175;
 
 pub const EPS: f32 = 1e-5;
 
+mod time;
+pub(crate) use time::with_wide_times;
+pub use time::{time_f32, time_f64, time_from_f32, Time, WIDE_TIME};
+
 mod anim;
+pub(crate) use anim::with_chained_layers;
 pub use anim::{Anim, AnimFloat, AnimVector, Keyframe, TweenFn};
 
+pub mod compact;
+
 mod bpm;
 pub use bpm::{BpmList, Triple};
 
 mod object;
-pub use object::{CtrlObject, Object};
+pub use object::{CtrlObject, CtrlValues, Object};
 
 mod tween;
 pub use tween::{
@@ -27,10 +34,15 @@ pub use color::{colors, Color};
 
 mod chart;
 pub use chart::{
-    Chart, ChartFormat, ChartInfo, ChartSettings, GifFrames, HitSound, HitSoundMap, JudgeLine,
-    JudgeLineKind, JudgeStatus, Judgement, Note, NoteKind, UIElement,
+    AudioRefs, Chart, ChartFormat, ChartInfo, ChartSettings, GifFrames, HitSound, HitSoundMap,
+    JudgeLine, JudgeLineKind, JudgeStatus, Judgement, Note, NoteCounts, NoteKind, UIElement,
 };
 
+mod transform;
+
+mod extra;
+pub use extra::{ChartExtra, Effect, EffectShader, Uniform, Video, VideoScale};
+
 mod texture;
 pub use texture::Texture;
 
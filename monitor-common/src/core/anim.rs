@@ -3,9 +3,11 @@
 //! Ported from prpr/src/core/anim.rs
 //! Provides keyframe-based animation for chart elements.
 
+use super::compact::{self, Quantize};
 use super::tween::{BezierTween, ClampedTween, TweenFunction, TweenId, Tweenable, TWEEN_FUNCTIONS};
-use super::Vector;
-use serde::{Deserialize, Serialize};
+use super::{time, time_f32, Time, Vector};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use std::cell::Cell;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum TweenFn {
@@ -17,13 +19,14 @@ pub enum TweenFn {
 /// A keyframe in an animation
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Keyframe<T> {
-    pub time: f32,
+    #[serde(with = "time")]
+    pub time: Time,
     pub value: T,
     pub tween: TweenFn,
 }
 
 impl<T> Keyframe<T> {
-    pub fn new(time: f32, value: T, tween: TweenId) -> Self {
+    pub fn new(time: Time, value: T, tween: TweenId) -> Self {
         Self {
             time,
             value,
@@ -31,7 +34,7 @@ impl<T> Keyframe<T> {
         }
     }
 
-    pub fn with_bezier(time: f32, value: T, p1: (f32, f32), p2: (f32, f32)) -> Self {
+    pub fn with_bezier(time: Time, value: T, p1: (f32, f32), p2: (f32, f32)) -> Self {
         Self {
             time,
             value,
@@ -39,7 +42,7 @@ impl<T> Keyframe<T> {
         }
     }
 
-    pub fn with_clamped(time: f32, value: T, tween: std::ops::Range<f32>, id: TweenId) -> Self {
+    pub fn with_clamped(time: Time, value: T, tween: std::ops::Range<f32>, id: TweenId) -> Self {
         Self {
             time,
             value,
@@ -57,15 +60,72 @@ impl<T> Keyframe<T> {
     }
 }
 
+thread_local! {
+    static CHAINED_LAYERS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Puts the previous layout back when dropped, also if `f` panics
+struct RestoreChainedLayers(bool);
+
+impl Drop for RestoreChainedLayers {
+    fn drop(&mut self) {
+        CHAINED_LAYERS.with(|c| c.set(self.0));
+    }
+}
+
+/// Runs `f` with animations deserialized in the layout of payload version 4,
+/// which linked further layers through a `next` field instead of listing
+/// them.
+pub(crate) fn with_chained_layers<R>(f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreChainedLayers(CHAINED_LAYERS.with(|c| c.replace(true)));
+    f()
+}
+
+fn deserialize_layers<'de, D, T>(deserializer: D) -> Result<Vec<Anim<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Tweenable + DeserializeOwned + Quantize,
+{
+    if !CHAINED_LAYERS.with(Cell::get) {
+        return Vec::deserialize(deserializer);
+    }
+    // The rest of the chain was already flattened into the next layer's list
+    let Some(mut next) = Option::<Box<Anim<T>>>::deserialize(deserializer)? else {
+        return Ok(Vec::new());
+    };
+    let rest = std::mem::take(&mut next.layers);
+    Ok(std::iter::once(*next)
+        .chain(rest)
+        .filter(|track| !track.keyframes.is_empty())
+        .collect())
+}
+
+/// Keyframes `Anim::set_time` walks before binary searching instead
+const SCAN_STEPS: usize = 4;
+
 /// Keyframe-based animation
 ///
 /// The tween function is taken from the first keyframe of each interval.
+/// An animation may have several tracks, like RPE event layers: each is
+/// evaluated on its own and their values are summed.
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize + Quantize",
+    deserialize = "T: DeserializeOwned + Quantize"
+))]
 pub struct Anim<T: Tweenable> {
-    pub time: f32,
+    #[serde(with = "time")]
+    pub time: Time,
+    #[serde(
+        serialize_with = "compact::serialize",
+        deserialize_with = "compact::deserialize"
+    )]
     pub keyframes: Vec<Keyframe<T>>,
     pub cursor: u32,
-    pub next: Option<Box<Anim<T>>>,
+    /// Tracks added on top of this one, each with its own cursor. Built by
+    /// `layered`, so none is empty or has layers of its own.
+    #[serde(deserialize_with = "deserialize_layers")]
+    pub layers: Vec<Anim<T>>,
 }
 
 impl<T: Tweenable> Default for Anim<T> {
@@ -74,7 +134,7 @@ impl<T: Tweenable> Default for Anim<T> {
             time: 0.0,
             keyframes: Vec::new(),
             cursor: 0,
-            next: None,
+            layers: Vec::new(),
         }
     }
 }
@@ -85,7 +145,7 @@ impl<T: Tweenable> Anim<T> {
             time: 0.0,
             keyframes,
             cursor: 0,
-            next: None,
+            layers: Vec::new(),
         }
     }
 
@@ -95,83 +155,238 @@ impl<T: Tweenable> Anim<T> {
             time: 0.0,
             keyframes: vec![Keyframe::new(0.0, value, 0)], // tween 0 = hold
             cursor: 0,
-            next: None,
+            layers: Vec::new(),
         }
     }
 
     pub fn is_default(&self) -> bool {
-        self.keyframes.is_empty() && self.next.is_none()
+        self.keyframes.is_empty() && self.layers.is_empty()
     }
 
-    pub fn chain(elements: Vec<Anim<T>>) -> Self {
-        if elements.is_empty() {
+    /// Combines `tracks` into one animation evaluating to their sum.
+    ///
+    /// Tracks without keyframes are dropped, they add nothing instead of
+    /// hiding the others. Layers of the given tracks become tracks of the
+    /// result.
+    pub fn layered(tracks: Vec<Anim<T>>) -> Self {
+        let mut tracks = tracks
+            .into_iter()
+            .flat_map(|mut track| {
+                let layers = std::mem::take(&mut track.layers);
+                std::iter::once(track).chain(layers)
+            })
+            .filter(|track| !track.keyframes.is_empty());
+        let Some(mut res) = tracks.next() else {
             return Self::default();
-        }
-        let mut elements: Vec<_> = elements.into_iter().map(Box::new).collect();
-        elements.last_mut().unwrap().next = None;
-        while elements.len() > 1 {
-            let last = elements.pop().unwrap();
-            elements.last_mut().unwrap().next = Some(last);
-        }
-        *elements.into_iter().next().unwrap()
+        };
+        res.layers = tracks.collect();
+        res
+    }
+
+    /// This track followed by its layers, in evaluation order
+    pub fn tracks(&self) -> impl Iterator<Item = &Anim<T>> {
+        std::iter::once(self).chain(&self.layers)
+    }
+
+    /// Sum of the values of the tracks that have one
+    fn sum(values: impl Iterator<Item = Option<T>>) -> Option<T> {
+        values.flatten().reduce(|a, b| T::add(&a, &b))
+    }
+
+    /// Time of the last finite keyframe in any track
+    pub fn last_keyframe_time(&self) -> Option<Time> {
+        self.tracks()
+            .filter_map(|track| {
+                track
+                    .keyframes
+                    .iter()
+                    .rev()
+                    .map(|kf| kf.time)
+                    .find(|t| t.is_finite())
+            })
+            .reduce(Time::max)
     }
 
+    /// Whether every track is past its last keyframe
     pub fn dead(&self) -> bool {
-        self.cursor as usize + 1 >= self.keyframes.len()
+        self.tracks()
+            .all(|track| track.cursor as usize + 1 >= track.keyframes.len())
     }
 
-    pub fn set_time(&mut self, time: f32) {
+    pub fn set_time(&mut self, time: Time) {
         if self.keyframes.is_empty() || time == self.time {
             self.time = time;
             return;
         }
-        while let Some(kf) = self.keyframes.get(self.cursor as usize + 1) {
-            if kf.time > time {
-                break;
-            }
-            self.cursor += 1;
+        // Playback moves the cursor by at most a keyframe or two per frame,
+        // anything further is a seek
+        let kfs = &self.keyframes;
+        let mut cursor = self.cursor as usize;
+        let mut steps = 0;
+        while steps < SCAN_STEPS && kfs.get(cursor + 1).is_some_and(|kf| kf.time <= time) {
+            cursor += 1;
+            steps += 1;
         }
-        while self.cursor != 0 && self.keyframes[self.cursor as usize].time > time {
-            self.cursor -= 1;
+        let settled = kfs.get(cursor + 1).is_none_or(|kf| kf.time > time)
+            && (cursor == 0 || kfs[cursor].time <= time);
+        if !settled {
+            cursor = kfs.partition_point(|kf| kf.time <= time).saturating_sub(1);
         }
+        self.cursor = cursor as u32;
         self.time = time;
-        if let Some(next) = &mut self.next {
-            next.set_time(time);
+        for layer in &mut self.layers {
+            layer.set_time(time);
         }
     }
 
-    fn now_opt_inner(&self) -> Option<T> {
+    /// Points the cursor at the keyframe in effect after the keyframes
+    /// changed
+    fn reseek(&mut self) {
+        self.cursor = self
+            .keyframes
+            .partition_point(|kf| kf.time <= self.time)
+            .saturating_sub(1) as u32;
+    }
+
+    /// Keeps, in every track, only the keyframes needed to evaluate
+    /// `start..=end`: those inside plus the nearest one on either side.
+    pub fn trim(&mut self, start: Time, end: Time) {
+        let kfs = &mut self.keyframes;
+        let last = kfs
+            .iter()
+            .position(|kf| kf.time >= end)
+            .unwrap_or(kfs.len().saturating_sub(1));
+        kfs.truncate(last + 1);
+        let first = kfs.partition_point(|kf| kf.time <= start).saturating_sub(1);
+        kfs.drain(..first);
+        self.reseek();
+        for layer in &mut self.layers {
+            layer.trim(start, end);
+        }
+    }
+
+    /// Maps every keyframe time in every track through `f`, which must be
+    /// increasing.
+    pub fn map_times(&mut self, f: impl Fn(Time) -> Time + Copy) {
+        for kf in &mut self.keyframes {
+            kf.time = f(kf.time);
+        }
+        self.time = f(self.time);
+        self.reseek();
+        for layer in &mut self.layers {
+            layer.map_times(f);
+        }
+    }
+
+    /// Value of this track alone at `time`, with `cursor` the keyframe in
+    /// effect
+    fn value_at_cursor(&self, cursor: usize, time: Time) -> Option<T> {
         if self.keyframes.is_empty() {
             return None;
         }
-        Some(if self.cursor as usize == self.keyframes.len() - 1 {
-            self.keyframes[self.cursor as usize].value.clone()
-        } else {
-            let kf1 = &self.keyframes[self.cursor as usize];
-            let kf2 = &self.keyframes[self.cursor as usize + 1];
-            let t = (self.time - kf1.time) / (kf2.time - kf1.time);
-            T::tween(&kf1.value, &kf2.value, kf1.ease(t))
-        })
+        // Before its first keyframe a track holds the first value instead of
+        // extrapolating into the time of the other tracks
+        Some(
+            if cursor == self.keyframes.len() - 1 || time < self.keyframes[cursor].time {
+                self.keyframes[cursor].value.clone()
+            } else {
+                let kf1 = &self.keyframes[cursor];
+                let kf2 = &self.keyframes[cursor + 1];
+                let t = time_f32((time - kf1.time) / (kf2.time - kf1.time));
+                T::tween(&kf1.value, &kf2.value, kf1.ease(t))
+            },
+        )
+    }
+
+    fn now_opt_inner(&self) -> Option<T> {
+        self.value_at_cursor(self.cursor as usize, self.time)
+    }
+
+    /// Value at `time`, or just before it with `before` set, which differs
+    /// where a keyframe at `time` jumps
+    fn value_in(&self, time: Time, before: bool) -> Option<T> {
+        Self::sum(self.tracks().map(|track| {
+            let cursor = track
+                .keyframes
+                .partition_point(|kf| kf.time < time || (!before && kf.time == time))
+                .saturating_sub(1);
+            track.value_at_cursor(cursor, time)
+        }))
+    }
+
+    /// Value at `time` without moving the cursor, for evaluating one
+    /// animation at many points in the same frame
+    pub fn value_at(&self, time: Time) -> Option<T> {
+        self.value_in(time, false)
+    }
+
+    /// Value just before `time`, the end of the segment leading up to it,
+    /// without stepping back by an epsilon that long times cannot resolve
+    pub fn value_before(&self, time: Time) -> Option<T> {
+        self.value_in(time, true)
+    }
+
+    /// `count` values taken every `1 / rate` seconds from `start`, empty if
+    /// there are no keyframes
+    pub fn resample(&self, start: Time, rate: Time, count: usize) -> Vec<T> {
+        (0..count)
+            .filter_map(|i| self.value_at(start + i as Time / rate))
+            .collect()
+    }
+
+    /// Collapses the tracks into a single keyframe list.
+    ///
+    /// Keyframes are placed at every track's keyframe times, and between
+    /// them at most `step` seconds apart, joined by linear tweens. Eased
+    /// segments are thus approximated while jumps are kept exact.
+    pub fn flatten(&self, step: Time) -> Self
+    where
+        T: PartialEq,
+    {
+        if self.layers.is_empty() {
+            return Self::new(self.keyframes.clone());
+        }
+        let mut times: Vec<_> = self
+            .tracks()
+            .flat_map(|track| track.keyframes.iter().map(|kf| kf.time))
+            .filter(|t| t.is_finite())
+            .collect();
+        times.sort_by(Time::total_cmp);
+        times.dedup();
+
+        let mut keyframes = Vec::new();
+        for (i, &time) in times.iter().enumerate() {
+            if let Some(&prev) = i.checked_sub(1).map(|i| &times[i]) {
+                let steps = if step > 0.0 {
+                    ((time - prev) / step).ceil() as usize
+                } else {
+                    1
+                };
+                for k in 1..steps {
+                    let t = prev + (time - prev) * k as Time / steps as Time;
+                    keyframes.extend(self.value_at(t).map(|v| Keyframe::new(t, v, 2)));
+                }
+                if let Some(before) = self.value_in(time, true) {
+                    if self.value_at(time).is_some_and(|at| at != before) {
+                        keyframes.push(Keyframe::new(time, before, 2));
+                    }
+                }
+            }
+            keyframes.extend(self.value_at(time).map(|v| Keyframe::new(time, v, 2)));
+        }
+        Self::new(keyframes)
     }
 
     pub fn now_opt(&self) -> Option<T> {
-        let Some(now) = self.now_opt_inner() else {
-            return None;
-        };
-        Some(if let Some(next) = &self.next {
-            T::add(&now, &next.now_opt().unwrap())
-        } else {
-            now
-        })
+        Self::sum(self.tracks().map(Self::now_opt_inner))
     }
 
     pub fn map_value(&mut self, mut f: impl FnMut(T) -> T) {
-        self.keyframes
-            .iter_mut()
+        let layers = self.layers.iter_mut().map(|layer| &mut layer.keyframes);
+        std::iter::once(&mut self.keyframes)
+            .chain(layers)
+            .flatten()
             .for_each(|it| it.value = f(it.value.clone()));
-        if let Some(next) = &mut self.next {
-            next.map_value(f);
-        }
     }
 }
 
@@ -196,6 +411,13 @@ impl AnimVector {
         Self { x, y }
     }
 
+    pub fn last_keyframe_time(&self) -> Option<Time> {
+        [self.x.last_keyframe_time(), self.y.last_keyframe_time()]
+            .into_iter()
+            .flatten()
+            .reduce(Time::max)
+    }
+
     pub fn fixed(v: Vector) -> Self {
         Self {
             x: AnimFloat::fixed(v.x),
@@ -203,7 +425,7 @@ impl AnimVector {
         }
     }
 
-    pub fn set_time(&mut self, time: f32) {
+    pub fn set_time(&mut self, time: Time) {
         self.x.set_time(time);
         self.y.set_time(time);
     }
@@ -244,6 +466,104 @@ mod tests {
         assert_eq!(anim.now(), 100.0);
     }
 
+    #[test]
+    fn test_set_time_seek() {
+        let mut anim = AnimFloat::new(
+            (0..100)
+                .map(|i| Keyframe::new(i as Time, i as f32, 2))
+                .collect(),
+        );
+        for time in [0.5, 1.5, 2.5, 80.5, 3.5, 0.0, 99.5, 150.0, 42.25] {
+            anim.set_time(time);
+            let expected = time_f32(time).clamp(0.0, 99.0);
+            assert!((anim.now() - expected).abs() < 0.001, "at {time}");
+        }
+    }
+
+    #[test]
+    fn test_value_at() {
+        let mut anim = AnimFloat::new(vec![
+            Keyframe::new(0.0, 0.0, 2),
+            Keyframe::new(1.0, 100.0, 2),
+            Keyframe::new(2.0, 0.0, 0),
+        ]);
+        anim.layers = vec![AnimFloat::fixed(1.0)];
+        anim.set_time(0.25);
+        assert!((anim.value_at(1.5).unwrap() - 51.0).abs() < 0.001);
+        assert_eq!(anim.value_at(5.0), Some(1.0));
+        // The cursor stays where set_time left it
+        assert!((anim.now() - 26.0).abs() < 0.001);
+        assert_eq!(AnimFloat::default().value_at(1.0), None);
+    }
+
+    #[test]
+    fn test_value_before_late_jump() {
+        let anim = AnimFloat::new(vec![
+            Keyframe::new(0.0, 0.0, 2),
+            Keyframe::new(3600.0, 10.0, 0),
+            Keyframe::new(3600.0, 0.0, 0),
+        ]);
+        // An hour in, stepping back by 1e-4 no longer leaves the keyframe
+        assert_eq!(3600.0f32 - 1e-4, 3600.0);
+        assert_eq!(anim.value_at(3600.0), Some(0.0));
+        assert_eq!(anim.value_before(3600.0), Some(10.0));
+    }
+
+    #[test]
+    fn test_layered() {
+        let anim = AnimFloat::layered(vec![
+            AnimFloat::default(),
+            AnimFloat::new(vec![
+                Keyframe::new(0.0, 0.0, 2),
+                Keyframe::new(2.0, 10.0, 0),
+            ]),
+            AnimFloat::default(),
+            // Overlaps the first track and ends earlier
+            AnimFloat::new(vec![Keyframe::new(1.0, 4.0, 2), Keyframe::new(1.5, 0.0, 0)]),
+        ]);
+        // Empty tracks add nothing rather than hiding the others
+        assert_eq!(anim.layers.len(), 1);
+        // The second track holds its first value until it starts
+        assert_eq!(anim.value_at(0.5), Some(6.5));
+        assert_eq!(anim.value_at(1.25), Some(8.25));
+        assert_eq!(anim.value_at(3.0), Some(10.0));
+        assert_eq!(anim.last_keyframe_time(), Some(2.0));
+        assert!(AnimFloat::layered(vec![AnimFloat::default()]).is_default());
+    }
+
+    #[test]
+    fn test_resample() {
+        let anim = AnimFloat::new(vec![
+            Keyframe::new(0.0, 0.0, 2),
+            Keyframe::new(1.0, 10.0, 0),
+        ]);
+        assert_eq!(
+            anim.resample(0.0, 4.0, 6),
+            vec![0.0, 2.5, 5.0, 7.5, 10.0, 10.0]
+        );
+        assert!(AnimFloat::default().resample(0.0, 60.0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut anim = AnimFloat::new(vec![
+            Keyframe::new(0.0, 0.0, 2),
+            Keyframe::new(2.0, 10.0, 0),
+        ]);
+        // Hold at 1 until t = 1, then jump to 5
+        anim.layers = vec![AnimFloat::new(vec![
+            Keyframe::new(0.0, 1.0, 0),
+            Keyframe::new(1.0, 5.0, 0),
+        ])];
+        let flat = anim.flatten(0.25);
+        assert!(flat.layers.is_empty());
+        for t in [0.0, 0.3, 0.9, 1.0, 1.5, 2.0, 3.0] {
+            let (want, got) = (anim.value_at(t).unwrap(), flat.value_at(t).unwrap());
+            assert!((want - got).abs() < 1e-4, "at {t}: {want} != {got}");
+        }
+        assert!((flat.value_in(1.0, true).unwrap() - 6.0).abs() < 1e-4);
+    }
+
     #[test]
     fn test_quad_easing() {
         let mut anim = AnimFloat::new(vec![
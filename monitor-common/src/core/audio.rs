@@ -4,7 +4,7 @@ use std::{fs::File, path::Path};
 use symphonia::core::{
     audio::SampleBuffer,
     codecs::DecoderOptions,
-    formats::FormatOptions,
+    formats::{FormatOptions, FormatReader},
     io::{MediaSource, MediaSourceStream},
     meta::MetadataOptions,
     probe::Hint,
@@ -26,7 +26,16 @@ impl AudioClip {
         }
     }
 
-    pub fn load_from(source: impl MediaSource + 'static, ext: &str) -> anyhow::Result<Self> {
+    /// Length in seconds
+    pub fn duration(&self) -> f32 {
+        let frames = self.samples.len() / self.channel_count.max(1) as usize;
+        frames as f32 / self.sample_rate.max(1) as f32
+    }
+
+    fn probe(
+        source: impl MediaSource + 'static,
+        ext: &str,
+    ) -> anyhow::Result<Box<dyn FormatReader>> {
         let mss = MediaSourceStream::new(Box::new(source), Default::default());
         let mut hint = Hint::new();
         hint.with_extension(ext);
@@ -40,8 +49,25 @@ impl AudioClip {
             &FormatOptions::default(),
             &MetadataOptions::default(),
         )?;
+        Ok(probed.format)
+    }
+
+    /// Length in seconds of compressed audio, taken from the container
+    /// when it records one and decoding the audio otherwise
+    pub fn duration_of(bytes: &[u8], ext: &str) -> anyhow::Result<f32> {
+        let format = Self::probe(std::io::Cursor::new(bytes.to_vec()), ext)?;
+        let params = &format
+            .default_track()
+            .with_context(|| "No default track found")?
+            .codec_params;
+        if let (Some(frames), Some(rate)) = (params.n_frames, params.sample_rate) {
+            return Ok(frames as f32 / rate.max(1) as f32);
+        }
+        Ok(Self::load_from_bytes(bytes, ext)?.duration())
+    }
 
-        let mut format = probed.format;
+    pub fn load_from(source: impl MediaSource + 'static, ext: &str) -> anyhow::Result<Self> {
+        let mut format = Self::probe(source, ext)?;
         let track = format
             .default_track()
             .with_context(|| "No default track found")?;
@@ -221,4 +247,15 @@ mod tests {
 
         assert!(result.is_err(), "读取不存在的文件应该报错");
     }
+
+    #[test]
+    fn test_duration_of_wav() {
+        let path = PathBuf::from("temp_test_duration.wav");
+        create_dummy_wav(&path, 22050, 2);
+        let bytes = std::fs::read(&path);
+        let _ = std::fs::remove_file(&path);
+
+        let duration = AudioClip::duration_of(&bytes.unwrap(), "wav").unwrap();
+        assert!((duration - 2.0).abs() < 1e-3, "时长不匹配: {}", duration);
+    }
 }
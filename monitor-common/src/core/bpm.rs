@@ -2,6 +2,7 @@
 //!
 //! Ported from prpr/src/core.rs
 //! Converts between beat coordinates and time in seconds.
+use super::{time, time_f64, time_from_f32, Time};
 use serde::{Deserialize, Serialize};
 
 /// `(i, n, d)` represents beat position: `i + n / d`
@@ -13,9 +14,9 @@ impl Triple {
         Self(i, n, d)
     }
 
-    /// Convert to beats as f32
-    pub fn beats(&self) -> f32 {
-        self.0 as f32 + self.1 as f32 / self.2 as f32
+    /// Convert to beats, as precise as chart times
+    pub fn beats(&self) -> Time {
+        self.0 as Time + self.1 as Time / self.2 as Time
     }
 }
 
@@ -25,7 +26,8 @@ impl Triple {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct BpmList {
     /// (beats, time_seconds, bpm)
-    elements: Vec<(f32, f32, f32)>,
+    #[serde(with = "elements")]
+    elements: Vec<(Time, Time, f32)>,
     /// Cursor for binary search optimization
     cursor: usize,
 }
@@ -43,13 +45,14 @@ impl BpmList {
     /// Create a new BpmList from a list of (beats, bpm) pairs
     ///
     /// Calculates the time offset for each BPM change.
-    pub fn new(ranges: Vec<(f32, f32)>) -> Self {
+    pub fn new(ranges: Vec<(Time, f32)>) -> Self {
         if ranges.is_empty() {
             return Self::default();
         }
 
         let mut elements = Vec::with_capacity(ranges.len());
-        let mut time = 0.0;
+        // Summed in f64, thousands of f32 steps drift by whole frames
+        let mut time = 0.0f64;
         let mut last_beats = 0.0;
         let mut last_bpm: Option<f32> = None;
 
@@ -57,11 +60,11 @@ impl BpmList {
             if let Some(prev_bpm) = last_bpm {
                 // Time = beats_delta * seconds_per_beat
                 // seconds_per_beat = 60 / bpm
-                time += (now_beats - last_beats) * (60.0 / prev_bpm);
+                time += time_f64(now_beats - last_beats) * (60.0 / prev_bpm as f64);
             }
             last_beats = now_beats;
             last_bpm = Some(bpm);
-            elements.push((now_beats, time, bpm));
+            elements.push((now_beats, time as Time, bpm));
         }
 
         BpmList {
@@ -71,26 +74,76 @@ impl BpmList {
     }
 
     /// Get the time in seconds for a given beat position
-    pub fn time_at_beats(&mut self, beats: f32) -> f32 {
+    pub fn time_at_beats(&mut self, beats: Time) -> Time {
         self.seek_by_beats(beats);
         let (start_beats, time, bpm) = &self.elements[self.cursor];
-        time + (beats - start_beats) * (60.0 / bpm)
+        time + (beats - start_beats) * (60.0 / time_from_f32(*bpm))
     }
 
     /// Get the time in seconds for a Triple beat position
-    pub fn time_at(&mut self, triple: &Triple) -> f32 {
+    pub fn time_at(&mut self, triple: &Triple) -> Time {
         self.time_at_beats(triple.beats())
     }
 
     /// Get the beat position for a given time in seconds
-    pub fn beats_at_time(&mut self, time: f32) -> f32 {
+    pub fn beats_at_time(&mut self, time: Time) -> Time {
         self.seek_by_time(time);
         let (beats, start_time, bpm) = &self.elements[self.cursor];
-        beats + (time - start_time) / (60.0 / bpm)
+        beats + (time - start_time) / (60.0 / time_from_f32(*bpm))
+    }
+
+    /// BPM in effect at a given time in seconds
+    pub fn bpm_at(&mut self, time: Time) -> f32 {
+        self.seek_by_time(time);
+        self.elements[self.cursor].2
+    }
+
+    /// Whole beats with time in `(from, to]`, as `(beat, time)` pairs
+    pub fn beats_between(&mut self, from: Time, to: Time) -> Vec<(i32, Time)> {
+        let mut beats = Vec::new();
+        let mut beat = self.beats_at_time(from).floor() as i32;
+        loop {
+            let time = self.time_at_beats(beat as Time);
+            if time > to {
+                break;
+            }
+            if time > from {
+                beats.push((beat, time));
+            }
+            beat += 1;
+        }
+        beats
+    }
+
+    /// Bars starting in `(from, to]`, as `(bar, time)` pairs. Bar `n` starts
+    /// at beat `n * beats_per_bar`.
+    pub fn bars_between(&mut self, from: Time, to: Time, beats_per_bar: u32) -> Vec<(i32, Time)> {
+        let beats_per_bar = beats_per_bar.max(1) as i32;
+        self.beats_between(from, to)
+            .into_iter()
+            .filter(|(beat, _)| beat.rem_euclid(beats_per_bar) == 0)
+            .map(|(beat, time)| (beat.div_euclid(beats_per_bar), time))
+            .collect()
+    }
+
+    /// Maps the time of every BPM change through `f`, which must be
+    /// increasing
+    pub fn map_times(&mut self, f: impl Fn(Time) -> Time) {
+        for (_, time, _) in &mut self.elements {
+            *time = f(*time);
+        }
+        self.cursor = 0;
+    }
+
+    /// Maps every BPM through `f`, keeping the times of the changes
+    pub fn map_bpm(&mut self, f: impl Fn(f32) -> f32) {
+        for (_, _, bpm) in &mut self.elements {
+            *bpm = f(*bpm);
+        }
     }
 
     /// Move cursor to the segment containing the given beats
-    fn seek_by_beats(&mut self, beats: f32) {
+    fn seek_by_beats(&mut self, beats: Time) {
         // Forward
         while let Some(kf) = self.elements.get(self.cursor + 1) {
             if kf.0 > beats {
@@ -105,7 +158,7 @@ impl BpmList {
     }
 
     /// Move cursor to the segment containing the given time
-    fn seek_by_time(&mut self, time: f32) {
+    fn seek_by_time(&mut self, time: Time) {
         // Forward
         while let Some(kf) = self.elements.get(self.cursor + 1) {
             if kf.1 > time {
@@ -125,6 +178,36 @@ impl BpmList {
     }
 }
 
+/// BPM changes with beats and times in the width of the payload
+mod elements {
+    use super::{time, Time};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Element(
+        #[serde(with = "time")] Time,
+        #[serde(with = "time")] Time,
+        f32,
+    );
+
+    pub fn serialize<S: Serializer>(
+        elements: &[(Time, Time, f32)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(elements.iter().map(|&(b, t, bpm)| Element(b, t, bpm)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(Time, Time, f32)>, D::Error> {
+        let elements = Vec::<Element>::deserialize(deserializer)?;
+        Ok(elements
+            .into_iter()
+            .map(|Element(b, t, bpm)| (b, t, bpm))
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,6 +241,59 @@ mod tests {
         assert!((bpm.beats_at_time(2.0) - 4.0).abs() < 0.001);
     }
 
+    #[test]
+    fn test_beats_between() {
+        let mut bpm = BpmList::new(vec![(0.0, 120.0), (2.0, 60.0)]);
+
+        let beats = bpm.beats_between(0.5, 3.0);
+        let expected = [(2, 1.0), (3, 2.0), (4, 3.0)];
+        assert_eq!(beats.len(), expected.len());
+        for ((beat, time), (want_beat, want_time)) in beats.into_iter().zip(expected) {
+            assert_eq!(beat, want_beat);
+            assert!((time - want_time).abs() < 0.001);
+        }
+        // Before the first BPM change the first BPM carries on
+        assert_eq!(bpm.beats_between(-1.0, 0.0).len(), 2);
+    }
+
+    #[test]
+    fn test_bpm_at() {
+        let mut bpm = BpmList::new(vec![(0.0, 120.0), (2.0, 60.0)]);
+
+        assert_eq!(bpm.bpm_at(-1.0), 120.0);
+        assert_eq!(bpm.bpm_at(0.99), 120.0);
+        assert_eq!(bpm.bpm_at(1.0), 60.0);
+        assert_eq!(bpm.bpm_at(0.5), 120.0);
+    }
+
+    #[test]
+    fn test_bars_between() {
+        // 4/4 at 120 BPM is a bar every 2s
+        let mut bpm = BpmList::new(vec![(0.0, 120.0)]);
+
+        let bars = bpm.bars_between(-0.1, 6.0, 4);
+        let starts: Vec<_> = bars.iter().map(|(bar, _)| *bar).collect();
+        assert_eq!(starts, vec![0, 1, 2, 3]);
+        assert!((bars[3].1 - 6.0).abs() < 0.001);
+        // Bars before time zero count down from -1
+        let bars = bpm.bars_between(-4.5, -0.1, 4);
+        assert_eq!(
+            bars.iter().map(|(bar, _)| *bar).collect::<Vec<_>>(),
+            vec![-2, -1]
+        );
+    }
+
+    #[test]
+    fn test_many_bpm_changes() {
+        // A marathon chart alternating BPM every beat for an hour
+        let ranges: Vec<_> = (0..14400)
+            .map(|i| (i as Time, if i % 2 == 0 { 200.0 } else { 280.0 }))
+            .collect();
+        let mut bpm = BpmList::new(ranges);
+        let expected = 7200.0 * (60.0 / 200.0 + 60.0 / 280.0);
+        assert!((time_f64(bpm.time_at_beats(14400.0)) - expected).abs() < 1e-3);
+    }
+
     #[test]
     fn test_triple() {
         let triple = Triple::new(1, 1, 2); // 1 + 1/2 = 1.5 beats
@@ -3,7 +3,10 @@
 //! Simplified from prpr/src/core for the web monitor.
 //! Contains only data definitions without rendering logic.
 
-use super::{Anim, AnimFloat, AudioClip, BpmList, Color, CtrlObject, Object, Texture};
+use super::{
+    time, time_from_f32, Anim, AnimFloat, AudioClip, BpmList, ChartExtra, Color, CtrlObject,
+    Object, Texture, Time,
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -12,10 +15,15 @@ use std::collections::HashMap;
 // Note types
 // ============================================================================
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub enum NoteKind {
+    #[default]
     Click,
-    Hold { end_time: f32, end_height: f32 },
+    Hold {
+        #[serde(with = "time")]
+        end_time: Time,
+        end_height: f32,
+    },
     Flick,
     Drag,
 }
@@ -36,19 +44,13 @@ impl NoteKind {
     }
 }
 
-impl Default for NoteKind {
-    fn default() -> Self {
-        Self::Click
-    }
-}
-
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub enum JudgeStatus {
     #[default]
     NotJudged,
     PreJudge,
     Judged,
-    Hold(bool, f32, f32, bool, f32), // perfect, at, diff, pre-judge, up-time
+    Hold(bool, Time, f32, bool, Time), // perfect, at, diff, pre-judge, up-time
 }
 
 #[repr(u8)]
@@ -62,12 +64,16 @@ pub enum Judgement {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Note {
+    /// Identifier assigned at parse time, unique within the chart and kept
+    /// when notes are re-sorted
+    pub id: u32,
     /// Object transform animations
     pub object: Object,
     /// Type of note
     pub kind: NoteKind,
     /// Time when note should be hit (seconds)
-    pub time: f32,
+    #[serde(with = "time")]
+    pub time: Time,
     /// Height on the judge line (y-position relative to line)
     pub height: f32,
     /// Speed multiplier
@@ -88,6 +94,7 @@ pub struct Note {
 impl Default for Note {
     fn default() -> Self {
         Self {
+            id: 0,
             object: Object::default(),
             kind: NoteKind::default(),
             time: 0.,
@@ -103,8 +110,9 @@ impl Default for Note {
 }
 
 impl Note {
-    pub fn new(kind: NoteKind, time: f32, height: f32) -> Self {
+    pub fn new(kind: NoteKind, time: Time, height: f32) -> Self {
         Self {
+            id: 0,
             object: Object::default(),
             kind,
             time,
@@ -130,12 +138,12 @@ impl Note {
     }
 
     /// Set time for the note's animations
-    pub fn set_time(&mut self, time: f32) {
+    pub fn set_time(&mut self, time: Time) {
         self.object.set_time(time);
     }
 
     /// Get end time for Hold notes
-    pub fn end_time(&self) -> f32 {
+    pub fn end_time(&self) -> Time {
         match &self.kind {
             NoteKind::Hold { end_time, .. } => *end_time,
             _ => self.time,
@@ -155,6 +163,32 @@ pub struct GifFrames {
     pub total_time: u128,
 }
 
+impl GifFrames {
+    /// Index of the frame shown `time` milliseconds into the animation (looping)
+    pub fn frame_at_time(&self, time: u128) -> usize {
+        if self.total_time == 0 {
+            return 0;
+        }
+        let time = time % self.total_time;
+        let mut end = 0;
+        for (i, (delay, _)) in self.frames.iter().enumerate() {
+            end += delay;
+            if time < end {
+                return i;
+            }
+        }
+        self.frames.len().saturating_sub(1)
+    }
+
+    /// Index of the frame at playback progress `prog` (0 = first frame, 1 = end)
+    pub fn frame_at_progress(&self, prog: f32) -> usize {
+        let prog = prog.clamp(0., 1.);
+        let time = (prog * self.total_time as f32) as u128;
+        // progress 1.0 means the very end, not the start of the next loop
+        self.frame_at_time(time.min(self.total_time.saturating_sub(1)))
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 #[repr(u8)]
@@ -204,13 +238,27 @@ pub struct JudgeLine {
     pub attach_ui: Option<UIElement>,
 }
 
+/// Number of real (non-fake) notes of each kind
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NoteCounts {
+    pub click: usize,
+    pub hold: usize,
+    pub flick: usize,
+    pub drag: usize,
+}
+
 impl JudgeLine {
     /// Set time for all animations
-    pub fn set_time(&mut self, time: f32) {
+    pub fn set_time(&mut self, time: Time) {
         self.object.set_time(time);
         self.height.set_time(time);
         self.incline.set_time(time);
         self.color.set_time(time);
+        match &mut self.kind {
+            JudgeLineKind::TextureGif(anim, ..) | JudgeLineKind::Paint(anim) => anim.set_time(time),
+            JudgeLineKind::Text(anim) => anim.set_time(time),
+            _ => {}
+        }
         for note in &mut self.notes {
             note.set_time(time);
         }
@@ -340,20 +388,34 @@ pub enum HitSound {
 
 pub type HitSoundMap = HashMap<HitSound, AudioClip>;
 
+/// Audio the proxy serves from its own URLs instead of embedding it in the
+/// chart, so browsers can cache it apart from the chart
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AudioRefs {
+    /// URL of the compressed music
+    pub music: Option<String>,
+    /// Length of the music in seconds, known before it is fetched
+    pub music_duration: f32,
+    /// URLs of the compressed chart hitsounds
+    pub hitsounds: HashMap<HitSound, String>,
+}
+
 /// A complete chart
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Chart {
     /// Music for the chart
     pub music: Option<AudioClip>,
     /// Offset in seconds (for sync adjustment)
-    pub offset: f32,
+    #[serde(with = "time")]
+    pub offset: Time,
     /// All judge lines
     pub lines: Vec<JudgeLine>,
     /// BPM list for beat-to-time conversion
     pub bpm_list: BpmList,
     /// Chart settings
     pub settings: ChartSettings,
-    // pub extra: ChartExtra,
+    /// Effects from extra.json
+    pub extra: ChartExtra,
     /// Line order according to z-index, lines with attach_ui will be removed from this list
     ///
     /// Store the index of the line in z-index ascending order
@@ -362,10 +424,13 @@ pub struct Chart {
     // /// TODO: docs from RPE
     // pub attach_ui: [Option<usize>; 7],
     pub hitsounds: HitSoundMap,
+    /// Music and hitsounds to fetch separately, in place of `music` and
+    /// `hitsounds`
+    pub audio: AudioRefs,
 }
 
 impl Chart {
-    pub fn new(offset: f32, lines: Vec<JudgeLine>, bpm_list: BpmList) -> Self {
+    pub fn new(offset: Time, lines: Vec<JudgeLine>, bpm_list: BpmList) -> Self {
         Self {
             music: None,
             offset,
@@ -376,10 +441,20 @@ impl Chart {
     }
 
     /// Set time for all chart elements
-    pub fn set_time(&mut self, time: f32) {
+    pub fn set_time(&mut self, time: Time) {
         for line in &mut self.lines {
             line.set_time(time);
         }
+        self.extra.set_time(time);
+    }
+
+    /// Rebuild `order` from the lines' z-index. Lines sharing a z-index
+    /// keep their index order.
+    pub fn update_order(&mut self) {
+        self.order = (0..self.lines.len())
+            .filter(|&i| self.lines[i].attach_ui.is_none())
+            .collect();
+        self.order.sort_by_key(|&i| self.lines[i].z_index);
     }
 
     /// Get total note count (excluding fake notes)
@@ -391,11 +466,85 @@ impl Chart {
     pub fn line_count(&self) -> usize {
         self.lines.len()
     }
+
+    /// Chart length in seconds: until the music or the last note ends,
+    /// whichever is later
+    pub fn duration(&self) -> Time {
+        // Music position p plays at chart time p - offset
+        let music = match (&self.music, &self.audio.music) {
+            (Some(clip), _) => time_from_f32(clip.duration()) - self.offset,
+            (None, Some(_)) => time_from_f32(self.audio.music_duration) - self.offset,
+            (None, None) => 0.0,
+        };
+        music.max(self.end_time())
+    }
+
+    pub fn note_counts_by_kind(&self) -> NoteCounts {
+        let mut counts = NoteCounts::default();
+        for note in self.lines.iter().flat_map(|l| &l.notes) {
+            if note.fake {
+                continue;
+            }
+            match note.kind {
+                NoteKind::Click => counts.click += 1,
+                NoteKind::Hold { .. } => counts.hold += 1,
+                NoteKind::Flick => counts.flick += 1,
+                NoteKind::Drag => counts.drag += 1,
+            }
+        }
+        counts
+    }
+
+    /// Time of the last judge line keyframe, 0 if no line is animated
+    pub fn max_line_time(&self) -> Time {
+        self.lines
+            .iter()
+            .flat_map(|line| {
+                let kind = match &line.kind {
+                    JudgeLineKind::TextureGif(anim, ..) | JudgeLineKind::Paint(anim) => {
+                        anim.last_keyframe_time()
+                    }
+                    JudgeLineKind::Text(anim) => anim.last_keyframe_time(),
+                    _ => None,
+                };
+                let object = &line.object;
+                let ctrl = &line.ctrl_obj;
+                [
+                    object.alpha.last_keyframe_time(),
+                    object.scale.last_keyframe_time(),
+                    object.rotation.last_keyframe_time(),
+                    object.translation.last_keyframe_time(),
+                    ctrl.alpha.last_keyframe_time(),
+                    ctrl.size.last_keyframe_time(),
+                    ctrl.pos.last_keyframe_time(),
+                    ctrl.y.last_keyframe_time(),
+                    line.height.last_keyframe_time(),
+                    line.incline.last_keyframe_time(),
+                    line.color.last_keyframe_time(),
+                    kind,
+                ]
+            })
+            .flatten()
+            .fold(0.0, Time::max)
+    }
+
+    /// Time the last note (or hold tail) ends, 0 for a chart without notes
+    pub fn end_time(&self) -> Time {
+        self.lines
+            .iter()
+            .flat_map(|l| &l.notes)
+            .map(|note| match note.kind {
+                NoteKind::Hold { end_time, .. } => end_time,
+                _ => note.time,
+            })
+            .fold(0.0, Time::max)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::Keyframe;
 
     #[test]
     fn test_note_kind_order() {
@@ -424,4 +573,91 @@ mod tests {
 
         assert_eq!(chart.note_count(), 2); // Fake notes not counted
     }
+
+    #[test]
+    fn test_chart_end_time() {
+        let mut chart = Chart::default();
+        assert_eq!(chart.end_time(), 0.0);
+
+        let mut line = JudgeLine::default();
+        line.notes.push(Note::new(NoteKind::Click, 5.0, 0.0));
+        line.notes.push(Note::new(
+            NoteKind::Hold {
+                end_time: 8.0,
+                end_height: 0.0,
+            },
+            4.0,
+            0.0,
+        ));
+        chart.lines.push(line);
+        let mut line = JudgeLine::default();
+        line.notes.push(Note::new(NoteKind::Drag, 6.0, 0.0));
+        chart.lines.push(line);
+
+        assert_eq!(chart.end_time(), 8.0);
+    }
+
+    #[test]
+    fn test_chart_summary() {
+        let mut chart = Chart::default();
+        assert_eq!(chart.max_line_time(), 0.0);
+
+        let mut line = JudgeLine::default();
+        line.notes.push(Note::new(NoteKind::Click, 1.0, 0.0));
+        line.notes.push(Note::new(NoteKind::Flick, 2.0, 0.0));
+        line.notes.push(Note::new(NoteKind::Click, 3.0, 0.0));
+        let mut fake = Note::new(NoteKind::Drag, 4.0, 0.0);
+        fake.fake = true;
+        line.notes.push(fake);
+        line.object.alpha = AnimFloat::new(vec![
+            Keyframe::new(0.0, 1.0, 2),
+            Keyframe::new(12.0, 0.0, 0),
+        ]);
+        line.height = AnimFloat::new(vec![Keyframe::new(Time::INFINITY, 0.0, 0)]);
+        chart.lines.push(line);
+
+        let counts = chart.note_counts_by_kind();
+        assert_eq!((counts.click, counts.flick, counts.drag), (2, 1, 0));
+        assert_eq!(chart.max_line_time(), 12.0);
+        assert_eq!(chart.duration(), 4.0);
+    }
+
+    #[test]
+    fn test_chart_update_order() {
+        let mut chart = Chart::default();
+        for (z_index, attach_ui) in [
+            (1, None),
+            (0, None),
+            (1, None),
+            (-1, Some(UIElement::Pause)),
+        ] {
+            chart.lines.push(JudgeLine {
+                z_index,
+                attach_ui,
+                ..Default::default()
+            });
+        }
+        chart.update_order();
+        assert_eq!(chart.order, [1, 0, 2]);
+    }
+
+    #[test]
+    fn test_gif_frame_selection() {
+        let gif = GifFrames {
+            frames: vec![
+                (100, Texture::empty()),
+                (50, Texture::empty()),
+                (150, Texture::empty()),
+            ],
+            total_time: 300,
+        };
+        assert_eq!(gif.frame_at_time(0), 0);
+        assert_eq!(gif.frame_at_time(99), 0);
+        assert_eq!(gif.frame_at_time(100), 1);
+        assert_eq!(gif.frame_at_time(150), 2);
+        assert_eq!(gif.frame_at_time(350), 0); // loops
+        assert_eq!(gif.frame_at_progress(0.0), 0);
+        assert_eq!(gif.frame_at_progress(0.4), 1);
+        assert_eq!(gif.frame_at_progress(1.0), 2);
+    }
 }
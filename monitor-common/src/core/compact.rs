@@ -0,0 +1,278 @@
+//! Compact keyframe encoding
+//!
+//! With [`Encoding::Compact`] active, keyframes serialize as quantized,
+//! delta-encoded times, quantized values and run-length encoded tweens
+//! instead of full `(f32, T, TweenFn)` triples. Animations that cannot be
+//! quantized (non-finite times or values out of range) are kept as they
+//! are. The encoding is picked per payload, see [`crate::payload`].
+use super::{Color, Keyframe, Time, TweenFn, Vector};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+
+/// Keyframe times are rounded to this many seconds
+pub const TIME_STEP: f32 = 1.0 / 8192.0;
+/// Float values are rounded to this step
+pub const VALUE_STEP: f32 = 1.0 / 16384.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Plain,
+    Compact,
+}
+
+thread_local! {
+    static ENCODING: Cell<Encoding> = const { Cell::new(Encoding::Plain) };
+}
+
+/// Puts the previous encoding back when dropped, also if `f` panics
+struct RestoreEncoding(Encoding);
+
+impl Drop for RestoreEncoding {
+    fn drop(&mut self) {
+        ENCODING.with(|e| e.set(self.0));
+    }
+}
+
+/// Runs `f` with keyframes (de)serialized in `encoding`.
+pub(crate) fn with_encoding<R>(encoding: Encoding, f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreEncoding(ENCODING.with(|e| e.replace(encoding)));
+    f()
+}
+
+fn quantize(value: f32, step: f32) -> Option<i64> {
+    let q = (value / step).round();
+    (q.is_finite() && q.abs() < i64::MAX as f32).then_some(q as i64)
+}
+
+/// Like `quantize`, keeping the precision of [`Time`]
+fn quantize_time(time: Time) -> Option<i64> {
+    let q = (time / TIME_STEP as Time).round();
+    (q.is_finite() && q.abs() < i64::MAX as Time).then_some(q as i64)
+}
+
+/// Keyframe values with a lossy compact representation
+pub trait Quantize: Sized {
+    type Packed: Serialize + DeserializeOwned;
+
+    /// `None` if the value cannot be represented
+    fn pack(&self) -> Option<Self::Packed>;
+    fn unpack(packed: Self::Packed) -> Self;
+}
+
+impl Quantize for f32 {
+    type Packed = i64;
+
+    fn pack(&self) -> Option<i64> {
+        quantize(*self, VALUE_STEP)
+    }
+
+    fn unpack(packed: i64) -> Self {
+        packed as f32 * VALUE_STEP
+    }
+}
+
+impl Quantize for Vector {
+    type Packed = (i64, i64);
+
+    fn pack(&self) -> Option<(i64, i64)> {
+        Some((self.x.pack()?, self.y.pack()?))
+    }
+
+    fn unpack((x, y): (i64, i64)) -> Self {
+        Vector::new(f32::unpack(x), f32::unpack(y))
+    }
+}
+
+impl Quantize for Color {
+    type Packed = [u8; 4];
+
+    fn pack(&self) -> Option<[u8; 4]> {
+        let channel = |c: f32| (0.0..=1.0).contains(&c).then(|| (c * 255.0).round() as u8);
+        Some([
+            channel(self.r)?,
+            channel(self.g)?,
+            channel(self.b)?,
+            channel(self.a)?,
+        ])
+    }
+
+    fn unpack([r, g, b, a]: [u8; 4]) -> Self {
+        Color::from_rgba(r, g, b, a)
+    }
+}
+
+impl Quantize for String {
+    type Packed = String;
+
+    fn pack(&self) -> Option<String> {
+        Some(self.clone())
+    }
+
+    fn unpack(packed: String) -> Self {
+        packed
+    }
+}
+
+/// Runs of tweens; consecutive plain tween ids are merged
+type TweenRuns = Vec<(u32, TweenFn)>;
+
+#[derive(Serialize)]
+enum PackedRef<'a, T: Quantize> {
+    Raw(&'a [Keyframe<T>]),
+    Quantized {
+        /// Differences between consecutive quantized times
+        times: Vec<i64>,
+        values: Vec<T::Packed>,
+        tweens: TweenRuns,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "T: Quantize + DeserializeOwned")]
+enum Packed<T: Quantize> {
+    Raw(Vec<Keyframe<T>>),
+    Quantized {
+        times: Vec<i64>,
+        values: Vec<T::Packed>,
+        tweens: TweenRuns,
+    },
+}
+
+fn pack<T: Quantize>(keyframes: &[Keyframe<T>]) -> PackedRef<'_, T> {
+    let quantized = (|| {
+        let mut times = Vec::with_capacity(keyframes.len());
+        let mut values = Vec::with_capacity(keyframes.len());
+        let mut tweens: TweenRuns = Vec::new();
+        let mut last = 0;
+        for kf in keyframes {
+            let time = quantize_time(kf.time)?;
+            times.push(time - last);
+            last = time;
+            values.push(kf.value.pack()?);
+            match (tweens.last_mut(), &kf.tween) {
+                (Some((count, TweenFn::TweenId(prev))), TweenFn::TweenId(id)) if prev == id => {
+                    *count += 1;
+                }
+                _ => tweens.push((1, kf.tween.clone())),
+            }
+        }
+        Some(PackedRef::Quantized {
+            times,
+            values,
+            tweens,
+        })
+    })();
+    quantized.unwrap_or(PackedRef::Raw(keyframes))
+}
+
+fn unpack<T: Quantize>(packed: Packed<T>) -> Result<Vec<Keyframe<T>>, String> {
+    let (times, values, tweens) = match packed {
+        Packed::Raw(keyframes) => return Ok(keyframes),
+        Packed::Quantized {
+            times,
+            values,
+            tweens,
+        } => (times, values, tweens),
+    };
+    let runs: usize = tweens.iter().map(|(count, _)| *count as usize).sum();
+    if values.len() != times.len() || runs != times.len() {
+        return Err(format!(
+            "{} keyframe times with {} values and {} tweens",
+            times.len(),
+            values.len(),
+            runs
+        ));
+    }
+    let tweens = tweens
+        .into_iter()
+        .flat_map(|(count, tween)| std::iter::repeat_n(tween, count as usize));
+    let mut time = 0;
+    let keyframes: Vec<_> = times
+        .into_iter()
+        .zip(values)
+        .zip(tweens)
+        .map(|((delta, value), tween)| {
+            time += delta;
+            Keyframe {
+                time: time as Time * TIME_STEP as Time,
+                value: T::unpack(value),
+                tween,
+            }
+        })
+        .collect();
+    Ok(keyframes)
+}
+
+pub(crate) fn serialize<T, S>(keyframes: &[Keyframe<T>], serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Quantize + Serialize,
+    S: Serializer,
+{
+    match ENCODING.with(Cell::get) {
+        Encoding::Plain => keyframes.serialize(serializer),
+        Encoding::Compact => pack(keyframes).serialize(serializer),
+    }
+}
+
+pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<Keyframe<T>>, D::Error>
+where
+    T: Quantize + DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    match ENCODING.with(Cell::get) {
+        Encoding::Plain => Vec::deserialize(deserializer),
+        Encoding::Compact => {
+            unpack(Packed::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::AnimFloat;
+    use bincode::Options;
+
+    fn roundtrip(anim: &AnimFloat, encoding: Encoding) -> (usize, AnimFloat) {
+        let options = bincode::options().with_varint_encoding();
+        with_encoding(encoding, || {
+            let bytes = options.serialize(anim).unwrap();
+            (bytes.len(), options.deserialize(&bytes).unwrap())
+        })
+    }
+
+    #[test]
+    fn test_compact_roundtrip() {
+        let anim = AnimFloat::new(
+            (0..100)
+                .map(|i| Keyframe::new(i as Time * 0.37, (i as f32 * 0.1).sin() * 300.0, 2))
+                .collect(),
+        );
+        let (plain_len, plain) = roundtrip(&anim, Encoding::Plain);
+        let (compact_len, compact) = roundtrip(&anim, Encoding::Compact);
+        assert!(compact_len < plain_len, "{compact_len} >= {plain_len}");
+        assert_eq!(plain.keyframes.len(), compact.keyframes.len());
+        for (a, b) in anim.keyframes.iter().zip(&compact.keyframes) {
+            assert!((a.time - b.time).abs() <= TIME_STEP as Time / 2.0);
+            assert!((a.value - b.value).abs() <= VALUE_STEP / 2.0);
+            assert!(matches!(b.tween, TweenFn::TweenId(2)));
+        }
+    }
+
+    #[test]
+    fn test_encoding_restored_after_panic() {
+        let result = std::panic::catch_unwind(|| with_encoding(Encoding::Compact, || panic!()));
+        assert!(result.is_err());
+        assert_eq!(ENCODING.with(Cell::get), Encoding::Plain);
+    }
+
+    #[test]
+    fn test_compact_keeps_unrepresentable() {
+        let anim = AnimFloat::new(vec![
+            Keyframe::new(0.0, 1.0, 0),
+            Keyframe::new(Time::INFINITY, 2.0, 0),
+        ]);
+        let (_, decoded) = roundtrip(&anim, Encoding::Compact);
+        assert_eq!(decoded.keyframes[1].time, Time::INFINITY);
+    }
+}
@@ -0,0 +1,153 @@
+//! Extra chart data declared in a chart's extra.json
+//!
+//! Ported from prpr/src/core/effect.rs and prpr/src/core/video.rs
+//! Only the data side lives here; compiling and running shaders and decoding
+//! videos is up to the renderer.
+use super::{time, Anim, AnimFloat, AnimVector, Color, Time};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Animated value bound to a shader uniform
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Uniform {
+    Float(Anim<f32>),
+    Vec2(AnimVector),
+    Color(Anim<Color>),
+}
+
+impl Uniform {
+    pub fn set_time(&mut self, time: Time) {
+        match self {
+            Self::Float(anim) => anim.set_time(time),
+            Self::Vec2(anim) => anim.set_time(time),
+            Self::Color(anim) => anim.set_time(time),
+        }
+    }
+}
+
+/// Shader an effect runs
+#[derive(Clone, Serialize, Deserialize)]
+pub enum EffectShader {
+    /// One of Phira's built-in shaders, e.g. `chromatic` or `vignette`
+    Builtin(String),
+    /// GLSL source shipped with the chart
+    Custom {
+        /// Path inside the chart archive, used as cache key
+        path: String,
+        source: String,
+    },
+}
+
+/// A full-screen shader pass active over a time range
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Effect {
+    /// Active time range in seconds
+    #[serde(with = "time::range")]
+    pub time_range: Range<Time>,
+    pub shader: EffectShader,
+    /// Whether the effect also applies to the UI drawn over the chart
+    pub global: bool,
+    pub uniforms: Vec<(String, Uniform)>,
+}
+
+impl Effect {
+    pub fn set_time(&mut self, time: Time) {
+        for (_, uniform) in &mut self.uniforms {
+            uniform.set_time(time);
+        }
+    }
+
+    pub fn active(&self, time: Time) -> bool {
+        self.time_range.contains(&time)
+    }
+}
+
+/// How a background video is fitted to the screen
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VideoScale {
+    /// Cover the screen, cropping the overflow
+    #[default]
+    CropCenter,
+    /// Fit inside the screen, keeping the aspect ratio
+    Inside,
+    /// Stretch to the screen
+    Fit,
+}
+
+/// A background video shipped with the chart
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Video {
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    /// File extension, used to pick the MIME type
+    pub ext: String,
+    /// Chart time in seconds the video starts at
+    #[serde(with = "time")]
+    pub start_time: Time,
+    pub scale: VideoScale,
+    pub alpha: AnimFloat,
+    /// Opacity of the black overlay drawn over the video
+    pub dim: AnimFloat,
+}
+
+impl Video {
+    pub fn set_time(&mut self, time: Time) {
+        self.alpha.set_time(time);
+        self.dim.set_time(time);
+    }
+}
+
+/// Extra chart data from extra.json
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ChartExtra {
+    pub effects: Vec<Effect>,
+    pub videos: Vec<Video>,
+}
+
+impl ChartExtra {
+    pub fn set_time(&mut self, time: Time) {
+        for effect in &mut self.effects {
+            effect.set_time(time);
+        }
+        for video in &mut self.videos {
+            video.set_time(time);
+        }
+    }
+
+    /// Whether any shader effects are declared
+    pub fn has_effects(&self) -> bool {
+        !self.effects.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Keyframe;
+
+    #[test]
+    fn test_effect_uniform_animation() {
+        let mut effect = Effect {
+            time_range: 1.0..3.0,
+            shader: EffectShader::Builtin("chromatic".to_string()),
+            global: false,
+            uniforms: vec![(
+                "power".to_string(),
+                Uniform::Float(Anim::new(vec![
+                    Keyframe::new(1.0, 0.0, 2),
+                    Keyframe::new(3.0, 1.0, 0),
+                ])),
+            )],
+        };
+        assert!(!effect.active(0.5));
+        assert!(effect.active(1.0));
+        assert!(!effect.active(3.0));
+
+        effect.set_time(2.0);
+        let Uniform::Float(power) = &effect.uniforms[0].1 else {
+            unreachable!()
+        };
+        assert!((power.now() - 0.5).abs() < 1e-5);
+    }
+}
@@ -4,7 +4,7 @@
 //! Provides transform animations for chart elements (notes, judge lines).
 
 use super::anim::{AnimFloat, AnimVector};
-use super::{Matrix, Vector};
+use super::{time_from_f32, Matrix, Time, Vector};
 use nalgebra::Rotation2;
 use serde::{Deserialize, Serialize};
 
@@ -32,7 +32,7 @@ impl Object {
     }
 
     /// Set time for all animations
-    pub fn set_time(&mut self, time: f32) {
+    pub fn set_time(&mut self, time: Time) {
         self.alpha.set_time(time);
         self.scale.set_time(time);
         self.rotation.set_time(time);
@@ -136,9 +136,43 @@ pub struct CtrlObject {
     pub y: AnimFloat,
 }
 
+/// Control values for one note, all multipliers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CtrlValues {
+    pub alpha: f32,
+    pub size: f32,
+    pub pos: f32,
+    pub y: f32,
+}
+
+impl Default for CtrlValues {
+    fn default() -> Self {
+        Self {
+            alpha: 1.0,
+            size: 1.0,
+            pos: 1.0,
+            y: 1.0,
+        }
+    }
+}
+
 impl CtrlObject {
+    /// Values at `height` (distance from judge line) without mutating the
+    /// animations, so one control object can serve every note on the line.
+    pub fn eval_at(&self, height: f32) -> CtrlValues {
+        // Keyed by height in place of time
+        let height = time_from_f32(height);
+        CtrlValues {
+            alpha: self.alpha.value_at(height).unwrap_or(1.0),
+            size: self.size.value_at(height).unwrap_or(1.0),
+            pos: self.pos.value_at(height).unwrap_or(1.0),
+            y: self.y.value_at(height).unwrap_or(1.0),
+        }
+    }
+
     /// Set time using height (distance from judge line)
     pub fn set_height(&mut self, height: f32) {
+        let height = time_from_f32(height);
         self.alpha.set_time(height);
         self.size.set_time(height);
         self.pos.set_time(height);
@@ -149,6 +183,7 @@ impl CtrlObject {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::Keyframe;
 
     #[test]
     fn test_default_object() {
@@ -157,6 +192,21 @@ mod tests {
         assert_eq!(obj.now_alpha(), 1.0);
     }
 
+    #[test]
+    fn test_ctrl_eval_at() {
+        let ctrl = CtrlObject {
+            alpha: AnimFloat::new(vec![
+                Keyframe::new(0.0, 1.0, 2),
+                Keyframe::new(100.0, 0.0, 0),
+            ]),
+            ..Default::default()
+        };
+        let values = ctrl.eval_at(50.0);
+        assert!((values.alpha - 0.5).abs() < 0.001);
+        assert_eq!(values.size, 1.0);
+        assert_eq!(ctrl.eval_at(200.0).alpha, 0.0);
+    }
+
     #[test]
     fn test_rotation_matrix() {
         let obj = Object::default();
@@ -0,0 +1,136 @@
+//! Chart time
+//!
+//! Chart times are seconds in [`Time`], which is `f32` unless the
+//! `f64-time` feature is enabled. Past half an hour an `f32` only resolves
+//! about a quarter of a millisecond, which marathon charts and long
+//! monitoring sessions outgrow. Values animated over time (positions,
+//! heights, alpha) stay `f32`.
+//!
+//! Payloads record whether their times were written as `f32` or `f64`, so
+//! builds with and without the feature read each other's charts, see
+//! [`crate::payload`].
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+
+/// Chart time in seconds
+#[cfg(not(feature = "f64-time"))]
+pub type Time = f32;
+/// Chart time in seconds
+#[cfg(feature = "f64-time")]
+pub type Time = f64;
+
+/// Whether [`Time`] is `f64` in this build
+pub const WIDE_TIME: bool = cfg!(feature = "f64-time");
+
+/// Narrows a time, or a span of time, to `f32` for tween progress and
+/// drawing
+#[allow(clippy::unnecessary_cast)]
+#[inline]
+pub fn time_f32(time: Time) -> f32 {
+    time as f32
+}
+
+/// Widens a time to `f64`, for sums that must not drift
+#[allow(clippy::unnecessary_cast)]
+#[inline]
+pub fn time_f64(time: Time) -> f64 {
+    time as f64
+}
+
+/// Widens an `f32` number of seconds to a [`Time`]
+#[allow(clippy::unnecessary_cast)]
+#[inline]
+pub fn time_from_f32(secs: f32) -> Time {
+    secs as Time
+}
+
+thread_local! {
+    static WIRE_WIDE: Cell<bool> = const { Cell::new(WIDE_TIME) };
+}
+
+/// Puts the previous width back when dropped, also if `f` panics
+struct RestoreWidth(bool);
+
+impl Drop for RestoreWidth {
+    fn drop(&mut self) {
+        WIRE_WIDE.with(|w| w.set(self.0));
+    }
+}
+
+/// Runs `f` with times (de)serialized as `f64` if `wide`, else as `f32`.
+pub(crate) fn with_wide_times<R>(wide: bool, f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreWidth(WIRE_WIDE.with(|w| w.replace(wide)));
+    f()
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn serialize<S: Serializer>(time: &Time, serializer: S) -> Result<S::Ok, S::Error> {
+    if WIRE_WIDE.with(Cell::get) {
+        (*time as f64).serialize(serializer)
+    } else {
+        (*time as f32).serialize(serializer)
+    }
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Time, D::Error> {
+    Ok(if WIRE_WIDE.with(Cell::get) {
+        f64::deserialize(deserializer)? as Time
+    } else {
+        f32::deserialize(deserializer)? as Time
+    })
+}
+
+/// A `Range<Time>` field in the width of the payload
+pub(crate) mod range {
+    use super::Time;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::ops::Range;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wire {
+        #[serde(with = "super")]
+        start: Time,
+        #[serde(with = "super")]
+        end: Time,
+    }
+
+    pub(crate) fn serialize<S: Serializer>(
+        range: &Range<Time>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Wire {
+            start: range.start,
+            end: range.end,
+        }
+        .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Range<Time>, D::Error> {
+        let Wire { start, end } = Wire::deserialize(deserializer)?;
+        Ok(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::Options;
+
+    #[derive(Serialize, Deserialize)]
+    struct Timed(#[serde(with = "super")] Time);
+
+    #[test]
+    fn test_wire_width() {
+        let options = bincode::options().with_fixint_encoding();
+        let time = 2160.0001;
+        for (wide, len) in [(false, 4), (true, 8)] {
+            let bytes = with_wide_times(wide, || options.serialize(&Timed(time)).unwrap());
+            assert_eq!(bytes.len(), len);
+            let Timed(decoded) = with_wide_times(wide, || options.deserialize(&bytes).unwrap());
+            assert!((decoded - time).abs() < 1e-3);
+        }
+    }
+}
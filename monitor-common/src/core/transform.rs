@@ -0,0 +1,258 @@
+//! Whole-chart edits returning a modified copy
+//!
+//! Only animations keyed by time are retimed. Line control events are keyed
+//! by distance from the line and are left alone.
+use super::{
+    time_from_f32, Anim, Chart, JudgeLine, JudgeLineKind, NoteKind, Object, Time, Tweenable,
+    Uniform,
+};
+
+/// Something done to every time-keyed animation of a chart
+trait AnimVisitor {
+    fn visit<T: Tweenable>(&mut self, anim: &mut Anim<T>);
+}
+
+/// Moves keyframe times by `f`, which must be increasing
+struct Retime<F>(F);
+
+impl<F: Fn(Time) -> Time + Copy> AnimVisitor for Retime<F> {
+    fn visit<T: Tweenable>(&mut self, anim: &mut Anim<T>) {
+        anim.map_times(self.0);
+    }
+}
+
+struct Trim {
+    start: Time,
+    end: Time,
+}
+
+impl AnimVisitor for Trim {
+    fn visit<T: Tweenable>(&mut self, anim: &mut Anim<T>) {
+        anim.trim(self.start, self.end);
+    }
+}
+
+fn visit_object(object: &mut Object, v: &mut impl AnimVisitor) {
+    v.visit(&mut object.alpha);
+    v.visit(&mut object.scale.x);
+    v.visit(&mut object.scale.y);
+    v.visit(&mut object.rotation);
+    v.visit(&mut object.translation.x);
+    v.visit(&mut object.translation.y);
+}
+
+fn visit_line(line: &mut JudgeLine, v: &mut impl AnimVisitor) {
+    visit_object(&mut line.object, v);
+    v.visit(&mut line.height);
+    v.visit(&mut line.incline);
+    v.visit(&mut line.color);
+    match &mut line.kind {
+        JudgeLineKind::TextureGif(anim, ..) | JudgeLineKind::Paint(anim) => v.visit(anim),
+        JudgeLineKind::Text(anim) => v.visit(anim),
+        _ => {}
+    }
+    for note in &mut line.notes {
+        visit_object(&mut note.object, v);
+    }
+}
+
+fn visit_chart(chart: &mut Chart, v: &mut impl AnimVisitor) {
+    for line in &mut chart.lines {
+        visit_line(line, v);
+    }
+    for effect in &mut chart.extra.effects {
+        for (_, uniform) in &mut effect.uniforms {
+            match uniform {
+                Uniform::Float(anim) => v.visit(anim),
+                Uniform::Vec2(anim) => {
+                    v.visit(&mut anim.x);
+                    v.visit(&mut anim.y);
+                }
+                Uniform::Color(anim) => v.visit(anim),
+            }
+        }
+    }
+    for video in &mut chart.extra.videos {
+        v.visit(&mut video.alpha);
+        v.visit(&mut video.dim);
+    }
+}
+
+impl Chart {
+    /// The part of the chart between `start` and `end` seconds, starting at
+    /// time 0.
+    ///
+    /// Notes hit outside the window are dropped, keyframes are trimmed to
+    /// those the window needs and line heights are re-based so every line
+    /// starts at height 0. The offset is moved so the music still lines up.
+    pub fn slice(&self, start: Time, end: Time) -> Chart {
+        let mut chart = self.clone();
+        for line in &mut chart.lines {
+            let base = line.height.value_at(start).unwrap_or(0.0);
+            line.notes.retain(|note| (start..=end).contains(&note.time));
+            for note in &mut line.notes {
+                note.height -= base;
+                if let NoteKind::Hold { end_height, .. } = &mut note.kind {
+                    *end_height -= base;
+                }
+            }
+            // Only the first layer, the layers add up
+            for kf in &mut line.height.keyframes {
+                kf.value -= base;
+            }
+        }
+        chart
+            .extra
+            .effects
+            .retain(|effect| effect.time_range.end > start && effect.time_range.start < end);
+        visit_chart(&mut chart, &mut Trim { start, end });
+        chart.retime(|t| t - start);
+        chart
+    }
+
+    /// The chart flipped horizontally, like `ChartRenderer::mirror` in the
+    /// client: line X positions, line rotations and note X offsets are
+    /// negated.
+    pub fn mirrored(&self) -> Chart {
+        let mut chart = self.clone();
+        for line in &mut chart.lines {
+            line.object.translation.x.map_value(|x| -x);
+            line.object.rotation.map_value(|r| -r);
+            for note in &mut line.notes {
+                note.object.translation.x.map_value(|x| -x);
+            }
+        }
+        chart
+    }
+
+    /// The whole chart, music included, `delta` seconds later, e.g. to add
+    /// a lead-in before the first note.
+    pub fn shifted(&self, delta: Time) -> Chart {
+        let mut chart = self.clone();
+        chart.retime(|t| t + delta);
+        chart
+    }
+
+    /// The chart played `rate` times as fast. The music is not resampled,
+    /// players have to play it back at `rate` themselves.
+    pub fn with_speed(&self, rate: f32) -> Chart {
+        let mut chart = self.clone();
+        let time_rate = time_from_f32(rate);
+        chart.retime(|t| t / time_rate);
+        chart.bpm_list.map_bpm(|bpm| bpm * rate);
+        chart
+    }
+
+    /// Moves every time in the chart by `f`, which must be increasing
+    fn retime(&mut self, f: impl Fn(Time) -> Time + Copy) {
+        visit_chart(self, &mut Retime(f));
+        for note in self.lines.iter_mut().flat_map(|line| &mut line.notes) {
+            note.time = f(note.time);
+            if let NoteKind::Hold { end_time, .. } = &mut note.kind {
+                *end_time = f(*end_time);
+            }
+        }
+        for effect in &mut self.extra.effects {
+            effect.time_range = f(effect.time_range.start)..f(effect.time_range.end);
+        }
+        for video in &mut self.extra.videos {
+            video.start_time = f(video.start_time);
+        }
+        // Music position p plays at chart time p - offset
+        self.offset = -f(-self.offset);
+        self.bpm_list.map_times(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{AnimFloat, BpmList, Keyframe, Note};
+
+    fn sample() -> Chart {
+        let line = JudgeLine {
+            // One height unit per second
+            height: AnimFloat::new(vec![
+                Keyframe::new(0.0, 0.0, 2),
+                Keyframe::new(100.0, 100.0, 0),
+            ]),
+            notes: (0..10)
+                .map(|i| Note::new(NoteKind::Click, i as Time, i as f32))
+                .collect(),
+            ..Default::default()
+        };
+        Chart::new(0.5, vec![line], BpmList::new(vec![(0.0, 120.0)]))
+    }
+
+    #[test]
+    fn test_slice() {
+        let mut chart = sample().slice(3.0, 6.0);
+        assert_eq!(chart.offset, 3.5);
+
+        let line = &mut chart.lines[0];
+        let times: Vec<_> = line.notes.iter().map(|n| n.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(line.notes[1].height, 1.0);
+        line.set_time(0.0);
+        assert!(line.now_height().abs() < 1e-4);
+        line.set_time(2.0);
+        assert!((line.now_height() - 2.0).abs() < 1e-4);
+
+        // Beats keep their numbering, beat 6 was at 3s
+        assert!(chart.bpm_list.time_at_beats(6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let mut chart = sample();
+        let line = &mut chart.lines[0];
+        line.object.rotation = AnimFloat::fixed(30.0);
+        line.object.translation.x = AnimFloat::fixed(0.25);
+        line.notes[0].object.translation.x = AnimFloat::fixed(-0.5);
+
+        let mut chart = chart.mirrored();
+        chart.set_time(0.0);
+        let line = &chart.lines[0];
+        assert_eq!(line.object.rotation.now(), -30.0);
+        assert_eq!(line.object.translation.x.now(), -0.25);
+        assert_eq!(line.notes[0].object.translation.x.now(), 0.5);
+    }
+
+    #[test]
+    fn test_shifted() {
+        let mut chart = sample().shifted(2.0);
+        assert_eq!(chart.offset, -1.5);
+        assert_eq!(chart.lines[0].notes[0].time, 2.0);
+        let line = &mut chart.lines[0];
+        line.set_time(3.0);
+        assert!((line.now_height() - 1.0).abs() < 1e-4);
+        assert!((chart.bpm_list.time_at_beats(2.0) - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_with_speed() {
+        let mut chart = sample().with_speed(2.0);
+        assert_eq!(chart.offset, 0.25);
+        assert_eq!(chart.lines[0].notes[4].time, 2.0);
+        let line = &mut chart.lines[0];
+        line.set_time(1.0);
+        assert!((line.now_height() - 2.0).abs() < 1e-4);
+        assert_eq!(chart.bpm_list.bpm_at(1.0), 240.0);
+        assert!((chart.bpm_list.time_at_beats(4.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_slice_trims_keyframes() {
+        let mut chart = sample();
+        chart.lines[0].object.alpha = AnimFloat::new(
+            (0..10)
+                .map(|i| Keyframe::new(i as Time, i as f32, 2))
+                .collect(),
+        );
+        let mut chart = chart.slice(3.5, 5.5);
+        let alpha = &mut chart.lines[0].object.alpha;
+        assert_eq!(alpha.keyframes.len(), 4);
+        alpha.set_time(1.0);
+        assert!((alpha.now() - 4.5).abs() < 1e-4);
+    }
+}
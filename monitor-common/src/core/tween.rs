@@ -251,8 +251,8 @@ pub struct BezierTween {
 impl BezierTween {
     pub fn new(p1: (f32, f32), p2: (f32, f32)) -> Self {
         let mut sample_table = [0.0; SAMPLE_TABLE_SIZE];
-        for i in 0..SAMPLE_TABLE_SIZE {
-            sample_table[i] = Self::sample(p1.0, p2.0, i as f32 * SAMPLE_STEP);
+        for (i, sample) in sample_table.iter_mut().enumerate() {
+            *sample = Self::sample(p1.0, p2.0, i as f32 * SAMPLE_STEP);
         }
         Self {
             sample_table,
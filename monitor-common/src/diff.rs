@@ -0,0 +1,282 @@
+//! Differences between two versions of a chart
+//!
+//! Lines are matched by index. Notes on a line are matched by kind and time:
+//! a note found at the same time with the same kind but a different
+//! position, side, fake flag or hold end is reported as moved, anything else
+//! unmatched as added or removed. Line events are compared field by field on
+//! their serialized keyframes.
+use crate::core::{Chart, JudgeLine, Note, NoteKind, Time};
+use serde::Serialize;
+
+/// Notes this close in time (seconds) are considered simultaneous
+const TIME_EPS: Time = 1e-3;
+/// Positions this close are considered equal
+const X_EPS: f32 = 1e-4;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteRef {
+    /// Index into the line's notes
+    pub index: usize,
+    pub kind: &'static str,
+    pub time: Time,
+    pub x: f32,
+    pub above: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NoteMove {
+    pub from: NoteRef,
+    pub to: NoteRef,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineDiff {
+    pub line: usize,
+    pub added: Vec<NoteRef>,
+    pub removed: Vec<NoteRef>,
+    pub moved: Vec<NoteMove>,
+    /// Names of the line properties whose events differ
+    pub changed_events: Vec<&'static str>,
+}
+
+impl LineDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.changed_events.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartDiff {
+    /// Whether the chart offsets differ
+    pub offset_changed: bool,
+    /// Indices of lines only in the new chart
+    pub added_lines: Vec<usize>,
+    /// Indices of lines only in the old chart
+    pub removed_lines: Vec<usize>,
+    /// Lines present in both charts that changed
+    pub lines: Vec<LineDiff>,
+}
+
+impl ChartDiff {
+    pub fn is_empty(&self) -> bool {
+        !self.offset_changed
+            && self.added_lines.is_empty()
+            && self.removed_lines.is_empty()
+            && self.lines.is_empty()
+    }
+}
+
+fn kind_name(kind: &NoteKind) -> &'static str {
+    match kind {
+        NoteKind::Click => "click",
+        NoteKind::Hold { .. } => "hold",
+        NoteKind::Flick => "flick",
+        NoteKind::Drag => "drag",
+    }
+}
+
+fn note_x(note: &Note) -> f32 {
+    note.object.translation.x.value_at(note.time).unwrap_or(0.0)
+}
+
+fn note_ref(index: usize, note: &Note) -> NoteRef {
+    NoteRef {
+        index,
+        kind: kind_name(&note.kind),
+        time: note.time,
+        x: note_x(note),
+        above: note.above,
+    }
+}
+
+/// Same kind at the same time
+fn same_slot(a: &Note, b: &Note) -> bool {
+    kind_name(&a.kind) == kind_name(&b.kind) && (a.time - b.time).abs() <= TIME_EPS
+}
+
+fn same_note(a: &Note, b: &Note) -> bool {
+    let same_end = match (&a.kind, &b.kind) {
+        (NoteKind::Hold { end_time: a, .. }, NoteKind::Hold { end_time: b, .. }) => {
+            (a - b).abs() <= TIME_EPS
+        }
+        _ => true,
+    };
+    same_slot(a, b)
+        && same_end
+        && (note_x(a) - note_x(b)).abs() <= X_EPS
+        && a.above == b.above
+        && a.fake == b.fake
+}
+
+/// Serialized form of a property, for comparing events without requiring
+/// `PartialEq` on every animated type
+fn fingerprint<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).unwrap_or_default()
+}
+
+fn line_events(line: &JudgeLine) -> [(&'static str, Vec<u8>); 10] {
+    [
+        ("alpha", fingerprint(&line.object.alpha)),
+        ("scale", fingerprint(&line.object.scale)),
+        ("rotation", fingerprint(&line.object.rotation)),
+        ("translation", fingerprint(&line.object.translation)),
+        ("height", fingerprint(&line.height)),
+        ("incline", fingerprint(&line.incline)),
+        ("color", fingerprint(&line.color)),
+        ("ctrl", fingerprint(&line.ctrl_obj)),
+        ("kind", fingerprint(&line.kind)),
+        ("parent", fingerprint(&(line.parent, line.z_index))),
+    ]
+}
+
+fn diff_notes(old: &[Note], new: &[Note], diff: &mut LineDiff) {
+    // New notes by time, so candidates are found by binary search
+    let mut order: Vec<usize> = (0..new.len()).collect();
+    order.sort_by(|&a, &b| new[a].time.total_cmp(&new[b].time));
+    let mut used = vec![false; new.len()];
+
+    let candidates = |note: &Note| {
+        let start = order.partition_point(|&i| new[i].time < note.time - TIME_EPS);
+        let end = order.partition_point(|&i| new[i].time <= note.time + TIME_EPS);
+        order[start..end].iter().copied()
+    };
+
+    let mut unmatched = Vec::new();
+    for (i, note) in old.iter().enumerate() {
+        match candidates(note).find(|&j| !used[j] && same_note(note, &new[j])) {
+            Some(j) => used[j] = true,
+            None => unmatched.push(i),
+        }
+    }
+    for i in unmatched {
+        let note = &old[i];
+        let x = note_x(note);
+        let closest = candidates(note)
+            .filter(|&j| !used[j] && same_slot(note, &new[j]))
+            .min_by(|&a, &b| {
+                (note_x(&new[a]) - x)
+                    .abs()
+                    .total_cmp(&(note_x(&new[b]) - x).abs())
+            });
+        match closest {
+            Some(j) => {
+                used[j] = true;
+                diff.moved.push(NoteMove {
+                    from: note_ref(i, note),
+                    to: note_ref(j, &new[j]),
+                });
+            }
+            None => diff.removed.push(note_ref(i, note)),
+        }
+    }
+    diff.added = (0..new.len())
+        .filter(|&j| !used[j])
+        .map(|j| note_ref(j, &new[j]))
+        .collect();
+}
+
+/// Compares `new` against `old`.
+pub fn diff_charts(old: &Chart, new: &Chart) -> ChartDiff {
+    let common = old.lines.len().min(new.lines.len());
+    let mut diff = ChartDiff {
+        offset_changed: (old.offset - new.offset).abs() > Time::EPSILON,
+        added_lines: (common..new.lines.len()).collect(),
+        removed_lines: (common..old.lines.len()).collect(),
+        lines: Vec::new(),
+    };
+    for (i, (old_line, new_line)) in old.lines.iter().zip(&new.lines).enumerate() {
+        let mut line = LineDiff {
+            line: i,
+            ..Default::default()
+        };
+        diff_notes(&old_line.notes, &new_line.notes, &mut line);
+        line.changed_events = line_events(old_line)
+            .into_iter()
+            .zip(line_events(new_line))
+            .filter(|((_, a), (_, b))| a != b)
+            .map(|((name, _), _)| name)
+            .collect();
+        if !line.is_empty() {
+            diff.lines.push(line);
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{time_f32, AnimFloat, Keyframe};
+
+    fn note(kind: NoteKind, time: Time, x: f32) -> Note {
+        let mut note = Note::new(kind, time, time_f32(time));
+        note.object.translation.x = AnimFloat::fixed(x);
+        note
+    }
+
+    fn chart(lines: Vec<Vec<Note>>) -> Chart {
+        let lines = lines
+            .into_iter()
+            .map(|notes| JudgeLine {
+                notes,
+                ..Default::default()
+            })
+            .collect();
+        Chart::new(0.0, lines, Default::default())
+    }
+
+    #[test]
+    fn test_identical_charts() {
+        let notes = vec![
+            note(NoteKind::Click, 1.0, 0.0),
+            note(NoteKind::Drag, 2.0, 0.5),
+        ];
+        let diff = diff_charts(&chart(vec![notes.clone()]), &chart(vec![notes]));
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn test_note_changes() {
+        let old = chart(vec![vec![
+            note(NoteKind::Click, 1.0, 0.0),
+            note(NoteKind::Flick, 2.0, 0.2),
+            note(NoteKind::Drag, 3.0, 0.0),
+        ]]);
+        let new = chart(vec![
+            vec![
+                note(NoteKind::Flick, 2.0, -0.3),
+                note(NoteKind::Click, 1.0, 0.0),
+                note(NoteKind::Click, 4.0, 0.0),
+            ],
+            vec![],
+        ]);
+        let diff = diff_charts(&old, &new);
+        assert_eq!(diff.added_lines, vec![1]);
+        let line = &diff.lines[0];
+        assert_eq!(line.moved.len(), 1);
+        assert_eq!((line.moved[0].from.index, line.moved[0].to.index), (1, 0));
+        assert_eq!(line.removed.len(), 1);
+        assert_eq!(line.removed[0].kind, "drag");
+        assert_eq!(line.added.len(), 1);
+        assert_eq!(line.added[0].time, 4.0);
+        assert!(line.changed_events.is_empty());
+    }
+
+    #[test]
+    fn test_event_changes() {
+        let old = chart(vec![vec![]]);
+        let mut new = chart(vec![vec![]]);
+        new.lines[0].object.rotation = AnimFloat::new(vec![
+            Keyframe::new(0.0, 0.0, 2),
+            Keyframe::new(1.0, 90.0, 0),
+        ]);
+        let diff = diff_charts(&old, &new);
+        assert_eq!(diff.lines[0].changed_events, vec!["rotation"]);
+    }
+}
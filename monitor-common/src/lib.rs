@@ -1,3 +1,8 @@
 //! Phira Web Monitor - Common Types & Logic
 
 pub mod core;
+pub mod diff;
+pub mod parse;
+pub mod payload;
+pub mod respack;
+pub mod score;
@@ -1,3 +1,4 @@
+pub mod archive;
 pub mod extra;
 pub mod pbc;
 pub mod pec;
@@ -16,10 +17,14 @@ pub trait ResourceLoader: Send + Sync {
     ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send + 'a>>;
 }
 
-use monitor_common::core::{easing_from, JudgeLine, TweenId, TweenMajor, TweenMinor};
+use crate::core::{easing_from, JudgeLine, TweenId, TweenMajor, TweenMinor};
 use std::cmp::Ordering;
 
-pub(in crate::chart) fn process_lines(v: &mut [JudgeLine]) {
+pub(crate) fn process_lines(v: &mut [JudgeLine]) {
+    // Number notes in source order, before anything re-sorts them
+    for (id, note) in v.iter_mut().flat_map(|line| &mut line.notes).enumerate() {
+        note.id = id as u32;
+    }
     let mut times = Vec::new();
     // TODO optimize using k-merge sort
     let sorts = v
@@ -97,3 +102,28 @@ pub const RPE_TWEEN_MAP: [TweenId; 30] = {
         easing_from(Bounce, InOut), easing_from(Elastic, InOut), // 28, 29
     ]
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{time_f32, Note, NoteKind, Time};
+
+    #[test]
+    fn test_note_ids() {
+        let line = |times: &[Time]| JudgeLine {
+            notes: times
+                .iter()
+                .map(|&t| Note::new(NoteKind::Click, t, time_f32(t)))
+                .collect(),
+            ..Default::default()
+        };
+        let mut lines = vec![line(&[2.0, 1.0]), line(&[1.0])];
+        process_lines(&mut lines);
+        let ids: Vec<_> = lines
+            .iter()
+            .flat_map(|line| line.notes.iter().map(|note| note.id))
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert!(lines[0].notes[1].multiple_hint);
+    }
+}
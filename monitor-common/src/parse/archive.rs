@@ -0,0 +1,280 @@
+//! Chart archive (zip) loading, shared by the proxy and the browser client.
+
+use super::{extra, pbc, pec, pgr, rpe, ResourceLoader};
+use crate::core::{AudioClip, Chart, ChartExtra, ChartFormat, ChartInfo, HitSound};
+use anyhow::Context;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, Mutex};
+
+struct ZipLoader {
+    archive: Arc<Mutex<zip::ZipArchive<Cursor<Vec<u8>>>>>,
+}
+
+impl ResourceLoader for ZipLoader {
+    fn load_file<'a>(
+        &'a mut self,
+        path: &'a str,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<Vec<u8>>> + Send + 'a>>
+    {
+        let archive = self.archive.clone();
+        let path = path.to_string();
+        Box::pin(async move {
+            let mut archive = archive.lock().unwrap();
+            let mut file = archive.by_name(&path)?;
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+            Ok(buffer)
+        })
+    }
+}
+
+/// Detect the chart format from the raw chart file.
+pub fn detect_format(chart_bytes: &[u8]) -> ChartFormat {
+    if chart_bytes.first() == Some(&b'{') {
+        if chart_bytes.windows(4).any(|w| w == b"META") {
+            log::info!("Detected RPE chart");
+            ChartFormat::Rpe
+        } else {
+            log::info!("Detected PGR chart");
+            ChartFormat::Pgr
+        }
+    } else if chart_bytes.first().is_some_and(u8::is_ascii) {
+        log::info!("Detected PEC chart");
+        ChartFormat::Pec
+    } else {
+        log::info!("Detected PBC chart");
+        ChartFormat::Pbc
+    }
+}
+
+/// Compressed audio of a chart, as stored in its zip
+#[derive(Default)]
+pub struct AudioFiles {
+    /// Music bytes and file extension
+    pub music: Option<(Vec<u8>, String)>,
+    /// Hitsound bytes and file extensions, from extra.json and RPE notes
+    pub hitsounds: Vec<(HitSound, Vec<u8>, String)>,
+}
+
+/// Parse a chart zip (info.yml, chart file, music, extra.json and the files
+/// they reference) into a playable chart.
+pub async fn parse_chart_zip(zip_bytes: Vec<u8>) -> anyhow::Result<(ChartInfo, Chart)> {
+    let (info, mut chart, audio) = parse_chart_zip_with_audio(zip_bytes).await?;
+    load_audio_into_chart(&info, audio, &mut chart);
+    Ok((info, chart))
+}
+
+/// Like [`parse_chart_zip`], but leaves the audio compressed for serving on
+/// its own. The chart comes without music, and its `hitsounds` only hold the
+/// custom RPE hitsounds the RPE parser already decoded.
+/// Audio is pre-extracted from the zip BEFORE format-specific parsing,
+/// so zip_bytes can safely be moved into RPE's ZipLoader.
+pub async fn parse_chart_zip_with_audio(
+    zip_bytes: Vec<u8>,
+) -> anyhow::Result<(ChartInfo, Chart, AudioFiles)> {
+    // Open zip archive — borrow, no clone
+    let mut zip = zip::ZipArchive::new(Cursor::new(&zip_bytes[..]))?;
+
+    // Read info.yml
+    let mut info: ChartInfo = serde_yaml::from_reader(
+        zip.by_path("info.yml")
+            .with_context(|| "Cannot find info.yml in chart zip")?,
+    )
+    .with_context(|| "Failed to parse info.yml")?;
+
+    // Read chart file
+    let mut chart_bytes = Vec::new();
+    zip.by_path(&info.chart)
+        .with_context(|| "Cannot find chart file")?
+        .read_to_end(&mut chart_bytes)
+        .with_context(|| "Failed to read chart file")?;
+
+    // Read extra.json (optional)
+    let extra_json = zip
+        .by_path("extra.json")
+        .and_then(|mut file| {
+            let mut s = String::new();
+            file.read_to_string(&mut s)?;
+            Ok(Some(s))
+        })
+        .unwrap_or(None);
+
+    // Extract audio BEFORE format dispatch (while we still borrow zip_bytes)
+    log::info!("Extracting audio resources...");
+    let music_data = extract_file_bytes(&mut zip, &info.music);
+    let mut hitsound_data = extract_hitsound_bytes(&mut zip, &extra_json);
+    let chart_extra = extract_chart_extra(&mut zip, &extra_json);
+
+    // Detect format from raw bytes (no clone needed)
+    let format = info
+        .format
+        .clone()
+        .unwrap_or_else(|| detect_format(&chart_bytes));
+    info.format = Some(format.clone());
+
+    // Drop the borrow-based zip so we can move zip_bytes if needed (RPE)
+    drop(zip);
+
+    // Parse chart
+    let mut chart = match format {
+        ChartFormat::Rpe => {
+            let chart_text = String::from_utf8(chart_bytes)
+                .map_err(|e| anyhow::anyhow!("Invalid UTF-8: {}", e))?;
+            // Move zip_bytes into the RPE loader (no clone)
+            let archive = Arc::new(Mutex::new(zip::ZipArchive::new(Cursor::new(zip_bytes))?));
+            let mut loader = ZipLoader { archive };
+            let mut chart = rpe::parse_rpe(&chart_text, &mut loader)
+                .await
+                .map_err(|e| anyhow::anyhow!("RPE parse error: {}", e))?;
+            // extra.json wins over the notes' own hitsound files
+            chart
+                .hitsounds
+                .retain(|kind, _| !hitsound_data.iter().any(|(mapped, ..)| mapped == kind));
+            for kind in chart.hitsounds.keys() {
+                if let HitSound::Custom(path) = kind {
+                    if let Ok(bytes) = loader.load_file(path).await {
+                        hitsound_data.push((kind.clone(), bytes, extension(path, "mp3")));
+                    }
+                }
+            }
+            chart
+        }
+        ChartFormat::Pgr => {
+            let chart_text = String::from_utf8(chart_bytes)
+                .map_err(|e| anyhow::anyhow!("Invalid UTF-8: {}", e))?;
+            pgr::parse_pgr(&chart_text)
+                .await
+                .map_err(|e| anyhow::anyhow!("PGR parse error: {}", e))?
+        }
+        ChartFormat::Pec => {
+            let chart_text = String::from_utf8(chart_bytes)
+                .map_err(|e| anyhow::anyhow!("Invalid UTF-8: {}", e))?;
+            pec::parse_pec(&chart_text)
+                .await
+                .map_err(|e| anyhow::anyhow!("PEC parse error: {}", e))?
+        }
+        ChartFormat::Pbc => pbc::parse_pbc(&chart_bytes)
+            .await
+            .map_err(|e| anyhow::anyhow!("PBC parse error: {}", e))?,
+    };
+
+    chart.extra = chart_extra;
+    let audio = AudioFiles {
+        music: music_data,
+        hitsounds: hitsound_data,
+    };
+
+    Ok((info, chart, audio))
+}
+
+// ── Audio Extraction Helpers ───────────────────────────────────────────────────
+
+fn extension(path: &str, default: &str) -> String {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or(default)
+        .to_string()
+}
+
+/// The hitsound an extra.json key stands for
+fn hitsound_kind(key: String) -> HitSound {
+    match key.to_lowercase().as_str() {
+        "click" => HitSound::Click,
+        "drag" => HitSound::Drag,
+        "flick" => HitSound::Flick,
+        _ => HitSound::Custom(key),
+    }
+}
+
+/// Extract raw bytes of a single file from the zip.
+fn extract_file_bytes(
+    zip: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    path: &str,
+) -> Option<(Vec<u8>, String)> {
+    let ext = extension(path, "mp3");
+    let mut file = zip.by_path(path).ok()?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    Some((bytes, ext))
+}
+
+/// Extract hitsound files referenced in extra.json.
+fn extract_hitsound_bytes(
+    zip: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    extra_json: &Option<String>,
+) -> Vec<(HitSound, Vec<u8>, String)> {
+    let mut result = Vec::new();
+    let Some(extra_source) = extra_json else {
+        return result;
+    };
+    let Ok(extra) = extra::parse_extra(extra_source) else {
+        return result;
+    };
+    let Some(mappings) = extra.hitsounds else {
+        return result;
+    };
+    for (kind_str, filename) in mappings {
+        if let Ok(mut file) = zip.by_name(&filename) {
+            let mut bytes = Vec::new();
+            if file.read_to_end(&mut bytes).is_ok() {
+                let ext = extension(&filename, "wav");
+                result.push((hitsound_kind(kind_str), bytes, ext));
+            }
+        }
+    }
+    result
+}
+
+/// Parse the effects and videos declared in extra.json, reading referenced
+/// files from the zip. A broken extra.json is logged and dropped rather than
+/// failing the chart.
+fn extract_chart_extra(
+    zip: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    extra_json: &Option<String>,
+) -> ChartExtra {
+    let Some(extra_source) = extra_json else {
+        return ChartExtra::default();
+    };
+    let result = extra::parse_extra(extra_source).and_then(|extra| {
+        extra::parse_chart_extra(&extra, |path| {
+            let mut bytes = Vec::new();
+            zip.by_name(path)?.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })
+    });
+    result.unwrap_or_else(|e| {
+        log::warn!("Failed to parse extra.json: {:#}", e);
+        ChartExtra::default()
+    })
+}
+
+/// Decode pre-extracted audio bytes and load them into the chart, skipping
+/// hitsounds the chart parser already decoded.
+fn load_audio_into_chart(info: &ChartInfo, audio: AudioFiles, chart: &mut Chart) {
+    if let Some((bytes, ext)) = audio.music {
+        match AudioClip::load_from_bytes(&bytes, &ext) {
+            Ok(clip) => {
+                log::info!(
+                    "Music Loaded: {} Hz, {} channels",
+                    clip.sample_rate,
+                    clip.channel_count
+                );
+                chart.music = Some(clip);
+            }
+            Err(e) => log::warn!("Failed to decode music {}: {}", info.music, e),
+        }
+    }
+
+    for (kind, bytes, ext) in audio.hitsounds {
+        if chart.hitsounds.contains_key(&kind) {
+            continue;
+        }
+        match AudioClip::load_from_bytes(&bytes, &ext) {
+            Ok(clip) => {
+                chart.hitsounds.insert(kind, clip);
+            }
+            Err(e) => log::warn!("Failed to decode hitsound: {}", e),
+        }
+    }
+}
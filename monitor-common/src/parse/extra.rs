@@ -0,0 +1,268 @@
+use super::RPE_TWEEN_MAP;
+use crate::core::{
+    Anim, AnimVector, BpmList, ChartExtra, Color, Effect, EffectShader, Keyframe, Triple,
+    Tweenable, Uniform, Video, VideoScale,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct ExtraJson {
+    pub hitsounds: Option<HashMap<String, String>>,
+    #[serde(default)]
+    bpm: Vec<ExtBpmItem>,
+    #[serde(default)]
+    effects: Vec<ExtEffect>,
+    #[serde(default)]
+    videos: Vec<ExtVideo>,
+}
+
+#[derive(Deserialize)]
+struct ExtBpmItem {
+    time: Triple,
+    bpm: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExtEvent<T> {
+    #[serde(default = "linear_easing")]
+    easing_type: i32,
+    start_time: Triple,
+    end_time: Triple,
+    start: T,
+    end: T,
+}
+
+fn linear_easing() -> i32 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExtAnim<V> {
+    Fixed(V),
+    Keyframes(Vec<ExtEvent<V>>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExtVariable {
+    Float(ExtAnim<f32>),
+    Vec2(ExtAnim<(f32, f32)>),
+    Color(ExtAnim<[u8; 4]>),
+}
+
+#[derive(Deserialize)]
+struct ExtEffect {
+    start: Triple,
+    end: Triple,
+    shader: String,
+    #[serde(default)]
+    global: bool,
+    #[serde(default)]
+    vars: HashMap<String, ExtVariable>,
+}
+
+#[derive(Deserialize)]
+struct ExtVideo {
+    path: String,
+    #[serde(default = "triple_zero")]
+    time: Triple,
+    #[serde(default)]
+    scale: VideoScale,
+    #[serde(default = "fixed_one")]
+    alpha: ExtAnim<f32>,
+    #[serde(default = "fixed_zero")]
+    dim: ExtAnim<f32>,
+}
+
+fn triple_zero() -> Triple {
+    Triple(0, 0, 1)
+}
+
+fn fixed_one() -> ExtAnim<f32> {
+    ExtAnim::Fixed(1.)
+}
+
+fn fixed_zero() -> ExtAnim<f32> {
+    ExtAnim::Fixed(0.)
+}
+
+pub fn parse_extra(source: &str) -> Result<ExtraJson> {
+    let extra: ExtraJson = serde_json::from_str(source)?;
+    Ok(extra)
+}
+
+fn parse_anim<V: Clone, T: Tweenable>(
+    r: &mut BpmList,
+    anim: &ExtAnim<V>,
+    f: impl Fn(&V) -> T,
+) -> Anim<T> {
+    match anim {
+        ExtAnim::Fixed(v) => Anim::fixed(f(v)),
+        ExtAnim::Keyframes(events) => {
+            let mut kfs = Vec::new();
+            for e in events {
+                let tween = RPE_TWEEN_MAP
+                    .get(e.easing_type.max(1) as usize)
+                    .copied()
+                    .unwrap_or(RPE_TWEEN_MAP[0]);
+                kfs.push(Keyframe::new(r.time_at(&e.start_time), f(&e.start), tween));
+                kfs.push(Keyframe::new(r.time_at(&e.end_time), f(&e.end), 0));
+            }
+            Anim::new(kfs)
+        }
+    }
+}
+
+fn parse_uniform(r: &mut BpmList, var: &ExtVariable) -> Uniform {
+    match var {
+        ExtVariable::Float(anim) => Uniform::Float(parse_anim(r, anim, |v| *v)),
+        ExtVariable::Vec2(anim) => Uniform::Vec2(AnimVector::new(
+            parse_anim(r, anim, |v| v.0),
+            parse_anim(r, anim, |v| v.1),
+        )),
+        ExtVariable::Color(anim) => Uniform::Color(parse_anim(r, anim, |&[r, g, b, a]| {
+            Color::from_rgba(r, g, b, a)
+        })),
+    }
+}
+
+/// Build the chart's effects and videos. Shaders starting with `/` are custom
+/// GLSL files inside the chart archive; they and the videos are read through
+/// `load_file`. Videos that fail to load are skipped.
+pub fn parse_chart_extra(
+    extra: &ExtraJson,
+    mut load_file: impl FnMut(&str) -> Result<Vec<u8>>,
+) -> Result<ChartExtra> {
+    let mut r = BpmList::new(
+        extra
+            .bpm
+            .iter()
+            .map(|item| (item.time.beats(), item.bpm))
+            .collect(),
+    );
+    let mut effects = Vec::with_capacity(extra.effects.len());
+    for effect in &extra.effects {
+        let shader = match effect.shader.strip_prefix('/') {
+            Some(path) => {
+                let source = load_file(path)
+                    .and_then(|bytes| Ok(String::from_utf8(bytes)?))
+                    .with_context(|| format!("Failed to load shader {}", path))?;
+                EffectShader::Custom {
+                    path: path.to_string(),
+                    source,
+                }
+            }
+            None => EffectShader::Builtin(effect.shader.clone()),
+        };
+        let mut uniforms: Vec<_> = effect
+            .vars
+            .iter()
+            .map(|(name, var)| (name.clone(), parse_uniform(&mut r, var)))
+            .collect();
+        uniforms.sort_by(|a, b| a.0.cmp(&b.0));
+        effects.push(Effect {
+            time_range: r.time_at(&effect.start)..r.time_at(&effect.end),
+            shader,
+            global: effect.global,
+            uniforms,
+        });
+    }
+
+    let mut videos = Vec::with_capacity(extra.videos.len());
+    for video in &extra.videos {
+        let data = match load_file(&video.path) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("Failed to load video {}: {}", video.path, e);
+                continue;
+            }
+        };
+        let ext = std::path::Path::new(&video.path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("mp4")
+            .to_lowercase();
+        videos.push(Video {
+            data,
+            ext,
+            start_time: r.time_at(&video.time),
+            scale: video.scale,
+            alpha: parse_anim(&mut r, &video.alpha, |v| *v),
+            dim: parse_anim(&mut r, &video.dim, |v| *v),
+        });
+    }
+    Ok(ChartExtra { effects, videos })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chart_extra() {
+        let extra = parse_extra(
+            r#"{
+                "bpm": [{ "time": [0, 0, 1], "bpm": 60 }],
+                "effects": [
+                    {
+                        "start": [2, 0, 1],
+                        "end": [4, 0, 1],
+                        "shader": "chromatic",
+                        "vars": {
+                            "power": [
+                                { "startTime": [2, 0, 1], "endTime": [4, 0, 1], "start": 0, "end": 1 }
+                            ],
+                            "center": [0.5, 0.5],
+                            "color": [255, 0, 0, 255]
+                        }
+                    },
+                    { "start": [0, 0, 1], "end": [1, 0, 1], "shader": "/fx.glsl", "global": true }
+                ],
+                "videos": [
+                    { "path": "bg.MP4", "time": [1, 0, 1], "scale": "inside", "dim": 0.5 },
+                    { "path": "missing.mp4" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut extra = parse_chart_extra(&extra, |path| match path {
+            "missing.mp4" => anyhow::bail!("not found"),
+            _ => Ok(format!("// {}", path).into_bytes()),
+        })
+        .unwrap();
+        assert_eq!(extra.effects.len(), 2);
+
+        let effect = &extra.effects[0];
+        assert_eq!(effect.time_range, 2.0..4.0);
+        assert!(!effect.global);
+        let names: Vec<_> = effect.uniforms.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["center", "color", "power"]);
+        assert!(matches!(effect.uniforms[0].1, Uniform::Vec2(_)));
+        assert!(matches!(effect.uniforms[1].1, Uniform::Color(_)));
+
+        extra.set_time(3.0);
+        let Uniform::Float(power) = &extra.effects[0].uniforms[2].1 else {
+            panic!("power should be a float uniform");
+        };
+        assert!((power.now() - 0.5).abs() < 1e-5);
+
+        let effect = &extra.effects[1];
+        assert!(effect.global);
+        assert!(matches!(
+            &effect.shader,
+            EffectShader::Custom { path, source } if path == "fx.glsl" && source == "// fx.glsl"
+        ));
+
+        assert_eq!(extra.videos.len(), 1);
+        let video = &extra.videos[0];
+        assert_eq!(video.ext, "mp4");
+        assert_eq!(video.start_time, 1.0);
+        assert!(matches!(video.scale, VideoScale::Inside));
+        assert_eq!(video.alpha.now(), 1.0);
+        assert_eq!(video.dim.now(), 0.5);
+    }
+}
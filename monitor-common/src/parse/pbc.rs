@@ -1,11 +1,11 @@
 use super::process_lines;
+use crate::core::{
+    time_from_f32, Anim, AnimVector, BezierTween, BpmList, Chart, ChartSettings, ClampedTween,
+    CtrlObject, JudgeLine, JudgeLineKind, Keyframe, Note, NoteKind, Object, Texture, Time, TweenFn,
+    Tweenable, UIElement,
+};
 use anyhow::{bail, Result};
 use byteorder::{LittleEndian as LE, ReadBytesExt};
-use monitor_common::core::{
-    Anim, AnimVector, BezierTween, BpmList, Chart, ChartSettings, ClampedTween, CtrlObject,
-    JudgeLine, JudgeLineKind, Keyframe, Note, NoteKind, Object, Texture, TweenFn, Tweenable,
-    UIElement,
-};
 use std::io::Read;
 
 pub struct BinaryReader<R: Read> {
@@ -25,9 +25,9 @@ impl<R: Read> BinaryReader<R> {
         self.time_cursor = 0;
     }
 
-    pub fn time(&mut self) -> Result<f32> {
+    pub fn time(&mut self) -> Result<Time> {
         self.time_cursor += self.uleb()? as u32;
-        Ok(self.time_cursor as f32 / 1000.0)
+        Ok(self.time_cursor as Time / 1000.0)
     }
 
     pub fn uleb(&mut self) -> Result<u64> {
@@ -102,23 +102,19 @@ fn read_keyframe<T: BinaryRead>(r: &mut BinaryReader<impl Read>) -> Result<Keyfr
     Ok(Keyframe { time, value, tween })
 }
 
-fn read_anim<T: BinaryRead + Tweenable>(
-    r: &mut BinaryReader<impl Read>,
-) -> Result<Option<Box<Anim<T>>>> {
-    Ok(match r.read_u8()? {
-        0 => None,
-        x => {
-            let mut res = if x == 1 {
-                Anim::default()
-            } else {
+fn read_anim<T: BinaryRead + Tweenable>(r: &mut BinaryReader<impl Read>) -> Result<Anim<T>> {
+    let mut tracks = Vec::new();
+    loop {
+        match r.read_u8()? {
+            0 => break,
+            1 => tracks.push(Anim::default()),
+            _ => {
                 r.reset_time();
-                let kfs = r.read_array(|r| read_keyframe(r))?;
-                Anim::new(kfs)
-            };
-            res.next = read_anim(r)?;
-            Some(Box::new(res))
+                tracks.push(Anim::new(r.read_array(|r| read_keyframe(r))?));
+            }
         }
-    })
+    }
+    Ok(Anim::layered(tracks))
 }
 
 trait BinaryRead: Sized {
@@ -140,15 +136,15 @@ impl BinaryRead for String {
 impl BinaryRead for Object {
     fn read_binary(r: &mut BinaryReader<impl Read>) -> Result<Self> {
         Ok(Self {
-            alpha: *read_anim(r)?.unwrap_or_default(),
+            alpha: read_anim(r)?,
             scale: AnimVector {
-                x: *read_anim(r)?.unwrap_or_default(),
-                y: *read_anim(r)?.unwrap_or_default(),
+                x: read_anim(r)?,
+                y: read_anim(r)?,
             },
-            rotation: *read_anim(r)?.unwrap_or_default(),
+            rotation: read_anim(r)?,
             translation: AnimVector {
-                x: *read_anim(r)?.unwrap_or_default(),
-                y: *read_anim(r)?.unwrap_or_default(),
+                x: read_anim(r)?,
+                y: read_anim(r)?,
             },
         })
     }
@@ -159,7 +155,7 @@ fn read_note(r: &mut BinaryReader<impl Read>) -> Result<Note> {
     let kind = match r.read_u8()? {
         0 => NoteKind::Click,
         1 => NoteKind::Hold {
-            end_time: r.read_f32()?,
+            end_time: time_from_f32(r.read_f32()?),
             end_height: r.read_f32()?,
         },
         2 => NoteKind::Flick,
@@ -185,12 +181,12 @@ fn read_judge_line(r: &mut BinaryReader<impl Read>) -> Result<JudgeLine> {
     let object = Object::read_binary(r)?;
     let kind = match r.read_u8()? {
         0 => JudgeLineKind::Normal,
-        1 => JudgeLineKind::Texture(Texture::empty().into(), r.read_string()?),
-        2 => JudgeLineKind::Text(*read_anim::<String>(r)?.unwrap_or_default()),
-        3 => JudgeLineKind::Paint(*read_anim::<f32>(r)?.unwrap_or_default()),
+        1 => JudgeLineKind::Texture(Texture::empty(), r.read_string()?),
+        2 => JudgeLineKind::Text(read_anim::<String>(r)?),
+        3 => JudgeLineKind::Paint(read_anim::<f32>(r)?),
         _ => bail!("invalid judge line kind"),
     };
-    let height = *read_anim::<f32>(r)?.unwrap_or_default();
+    let height = read_anim::<f32>(r)?;
     let notes = r.read_array(|r| read_note(r))?;
 
     // Skip color (4 u8s)
@@ -222,13 +218,13 @@ fn read_judge_line(r: &mut BinaryReader<impl Read>) -> Result<JudgeLine> {
 
     assert_eq!(r.read_u8()?, 8);
     let ctrl_obj = CtrlObject {
-        alpha: *read_anim::<f32>(r)?.unwrap_or_default(),
-        size: *read_anim::<f32>(r)?.unwrap_or_default(),
-        pos: *read_anim::<f32>(r)?.unwrap_or_default(),
-        y: *read_anim::<f32>(r)?.unwrap_or_default(),
+        alpha: read_anim::<f32>(r)?,
+        size: read_anim::<f32>(r)?,
+        pos: read_anim::<f32>(r)?,
+        y: read_anim::<f32>(r)?,
     };
 
-    let incline = *read_anim::<f32>(r)?.unwrap_or_default();
+    let incline = read_anim::<f32>(r)?;
     let z_index = r.read_i32()?;
 
     Ok(JudgeLine {
@@ -248,8 +244,8 @@ fn read_judge_line(r: &mut BinaryReader<impl Read>) -> Result<JudgeLine> {
 
 pub async fn parse_pbc(source: &[u8]) -> Result<Chart> {
     let mut r = BinaryReader::new(source);
-    let offset = r.read_f32()?;
-    let mut lines = r.read_array(|r| read_judge_line(r))?;
+    let offset = time_from_f32(r.read_f32()?);
+    let mut lines = r.read_array(read_judge_line)?;
     process_lines(&mut lines);
     let mut chart = Chart::new(offset, lines, BpmList::default());
     chart.settings = ChartSettings {
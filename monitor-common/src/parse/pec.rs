@@ -1,7 +1,7 @@
 use super::{process_lines, RPE_TWEEN_MAP};
-use monitor_common::core::{
-    Anim, AnimFloat, AnimVector, BpmList, Chart, JudgeLine, JudgeLineKind, Keyframe, Note,
-    NoteKind, Object, TweenId, EPS,
+use crate::core::{
+    time_f64, time_from_f32, Anim, AnimFloat, AnimVector, BpmList, Chart, JudgeLine, JudgeLineKind,
+    Keyframe, Note, NoteKind, Object, Time, TweenId, EPS,
 };
 use ordered_float::{Float, NotNan};
 
@@ -21,7 +21,7 @@ trait Take {
     fn take_f32(&mut self) -> Result<f32>;
     fn take_usize(&mut self) -> Result<usize>;
     fn take_tween(&mut self) -> Result<TweenId>;
-    fn take_time(&mut self, b: &mut BpmList) -> Result<f32>;
+    fn take_time(&mut self, b: &mut BpmList) -> Result<Time>;
 }
 
 impl<'a, T: Iterator<Item = &'a str>> Take for T {
@@ -52,20 +52,20 @@ impl<'a, T: Iterator<Item = &'a str>> Take for T {
             .context("expected tween")
     }
 
-    fn take_time(&mut self, b: &mut BpmList) -> Result<f32> {
-        self.take_f32().map(|it| b.time_at_beats(it))
+    fn take_time(&mut self, b: &mut BpmList) -> Result<Time> {
+        self.take_f32().map(|it| b.time_at_beats(time_from_f32(it)))
     }
 }
 
 struct PECEvent {
-    pub start_time: f32,
-    pub end_time: f32,
+    pub start_time: Time,
+    pub end_time: Time,
     pub end: f32,
     pub easing: TweenId,
 }
 
 impl PECEvent {
-    pub fn new(start_time: f32, end_time: f32, end: f32, tween: TweenId) -> Self {
+    pub fn new(start_time: Time, end_time: Time, end: f32, tween: TweenId) -> Self {
         Self {
             start_time,
             end_time,
@@ -74,14 +74,14 @@ impl PECEvent {
         }
     }
 
-    pub fn single(time: f32, value: f32) -> Self {
+    pub fn single(time: Time, value: f32) -> Self {
         Self::new(time, time, value, 0)
     }
 }
 
 #[derive(Default)]
 struct PECJudgeLine {
-    pub speed_events: Vec<(f32, f32)>,
+    pub speed_events: Vec<(Time, f32)>,
     pub alpha_events: Vec<PECEvent>,
     pub move_events: (Vec<PECEvent>, Vec<PECEvent>),
     pub rotate_events: Vec<PECEvent>,
@@ -90,7 +90,7 @@ struct PECJudgeLine {
 
 fn sanitize_events(events: &mut [PECEvent], id: usize, desc: &str) {
     events.sort_by_key(|e| (e.end_time.not_nan(), e.start_time.not_nan()));
-    let mut last_end = f32::NEG_INFINITY;
+    let mut last_end = Time::NEG_INFINITY;
     for e in events.iter_mut() {
         if e.start_time < last_end {
             log::warn!(
@@ -127,33 +127,44 @@ fn parse_events(mut events: Vec<PECEvent>, id: usize, desc: &str) -> Result<Anim
     Ok(AnimFloat::new(kfs))
 }
 
-fn parse_speed_events(mut pec: Vec<(f32, f32)>, max_time: f32) -> AnimFloat {
+/// Integrates `cv` speeds into line heights. Negative speeds move the line
+/// back, so heights may decrease.
+fn parse_speed_events(mut pec: Vec<(Time, f32)>, id: usize, max_time: Time) -> AnimFloat {
     if pec.is_empty() {
         return AnimFloat::default();
     }
-    if pec[0].0 >= EPS {
+    if pec.windows(2).any(|w| w[1].0 < w[0].0) {
+        log::warn!(
+            "Speed events for judge line {} are out of order, sorting them",
+            id
+        );
+        // Stable, so the later of two events at the same time still wins
+        pec.sort_by_key(|e| e.0.not_nan());
+    }
+    if pec[0].0 >= EPS as Time {
         pec.insert(0, (0., 0.));
     }
     let mut kfs = Vec::new();
-    let mut height = 0.0;
+    // Summed in f64 so long charts do not drift
+    let mut height = 0.0f64;
     let mut last_time = 0.0;
     let mut last_speed = 0.0;
     for (time, speed) in pec {
-        height += (time - last_time) * last_speed;
-        kfs.push(Keyframe::new(time, height, 2));
+        height += time_f64(time - last_time) * last_speed as f64;
+        kfs.push(Keyframe::new(time, height as f32, 2));
         last_time = time;
         last_speed = speed;
     }
     kfs.push(Keyframe::new(
         max_time,
-        height + (max_time - last_time) * last_speed,
+        (height + time_f64(max_time - last_time) * last_speed as f64) as f32,
         0,
     ));
     AnimFloat::new(kfs)
 }
 
-fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<JudgeLine> {
-    let mut height = parse_speed_events(pec.speed_events, max_time);
+fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: Time) -> Result<JudgeLine> {
+    let mut height = parse_speed_events(pec.speed_events, id, max_time);
     for note in &mut pec.notes {
         height.set_time(note.time);
         note.height = height.now();
@@ -193,7 +204,7 @@ fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<J
                 y: AnimFloat::default(),
             },
         },
-        ctrl_obj: monitor_common::core::CtrlObject::default(),
+        ctrl_obj: crate::core::CtrlObject::default(),
         kind: JudgeLineKind::Normal,
         height,
         incline: AnimFloat::default(),
@@ -206,11 +217,57 @@ fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<J
     })
 }
 
+/// Collects the `bp` commands wherever they appear, so a BPM change in the
+/// middle of the file applies to the events before it too. Entries that
+/// would give wrong times are warned about and fixed up: non-positive BPMs
+/// are dropped, out of order entries sorted, and of several entries at the
+/// same beat the last one kept.
+fn parse_bpm_list(source: &str) -> Result<BpmList> {
+    let mut bpm_list: Vec<(Time, f32)> = Vec::new();
+    // The first line is the offset
+    for (line_id, line_content) in source.lines().enumerate().skip(1) {
+        let mut it = line_content.split_whitespace();
+        if it.next() != Some("bp") {
+            continue;
+        }
+        let (beats, bpm) = (time_from_f32(it.take_f32()?), it.take_f32()?);
+        if !(bpm.is_finite() && bpm > 0.) {
+            log::warn!("Ignoring BPM {} at line {}", bpm, line_id + 1);
+            continue;
+        }
+        if bpm_list.last().is_some_and(|&(last, _)| beats < last) {
+            log::warn!(
+                "BPM change at line {} comes before the previous one, sorting BPM changes",
+                line_id + 1
+            );
+        }
+        bpm_list.push((beats, bpm));
+    }
+    bpm_list.sort_by_key(|e| e.0.not_nan());
+    let count = bpm_list.len();
+    // Keep the last entry of each beat
+    bpm_list.reverse();
+    bpm_list.dedup_by_key(|e| e.0);
+    bpm_list.reverse();
+    if bpm_list.len() < count {
+        log::warn!("Several BPMs at the same beat, keeping the last one of each");
+    }
+    if let Some(first) = bpm_list.first_mut() {
+        if first.0 != 0. {
+            log::warn!(
+                "First BPM starts at beat {}, treating it as starting at 0",
+                first.0
+            );
+            first.0 = 0.;
+        }
+    }
+    Ok(BpmList::new(bpm_list))
+}
+
 pub async fn parse_pec(source: &str) -> Result<Chart> {
     let mut offset = None;
-    let mut b = None;
+    let mut b = parse_bpm_list(source)?;
     let mut lines = Vec::new();
-    let mut bpm_list = Vec::new();
     let mut last_line = None;
 
     fn get_line(lines: &mut Vec<PECJudgeLine>, id: usize) -> &mut PECJudgeLine {
@@ -222,42 +279,27 @@ pub async fn parse_pec(source: &str) -> Result<Chart> {
         &mut lines[id]
     }
 
-    fn ensure_bpm<'a>(
-        b: &'a mut Option<BpmList>,
-        bpm_list: &mut Vec<(f32, f32)>,
-    ) -> &'a mut BpmList {
-        if b.is_none() {
-            *b = Some(BpmList::new(std::mem::take(bpm_list)));
-        }
-        b.as_mut().unwrap()
-    }
-
     for (line_id, line_content) in source.lines().enumerate() {
         let mut it = line_content.split_whitespace();
         if offset.is_none() {
-            offset = Some(it.take_f32()? / 1000. - 0.15);
+            offset = Some(time_from_f32(it.take_f32()?) / 1000. - 0.15);
         } else {
             let Some(cmd) = it.next() else {
                 continue;
             };
             let cs: Vec<_> = cmd.chars().collect();
             match cs[0] {
-                'b' if cmd == "bp" => {
-                    if b.is_some() {
-                        bail!("bp error at line {}", line_id + 1);
-                    }
-                    bpm_list.push((it.take_f32()?, it.take_f32()?));
-                }
+                // Read up front by `parse_bpm_list`
+                'b' if cmd == "bp" => {}
                 'n' if cs.len() == 2 && ('1'..='4').contains(&cs[1]) => {
-                    let b_ref = ensure_bpm(&mut b, &mut bpm_list);
                     let line_idx = it.take_usize()?;
                     last_line = Some(line_idx);
                     let p_line = get_line(&mut lines, line_idx);
-                    let time = it.take_time(b_ref)?;
+                    let time = it.take_time(&mut b)?;
                     let kind = match cs[1] {
                         '1' => NoteKind::Click,
                         '2' => NoteKind::Hold {
-                            end_time: it.take_time(b_ref)?,
+                            end_time: it.take_time(&mut b)?,
                             end_height: 0.0,
                         },
                         '3' => NoteKind::Flick,
@@ -317,10 +359,9 @@ pub async fn parse_pec(source: &str) -> Result<Chart> {
                     }
                 }
                 'c' if cs.len() == 2 => {
-                    let b_ref = ensure_bpm(&mut b, &mut bpm_list);
                     let line_idx = it.take_usize()?;
                     let p_line = get_line(&mut lines, line_idx);
-                    let time = it.take_time(b_ref)?;
+                    let time = it.take_time(&mut b)?;
                     match cs[1] {
                         'v' => {
                             p_line.speed_events.push((time, it.take_f32()? / 5.85));
@@ -342,7 +383,7 @@ pub async fn parse_pec(source: &str) -> Result<Chart> {
                                 .push(PECEvent::single(time, it.take_f32()?));
                         }
                         'm' => {
-                            let end_time = it.take_time(b_ref)?;
+                            let end_time = it.take_time(&mut b)?;
                             let x = it.take_f32()?;
                             let y = it.take_f32()?;
                             let t = it.take_tween()?;
@@ -358,7 +399,7 @@ pub async fn parse_pec(source: &str) -> Result<Chart> {
                         'r' => {
                             p_line.rotate_events.push(PECEvent::new(
                                 time,
-                                it.take_time(b_ref)?,
+                                it.take_time(&mut b)?,
                                 -it.take_f32()?,
                                 it.take_tween()?,
                             ));
@@ -366,7 +407,7 @@ pub async fn parse_pec(source: &str) -> Result<Chart> {
                         'f' => {
                             p_line.alpha_events.push(PECEvent::new(
                                 time,
-                                it.take_time(b_ref)?,
+                                it.take_time(&mut b)?,
                                 it.take_f32()?,
                                 2,
                             ));
@@ -404,10 +445,90 @@ pub async fn parse_pec(source: &str) -> Result<Chart> {
         .collect::<Result<Vec<_>>>()?;
 
     process_lines(&mut final_lines);
-    ensure_bpm(&mut b, &mut bpm_list);
-    Ok(Chart::new(
-        offset.unwrap_or(0.),
-        final_lines,
-        b.unwrap_or_default(),
-    ))
+    Ok(Chart::new(offset.unwrap_or(0.), final_lines, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unsorted_and_negative_speeds() {
+        // At 60 BPM a beat is one second, and `cv` 5.85 is one height unit
+        // per second
+        let source = "0
+bp 0 60
+cv 0 2 5.85
+cv 0 0 11.7
+cv 0 4 -5.85
+n1 0 1 0 1 0
+n1 0 3 0 1 0
+n1 0 6 0 1 0";
+        let chart = parse_pec(source).await.unwrap();
+        let heights: Vec<_> = chart.lines[0].notes.iter().map(|n| n.height).collect();
+        for (height, expected) in heights.iter().zip([2., 5., 4.]) {
+            assert!((height - expected).abs() < 1e-4, "{heights:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bpm_list_validation() {
+        // The BPM at beat 0 comes after a note, and a zero BPM is skipped
+        let source = "0
+bp 4 120
+bp 2 0
+n1 0 6 0 1 0
+bp 0 60";
+        let chart = parse_pec(source).await.unwrap();
+        assert!((chart.lines[0].notes[0].time - 5.).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_long_chart_precision() {
+        // About 36 minutes with the BPM and line speed changing on every beat
+        let beats = 8400;
+        let mut source = String::from("0\n");
+        for i in 0..beats {
+            let (bpm, speed) = if i % 2 == 0 { (200, 5.85) } else { (280, 11.7) };
+            source += &format!("bp {i} {bpm}\ncv 0 {i} {speed}\n");
+        }
+        for i in (0..=beats).step_by(97) {
+            source += &format!("n1 0 {i} 0 1 0\n");
+        }
+        let chart = parse_pec(&source).await.unwrap();
+
+        let (mut time, mut height) = (0f64, 0f64);
+        let mut expected = Vec::new();
+        for i in 0..=beats {
+            if i % 97 == 0 {
+                expected.push((time, height));
+            }
+            let (step, speed) = if i % 2 == 0 {
+                (0.3, 1.)
+            } else {
+                (60. / 280., 2.)
+            };
+            time += step;
+            height += step * speed;
+        }
+        assert!(time > 30. * 60.);
+
+        // Note times stay sub-millisecond. Heights inherit the rounding of
+        // every f32 keyframe time, so they get a couple of milliseconds of
+        // scroll instead
+        let notes = &chart.lines[0].notes;
+        assert_eq!(notes.len(), expected.len());
+        for (note, (time, height)) in notes.iter().zip(expected) {
+            assert!(
+                (time_f64(note.time) - time).abs() < 1e-3,
+                "{} vs {time}",
+                note.time
+            );
+            assert!(
+                (note.height as f64 - height).abs() < 2e-3,
+                "{} vs {height}",
+                note.height
+            );
+        }
+    }
 }
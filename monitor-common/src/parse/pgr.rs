@@ -1,7 +1,7 @@
 use super::process_lines;
-use monitor_common::core::{
-    Anim, AnimFloat, AnimVector, BpmList, Chart, JudgeLine, JudgeLineKind, Keyframe, Note,
-    NoteKind, Object, HEIGHT_RATIO,
+use crate::core::{
+    time_f64, Anim, AnimFloat, AnimVector, BpmList, Chart, JudgeLine, JudgeLineKind, Keyframe,
+    Note, NoteKind, Object, Time, HEIGHT_RATIO,
 };
 use ordered_float::{Float, NotNan};
 
@@ -21,8 +21,8 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrEvent {
-    pub start_time: f32,
-    pub end_time: f32,
+    pub start_time: Time,
+    pub end_time: Time,
     pub start: f32,
     pub end: f32,
     #[serde(default)]
@@ -34,8 +34,8 @@ struct PgrEvent {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrSpeedEvent {
-    pub start_time: f32,
-    pub end_time: f32,
+    pub start_time: Time,
+    pub end_time: Time,
     pub value: f32,
 }
 
@@ -44,9 +44,9 @@ struct PgrSpeedEvent {
 pub struct PgrNote {
     #[serde(rename = "type")]
     kind: u8,
-    time: f32,
+    time: Time,
     position_x: f32,
-    hold_time: f32,
+    hold_time: Time,
     speed: f32,
     #[allow(unused)]
     floor_position: f32,
@@ -72,7 +72,7 @@ struct PgrJudgeLine {
 #[serde(rename_all = "camelCase")]
 struct PgrChart {
     format_version: u32,
-    offset: f32,
+    offset: Time,
     judge_line_list: Vec<PgrJudgeLine>,
 }
 
@@ -90,9 +90,9 @@ macro_rules! validate_events {
 }
 
 fn parse_speed_events(
-    r: f32,
+    r: Time,
     mut pgr: Vec<PgrSpeedEvent>,
-    max_time: f32,
+    max_time: Time,
 ) -> Result<(AnimFloat, AnimFloat)> {
     validate_events!(pgr);
     if pgr.is_empty() {
@@ -102,17 +102,18 @@ fn parse_speed_events(
         pgr[0].start_time = 0.
     }
     let mut kfs = Vec::new();
-    let mut pos = 0.;
+    // Summed in f64 so long charts do not drift
+    let mut pos = 0f64;
     kfs.extend(pgr[..pgr.len().saturating_sub(1)].iter().map(|it| {
-        let from_pos = pos;
-        pos += (it.end_time - it.start_time) * r * it.value;
+        let from_pos = pos as f32;
+        pos += time_f64((it.end_time - it.start_time) * r) * it.value as f64;
         Keyframe::new(it.start_time * r, from_pos, 2)
     }));
     let last = pgr.last().unwrap();
-    kfs.push(Keyframe::new(last.start_time * r, pos, 2));
+    kfs.push(Keyframe::new(last.start_time * r, pos as f32, 2));
     kfs.push(Keyframe::new(
         max_time,
-        pos + (max_time - last.start_time * r) * last.value,
+        (pos + time_f64(max_time - last.start_time * r) * last.value as f64) as f32,
         0,
     ));
     for kf in &mut kfs {
@@ -128,11 +129,11 @@ fn parse_speed_events(
     ))
 }
 
-fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
+fn parse_float_events(r: Time, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
     validate_events!(pgr);
     let mut kfs = Vec::<Keyframe<f32>>::new();
     for e in pgr {
-        if !kfs.last().map_or(false, |it| it.value == e.start) {
+        if !kfs.last().is_some_and(|it| it.value == e.start) {
             kfs.push(Keyframe::new((e.start_time * r).max(0.), e.start, 2));
         }
         kfs.push(Keyframe::new(e.end_time * r, e.end, 2));
@@ -141,17 +142,17 @@ fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
     Ok(AnimFloat::new(kfs))
 }
 
-fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
+fn parse_move_events(r: Time, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
     validate_events!(pgr);
     let mut kf1 = Vec::<Keyframe<f32>>::new();
     let mut kf2 = Vec::<Keyframe<f32>>::new();
     for e in pgr {
         let st = (e.start_time * r).max(0.);
         let en = e.end_time * r;
-        if !kf1.last().map_or(false, |it| it.value == e.start) {
+        if !kf1.last().is_some_and(|it| it.value == e.start) {
             kf1.push(Keyframe::new(st, e.start, 2));
         }
-        if !kf2.last().map_or(false, |it| it.value == e.start2) {
+        if !kf2.last().is_some_and(|it| it.value == e.start2) {
             kf2.push(Keyframe::new(st, e.start2, 2));
         }
         kf1.push(Keyframe::new(en, e.end, 2));
@@ -171,18 +172,18 @@ fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
     })
 }
 
-fn parse_move_events_fv1(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
+fn parse_move_events_fv1(r: Time, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
     validate_events!(pgr);
     let mut kf1 = Vec::<Keyframe<f32>>::new();
     let mut kf2 = Vec::<Keyframe<f32>>::new();
     for e in pgr {
         let st = (e.start_time * r).max(0.);
         let en = e.end_time * r;
-        if !kf1.last().map_or(false, |it| it.value == e.start) {
+        if !kf1.last().is_some_and(|it| it.value == e.start) {
             let start = (e.start - e.start % 1000.) / 1000.;
             kf1.push(Keyframe::new(st, start, 2));
         }
-        if !kf2.last().map_or(false, |it| it.value == e.start2) {
+        if !kf2.last().is_some_and(|it| it.value == e.start2) {
             let start2 = e.start % 1000.;
             kf2.push(Keyframe::new(st, start2, 2));
         }
@@ -205,10 +206,24 @@ fn parse_move_events_fv1(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
     })
 }
 
+/// Speed of a hold starting at `time`. PGR gives holds the absolute speed
+/// of their body while other notes scale the line's speed, so like prpr the
+/// hold's speed is divided by the line's. A stopped line keeps the hold's
+/// own speed.
+fn hold_speed(speed: &mut AnimFloat, time: Time, hold_speed: f32) -> f32 {
+    speed.set_time(time);
+    let line_speed = speed.now();
+    if line_speed == 0. {
+        hold_speed
+    } else {
+        hold_speed / line_speed
+    }
+}
+
 fn parse_notes(
-    r: f32,
+    r: Time,
     mut pgr: Vec<PgrNote>,
-    _speed: &mut AnimFloat,
+    speed: &mut AnimFloat,
     height: &mut AnimFloat,
     above: bool,
 ) -> Result<Vec<Note>> {
@@ -219,6 +234,8 @@ fn parse_notes(
     pgr.into_iter()
         .map(|pgr| {
             let time = pgr.time * r;
+            height.set_time(time);
+            let note_height = height.now();
             let kind = match pgr.kind {
                 1 => NoteKind::Click,
                 2 => NoteKind::Drag,
@@ -245,11 +262,12 @@ fn parse_notes(
                 kind,
                 hitsound: None, // Will be handled by client
                 time,
-                speed: if pgr.kind == 3 { 1. } else { pgr.speed },
-                height: {
-                    height.set_time(time);
-                    height.now()
+                speed: if pgr.kind == 3 {
+                    hold_speed(speed, time, pgr.speed)
+                } else {
+                    pgr.speed
                 },
+                height: note_height,
                 above,
                 multiple_hint: false,
                 fake: false,
@@ -259,8 +277,8 @@ fn parse_notes(
         .collect()
 }
 
-fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32, format_version: u32) -> Result<JudgeLine> {
-    let r = 60. / 32. / pgr.bpm;
+fn parse_judge_line(pgr: PgrJudgeLine, max_time: Time, format_version: u32) -> Result<JudgeLine> {
+    let r = 60. / 32. / pgr.bpm as Time;
     let (mut speed, mut height) = parse_speed_events(r, pgr.speed_events, max_time)
         .context("Failed to parse speed events")?;
     let notes_above = parse_notes(r, pgr.notes_above, &mut speed, &mut height, true)
@@ -286,7 +304,7 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32, format_version: u32) -> Re
             },
             ..Default::default()
         },
-        ctrl_obj: monitor_common::core::CtrlObject::default(),
+        ctrl_obj: crate::core::CtrlObject::default(),
         kind: JudgeLineKind::Normal,
         height,
         incline: AnimFloat::default(),
@@ -312,11 +330,13 @@ pub async fn parse_pgr(source: &str) -> Result<Chart> {
                 .map(|note| note.time.not_nan())
                 .max()
                 .unwrap_or_default()
-                * (60. / line.bpm / 32.)
+                * (60. / line.bpm as Time / 32.)
         })
         .max()
         .unwrap_or_default()
         + 1.;
+    // Every line has its own BPM, in practice they all share one
+    let bpm = pgr.judge_line_list.first().map_or(120., |line| line.bpm);
     let mut lines = pgr
         .judge_line_list
         .into_iter()
@@ -328,5 +348,49 @@ pub async fn parse_pgr(source: &str) -> Result<Chart> {
         .collect::<Result<Vec<_>>>()?;
 
     process_lines(&mut lines);
-    Ok(Chart::new(pgr.offset, lines, BpmList::default()))
+    Ok(Chart::new(pgr.offset, lines, BpmList::new(vec![(0., bpm)])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// At 1.875 BPM a PGR time unit is one second
+    const CHART: &str = r#"{
+        "formatVersion": 3,
+        "offset": 0,
+        "judgeLineList": [{
+            "bpm": 1.875,
+            "judgeLineDisappearEvents": [],
+            "judgeLineRotateEvents": [],
+            "judgeLineMoveEvents": [],
+            "speedEvents": [
+                {"startTime": 0, "endTime": 2, "value": 2},
+                {"startTime": 2, "endTime": 4, "value": -1}
+            ],
+            "notesAbove": [
+                {"type": 3, "time": 1, "positionX": 0, "holdTime": 0.5, "speed": 3, "floorPosition": 0},
+                {"type": 1, "time": 3, "positionX": 0, "holdTime": 0, "speed": 1, "floorPosition": 0}
+            ],
+            "notesBelow": []
+        }]
+    }"#;
+
+    #[tokio::test]
+    async fn test_negative_speed_and_hold_speed() {
+        let chart = parse_pgr(CHART).await.unwrap();
+        let notes = &chart.lines[0].notes;
+
+        // Holds scale against the line speed at their start
+        assert_eq!(notes[0].speed, 1.5);
+        assert!((notes[0].height - 2. / HEIGHT_RATIO).abs() < 1e-4);
+        let NoteKind::Hold { end_height, .. } = notes[0].kind else {
+            panic!("expected a hold");
+        };
+        assert!((end_height - 3. / HEIGHT_RATIO).abs() < 1e-4);
+
+        // The line runs back under the negative speed
+        assert_eq!(notes[1].speed, 1.);
+        assert!((notes[1].height - 3. / HEIGHT_RATIO).abs() < 1e-4);
+    }
 }
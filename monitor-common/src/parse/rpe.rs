@@ -4,10 +4,11 @@
 //! Parses the JSON chart format used by RPE (Re:PhiEdit).
 
 use super::{process_lines, ResourceLoader, RPE_TWEEN_MAP};
-use monitor_common::core::{
-    colors::WHITE, Anim, AnimFloat, AnimVector, AudioClip, BezierTween, BpmList, Chart, Color,
-    CtrlObject, GifFrames, HitSound, HitSoundMap, JudgeLine, JudgeLineKind, Keyframe, Note,
-    NoteKind, Object, Texture, Triple, Tweenable, UIElement, EPS, HEIGHT_RATIO,
+use crate::core::{
+    colors::WHITE, time_f32, time_f64, time_from_f32, Anim, AnimFloat, AnimVector, AudioClip,
+    BezierTween, BpmList, Chart, Color, CtrlObject, GifFrames, HitSound, HitSoundMap, JudgeLine,
+    JudgeLineKind, Keyframe, Note, NoteKind, Object, Texture, Time, Triple, Tweenable, UIElement,
+    EPS, HEIGHT_RATIO,
 };
 
 use anyhow::{bail, Context, Result};
@@ -115,7 +116,7 @@ struct RPENote {
     size: f32,
     speed: f32,
     is_fake: u8,
-    visible_time: f32,
+    visible_time: Time,
 }
 
 #[derive(Deserialize)]
@@ -164,12 +165,6 @@ struct RPEChart {
 
 type BezierMap = HashMap<(u16, i16, i16), BezierTween>;
 
-fn bezier_key<T>(event: &RPEEvent<T>) -> (u16, i16, i16) {
-    let p = &event.bezier_points;
-    let int = |p: f32| (p * 100.).round() as i16;
-    ((int(p[0]) * 100 + int(p[1])) as u16, int(p[2]), int(p[3]))
-}
-
 fn parse_events<T: Tweenable, V: Clone + Into<T>>(
     r: &mut BpmList,
     rpe: &[RPEEvent<V>],
@@ -178,7 +173,7 @@ fn parse_events<T: Tweenable, V: Clone + Into<T>>(
 ) -> Result<Anim<T>> {
     let mut kfs = Vec::new();
     if let Some(default) = default {
-        if rpe.get(0).map_or(false, |e| e.start_time.beats() != 0.0) {
+        if rpe.first().is_some_and(|e| e.start_time.beats() != 0.0) {
             kfs.push(Keyframe::new(0.0, default, 0));
         }
     }
@@ -219,7 +214,7 @@ fn parse_events<T: Tweenable, V: Clone + Into<T>>(
     Ok(Anim::new(kfs))
 }
 
-fn parse_speed_events(r: &mut BpmList, rpe: &[RPEEventLayer], max_time: f32) -> Result<AnimFloat> {
+fn parse_speed_events(r: &mut BpmList, rpe: &[RPEEventLayer], max_time: Time) -> Result<AnimFloat> {
     let rpe_events: Vec<_> = rpe
         .iter()
         .filter_map(|it| it.speed_events.as_ref())
@@ -245,53 +240,49 @@ fn parse_speed_events(r: &mut BpmList, rpe: &[RPEEventLayer], max_time: f32) ->
     pts.push(max_time);
     pts.sort_by(|a, b| a.partial_cmp(b).unwrap());
     pts.dedup();
-    let mut sani = AnimFloat::chain(anis);
+    let mut sani = AnimFloat::layered(anis);
     sani.map_value(|v| v * SPEED_RATIO);
     for i in 0..(pts.len() - 1) {
         let now_time = pts[i];
         let end_time = pts[i + 1];
-        sani.set_time(now_time);
-        let speed = sani.now();
-        sani.set_time(end_time - 1e-4);
-        let end_speed = sani.now();
-        if speed.signum() * end_speed.signum() < 0. {
-            if (speed - end_speed).abs() > EPS {
-                let t = f32::tween(&now_time, &end_time, speed / (speed - end_speed));
-                pts.push(t);
-            }
+        let speed = sani.value_at(now_time).unwrap_or_default();
+        let end_speed = sani.value_before(end_time).unwrap_or_default();
+        if speed.signum() * end_speed.signum() < 0. && (speed - end_speed).abs() > EPS {
+            let t = now_time + (end_time - now_time) * time_from_f32(speed / (speed - end_speed));
+            pts.push(t);
         }
     }
     pts.sort_by(|a, b| a.partial_cmp(b).unwrap());
     pts.dedup();
     let mut kfs = Vec::new();
-    let mut height = 0.0;
+    // Summed in f64 so long charts do not drift
+    let mut height = 0.0f64;
     for i in 0..(pts.len() - 1) {
         let now_time = pts[i];
         let end_time = pts[i + 1];
-        sani.set_time(now_time);
-        let speed = sani.now();
-        sani.set_time(end_time - 1e-4);
-        let end_speed = sani.now();
+        let speed = sani.value_at(now_time).unwrap_or_default();
+        let end_speed = sani.value_before(end_time).unwrap_or_default();
+        let height_now = height as f32;
         kfs.push(if (speed - end_speed).abs() < EPS {
-            Keyframe::new(now_time, height, 2)
+            Keyframe::new(now_time, height_now, 2)
         } else if speed.abs() > end_speed.abs() {
             Keyframe::with_clamped(
                 now_time,
-                height,
+                height_now,
                 0.0..(1. - end_speed / speed),
                 7, // QuadOut
             )
         } else {
             Keyframe::with_clamped(
                 now_time,
-                height,
+                height_now,
                 (speed / end_speed)..1.,
                 6, // QuadIn
             )
         });
-        height += (speed + end_speed) * (end_time - now_time) / 2.;
+        height += (speed + end_speed) as f64 * time_f64(end_time - now_time) / 2.;
     }
-    kfs.push(Keyframe::new(max_time, height, 0));
+    kfs.push(Keyframe::new(max_time, height as f32, 0));
     Ok(AnimFloat::new(kfs))
 }
 
@@ -305,13 +296,15 @@ fn parse_gif_events<V: Clone + Into<f32>>(
     kfs.push(Keyframe::new(0.0, 0.0, 2));
     let mut next_rep_time: u128 = 0;
     for e in rpe {
-        while r.time_at(&e.start_time) > next_rep_time as f32 / 1000. {
-            kfs.push(Keyframe::new(next_rep_time as f32 / 1000., 1.0, 0));
-            kfs.push(Keyframe::new(next_rep_time as f32 / 1000., 0.0, 2));
+        while r.time_at(&e.start_time) > next_rep_time as Time / 1000. {
+            kfs.push(Keyframe::new(next_rep_time as Time / 1000., 1.0, 0));
+            kfs.push(Keyframe::new(next_rep_time as Time / 1000., 0.0, 2));
             next_rep_time += gif.total_time;
         }
-        let stop_prog =
-            1. - (next_rep_time as f32 - r.time_at(&e.start_time) * 1000.) / gif.total_time as f32;
+        let stop_prog = 1.
+            - time_f32(
+                (next_rep_time as Time - r.time_at(&e.start_time) * 1000.) / gif.total_time as Time,
+            );
         kfs.push(Keyframe::new(r.time_at(&e.start_time), stop_prog, 0));
 
         let time = r.time_at(&e.start_time);
@@ -347,14 +340,14 @@ fn parse_gif_events<V: Clone + Into<f32>>(
             2, // Linear
         ));
         next_rep_time = (r.time_at(&e.end_time) * 1000.
-            + gif.total_time as f32 * (1. - Into::<f32>::into(e.end.clone())))
+            + gif.total_time as Time * time_from_f32(1. - Into::<f32>::into(e.end.clone())))
         .round() as u128;
     }
 
-    const GIF_MAX_TIME: f32 = 2000.;
-    while GIF_MAX_TIME > next_rep_time as f32 / 1000. {
-        kfs.push(Keyframe::new(next_rep_time as f32 / 1000., 1.0, 0));
-        kfs.push(Keyframe::new(next_rep_time as f32 / 1000., 0.0, 2));
+    const GIF_MAX_TIME: Time = 2000.;
+    while GIF_MAX_TIME > next_rep_time as Time / 1000. {
+        kfs.push(Keyframe::new(next_rep_time as Time / 1000., 1.0, 0));
+        kfs.push(Keyframe::new(next_rep_time as Time / 1000., 0.0, 2));
         next_rep_time += gif.total_time;
     }
     Ok(Anim::new(kfs))
@@ -377,7 +370,7 @@ async fn parse_notes(
 ) -> Result<Vec<Note>> {
     let mut notes = Vec::new();
     for note in rpe {
-        let time: f32 = r.time_at(&note.start_time);
+        let time = r.time_at(&note.start_time);
         height.set_time(time);
         let note_height = height.now();
         let y_offset = note.y_offset * 2. / RPE_HEIGHT * note.speed;
@@ -475,10 +468,10 @@ fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> AnimFloat {
     }
     AnimFloat::new(
         rpe.iter()
-            .zip(vals.into_iter())
+            .zip(vals)
             .map(|(it, val)| {
                 Keyframe::new(
-                    it.x,
+                    time_from_f32(it.x),
                     val,
                     RPE_TWEEN_MAP
                         .get(it.easing.max(1) as usize)
@@ -493,7 +486,7 @@ fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> AnimFloat {
 async fn parse_judge_line(
     r: &mut BpmList,
     rpe: RPEJudgeLine,
-    max_time: f32,
+    max_time: Time,
     fs: &mut dyn ResourceLoader,
     bezier_map: &BezierMap,
     line_texture_map: &mut HashMap<String, Texture>,
@@ -517,7 +510,7 @@ async fn parse_judge_line(
             })
             .collect::<Result<_>>()
             .with_context(|| format!("type-events-parse-failed: {}", desc))?;
-        let mut res = AnimFloat::chain(anis);
+        let mut res = AnimFloat::layered(anis);
         res.map_value(|v| v * factor);
         Ok(res)
     }
@@ -589,8 +582,8 @@ async fn parse_judge_line(
                                 &e.scale_x_events,
                                 factor
                                     * if rpe.texture == "line.png"
-                                        && rpe.extended.as_ref().map_or(true, |it| {
-                                            it.text_events.as_ref().map_or(true, |it| it.is_empty())
+                                        && rpe.extended.as_ref().is_none_or(|it| {
+                                            it.text_events.as_ref().is_none_or(|it| it.is_empty())
                                         })
                                         && rpe.attach_ui.is_none()
                                     {
@@ -775,7 +768,7 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn ResourceLoader) -> Result<Char
             .map(|it| (it.start_time.beats(), it.bpm))
             .collect(),
     );
-    fn vec<'a, T>(v: &'a Option<Vec<T>>) -> impl Iterator<Item = &'a T> {
+    fn vec<T>(v: &Option<Vec<T>>) -> impl Iterator<Item = &T> {
         v.iter().flat_map(|it| it.iter())
     }
 
@@ -874,7 +867,7 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn ResourceLoader) -> Result<Char
     }
 
     process_lines(&mut lines);
-    let mut chart = Chart::new(rpe.meta.offset as f32 / 1000.0, lines, r);
+    let mut chart = Chart::new(rpe.meta.offset as Time / 1000.0, lines, r);
     chart.hitsounds = hitsounds;
     Ok(chart)
 }
@@ -935,7 +928,7 @@ mod tests {
                 println!("Successfully parsed chart!");
                 println!("JudgeLines: {}", chart.lines.len());
                 println!("Offset: {}", chart.offset);
-                assert!(chart.lines.len() > 0);
+                assert!(!chart.lines.is_empty());
             }
             Err(e) => {
                 panic!("Failed to parse chart: {:?}", e);
@@ -0,0 +1,166 @@
+//! Versioned wire format of the parsed chart served by the proxy
+//!
+//! A payload is [`MAGIC`], a version byte, the keyframe [`Encoding`] byte,
+//! the time width byte (0 for `f32`, 1 for `f64`, see [`crate::core::Time`])
+//! and the bincode encoded `(ChartInfo, Chart)`. Payloads from before
+//! versioning carry no header at all and count as version 0; version 1 has no
+//! encoding byte. Versions before 3 have no note IDs and version 3 has no
+//! audio references; they are rejected, the proxy re-processes its cached
+//! charts instead. Version 4 chains animation layers, which are turned into
+//! lists when decoded. Versions 4 and 5 have no time width byte, their times
+//! are `f32`.
+use crate::core::compact::with_encoding;
+pub use crate::core::compact::Encoding;
+use crate::core::{with_chained_layers, with_wide_times};
+use crate::core::{Chart, ChartInfo, WIDE_TIME};
+use anyhow::{bail, Context, Result};
+use bincode::Options;
+
+pub const MAGIC: [u8; 4] = *b"PWMC";
+/// Bumped whenever the layout of `ChartInfo` or `Chart` changes
+pub const VERSION: u8 = 6;
+
+fn options() -> impl Options {
+    bincode::options().with_varint_encoding()
+}
+
+pub fn encode(info: &ChartInfo, chart: &Chart, encoding: Encoding) -> Result<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(match encoding {
+        Encoding::Plain => 0,
+        Encoding::Compact => 1,
+    });
+    bytes.push(WIDE_TIME as u8);
+    with_encoding(encoding, || {
+        with_wide_times(WIDE_TIME, || {
+            options().serialize_into(&mut bytes, &(info, chart))
+        })
+    })
+    .context("failed to serialize chart")?;
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<(ChartInfo, Chart)> {
+    let (version, body) = match bytes.strip_prefix(&MAGIC) {
+        Some([version, body @ ..]) => (*version, body),
+        Some([]) => bail!("truncated chart payload"),
+        None => (0, bytes),
+    };
+    let (encoding, body) = match (version, body) {
+        (0..=3, _) => bail!("chart payload v{version} is outdated, please reload the chart"),
+        (4..=6, [0, body @ ..]) => (Encoding::Plain, body),
+        (4..=6, [1, body @ ..]) => (Encoding::Compact, body),
+        (4..=6, _) => bail!("unknown keyframe encoding in chart payload"),
+        _ => bail!(
+            "chart payload v{version} is newer than the supported v{VERSION}, please refresh the page"
+        ),
+    };
+    let (wide, body) = match (version, body) {
+        (4..=5, body) => (false, body),
+        (_, [0, body @ ..]) => (false, body),
+        (_, [1, body @ ..]) => (true, body),
+        _ => bail!("unknown time width in chart payload"),
+    };
+    with_encoding(encoding, || {
+        with_wide_times(wide, || {
+            if version == 4 {
+                with_chained_layers(|| options().deserialize(body))
+            } else {
+                options().deserialize(body)
+            }
+        })
+    })
+    .with_context(|| format!("failed to decode chart payload v{version}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> (ChartInfo, Chart) {
+        let info = ChartInfo {
+            name: "Test".to_string(),
+            ..Default::default()
+        };
+        let chart = Chart {
+            offset: 0.25,
+            ..Default::default()
+        };
+        (info, chart)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (info, chart) = sample();
+        for encoding in [Encoding::Plain, Encoding::Compact] {
+            let bytes = encode(&info, &chart, encoding).unwrap();
+            assert!(bytes.starts_with(&MAGIC));
+            let (info, chart) = decode(&bytes).unwrap();
+            assert_eq!(info.name, "Test");
+            assert_eq!(chart.offset, 0.25);
+        }
+    }
+
+    #[test]
+    fn test_decode_other_time_width() {
+        // As written by a build with the other `Time` width
+        let (info, mut chart) = sample();
+        chart.lines.push(Default::default());
+        chart.lines[0].notes.push(crate::core::Note::new(
+            crate::core::NoteKind::Click,
+            1800.5,
+            0.0,
+        ));
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([VERSION, 0, !WIDE_TIME as u8]);
+        with_wide_times(!WIDE_TIME, || {
+            options()
+                .serialize_into(&mut bytes, &(&info, &chart))
+                .unwrap()
+        });
+        let (_, chart) = decode(&bytes).unwrap();
+        assert_eq!(chart.offset, 0.25);
+        assert_eq!(chart.lines[0].notes[0].time, 1800.5);
+    }
+
+    #[test]
+    fn test_reject_older_version() {
+        let (info, chart) = sample();
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(1);
+        options()
+            .serialize_into(&mut bytes, &(&info, &chart))
+            .unwrap();
+        let err = decode(&bytes).err().unwrap().to_string();
+        assert!(err.contains("outdated"), "{err}");
+
+        let bytes = options().serialize(&(info, chart)).unwrap();
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_v4() {
+        // Encoded by version 4: alpha is a linear 0 -> 1 over two seconds
+        // with a layer fixed at 0.25 chained through `next`
+        let bytes = include_bytes!("../testdata/payload_v4.bin");
+        assert_eq!(bytes[MAGIC.len()], 4);
+        let (info, chart) = decode(bytes).unwrap();
+        assert_eq!(info.name, "Layers");
+        assert_eq!(chart.offset, 0.125);
+        let alpha = &chart.lines[0].object.alpha;
+        assert_eq!(alpha.layers.len(), 1);
+        assert_eq!(alpha.value_at(1.0), Some(0.75));
+        assert_eq!(chart.lines[0].notes.len(), 1);
+        assert_eq!(chart.lines[0].notes[0].time, 1.0);
+    }
+
+    #[test]
+    fn test_reject_newer_version() {
+        let (info, chart) = sample();
+        let mut bytes = encode(&info, &chart, Encoding::Plain).unwrap();
+        bytes[MAGIC.len()] = VERSION + 1;
+        let err = decode(&bytes).err().unwrap().to_string();
+        assert!(err.contains("please refresh"), "{err}");
+    }
+}
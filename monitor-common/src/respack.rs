@@ -0,0 +1,748 @@
+//! Resource pack preprocessing
+//!
+//! A pack zip is validated and turned into a bundle the client uploads as
+//! is: every texture decoded to RGBA and packed into one atlas together
+//! with, for `holdRepeat` packs, the hold bodies cut out of `hold.png` and
+//! `hold_mh.png` so they tile without bleeding into the head and tail. A
+//! bundle is [`MAGIC`], a version byte, the little-endian length of a JSON
+//! [`Header`], the header, and the pixel and sound data it points into.
+//!
+//! The atlas stays raw RGBA, so the client uploads it as a single texture
+//! without decoding and draws every sprite from it; [`MAX_PACK_PIXELS`] and
+//! [`MAX_BUNDLE_SIZE`] bound the result, and the proxy sends bundles
+//! gzip-compressed.
+use crate::core::{colors, Color};
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+pub const MAGIC: [u8; 4] = *b"PWMR";
+/// Bumped whenever the bundle layout changes
+pub const VERSION: u8 = 2;
+
+/// Textures every pack must have, without the `.png`
+pub const NOTE_TEXTURES: [&str; 8] = [
+    "click", "hold", "flick", "drag", "click_mh", "hold_mh", "flick_mh", "drag_mh",
+];
+/// Textures a pack may leave out
+pub const OPTIONAL_TEXTURES: [&str; 2] = ["hit_fx", "font"];
+/// Hitsounds, looked up with any of [`SOUND_EXTENSIONS`]
+pub const SOUNDS: [&str; 3] = ["click", "drag", "flick"];
+pub const SOUND_EXTENSIONS: [&str; 3] = ["mp3", "ogg", "wav"];
+
+/// Largest file of a pack once uncompressed
+pub const MAX_FILE_SIZE: u64 = 32 * 1024 * 1024;
+/// Largest total of a pack's files once uncompressed
+pub const MAX_TOTAL_SIZE: u64 = 128 * 1024 * 1024;
+/// Largest atlas width and height
+pub const MAX_ATLAS_SIZE: u32 = 8192;
+/// Pixels around every sprite in the atlas, repeating its edge so linear
+/// filtering and mipmaps don't pick up its neighbours
+pub const ATLAS_PADDING: u32 = 2;
+/// Largest texture width and height, so that it fits the atlas padded
+pub const MAX_TEXTURE_SIZE: u32 = MAX_ATLAS_SIZE - 2 * ATLAS_PADDING;
+/// Most pixels the textures of a pack may decode to, cut hold bodies
+/// included, which bounds the RGBA in a bundle at 64 MB
+pub const MAX_PACK_PIXELS: u64 = 16 * 1024 * 1024;
+/// Largest bundle, RGBA and hitsounds together
+pub const MAX_BUNDLE_SIZE: usize = 96 * 1024 * 1024;
+/// Most memory decoding a single texture may take
+const MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
+
+#[inline]
+fn default_scale() -> f32 {
+    1.
+}
+
+#[inline]
+fn default_duration() -> f32 {
+    0.5
+}
+
+#[inline]
+fn default_perfect() -> u32 {
+    0xe1ffec9f
+}
+
+#[inline]
+fn default_good() -> u32 {
+    0xebb4e1ff
+}
+
+#[inline]
+fn default_tinted() -> bool {
+    true
+}
+
+/// The pack's `info.yml`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResPackInfo {
+    pub name: String,
+    pub author: String,
+    pub description: String,
+    pub hold_atlas: (u32, u32),
+    #[serde(rename = "holdAtlasMH")]
+    pub hold_atlas_mh: (u32, u32),
+    #[serde(default)]
+    pub hold_repeat: bool,
+    #[serde(default)]
+    pub hold_compact: bool,
+
+    pub hit_fx: (u32, u32),
+    #[serde(default = "default_duration")]
+    pub hit_fx_duration: f32,
+    #[serde(default = "default_scale")]
+    pub hit_fx_scale: f32,
+    #[serde(default)]
+    pub hit_fx_rotate: bool,
+    #[serde(default)]
+    pub hide_particles: bool,
+    #[serde(default = "default_tinted")]
+    pub hit_fx_tinted: bool,
+
+    #[serde(default = "default_perfect")]
+    pub color_perfect: u32,
+    #[serde(default = "default_good")]
+    pub color_good: u32,
+}
+
+impl ResPackInfo {
+    pub fn fx_perfect(&self) -> Color {
+        if self.hit_fx_tinted {
+            Color::from_hex(self.color_perfect)
+        } else {
+            colors::WHITE
+        }
+    }
+
+    pub fn fx_good(&self) -> Color {
+        if self.hit_fx_tinted {
+            Color::from_hex(self.color_good)
+        } else {
+            colors::WHITE
+        }
+    }
+}
+
+/// Decoded texture, rows top to bottom
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    /// Rows `top..bottom`
+    fn rows(&self, top: u32, bottom: u32) -> Image {
+        let stride = self.width as usize * 4;
+        Image {
+            width: self.width,
+            height: bottom - top,
+            rgba: self.rgba[top as usize * stride..bottom as usize * stride].to_vec(),
+        }
+    }
+}
+
+/// Where a texture lies in the atlas, in pixels from the top left
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sprite {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Sprite {
+    /// `[u, v, width, height]` of the sprite in texture coordinates of
+    /// `atlas`
+    pub fn uv(&self, atlas: &Image) -> [f32; 4] {
+        let (w, h) = (atlas.width as f32, atlas.height as f32);
+        [
+            self.x as f32 / w,
+            self.y as f32 / h,
+            self.width as f32 / w,
+            self.height as f32 / h,
+        ]
+    }
+}
+
+/// A prepared pack
+pub struct Bundle {
+    pub info: ResPackInfo,
+    /// Every texture of the pack
+    pub atlas: Image,
+    /// Keyed by file name without `.png`. Cut hold bodies are
+    /// `hold_body` and `hold_mh_body`.
+    pub sprites: HashMap<String, Sprite>,
+    /// Undecoded hitsounds keyed by file name
+    pub sounds: HashMap<String, Vec<u8>>,
+}
+
+/// Where a file's data lies in the bundle, after the header
+#[derive(Serialize, Deserialize)]
+struct Span {
+    name: String,
+    offset: usize,
+    len: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextureEntry {
+    #[serde(flatten)]
+    span: Span,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SpriteEntry {
+    name: String,
+    #[serde(flatten)]
+    sprite: Sprite,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    info: ResPackInfo,
+    atlas: TextureEntry,
+    sprites: Vec<SpriteEntry>,
+    sounds: Vec<Span>,
+}
+
+/// Reads the files of a pack zip by name, ignoring the folders they are in.
+///
+/// Files over [`MAX_FILE_SIZE`] or packs over [`MAX_TOTAL_SIZE`] once
+/// uncompressed are rejected, whatever sizes the zip claims.
+pub fn read_zip(zip_bytes: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(zip_bytes)).context("invalid pack zip")?;
+    let mut files = HashMap::new();
+    let mut total = 0;
+    for i in 0..zip.len() {
+        let file = zip.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let Some(name) = file.name().rsplit('/').next().map(str::to_string) else {
+            continue;
+        };
+        ensure!(
+            file.size() <= MAX_FILE_SIZE,
+            "{name} is larger than {MAX_FILE_SIZE} bytes"
+        );
+        let mut bytes = Vec::with_capacity(file.size() as usize);
+        file.take(MAX_FILE_SIZE + 1)
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("failed to read {name}"))?;
+        ensure!(
+            bytes.len() as u64 <= MAX_FILE_SIZE,
+            "{name} is larger than {MAX_FILE_SIZE} bytes"
+        );
+        total += bytes.len() as u64;
+        ensure!(
+            total <= MAX_TOTAL_SIZE,
+            "pack is larger than {MAX_TOTAL_SIZE} bytes uncompressed"
+        );
+        files.insert(name, bytes);
+    }
+    Ok(files)
+}
+
+/// Takes `width` x `height` pixels from what is left of the pack's
+/// [`MAX_PACK_PIXELS`]
+fn take_pixels(pixels_left: &mut u64, name: &str, width: u32, height: u32) -> Result<()> {
+    let pixels = width as u64 * height as u64;
+    ensure!(
+        pixels <= *pixels_left,
+        "{name} does not fit the pack's budget of {MAX_PACK_PIXELS} texture pixels"
+    );
+    *pixels_left -= pixels;
+    Ok(())
+}
+
+/// Decodes `name.png`, checking its size against `pixels_left` before the
+/// pixels are decoded
+fn decode_png(
+    files: &HashMap<String, Vec<u8>>,
+    name: &str,
+    pixels_left: &mut u64,
+) -> Result<Option<Image>> {
+    let Some(bytes) = files.get(&format!("{name}.png")) else {
+        return Ok(None);
+    };
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_TEXTURE_SIZE);
+    limits.max_image_height = Some(MAX_TEXTURE_SIZE);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = image::ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    let decoder = reader
+        .into_decoder()
+        .with_context(|| format!("failed to decode {name}.png"))?;
+    let (width, height) = image::ImageDecoder::dimensions(&decoder);
+    take_pixels(pixels_left, &format!("{name}.png"), width, height)?;
+    let image = image::DynamicImage::from_decoder(decoder)
+        .with_context(|| format!("failed to decode {name}.png"))?
+        .to_rgba8();
+    Ok(Some(Image {
+        width: image.width(),
+        height: image.height(),
+        rgba: image.into_raw(),
+    }))
+}
+
+/// Cuts the part of a hold texture between tail and head, see
+/// `holdAtlas` in `info.yml`
+fn hold_body(hold: &Image, (tail, head): (u32, u32)) -> Option<Image> {
+    (tail.saturating_add(head) < hold.height).then(|| hold.rows(tail, hold.height - head))
+}
+
+/// Places `sizes` on shelves `width` pixels wide, tallest first, padding
+/// included. Returns where each one goes and the height used.
+fn shelve(sizes: &[(u32, u32)], order: &[usize], width: u32) -> (Vec<Sprite>, u32) {
+    let mut sprites = vec![
+        Sprite {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        sizes.len()
+    ];
+    let (mut x, mut y, mut shelf) = (0, 0, 0);
+    for &i in order {
+        let (w, h) = sizes[i];
+        let (padded_w, padded_h) = (w + 2 * ATLAS_PADDING, h + 2 * ATLAS_PADDING);
+        if x + padded_w > width {
+            (x, y, shelf) = (0, y + shelf, 0);
+        }
+        sprites[i] = Sprite {
+            x: x + ATLAS_PADDING,
+            y: y + ATLAS_PADDING,
+            width: w,
+            height: h,
+        };
+        x += padded_w;
+        shelf = shelf.max(padded_h);
+    }
+    (sprites, y + shelf)
+}
+
+/// Copies `image` into `atlas` at `sprite`, repeating its edge pixels
+/// into the padding around it
+fn blit(atlas: &mut Image, sprite: &Sprite, image: &Image) {
+    let stride = atlas.width as usize * 4;
+    let row_len = image.width as usize * 4;
+    let pad = ATLAS_PADDING as usize * 4;
+    for y in 0..sprite.height + 2 * ATLAS_PADDING {
+        let src_y = y.saturating_sub(ATLAS_PADDING).min(image.height - 1) as usize;
+        let src = &image.rgba[src_y * row_len..][..row_len];
+        let start = (sprite.y - ATLAS_PADDING + y) as usize * stride
+            + (sprite.x - ATLAS_PADDING) as usize * 4;
+        let dst = &mut atlas.rgba[start..start + row_len + 2 * pad];
+        let (left, rest) = dst.split_at_mut(pad);
+        let (middle, right) = rest.split_at_mut(row_len);
+        middle.copy_from_slice(src);
+        for px in left.chunks_exact_mut(4) {
+            px.copy_from_slice(&src[..4]);
+        }
+        for px in right.chunks_exact_mut(4) {
+            px.copy_from_slice(&src[row_len - 4..]);
+        }
+    }
+}
+
+/// Packs `textures` into one atlas no larger than [`MAX_ATLAS_SIZE`] on
+/// either side, starting near square and widening until it fits
+fn pack_atlas(textures: &[(String, Image)]) -> Result<(Image, Vec<SpriteEntry>)> {
+    let sizes: Vec<_> = textures
+        .iter()
+        .map(|(_, image)| (image.width, image.height))
+        .collect();
+    let mut order: Vec<_> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+    let area: u64 = sizes
+        .iter()
+        .map(|&(w, h)| (w + 2 * ATLAS_PADDING) as u64 * (h + 2 * ATLAS_PADDING) as u64)
+        .sum();
+    let widest = sizes.iter().map(|&(w, _)| w).max().unwrap_or(0) + 2 * ATLAS_PADDING;
+    let mut width = widest.max((area as f64).sqrt().ceil() as u32);
+    let (sprites, height) = loop {
+        let width_fit = width.min(MAX_ATLAS_SIZE);
+        let (sprites, height) = shelve(&sizes, &order, width_fit);
+        if height <= MAX_ATLAS_SIZE {
+            width = width_fit;
+            break (sprites, height);
+        }
+        ensure!(
+            width_fit < MAX_ATLAS_SIZE,
+            "textures do not fit a {MAX_ATLAS_SIZE}x{MAX_ATLAS_SIZE} atlas"
+        );
+        width = width_fit + width_fit / 4;
+    };
+
+    let mut atlas = Image {
+        width,
+        height,
+        rgba: vec![0; width as usize * height as usize * 4],
+    };
+    let mut entries = Vec::with_capacity(textures.len());
+    for ((name, image), sprite) in textures.iter().zip(sprites) {
+        blit(&mut atlas, &sprite, image);
+        entries.push(SpriteEntry {
+            name: name.clone(),
+            sprite,
+        });
+    }
+    Ok((atlas, entries))
+}
+
+/// Validates the files of a pack and turns them into a bundle.
+pub fn prepare(files: &HashMap<String, Vec<u8>>) -> Result<Vec<u8>> {
+    let info = files.get("info.yml").context("missing info.yml")?;
+    let info: ResPackInfo = serde_yaml::from_slice(info).context("invalid info.yml")?;
+    ensure!(
+        info.hit_fx.0 > 0 && info.hit_fx.1 > 0,
+        "hitFx grid {:?} is empty",
+        info.hit_fx
+    );
+
+    let mut pixels_left = MAX_PACK_PIXELS;
+    let mut textures = Vec::new();
+    for name in NOTE_TEXTURES {
+        let image = decode_png(files, name, &mut pixels_left)?
+            .with_context(|| format!("missing {name}.png"))?;
+        textures.push((name.to_string(), image));
+    }
+    for name in OPTIONAL_TEXTURES {
+        if let Some(image) = decode_png(files, name, &mut pixels_left)? {
+            textures.push((name.to_string(), image));
+        }
+    }
+    let mut bodies = Vec::new();
+    for (name, atlas) in [("hold", info.hold_atlas), ("hold_mh", info.hold_atlas_mh)] {
+        let hold = &textures.iter().find(|(n, _)| n == name).unwrap().1;
+        ensure!(
+            atlas.0.saturating_add(atlas.1) <= hold.height,
+            "holdAtlas {:?} does not fit {name}.png, which is {} pixels high",
+            atlas,
+            hold.height
+        );
+        if info.hold_repeat {
+            if let Some(body) = hold_body(hold, atlas) {
+                let name = format!("{name}_body");
+                take_pixels(&mut pixels_left, &name, body.width, body.height)?;
+                bodies.push((name, body));
+            }
+        }
+    }
+    textures.extend(bodies);
+    let (atlas, sprites) = pack_atlas(&textures)?;
+
+    let mut data = Vec::new();
+    let mut span = |name: String, bytes: &[u8]| {
+        let span = Span {
+            name,
+            offset: data.len(),
+            len: bytes.len(),
+        };
+        data.extend_from_slice(bytes);
+        span
+    };
+    let atlas = TextureEntry {
+        span: span("atlas".into(), &atlas.rgba),
+        width: atlas.width,
+        height: atlas.height,
+    };
+    let sounds = SOUNDS
+        .iter()
+        .flat_map(|name| SOUND_EXTENSIONS.map(|ext| format!("{name}.{ext}")))
+        .filter_map(|file| {
+            let bytes = files.get(&file)?;
+            Some(span(file, bytes))
+        })
+        .collect();
+    ensure!(
+        data.len() <= MAX_BUNDLE_SIZE,
+        "bundle would be larger than {MAX_BUNDLE_SIZE} bytes"
+    );
+
+    let header = serde_json::to_vec(&Header {
+        info,
+        atlas,
+        sprites,
+        sounds,
+    })?;
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&data);
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Bundle> {
+    let Some([version, body @ ..]) = bytes.strip_prefix(&MAGIC) else {
+        bail!("not a resource pack bundle");
+    };
+    ensure!(
+        *version == VERSION,
+        "resource pack bundle v{version} is not the supported v{VERSION}, please prepare it again"
+    );
+    let (len, body) = body
+        .split_first_chunk::<4>()
+        .context("truncated resource pack bundle")?;
+    let len = u32::from_le_bytes(*len) as usize;
+    ensure!(body.len() >= len, "truncated resource pack bundle");
+    let (header, data) = body.split_at(len);
+    let header: Header =
+        serde_json::from_slice(header).context("invalid resource pack bundle header")?;
+
+    let slice = |span: &Span| {
+        span.offset
+            .checked_add(span.len)
+            .and_then(|end| data.get(span.offset..end))
+            .with_context(|| format!("{} lies outside the bundle", span.name))
+    };
+    let entry = &header.atlas;
+    let rgba = slice(&entry.span)?;
+    let size = (entry.width as usize)
+        .checked_mul(entry.height as usize)
+        .and_then(|pixels| pixels.checked_mul(4));
+    ensure!(
+        size == Some(rgba.len()),
+        "{} is not {}x{}",
+        entry.span.name,
+        entry.width,
+        entry.height
+    );
+    let atlas = Image {
+        width: entry.width,
+        height: entry.height,
+        rgba: rgba.to_vec(),
+    };
+    let mut sprites = HashMap::new();
+    for SpriteEntry { name, sprite } in header.sprites {
+        let inside = |start: u32, len: u32, size: u32| {
+            len > 0 && start.checked_add(len).is_some_and(|end| end <= size)
+        };
+        ensure!(
+            inside(sprite.x, sprite.width, atlas.width)
+                && inside(sprite.y, sprite.height, atlas.height),
+            "{name} lies outside the atlas"
+        );
+        sprites.insert(name, sprite);
+    }
+    let mut sounds = HashMap::new();
+    for span in &header.sounds {
+        sounds.insert(span.name.clone(), slice(span)?.to_vec());
+    }
+    Ok(Bundle {
+        info: header.info,
+        atlas,
+        sprites,
+        sounds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        // Row y is filled with value y, to tell cut rows apart
+        let image = RgbaImage::from_fn(width, height, |_, y| Rgba([y as u8, 0, 0, 255]));
+        let mut cursor = Cursor::new(Vec::new());
+        image.write_to(&mut cursor, ImageFormat::Png).unwrap();
+        cursor.into_inner()
+    }
+
+    fn pack(info: &str) -> HashMap<String, Vec<u8>> {
+        let mut files: HashMap<_, _> = NOTE_TEXTURES
+            .iter()
+            .map(|name| (format!("{name}.png"), png(4, 10)))
+            .collect();
+        files.insert("info.yml".into(), info.as_bytes().to_vec());
+        files.insert("click.ogg".into(), vec![1, 2, 3]);
+        files
+    }
+
+    const INFO: &str = "name: Test\nauthor: A\ndescription: D\nholdAtlas: [2, 3]\nholdAtlasMH: [2, 3]\nhitFx: [5, 6]\n";
+
+    /// Pixel `(x, y)` of sprite `name`, which may lie in its padding
+    fn pixel(bundle: &Bundle, name: &str, x: i32, y: i32) -> [u8; 4] {
+        let sprite = bundle.sprites[name];
+        let x = (sprite.x as i32 + x) as usize;
+        let y = (sprite.y as i32 + y) as usize;
+        let at = (y * bundle.atlas.width as usize + x) * 4;
+        bundle.atlas.rgba[at..at + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let bundle = decode(&prepare(&pack(INFO)).unwrap()).unwrap();
+        assert_eq!(bundle.info.name, "Test");
+        assert_eq!(bundle.info.hit_fx, (5, 6));
+        let click = bundle.sprites["click"];
+        assert_eq!((click.width, click.height), (4, 10));
+        assert_eq!(bundle.sprites.len(), NOTE_TEXTURES.len());
+        assert!(!bundle.sprites.contains_key("hold_body"));
+        assert_eq!(bundle.sounds["click.ogg"], vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_hold_repeat_bodies() {
+        let info = format!("{INFO}holdRepeat: true\n");
+        let bundle = decode(&prepare(&pack(&info)).unwrap()).unwrap();
+        let body = bundle.sprites["hold_body"];
+        // 2 tail rows on top, 3 head rows at the bottom
+        assert_eq!(body.height, 5);
+        assert_eq!(pixel(&bundle, "hold_body", 0, 0)[0], 2);
+        assert_eq!(pixel(&bundle, "hold_body", 3, 4)[0], 6);
+        assert!(bundle.sprites.contains_key("hold_mh_body"));
+    }
+
+    #[test]
+    fn test_atlas_layout() {
+        let mut files = pack(&format!("{INFO}holdRepeat: true\n"));
+        files.insert("hit_fx.png".into(), png(30, 7));
+        files.insert("font.png".into(), png(1, 1));
+        let bundle = decode(&prepare(&files).unwrap()).unwrap();
+        assert_eq!(bundle.sprites.len(), NOTE_TEXTURES.len() + 4);
+
+        let pad = ATLAS_PADDING;
+        let padded: Vec<_> = bundle
+            .sprites
+            .values()
+            .map(|s| {
+                (
+                    s.x - pad,
+                    s.y - pad,
+                    s.x + s.width + pad,
+                    s.y + s.height + pad,
+                )
+            })
+            .collect();
+        for (i, a) in padded.iter().enumerate() {
+            assert!(a.2 <= bundle.atlas.width && a.3 <= bundle.atlas.height);
+            for b in &padded[i + 1..] {
+                assert!(a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1);
+            }
+        }
+
+        let hit_fx = bundle.sprites["hit_fx"];
+        assert_eq!((hit_fx.width, hit_fx.height), (30, 7));
+        assert_eq!(pixel(&bundle, "hit_fx", 29, 6), [6, 0, 0, 255]);
+        // The padding repeats the edges
+        let pad = pad as i32;
+        assert_eq!(pixel(&bundle, "hit_fx", -pad, -pad), [0, 0, 0, 255]);
+        assert_eq!(pixel(&bundle, "hit_fx", 29 + pad, 6 + pad), [6, 0, 0, 255]);
+        assert_eq!(pixel(&bundle, "font", -1, 1), [0, 0, 0, 255]);
+
+        let uv = hit_fx.uv(&bundle.atlas);
+        assert_eq!(uv[2], 30. / bundle.atlas.width as f32);
+    }
+
+    #[test]
+    fn test_invalid_packs() {
+        let mut files = pack(INFO);
+        files.remove("drag_mh.png");
+        let err = prepare(&files).unwrap_err().to_string();
+        assert!(err.contains("drag_mh.png"), "{err}");
+
+        let info = INFO.replace("holdAtlas: [2, 3]", "holdAtlas: [6, 6]");
+        assert!(prepare(&pack(&info)).is_err());
+        assert!(prepare(&pack("name: Test\n")).is_err());
+    }
+
+    #[test]
+    fn test_reject_garbage() {
+        assert!(decode(b"PWMC\x03").is_err());
+        let mut bytes = prepare(&pack(INFO)).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(decode(&bytes).is_err());
+
+        let info =
+            serde_json::to_string(&decode(&prepare(&pack(INFO)).unwrap()).unwrap().info).unwrap();
+        let bundle = |sprites: &str, sounds: &str| {
+            let header = format!(
+                r#"{{"info":{info},"atlas":{{"name":"atlas","offset":0,"len":16,"width":2,"height":2}},"sprites":[{sprites}],"sounds":[{sounds}]}}"#
+            );
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.extend_from_slice(&[0; 16]);
+            bytes
+        };
+        assert!(decode(&bundle(
+            r#"{"name":"click","x":1,"y":1,"width":1,"height":1}"#,
+            ""
+        ))
+        .is_ok());
+
+        // A span whose end overflows
+        let sound = format!(r#"{{"name":"click.ogg","offset":1,"len":{}}}"#, usize::MAX);
+        let err = decode(&bundle("", &sound)).err().unwrap().to_string();
+        assert!(err.contains("outside"), "{err}");
+
+        // A sprite past the edge of the atlas
+        let sprite = r#"{"name":"click","x":1,"y":1,"width":2,"height":1}"#;
+        let err = decode(&bundle(sprite, "")).err().unwrap().to_string();
+        assert!(err.contains("click") && err.contains("outside"), "{err}");
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for (name, bytes) in files {
+            writer.start_file(*name, options).unwrap();
+            std::io::Write::write_all(&mut writer, bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_read_zip_limits() {
+        let files = read_zip(&zip(&[("pack/info.yml", b"name: Test")])).unwrap();
+        assert_eq!(files["info.yml"], b"name: Test");
+
+        // Zeros deflate to next to nothing
+        let bomb = vec![0; MAX_FILE_SIZE as usize + 1];
+        let err = read_zip(&zip(&[("click.png", &bomb)])).unwrap_err();
+        assert!(err.to_string().contains("click.png"), "{err}");
+
+        let part = vec![0; MAX_FILE_SIZE as usize];
+        let parts: Vec<_> = (0..5).map(|i| format!("{i}.ogg")).collect();
+        let entries: Vec<_> = parts.iter().map(|n| (n.as_str(), &part[..])).collect();
+        let err = read_zip(&zip(&entries)).unwrap_err();
+        assert!(err.to_string().contains("uncompressed"), "{err}");
+    }
+
+    #[test]
+    fn test_pixel_budget() {
+        // Seven of these fit the budget, the eighth does not
+        let big = png(1500, 1500);
+        let mut files = pack(INFO);
+        for name in NOTE_TEXTURES {
+            files.insert(format!("{name}.png"), big.clone());
+        }
+        let err = format!("{:#}", prepare(&files).unwrap_err());
+        assert!(
+            err.contains("drag_mh.png") && err.contains("budget"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_texture_size_limit() {
+        let mut files = pack(INFO);
+        files.insert("click.png".into(), png(MAX_TEXTURE_SIZE + 1, 1));
+        let err = format!("{:#}", prepare(&files).unwrap_err());
+        assert!(err.contains("click.png"), "{err}");
+    }
+}
@@ -0,0 +1,197 @@
+//! Phira's scoring formula, shared by everything that tallies judgements
+//!
+//! Ported from prpr/src/judge.rs. The score is 90% accuracy and 10% max
+//! combo, scaled to [`TOTAL_SCORE`], with an all-perfect play always scoring
+//! the full amount.
+use crate::core::Judgement;
+use serde::Serialize;
+
+pub const TOTAL_SCORE: f64 = 1_000_000.;
+
+/// Accuracy a judgement contributes
+pub fn weight(judgement: Judgement) -> f64 {
+    match judgement {
+        Judgement::Perfect => 1.,
+        Judgement::Good => 0.65,
+        Judgement::Bad | Judgement::Miss => 0.,
+    }
+}
+
+/// Running score state, following Phira's scoring formula.
+#[derive(Clone, Default)]
+pub struct ScoreCounter {
+    pub num_of_notes: u32,
+    /// Perfect, Good, Bad, Miss
+    pub counts: [u32; 4],
+    pub combo: u32,
+    pub max_combo: u32,
+}
+
+/// Snapshot of a `ScoreCounter` handed to JS
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreSummary {
+    pub score: u32,
+    /// Accuracy over the whole chart, 0 to 1
+    pub accuracy: f64,
+    /// Accuracy over the notes judged so far, 0 to 1
+    pub real_time_accuracy: f64,
+    pub combo: u32,
+    pub max_combo: u32,
+    pub perfect: u32,
+    pub good: u32,
+    pub bad: u32,
+    pub miss: u32,
+    pub num_of_notes: u32,
+    pub finished: bool,
+}
+
+impl ScoreCounter {
+    pub fn new(num_of_notes: u32) -> Self {
+        Self {
+            num_of_notes,
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.num_of_notes);
+    }
+
+    pub fn push(&mut self, judgement: Judgement) {
+        self.counts[judgement as usize] += 1;
+        match judgement {
+            Judgement::Perfect | Judgement::Good => {
+                self.combo += 1;
+                self.max_combo = self.max_combo.max(self.combo);
+            }
+            Judgement::Bad | Judgement::Miss => self.combo = 0,
+        }
+    }
+
+    pub fn judged(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    /// Sum of the accuracy weights of the notes judged so far
+    fn weighted(&self) -> f64 {
+        [
+            Judgement::Perfect,
+            Judgement::Good,
+            Judgement::Bad,
+            Judgement::Miss,
+        ]
+        .into_iter()
+        .map(|j| self.counts[j as usize] as f64 * weight(j))
+        .sum()
+    }
+
+    /// Whether every note has been judged
+    pub fn finished(&self) -> bool {
+        self.num_of_notes > 0 && self.judged() >= self.num_of_notes
+    }
+
+    pub fn summary(&self) -> ScoreSummary {
+        let [perfect, good, bad, miss] = self.counts;
+        ScoreSummary {
+            score: self.score(),
+            accuracy: self.accuracy(),
+            real_time_accuracy: self.real_time_accuracy(),
+            combo: self.combo,
+            max_combo: self.max_combo,
+            perfect,
+            good,
+            bad,
+            miss,
+            num_of_notes: self.num_of_notes,
+            finished: self.finished(),
+        }
+    }
+
+    /// Accuracy over the whole chart
+    pub fn accuracy(&self) -> f64 {
+        if self.num_of_notes == 0 {
+            return 1.;
+        }
+        self.weighted() / self.num_of_notes as f64
+    }
+
+    /// Accuracy over the notes judged so far
+    pub fn real_time_accuracy(&self) -> f64 {
+        let judged = self.judged();
+        if judged == 0 {
+            return 1.;
+        }
+        self.weighted() / judged as f64
+    }
+
+    pub fn score(&self) -> u32 {
+        if self.num_of_notes == 0 || self.counts[0] == self.num_of_notes {
+            return TOTAL_SCORE as u32;
+        }
+        let score = (0.9 * self.accuracy()
+            + 0.1 * self.max_combo as f64 / self.num_of_notes as f64)
+            * TOTAL_SCORE;
+        score.round() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(judgements: impl IntoIterator<Item = (Judgement, u32)>) -> ScoreCounter {
+        let judgements: Vec<_> = judgements.into_iter().collect();
+        let mut counter = ScoreCounter::new(judgements.iter().map(|(_, n)| n).sum());
+        for (judgement, n) in judgements {
+            for _ in 0..n {
+                counter.push(judgement);
+            }
+        }
+        counter
+    }
+
+    #[test]
+    fn test_all_perfect() {
+        let counter = play([(Judgement::Perfect, 100)]);
+        assert_eq!(counter.score(), 1_000_000);
+        assert_eq!(counter.accuracy(), 1.);
+        assert!(counter.finished());
+    }
+
+    #[test]
+    fn test_full_combo_with_good() {
+        let counter = play([(Judgement::Perfect, 99), (Judgement::Good, 1)]);
+        assert_eq!(counter.max_combo, 100);
+        assert!((counter.accuracy() - 0.9965).abs() < 1e-9);
+        assert_eq!(counter.score(), 996_850);
+    }
+
+    #[test]
+    fn test_combo_break() {
+        let counter = play([
+            (Judgement::Perfect, 50),
+            (Judgement::Miss, 1),
+            (Judgement::Perfect, 49),
+        ]);
+        assert_eq!((counter.combo, counter.max_combo), (49, 50));
+        assert_eq!(counter.score(), 941_000);
+
+        let counter = play([(Judgement::Bad, 1), (Judgement::Perfect, 3)]);
+        assert_eq!(counter.max_combo, 3);
+        assert_eq!(counter.score(), 750_000);
+    }
+
+    #[test]
+    fn test_real_time_accuracy() {
+        let mut counter = ScoreCounter::new(10);
+        assert_eq!(counter.real_time_accuracy(), 1.);
+        counter.push(Judgement::Perfect);
+        counter.push(Judgement::Good);
+        assert!((counter.real_time_accuracy() - 0.825).abs() < 1e-9);
+        assert!((counter.accuracy() - 0.165).abs() < 1e-9);
+        assert!(!counter.finished());
+        counter.reset();
+        assert_eq!(counter.judged(), 0);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{json_err, AppState};
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// Header carrying the key. Event streams cannot set headers, so the
+/// `api_key` query parameter works too.
+pub const HEADER: &str = "x-api-key";
+
+/// An entry of the keys file
+#[derive(Deserialize)]
+struct KeyConfig {
+    key: String,
+    /// Who the key was handed to, for logs and usage
+    name: String,
+    /// Requests allowed per minute, bursting up to this many at once
+    per_minute: u32,
+}
+
+pub struct ApiKey {
+    name: String,
+    per_minute: u32,
+    /// (tokens left, last refill)
+    bucket: Mutex<(f64, Instant)>,
+    requests: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ApiKey {
+    fn new(name: String, per_minute: u32, now: Instant) -> Self {
+        Self {
+            name,
+            per_minute,
+            bucket: Mutex::new((per_minute as f64, now)),
+            requests: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes a token from the bucket, or returns the seconds until one is
+    /// available.
+    fn take(&self) -> Result<(), u64> {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&self, now: Instant) -> Result<(), u64> {
+        let rate = self.per_minute as f64 / 60.0;
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, last) = &mut *bucket;
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * rate).min(self.per_minute as f64);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else if rate > 0.0 {
+            Err(((1.0 - *tokens) / rate).ceil() as u64)
+        } else {
+            Err(60)
+        }
+    }
+}
+
+/// Keys allowed to use the chart and live endpoints
+pub struct ApiKeys(HashMap<String, Arc<ApiKey>>);
+
+impl ApiKeys {
+    /// Reads a JSON list of `{"key", "name", "per_minute"}` objects.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let configs: Vec<KeyConfig> = serde_json::from_slice(&std::fs::read(path)?)?;
+        let keys = configs
+            .into_iter()
+            .map(|config| {
+                let key = ApiKey::new(config.name, config.per_minute, Instant::now());
+                (config.key, Arc::new(key))
+            })
+            .collect();
+        Ok(Self(keys))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    api_key: Option<String>,
+}
+
+fn key_of(req: &Request) -> Option<String> {
+    if let Some(key) = req.headers().get(HEADER) {
+        return key.to_str().ok().map(str::to_string);
+    }
+    let Query(query) = Query::<KeyQuery>::try_from_uri(req.uri()).ok()?;
+    query.api_key
+}
+
+/// Rejects requests without a known key, or over the key's quota. Lets
+/// everything through when no keys are configured.
+pub async fn middleware(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let Some(keys) = &state.api_keys else {
+        return next.run(req).await;
+    };
+    let Some(key) = key_of(&req).and_then(|key| keys.0.get(&key)).cloned() else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            json_err!("missing or unknown API key"),
+        )
+            .into_response();
+    };
+    if let Err(retry_after) = key.take() {
+        key.rejected.fetch_add(1, Ordering::Relaxed);
+        log::warn!("API key {} is over its quota", key.name);
+        let mut resp = (
+            StatusCode::TOO_MANY_REQUESTS,
+            json_err!(
+                "rate limit of {} requests per minute exceeded",
+                key.per_minute
+            ),
+        )
+            .into_response();
+        resp.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        return resp;
+    }
+    key.requests.fetch_add(1, Ordering::Relaxed);
+    req.extensions_mut().insert(key);
+    next.run(req).await
+}
+
+/// Usage of the key the request was made with
+pub async fn get_usage(key: Option<Extension<Arc<ApiKey>>>) -> (StatusCode, Response) {
+    let Some(Extension(key)) = key else {
+        return (StatusCode::NOT_FOUND, json_err!("API keys are not enabled"));
+    };
+    (
+        StatusCode::OK,
+        Json(json!({
+            "name": key.name,
+            "per_minute": key.per_minute,
+            "requests": key.requests.load(Ordering::Relaxed),
+            "rejected": key.rejected.load(Ordering::Relaxed),
+        }))
+        .into_response(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_take_bursts_then_limits() {
+        let start = Instant::now();
+        let key = ApiKey::new("test".to_string(), 3, start);
+        for _ in 0..3 {
+            assert_eq!(key.take_at(start), Ok(()));
+        }
+        // 3 per minute refill one token every 20 seconds
+        assert_eq!(key.take_at(start), Err(20));
+        assert_eq!(key.take_at(start + Duration::from_secs(5)), Err(15));
+        assert_eq!(key.take_at(start + Duration::from_secs(20)), Ok(()));
+    }
+
+    #[test]
+    fn test_take_refill_is_capped() {
+        let start = Instant::now();
+        let key = ApiKey::new("test".to_string(), 60, start);
+        for _ in 0..60 {
+            key.take_at(start).unwrap();
+        }
+        // An hour idle refills the burst, not an hour's worth of requests
+        let later = start + Duration::from_secs(3600);
+        for _ in 0..60 {
+            assert_eq!(key.take_at(later), Ok(()));
+        }
+        assert_eq!(key.take_at(later), Err(1));
+    }
+
+    #[test]
+    fn test_take_zero_quota() {
+        let key = ApiKey::new("test".to_string(), 0, Instant::now());
+        assert_eq!(key.take(), Err(60));
+    }
+
+    #[test]
+    fn test_key_of_decodes_query() {
+        let req = Request::builder()
+            .uri("/chart/1?api_key=a%2Bb%3D%20c&v=2")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(key_of(&req).as_deref(), Some("a+b= c"));
+
+        let req = Request::builder()
+            .uri("/chart/1?api_key=query")
+            .header(HEADER, "header")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(key_of(&req).as_deref(), Some("header"));
+    }
+}
@@ -1,29 +1,99 @@
 mod cache;
-pub(crate) mod parse;
 mod process;
 mod test_chart;
 
-use crate::AppState;
-use anyhow::Context;
+use crate::{json_err, AppState};
 use axum::{
     body::Body,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
+use monitor_common::{core::Time, diff, payload};
 use reqwest::header;
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinHandle,
+};
+
+pub use cache::CacheIndex;
+
+/// Outcome of an in-flight chart task, shared with every request waiting on it
+pub type TaskResult = Result<Arc<Vec<u8>>, String>;
+
+/// Chart id to the sender every request waiting on its task listens to
+pub type InFlight = Mutex<HashMap<String, broadcast::Sender<TaskResult>>>;
+
+/// Pause between charts during a background refresh
+const REFRESH_PAUSE: Duration = Duration::from_secs(1);
+
+/// Time window in seconds to serve instead of the whole chart, for
+/// practicing a section
+#[derive(Deserialize)]
+pub struct SliceQuery {
+    start: Option<Time>,
+    end: Option<Time>,
+}
+
+impl SliceQuery {
+    /// The `(start, end)` window asked for, `None` for the whole chart
+    fn window(&self) -> Result<Option<(Time, Time)>, String> {
+        if self.start.is_none() && self.end.is_none() {
+            return Ok(None);
+        }
+        let start = self.start.unwrap_or(0.0);
+        let end = self.end.unwrap_or(Time::INFINITY);
+        if !start.is_finite() || self.end.is_some_and(|end| !end.is_finite()) {
+            return Err("start and end must be finite".to_string());
+        }
+        if start >= end {
+            return Err(format!("start {start} must be before end {end}"));
+        }
+        Ok(Some((start, end)))
+    }
+}
 
-use tokio::sync::broadcast;
+/// Whether `id` names a chart: a Phira chart id or `test`. Ids end up in
+/// cache file names, so nothing else may get through.
+fn is_chart_id(id: &str) -> bool {
+    id == "test" || (!id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn invalid_id(id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        format!("Error: invalid chart id {:?}", id),
+    )
+        .into_response()
+}
 
 pub async fn fetch_and_parse_chart(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    Query(slice): Query<SliceQuery>,
 ) -> Response {
+    if !is_chart_id(&id) {
+        return invalid_id(&id);
+    }
+    let window = match slice.window() {
+        Ok(window) => window,
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("{e}")).into_response(),
+    };
     log::info!("Processing chart request for ID: {}", id);
 
-    match handle_chart_request(&state, &id).await {
+    let result = match (handle_chart_request(&state, &id).await, window) {
+        (Ok(bytes), Some((start, end))) => slice_chart(&state, bytes, start, end).await,
+        (result, _) => result,
+    };
+    match result {
         Ok(bytes) => {
             log::info!("Chart {} ready ({} bytes)", id, bytes.len());
+            if id != "test" {
+                state.stats.chart_viewed(&id);
+            }
             Response::builder()
                 .status(StatusCode::OK)
                 .header(header::CONTENT_TYPE, "application/octet-stream")
@@ -37,6 +107,153 @@ pub async fn fetch_and_parse_chart(
     }
 }
 
+async fn slice_chart(
+    state: &AppState,
+    bytes: Vec<u8>,
+    start: Time,
+    end: Time,
+) -> anyhow::Result<Vec<u8>> {
+    let encoding = state.args.encoding();
+    tokio::task::spawn_blocking(move || {
+        let (info, chart) = payload::decode(&bytes)?;
+        payload::encode(&info, &chart.slice(start, end), encoding)
+    })
+    .await?
+}
+
+/// How long browsers may keep chart audio. Its URLs carry the chart version,
+/// so a changed chart is fetched under a new URL.
+const AUDIO_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// Chart version tag from the audio URLs in the payload
+#[derive(Deserialize)]
+pub struct AudioQuery {
+    v: Option<String>,
+}
+
+/// The compressed music of a chart, as referenced by its payload
+pub async fn get_music(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<AudioQuery>,
+) -> Response {
+    if !is_chart_id(&id) {
+        return invalid_id(&id);
+    }
+    serve_audio(&state, &id, "music", query.v.as_deref()).await
+}
+
+/// A compressed chart hitsound, as referenced by the chart payload
+pub async fn get_hitsound(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+    Query(query): Query<AudioQuery>,
+) -> Response {
+    if !is_chart_id(&id) {
+        return invalid_id(&id);
+    }
+    // Only names we hand out, nothing that could leave the cache directory
+    if name == "music" || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return (StatusCode::NOT_FOUND, "Error: unknown hitsound").into_response();
+    }
+    serve_audio(&state, &id, &name, query.v.as_deref()).await
+}
+
+/// Serves cached audio, as `immutable` only when `tag` names the cached
+/// chart version. A stale tag reprocesses the chart and 404s if the version
+/// is still not the one asked for.
+async fn serve_audio(state: &AppState, id: &str, name: &str, tag: Option<&str>) -> Response {
+    let cache_dir = &state.args.cache_dir;
+    let tag_matches =
+        || cache::cached_version(cache_dir, id).is_some_and(|v| Some(v.tag().as_str()) == tag);
+    let mut path = cache::audio_file(cache_dir, id, name);
+    if path.is_none() || (tag.is_some() && !tag_matches()) {
+        // Evicted, never requested or outdated: process the chart first
+        if let Err(e) = handle_chart_request(state, id).await {
+            log::error!("Error processing chart {}: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response();
+        }
+        if tag.is_some() && !tag_matches() {
+            return (
+                StatusCode::NOT_FOUND,
+                "Error: chart version is no longer available",
+            )
+                .into_response();
+        }
+        path = cache::audio_file(cache_dir, id, name);
+    }
+    // Without a tag the audio may change under the same URL
+    let cache_control = if tag.is_some() {
+        format!("public, max-age={AUDIO_MAX_AGE}, immutable")
+    } else {
+        "no-cache".to_string()
+    };
+    let Some(path) = path else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Error: chart has no {}", name),
+        )
+            .into_response();
+    };
+    let bytes = match tokio::fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Error reading {:?}: {}", path, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response();
+        }
+    };
+    let content_type = match path.extension().and_then(|e| e.to_str()) {
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        _ => "application/octet-stream",
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(Body::from(bytes))
+        .unwrap()
+}
+
+/// Reports what changed from chart `old` to chart `new`, see
+/// [`monitor_common::diff`].
+pub async fn diff_charts(
+    State(state): State<AppState>,
+    Path((old, new)): Path<(String, String)>,
+) -> Response {
+    if let Some(id) = [&old, &new].into_iter().find(|id| !is_chart_id(id)) {
+        return invalid_id(id);
+    }
+    log::info!("Diffing chart {} against {}", old, new);
+
+    match handle_diff_request(&state, &old, &new).await {
+        Ok(diff) => Json(diff).into_response(),
+        Err(e) => {
+            log::error!("Error diffing charts {} and {}: {}", old, new, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+async fn handle_diff_request(
+    state: &AppState,
+    old: &str,
+    new: &str,
+) -> anyhow::Result<diff::ChartDiff> {
+    let (old_bytes, new_bytes) = tokio::try_join!(
+        handle_chart_request(state, old),
+        handle_chart_request(state, new)
+    )?;
+    tokio::task::spawn_blocking(move || {
+        let (_, old) = payload::decode(&old_bytes)?;
+        let (_, new) = payload::decode(&new_bytes)?;
+        Ok(diff::diff_charts(&old, &new))
+    })
+    .await?
+}
+
 async fn handle_chart_request(state: &AppState, id: &str) -> anyhow::Result<Vec<u8>> {
     // Test chart bypasses everything
     if id == "test" {
@@ -45,6 +262,21 @@ async fn handle_chart_request(state: &AppState, id: &str) -> anyhow::Result<Vec<
     }
 
     // 1. Always fetch metadata (cheap, ~1KB) to get chartUpdated
+    let info_json = fetch_chart_info(state, id).await?;
+    let version = cache::Version::of(&info_json);
+
+    // 2. Check disk cache
+    if let Some(data) = cache::check(&state.args.cache_dir, id, &version) {
+        log::info!("Chart {} served from disk cache", id);
+        state.cache_index.lock().unwrap().touch(id);
+        state.status.cache("hit", id);
+        return Ok(data);
+    }
+
+    process_chart(state, id, &info_json, &version).await
+}
+
+async fn fetch_chart_info(state: &AppState, id: &str) -> anyhow::Result<serde_json::Value> {
     let info_url = format!("{}/chart/{}", state.args.api_base, id);
     let info_resp = state.http_client.get(&info_url).send().await?;
     if !info_resp.status().is_success() {
@@ -53,65 +285,172 @@ async fn handle_chart_request(state: &AppState, id: &str) -> anyhow::Result<Vec<
             info_resp.status()
         ));
     }
-    let info_json: serde_json::Value = info_resp.json().await?;
-    let chart_updated = info_json["chartUpdated"].as_str().unwrap_or("").to_string();
-
-    // 2. Check disk cache
-    if let Some(data) = cache::check(&state.args.cache_dir, id, &chart_updated) {
-        log::info!("Chart {} served from disk cache", id);
-        return Ok(data);
-    }
+    Ok(info_resp.json().await?)
+}
 
-    // 3. Check in-flight tasks / register ourselves
-    {
+/// Downloads and parses a chart into the disk cache, or waits for the task
+/// already doing so. The work runs in its own task, so it finishes for the
+/// other waiters even if the request that started it goes away.
+async fn process_chart(
+    state: &AppState,
+    id: &str,
+    info_json: &serde_json::Value,
+    version: &cache::Version,
+) -> anyhow::Result<Vec<u8>> {
+    // 3. Join the in-flight task, or start one
+    let mut rx = {
         let mut in_flight = state.in_flight.lock().await;
         if let Some(tx) = in_flight.get(id) {
-            // Someone else is already downloading this chart — wait for them
-            let mut rx = tx.subscribe();
-            drop(in_flight);
             log::info!("Chart {} waiting for in-flight task", id);
-            match rx.recv().await {
-                Ok(Ok(())) => {
-                    // Task completed, read from disk
-                    return std::fs::read(cache::bin_path(&state.args.cache_dir, id))
-                        .with_context(|| "Failed to read cached result after in-flight wait");
-                }
-                Ok(Err(e)) => return Err(anyhow::anyhow!("In-flight task failed: {}", e)),
-                Err(e) => return Err(anyhow::anyhow!("Broadcast channel error: {}", e)),
-            }
+            tx.subscribe()
+        } else if let Some(data) = cache::check(&state.args.cache_dir, id, version) {
+            // A task finished between our cache check and taking the lock
+            return Ok(data);
+        } else {
+            let (tx, rx) = broadcast::channel(1);
+            in_flight.insert(id.to_string(), tx);
+            let task = tokio::spawn(run_chart_task(
+                state.clone(),
+                id.to_string(),
+                info_json.clone(),
+                version.clone(),
+            ));
+            let (state, id) = (state.clone(), id.to_string());
+            tokio::spawn(async move { finish_chart_task(&state.in_flight, &id, task).await });
+            rx
         }
+    };
 
-        // Register ourselves as the in-flight task
-        let (tx, _) = broadcast::channel(16);
-        in_flight.insert(id.to_string(), tx);
+    match rx.recv().await {
+        Ok(Ok(data)) => Ok(Arc::unwrap_or_clone(data)),
+        Ok(Err(e)) => Err(anyhow::anyhow!("In-flight task failed: {}", e)),
+        Err(e) => Err(anyhow::anyhow!("Broadcast channel error: {}", e)),
     }
+}
 
-    // 4. Download, parse, serialize — we are the worker
-    let result = process::process_chart_from_api(&state.http_client, &info_json).await;
-
-    // 5. Store or broadcast error, then clean up in-flight entry
-    let tx = {
-        let mut in_flight = state.in_flight.lock().await;
-        in_flight.remove(id)
-    };
+async fn run_chart_task(
+    state: AppState,
+    id: String,
+    info_json: serde_json::Value,
+    version: cache::Version,
+) -> TaskResult {
+    // 4. Download, parse, serialize
+    let result =
+        process::process_chart_from_api(&state.http_client, &id, &info_json, state.args.encoding())
+            .await;
 
-    match &result {
-        Ok(data) => {
-            if let Err(e) = cache::write(&state.args.cache_dir, id, &chart_updated, data) {
-                log::warn!("Failed to write disk cache for chart {}: {}", id, e);
-            } else {
-                log::info!("Chart {} cached to disk", id);
-            }
-            if let Some(tx) = tx {
-                let _ = tx.send(Ok(()));
+    // 5. Store before `finish_chart_task` removes the entry, so later
+    // requests find one of them
+    if let Ok(processed) = &result {
+        if let Err(e) = cache::write(
+            &state.args.cache_dir,
+            &id,
+            &version,
+            &processed.payload,
+            &processed.audio,
+        ) {
+            log::warn!("Failed to write disk cache for chart {}: {}", id, e);
+        } else {
+            log::info!("Chart {} cached to disk", id);
+            let evicted = state.cache_index.lock().unwrap().insert(&id);
+            state.status.cache("stored", &id);
+            for evicted in evicted {
+                state.status.cache("evicted", &evicted);
             }
         }
-        Err(e) => {
-            if let Some(tx) = tx {
-                let _ = tx.send(Err(e.to_string()));
+    }
+    result
+        .map(|processed| Arc::new(processed.payload))
+        .map_err(|e| e.to_string())
+}
+
+/// Waits for a chart task and hands its result to everyone waiting on it.
+/// A task that panicked counts as failed, so its entry does not linger in
+/// `in_flight` and hang the waiters and the background refresh.
+async fn finish_chart_task(in_flight: &InFlight, id: &str, task: JoinHandle<TaskResult>) {
+    let result = task.await.unwrap_or_else(|e| {
+        log::error!("Chart task for {} failed: {}", id, e);
+        Err(format!("chart task failed: {e}"))
+    });
+    let tx = in_flight.lock().await.remove(id);
+    if let Some(tx) = tx {
+        let _ = tx.send(result);
+    }
+}
+
+/// Re-processes cached charts changed upstream every `interval`, so the
+/// first viewer after an update does not have to wait for it. Charts are
+/// checked one at a time, most recently used first, and the refresh steps
+/// aside while viewers' own downloads are running.
+pub async fn refresh_cache(state: AppState, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let ids = state.cache_index.lock().unwrap().ids();
+        log::info!("Checking {} cached charts for updates", ids.len());
+        let mut refreshed = 0;
+        for id in ids {
+            while !state.in_flight.lock().await.is_empty() {
+                tokio::time::sleep(REFRESH_PAUSE).await;
             }
+            match refresh_chart(&state, &id).await {
+                Ok(true) => refreshed += 1,
+                Ok(false) => {}
+                Err(e) => log::warn!("Failed to refresh chart {}: {}", id, e),
+            }
+            tokio::time::sleep(REFRESH_PAUSE).await;
         }
+        log::info!("Refreshed {} cached charts", refreshed);
     }
+}
 
-    result
+/// Returns whether the chart had to be re-processed
+async fn refresh_chart(state: &AppState, id: &str) -> anyhow::Result<bool> {
+    let info_json = fetch_chart_info(state, id).await?;
+    let version = cache::Version::of(&info_json);
+    if cache::is_fresh(&state.args.cache_dir, id, &version) {
+        return Ok(false);
+    }
+    log::info!("Chart {} changed upstream, refreshing", id);
+    process_chart(state, id, &info_json, &version).await?;
+    state.status.cache("refreshed", id);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_chart_id() {
+        assert!(is_chart_id("1001"));
+        assert!(is_chart_id("test"));
+        assert!(!is_chart_id(""));
+        assert!(!is_chart_id("../../etc"));
+        assert!(!is_chart_id("1001/../2"));
+        assert!(!is_chart_id("-1"));
+        assert!(!is_chart_id("１２"));
+    }
+
+    #[tokio::test]
+    async fn test_panicked_task_fails_waiters() {
+        let in_flight = InFlight::default();
+        let (tx, mut rx) = broadcast::channel(1);
+        in_flight.lock().await.insert("1001".to_string(), tx);
+        let task = tokio::spawn(async { panic!("parser bug") });
+        finish_chart_task(&in_flight, "1001", task).await;
+        assert!(matches!(rx.recv().await, Ok(Err(_))));
+        assert!(in_flight.lock().await.is_empty());
+    }
+
+    #[test]
+    fn test_slice_window() {
+        let window = |start, end| SliceQuery { start, end }.window();
+        assert_eq!(window(None, None), Ok(None));
+        assert_eq!(window(None, Some(10.0)), Ok(Some((0.0, 10.0))));
+        assert_eq!(window(Some(5.0), None), Ok(Some((5.0, Time::INFINITY))));
+        assert!(window(Some(Time::NAN), Some(10.0)).is_err());
+        assert!(window(Some(0.0), Some(Time::INFINITY)).is_err());
+        assert!(window(Some(10.0), Some(10.0)).is_err());
+        assert!(window(Some(10.0), Some(5.0)).is_err());
+    }
 }
@@ -1,8 +1,54 @@
-use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Bumped whenever the serialized chart layout changes, so stale entries
+/// are re-processed instead of failing to decode on the client. Also bumped
+/// when a parser fix changes the output for charts already cached.
+const FORMAT_VERSION: u32 = 9;
+
+/// The upstream chart an entry was built from
+#[derive(Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Version {
+    chart_updated: String,
+    /// Download URL, changes whenever the chart file is replaced
+    #[serde(default)]
+    file: String,
+}
+
+impl Version {
+    /// Reads the version from the chart info returned by the Phira API
+    pub fn of(info_json: &serde_json::Value) -> Self {
+        Self {
+            chart_updated: info_json["chartUpdated"].as_str().unwrap_or("").to_string(),
+            file: info_json["file"].as_str().unwrap_or("").to_string(),
+        }
+    }
+
+    /// Short hash of the version, for URLs that change with the chart. The
+    /// URLs are stored in cached payloads, so the digest must not change
+    /// between builds.
+    pub fn tag(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.chart_updated.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.file.as_bytes());
+        hasher.finalize()[..8]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
 struct CacheMeta {
-    chart_updated: String,
+    #[serde(flatten)]
+    version: Version,
+    #[serde(default)]
+    format: u32,
 }
 
 pub fn meta_path(cache_dir: &Path, id: &str) -> PathBuf {
@@ -13,29 +59,80 @@ pub fn bin_path(cache_dir: &Path, id: &str) -> PathBuf {
     cache_dir.join(format!("{}.bin", id))
 }
 
-/// Check if the disk cache has a valid entry for this chart.
-pub fn check(cache_dir: &Path, id: &str, chart_updated: &str) -> Option<Vec<u8>> {
-    let meta_p = meta_path(cache_dir, id);
-    let bin_p = bin_path(cache_dir, id);
+/// Directory holding the chart's compressed music and hitsounds
+pub fn audio_dir(cache_dir: &Path, id: &str) -> PathBuf {
+    cache_dir.join(format!("{}.audio", id))
+}
 
-    let meta_bytes = std::fs::read(&meta_p).ok()?;
-    let meta: CacheMeta = serde_json::from_slice(&meta_bytes).ok()?;
+/// The cached audio file served as `name`, whatever its extension
+pub fn audio_file(cache_dir: &Path, id: &str, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(audio_dir(cache_dir, id))
+        .ok()?
+        .flatten()
+        .map(|file| file.path())
+        .find(|path| path.file_stem().and_then(|s| s.to_str()) == Some(name))
+}
 
-    if meta.chart_updated != chart_updated {
-        return None;
+/// Removes a cache file or directory
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
     }
+}
 
-    std::fs::read(&bin_p).ok()
+/// Whether the disk cache entry for this chart was built from `version`.
+pub fn is_fresh(cache_dir: &Path, id: &str, version: &Version) -> bool {
+    let Ok(meta_bytes) = std::fs::read(meta_path(cache_dir, id)) else {
+        return false;
+    };
+    serde_json::from_slice::<CacheMeta>(&meta_bytes)
+        .is_ok_and(|meta| meta.version == *version && meta.format == FORMAT_VERSION)
+}
+
+/// Version of the chart currently in the disk cache, if any
+pub fn cached_version(cache_dir: &Path, id: &str) -> Option<Version> {
+    let meta_bytes = std::fs::read(meta_path(cache_dir, id)).ok()?;
+    serde_json::from_slice::<CacheMeta>(&meta_bytes)
+        .ok()
+        .filter(|meta| meta.format == FORMAT_VERSION)
+        .map(|meta| meta.version)
+}
+
+/// Check if the disk cache has a valid entry for this chart.
+pub fn check(cache_dir: &Path, id: &str, version: &Version) -> Option<Vec<u8>> {
+    if !is_fresh(cache_dir, id, version) {
+        return None;
+    }
+    std::fs::read(bin_path(cache_dir, id)).ok()
 }
 
 /// Write the result to disk cache atomically (write tmp, then rename).
-pub fn write(cache_dir: &Path, id: &str, chart_updated: &str, data: &[u8]) -> anyhow::Result<()> {
+pub fn write(
+    cache_dir: &Path,
+    id: &str,
+    version: &Version,
+    data: &[u8],
+    audio: &[(String, Vec<u8>)],
+) -> anyhow::Result<()> {
     std::fs::create_dir_all(cache_dir)?;
 
     let bin_p = bin_path(cache_dir, id);
     let meta_p = meta_path(cache_dir, id);
+    let audio_p = audio_dir(cache_dir, id);
     let bin_tmp = bin_p.with_extension("bin.tmp");
     let meta_tmp = meta_p.with_extension("meta.tmp");
+    let audio_tmp = audio_p.with_extension("audio.tmp");
+
+    // Write audio, replacing the previous version's
+    let _ = remove_path(&audio_tmp);
+    std::fs::create_dir(&audio_tmp)?;
+    for (name, bytes) in audio {
+        std::fs::write(audio_tmp.join(name), bytes)?;
+    }
+    let _ = remove_path(&audio_p);
+    std::fs::rename(&audio_tmp, &audio_p)?;
 
     // Write bin
     std::fs::write(&bin_tmp, data)?;
@@ -43,10 +140,176 @@ pub fn write(cache_dir: &Path, id: &str, chart_updated: &str, data: &[u8]) -> an
 
     // Write meta
     let meta = CacheMeta {
-        chart_updated: chart_updated.to_string(),
+        version: version.clone(),
+        format: FORMAT_VERSION,
     };
     std::fs::write(&meta_tmp, serde_json::to_vec(&meta)?)?;
     std::fs::rename(&meta_tmp, &meta_p)?;
 
     Ok(())
 }
+
+struct Entry {
+    /// Bytes on disk, data, meta and audio files together
+    size: u64,
+    last_used: SystemTime,
+}
+
+/// Sizes and last use of the cached charts, for keeping the cache under a
+/// byte budget by evicting the least recently used charts. The last use is
+/// kept as the data file's modification time, so the order survives
+/// restarts.
+pub struct CacheIndex {
+    dir: PathBuf,
+    /// Bytes the cache may take, 0 for no limit
+    budget: u64,
+    entries: HashMap<String, Entry>,
+    total: u64,
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+fn dir_size(path: &Path) -> u64 {
+    std::fs::read_dir(path).map_or(0, |dir| {
+        dir.flatten().map(|file| file_size(&file.path())).sum()
+    })
+}
+
+impl CacheIndex {
+    /// Builds the index from the files in `cache_dir`, dropping leftovers of
+    /// interrupted writes and evicting down to `budget`.
+    pub fn scan(cache_dir: &Path, budget: u64) -> Self {
+        let mut index = Self {
+            dir: cache_dir.to_path_buf(),
+            budget,
+            entries: HashMap::new(),
+            total: 0,
+        };
+        let Ok(dir) = std::fs::read_dir(cache_dir) else {
+            return index;
+        };
+        for file in dir.flatten() {
+            let path = file.path();
+            let (Some(id), Some(ext)) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|s| s.to_str()),
+            ) else {
+                continue;
+            };
+            let orphan = match ext {
+                "bin" => !meta_path(cache_dir, id).exists(),
+                "meta" | "audio" => !bin_path(cache_dir, id).exists(),
+                "tmp" => true,
+                _ => false,
+            };
+            if orphan {
+                log::info!("Removing stale cache file {:?}", path);
+                let _ = remove_path(&path);
+            } else if ext == "bin" {
+                let last_used = file
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                index.add(id, last_used);
+            }
+        }
+        log::info!(
+            "Chart cache holds {} charts, {} bytes",
+            index.entries.len(),
+            index.total
+        );
+        index.evict(None);
+        index
+    }
+
+    fn add(&mut self, id: &str, last_used: SystemTime) {
+        self.remove(id);
+        let size = file_size(&bin_path(&self.dir, id))
+            + file_size(&meta_path(&self.dir, id))
+            + dir_size(&audio_dir(&self.dir, id));
+        self.total += size;
+        self.entries
+            .insert(id.to_string(), Entry { size, last_used });
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some(entry) = self.entries.remove(id) {
+            self.total -= entry.size;
+        }
+    }
+
+    /// Ids of the cached charts, most recently used first
+    pub fn ids(&self) -> Vec<String> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+        entries.into_iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Marks a chart as just served
+    pub fn touch(&mut self, id: &str) {
+        let now = SystemTime::now();
+        if let Some(entry) = self.entries.get_mut(id) {
+            entry.last_used = now;
+        }
+        let touched = std::fs::File::options()
+            .write(true)
+            .open(bin_path(&self.dir, id))
+            .and_then(|file| file.set_modified(now));
+        if let Err(e) = touched {
+            log::warn!("Failed to mark chart {} as used: {}", id, e);
+        }
+    }
+
+    /// Records a chart just written by `write`, evicting others to make
+    /// room for it. Returns the evicted charts.
+    pub fn insert(&mut self, id: &str) -> Vec<String> {
+        self.add(id, SystemTime::now());
+        self.evict(Some(id))
+    }
+
+    /// Removes the least recently used charts, except `keep`, until the
+    /// cache fits the budget.
+    fn evict(&mut self, keep: Option<&str>) -> Vec<String> {
+        let mut evicted = Vec::new();
+        if self.budget == 0 || self.total <= self.budget {
+            return evicted;
+        }
+        let mut order: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(id, _)| Some(id.as_str()) != keep)
+            .map(|(id, entry)| (entry.last_used, id.clone()))
+            .collect();
+        order.sort();
+        for (_, id) in order {
+            if self.total <= self.budget {
+                break;
+            }
+            log::info!("Evicting chart {} from disk cache", id);
+            let _ = std::fs::remove_file(meta_path(&self.dir, &id));
+            let _ = std::fs::remove_file(bin_path(&self.dir, &id));
+            let _ = remove_path(&audio_dir(&self.dir, &id));
+            self.remove(&id);
+            evicted.push(id);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_is_stable() {
+        let version = Version::of(&serde_json::json!({
+            "chartUpdated": "2024-01-01T00:00:00Z",
+            "file": "https://example.com/chart.zip",
+        }));
+        // Stored in cached payloads, must not change with the toolchain
+        assert_eq!(version.tag(), "8e42c24d8aa8c7e6");
+        assert_ne!(version.tag(), Version::of(&serde_json::json!({})).tag());
+    }
+}
@@ -1,5 +1,7 @@
 pub fn generate_test_chart() -> anyhow::Result<Vec<u8>> {
-    use monitor_common::core::{AnimFloat, Chart, ChartInfo, JudgeLine, Keyframe, Note, NoteKind};
+    use monitor_common::core::{
+        time_f32, AnimFloat, Chart, ChartInfo, JudgeLine, Keyframe, Note, NoteKind, Time,
+    };
 
     let mut line = JudgeLine::default();
     const HEIGHT_PER_SEC: f32 = 1.0;
@@ -9,9 +11,10 @@ pub fn generate_test_chart() -> anyhow::Result<Vec<u8>> {
         Keyframe::new(100.0, 100.0 * HEIGHT_PER_SEC, 0),
     ]);
 
-    let mut add_note = |kind: NoteKind, time: f32| {
-        let h = time * HEIGHT_PER_SEC;
+    let mut add_note = |kind: NoteKind, time: Time| {
+        let h = time_f32(time) * HEIGHT_PER_SEC;
         line.notes.push(Note {
+            id: line.notes.len() as u32,
             kind,
             time,
             height: h,
@@ -25,15 +28,16 @@ pub fn generate_test_chart() -> anyhow::Result<Vec<u8>> {
     add_note(NoteKind::Drag, 3.5);
     add_note(NoteKind::Flick, 4.0);
 
-    let start_t = 5.0;
-    let end_t = 7.0;
+    let start_t: Time = 5.0;
+    let end_t: Time = 7.0;
     line.notes.push(Note {
+        id: line.notes.len() as u32,
         kind: NoteKind::Hold {
             end_time: end_t,
-            end_height: end_t * HEIGHT_PER_SEC,
+            end_height: time_f32(end_t) * HEIGHT_PER_SEC,
         },
         time: start_t,
-        height: start_t * HEIGHT_PER_SEC,
+        height: time_f32(start_t) * HEIGHT_PER_SEC,
         speed: 1.0,
         ..Default::default()
     });
@@ -45,9 +49,5 @@ pub fn generate_test_chart() -> anyhow::Result<Vec<u8>> {
         ..Default::default()
     };
 
-    use bincode::Options;
-    let encoded = bincode::options()
-        .with_varint_encoding()
-        .serialize(&(info, chart))?;
-    Ok(encoded)
+    monitor_common::payload::encode(&info, &chart, monitor_common::payload::Encoding::Plain)
 }
@@ -0,0 +1,222 @@
+use crate::{json_err, ttl::TtlCache, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long a fetched leaderboard is served before asking Phira again
+const TTL: Duration = Duration::from_secs(60);
+
+/// Most leaderboards kept
+const MAX_LEADERBOARDS: usize = 1024;
+
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+    /// Rank by the std score instead of the score
+    #[serde(default)]
+    std: bool,
+}
+
+/// A record as returned by Phira, which has used both snake and camel case
+#[derive(Deserialize)]
+struct PhiraRecord {
+    #[serde(default)]
+    rank: Option<u32>,
+    #[serde(alias = "playerId")]
+    player: i32,
+    #[serde(default, alias = "playerName")]
+    player_name: Option<String>,
+    score: i32,
+    accuracy: f32,
+    #[serde(default, alias = "fullCombo")]
+    full_combo: bool,
+    #[serde(default)]
+    std: Option<f32>,
+    #[serde(default, alias = "stdScore")]
+    std_score: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PhiraLeaderboard {
+    Top { top: Vec<PhiraRecord> },
+    List(Vec<PhiraRecord>),
+}
+
+#[derive(Serialize)]
+pub struct LeaderboardEntry {
+    rank: u32,
+    player: i32,
+    player_name: Option<String>,
+    score: i32,
+    accuracy: f32,
+    full_combo: bool,
+    std: Option<f32>,
+    std_score: Option<f32>,
+}
+
+#[derive(Serialize)]
+pub struct Leaderboard {
+    chart: i32,
+    std: bool,
+    /// When the leaderboard was fetched from Phira, in milliseconds
+    updated: i64,
+    records: Vec<LeaderboardEntry>,
+}
+
+/// Leaderboards by chart ID and whether ranked by std score
+pub type Cache = TtlCache<(i32, bool), Leaderboard>;
+
+pub fn cache() -> Cache {
+    TtlCache::new(TTL, MAX_LEADERBOARDS)
+}
+
+/// Top records of a chart from Phira, ranked and with field names
+/// normalized.
+pub async fn get_leaderboard(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Query(query): Query<LeaderboardQuery>,
+) -> (StatusCode, Response) {
+    let key = (id, query.std);
+    if let Some(board) = state.leaderboards.get(&key) {
+        return (StatusCode::OK, Json(&*board).into_response());
+    }
+    match fetch_leaderboard(&state, id, query.std).await {
+        Ok(board) => {
+            let board = state.leaderboards.insert(key, board);
+            (StatusCode::OK, Json(&*board).into_response())
+        }
+        Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {
+            (StatusCode::NOT_FOUND, json_err!("chart not found"))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json_err!("failed to fetch leaderboard: {e}"),
+        ),
+    }
+}
+
+async fn fetch_leaderboard(state: &AppState, id: i32, std: bool) -> reqwest::Result<Leaderboard> {
+    let mut url = format!("{}/record/query/{id}", state.args.api_base);
+    if std {
+        url.push_str("?std=1");
+    }
+    let records = state
+        .http_client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<PhiraLeaderboard>()
+        .await?;
+
+    Ok(Leaderboard {
+        chart: id,
+        std,
+        updated: chrono::Utc::now().timestamp_millis(),
+        records: normalize(records, std),
+    })
+}
+
+/// Maps Phira's records to entries, ranking them by score (or std score if
+/// `std`) if Phira left the ranks out.
+fn normalize(records: PhiraLeaderboard, std: bool) -> Vec<LeaderboardEntry> {
+    let records = match records {
+        PhiraLeaderboard::Top { top } => top,
+        PhiraLeaderboard::List(list) => list,
+    };
+    let mut records: Vec<_> = records
+        .into_iter()
+        .map(|r| LeaderboardEntry {
+            rank: r.rank.unwrap_or(0),
+            player: r.player,
+            player_name: r.player_name,
+            score: r.score,
+            accuracy: r.accuracy,
+            full_combo: r.full_combo,
+            std: r.std,
+            std_score: r.std_score,
+        })
+        .collect();
+    if records.iter().any(|r| r.rank == 0) {
+        if std {
+            records.sort_by(|a, b| {
+                b.std_score
+                    .unwrap_or(0.0)
+                    .total_cmp(&a.std_score.unwrap_or(0.0))
+            });
+        } else {
+            records.sort_by_key(|r| std::cmp::Reverse(r.score));
+        }
+        for (i, record) in records.iter_mut().enumerate() {
+            record.rank = i as u32 + 1;
+        }
+    } else {
+        records.sort_by_key(|r| r.rank);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str, std: bool) -> Vec<LeaderboardEntry> {
+        normalize(serde_json::from_str(json).unwrap(), std)
+    }
+
+    #[test]
+    fn test_normalize_field_names() {
+        let camel = r#"{"top": [{"playerId": 1, "playerName": "A", "score": 990000,
+            "accuracy": 0.99, "fullCombo": true, "std": 1.5, "stdScore": 980000}]}"#;
+        let snake = r#"[{"player": 1, "player_name": "A", "score": 990000,
+            "accuracy": 0.99, "full_combo": true, "std": 1.5, "std_score": 980000}]"#;
+        for json in [camel, snake] {
+            let entry = &parse(json, false)[0];
+            assert_eq!(entry.rank, 1);
+            assert_eq!(entry.player, 1);
+            assert_eq!(entry.player_name.as_deref(), Some("A"));
+            assert_eq!(entry.score, 990000);
+            assert_eq!(entry.accuracy, 0.99);
+            assert!(entry.full_combo);
+            assert_eq!(entry.std, Some(1.5));
+            assert_eq!(entry.std_score, Some(980000.));
+        }
+
+        // Only the player, score and accuracy are required
+        let entry = &parse(r#"[{"player": 2, "score": 1, "accuracy": 0.5}]"#, false)[0];
+        assert_eq!(entry.player_name, None);
+        assert!(!entry.full_combo);
+        assert_eq!((entry.std, entry.std_score), (None, None));
+    }
+
+    #[test]
+    fn test_normalize_ranks() {
+        let records = r#"[
+            {"player": 1, "score": 900000, "accuracy": 0.9, "stdScore": 950000},
+            {"playerId": 2, "score": 950000, "accuracy": 0.95, "std_score": 900000},
+            {"player": 3, "score": 800000, "accuracy": 0.8}
+        ]"#;
+        let ranked = |std| {
+            parse(records, std)
+                .iter()
+                .map(|r| (r.rank, r.player))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranked(false), [(1, 2), (2, 1), (3, 3)]);
+        assert_eq!(ranked(true), [(1, 1), (2, 2), (3, 3)]);
+
+        // Ranks from Phira are kept, records ordered by them
+        let ranked = r#"[
+            {"rank": 2, "player": 1, "score": 1, "accuracy": 1},
+            {"rank": 1, "player": 2, "score": 0, "accuracy": 1}
+        ]"#;
+        let players: Vec<_> = parse(ranked, false).iter().map(|r| r.player).collect();
+        assert_eq!(players, [2, 1]);
+    }
+}
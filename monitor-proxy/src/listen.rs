@@ -0,0 +1,90 @@
+use anyhow::Context;
+use axum::Router;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{fmt, net::SocketAddr, path::PathBuf, str::FromStr};
+
+/// An address to serve on: `host:port` for TCP (`[::]:3080` for IPv6), or
+/// `unix:<path>` for a Unix domain socket behind a reverse proxy
+#[derive(Debug, Clone)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|e| format!("invalid listen address {s:?}: {e}"))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "http://{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Binds a TCP listener. IPv6 sockets only take IPv6, so `[::]` and
+/// `0.0.0.0` can share a port even where IPv6 sockets accept IPv4 by
+/// default (Linux `bindv6only=0`).
+fn bind_tcp(addr: SocketAddr) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Like `TcpListener::bind`, so restarts are not held up by TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+pub async fn serve(addr: ListenAddr, app: Router) -> anyhow::Result<()> {
+    match &addr {
+        ListenAddr::Tcp(socket) => {
+            let listener = bind_tcp(*socket).with_context(|| format!("failed to bind {addr}"))?;
+            log::info!("Listening on {addr}");
+            axum::serve(listener, app).await?;
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+            // A socket left behind by an earlier run would fail the bind
+            if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("failed to remove stale socket {addr}"))?;
+            }
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("failed to bind {addr}"))?;
+            log::info!("Listening on {addr}");
+            axum::serve(listener, app).await?;
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(_) => anyhow::bail!("{addr}: Unix sockets are not supported here"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_both_families_on_one_port() {
+        let v4 = bind_tcp("0.0.0.0:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let v6 = bind_tcp(SocketAddr::from(([0u16; 8], port))).unwrap();
+        assert_eq!(v6.local_addr().unwrap().port(), port);
+    }
+}
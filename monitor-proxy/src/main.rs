@@ -2,24 +2,45 @@
 //!
 //! This server provides:
 //! 1. Static file serving for the web frontend
-//! 2. Server-side chart parsing (download -> unzip -> parse -> bincode)
+//! 2. Server-side chart parsing (download -> unzip -> parse -> versioned bincode)
 //! 3. Disk-based chart caching with in-flight request deduplication
-
-use axum::{http::Method, middleware, routing::get, routing::post, Router};
+//! 4. Resource pack preprocessing into ready-to-upload bundles
+//! 5. Cached chart leaderboards and user profiles from the Phira API
+//! 6. A live status stream of logs, cache events and rooms for operators
+//! 7. Chart view and room watch time statistics
+
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{HeaderName, HeaderValue, Method},
+    middleware,
+    routing::get,
+    routing::post,
+    Router,
+};
 use axum_extra::extract::cookie;
 use clap::Parser;
 use phira_mp_common::generate_secret_key;
 use reqwest::Client;
-use std::{collections::HashMap, env, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, env, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 use tokio::sync::{broadcast, Mutex};
 use tower_http::{
+    compression::CompressionLayer,
     cors::{Any, CorsLayer},
     services::ServeDir,
 };
 
+mod api_keys;
 mod auth;
 mod chart;
+mod leaderboard;
+mod listen;
+mod operator;
+mod respack;
 mod rooms;
+mod stats;
+mod status;
+mod ttl;
+mod users;
 
 // ── CLI Arguments ──────────────────────────────────────────────────────────────
 
@@ -33,18 +54,51 @@ fn default_cache_path() -> PathBuf {
 #[derive(Parser, Debug, Clone)]
 #[command(name = "monitor-proxy", about = "Phira Web Monitor Proxy Server")]
 pub struct Args {
-    /// Debug mode
+    /// Debug mode, also lifts the CORS restrictions below
     #[arg(long)]
     pub debug: bool,
 
-    /// Port to listen on
+    /// Origin allowed to call the proxy from another site, repeatable.
+    /// None by default, the bundled frontend is served same-origin.
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    pub cors_origins: Vec<HeaderValue>,
+
+    /// Request header allowed in cross-origin requests, repeatable
+    #[arg(long = "cors-header", value_name = "HEADER", default_values = ["content-type", api_keys::HEADER, operator::HEADER])]
+    pub cors_headers: Vec<HeaderName>,
+
+    /// Method allowed in cross-origin requests, repeatable
+    #[arg(long = "cors-method", value_name = "METHOD", default_values = ["GET", "POST", "OPTIONS"])]
+    pub cors_methods: Vec<Method>,
+
+    /// Port to listen on when no --listen address is given
     #[arg(long, default_value_t = 3080)]
     pub port: u16,
 
+    /// Address to serve on, repeatable: `0.0.0.0:3080`, `[::]:3080` or
+    /// `unix:/run/monitor-proxy.sock`
+    #[arg(long = "listen", value_name = "ADDR")]
+    pub listen: Vec<listen::ListenAddr>,
+
     /// Directory for disk-based chart cache
     #[arg(long, default_value_os_t = default_cache_path())]
     pub cache_dir: PathBuf,
 
+    /// Megabytes the chart cache may take before the least recently used
+    /// charts are evicted, 0 for no limit
+    #[arg(long, default_value_t = 2048)]
+    pub cache_budget_mb: u64,
+
+    /// Minutes between checks of the cached charts for upstream updates,
+    /// 0 to only check when a chart is requested
+    #[arg(long, default_value_t = 60)]
+    pub refresh_interval_mins: u64,
+
+    /// JSON file of API keys (`[{"key", "name", "per_minute"}]`) required
+    /// for the chart and room endpoints. Open to everyone when left out.
+    #[arg(long, value_name = "PATH")]
+    pub api_keys: Option<PathBuf>,
+
     /// Phira API base URL
     #[arg(long, default_value = "https://phira.5wyxi.com")]
     pub api_base: String,
@@ -52,6 +106,43 @@ pub struct Args {
     /// Phira-mp server address
     #[arg(long, default_value = "localhost:12346")]
     pub mp_server: String,
+
+    /// Serve charts with quantized keyframes, shrinking animation-heavy
+    /// charts at a tiny loss of precision
+    #[arg(long)]
+    pub compact_charts: bool,
+
+    /// Token for the `/ws/status` operator stream, which is disabled
+    /// without one
+    #[arg(long, env = "HSN_STATUS_TOKEN", hide_env_values = true)]
+    pub status_token: Option<String>,
+
+    /// Token required in `X-Operator-Token` to create, chat in and control
+    /// rooms through the proxy's MP session, which are disabled without one
+    #[arg(long, env = "HSN_OPERATOR_TOKEN", hide_env_values = true)]
+    pub operator_token: Option<String>,
+}
+
+impl Args {
+    pub fn cors(&self) -> CorsLayer {
+        let cors = CorsLayer::new().allow_methods(self.cors_methods.clone());
+        if self.debug {
+            // Any page may call the proxy, e.g. a dev server on another port
+            return cors.allow_origin(Any).allow_headers(Any);
+        }
+        cors.allow_origin(self.cors_origins.clone())
+            .allow_headers(self.cors_headers.clone())
+            // Origins are listed explicitly, so the login cookie may go along
+            .allow_credentials(true)
+    }
+
+    pub fn encoding(&self) -> monitor_common::payload::Encoding {
+        if self.compact_charts {
+            monitor_common::payload::Encoding::Compact
+        } else {
+            monitor_common::payload::Encoding::Plain
+        }
+    }
 }
 
 // ── Application State ──────────────────────────────────────────────────────────
@@ -63,12 +154,33 @@ pub struct AppStateInner {
     /// HTTP client
     pub http_client: Client,
 
+    /// HTTP client for avatars, kept to Phira's hosts
+    pub avatar_client: Client,
+
     /// Room monitor client
     pub room_monitor_client: rooms::RoomMonitorClient,
 
     /// In-flight task deduplication: chart_id → broadcast sender.
-    /// Waiters receive Ok(()) on success (then read from disk), or Err(msg) on failure.
-    pub in_flight: Mutex<HashMap<String, broadcast::Sender<Result<(), String>>>>,
+    /// Waiters receive the serialized chart on success, or Err(msg) on failure.
+    pub in_flight: Mutex<HashMap<String, broadcast::Sender<chart::TaskResult>>>,
+
+    /// Sizes and last use of the disk cache entries
+    pub cache_index: std::sync::Mutex<chart::CacheIndex>,
+
+    /// Keys for the chart and room endpoints, `None` if they are open
+    pub api_keys: Option<api_keys::ApiKeys>,
+
+    /// Recently fetched chart leaderboards
+    pub leaderboards: leaderboard::Cache,
+
+    /// Recently fetched user profiles
+    pub users: users::Cache,
+
+    /// Log lines and cache events for `/ws/status`
+    pub status: status::Status,
+
+    /// Chart views and room watch time
+    pub stats: stats::Stats,
 
     /// Secret key for cookie signing
     pub cookie_key: cookie::Key,
@@ -77,21 +189,39 @@ pub struct AppStateInner {
 pub struct AppState(Arc<AppStateInner>);
 
 impl AppState {
-    pub async fn new(args: Args) -> Self {
+    pub async fn new(args: Args, status: status::Status) -> Self {
         let cookie_key = cookie::Key::from(
             &generate_secret_key("cookie", 64).expect("failed to generate key for cookie"),
         );
         let http_client = Client::new();
+        let avatar_client = users::avatar_client(&args.api_base);
         let room_monitor_client = rooms::RoomMonitorClient::new(&args.mp_server)
             .await
             .expect("failed to create RoomMonitorClient");
         let in_flight = Mutex::default();
+        let api_keys = args.api_keys.as_deref().map(|path| {
+            let keys = api_keys::ApiKeys::load(path).expect("failed to load API keys");
+            log::info!("Loaded {} API keys", keys.len());
+            keys
+        });
+        let cache_index = std::sync::Mutex::new(chart::CacheIndex::scan(
+            &args.cache_dir,
+            args.cache_budget_mb * 1024 * 1024,
+        ));
+        let stats = stats::Stats::load(&args.cache_dir);
 
         Self(Arc::new(AppStateInner {
             args,
             http_client,
+            avatar_client,
             room_monitor_client,
             in_flight,
+            cache_index,
+            api_keys,
+            leaderboards: leaderboard::cache(),
+            users: users::cache(),
+            status,
+            stats,
             cookie_key,
         }))
     }
@@ -145,48 +275,102 @@ macro_rules! json_msg {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let status = status::Status::new();
+    status::init_logger(status.clone());
 
     let args = Args::parse();
     log::info!("Phira Web Monitor Proxy starting...");
     log::info!("API Base: {}", args.api_base);
     log::info!("Cache Dir: {:?}", args.cache_dir);
 
-    let port = args.port;
-    let state = AppState::new(args).await;
-
-    // CORS configuration
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(Any);
+    let addrs = if args.listen.is_empty() {
+        vec![listen::ListenAddr::Tcp(SocketAddr::from((
+            [0, 0, 0, 0],
+            args.port,
+        )))]
+    } else {
+        args.listen.clone()
+    };
+    let cors = args.cors();
+    if args.debug {
+        log::warn!("Debug mode: accepting cross-origin requests from any origin");
+    }
+    let refresh_interval = args.refresh_interval_mins;
+    let state = AppState::new(args, status).await;
+    if refresh_interval > 0 {
+        tokio::spawn(chart::refresh_cache(
+            state.clone(),
+            Duration::from_secs(refresh_interval * 60),
+        ));
+    }
+    tokio::spawn(stats::save_periodically(state.clone()));
 
-    let public_routes = Router::new()
+    let keyed_routes = Router::new()
         .route("/chart/{id}", get(chart::fetch_and_parse_chart))
+        .route("/chart/{id}/music", get(chart::get_music))
+        .route("/chart/{id}/hitsound/{name}", get(chart::get_hitsound))
+        .route("/chart/{old}/diff/{new}", get(chart::diff_charts))
+        .route("/chart/{id}/leaderboard", get(leaderboard::get_leaderboard))
         .route("/rooms/info", get(rooms::get_room_list))
         .route("/rooms/info/{id}", get(rooms::get_room_by_id))
         .route("/rooms/user/{id}", get(rooms::get_room_of_user))
+        .route("/rooms/timeline/{id}", get(rooms::get_room_timeline))
+        .route("/rooms/scoreboard/{id}", get(rooms::get_room_scoreboard))
         .route("/rooms/listen", get(rooms::listen))
-        .route("/auth/login", post(auth::login));
+        .route("/stats/charts", get(stats::get_chart_stats))
+        .route("/keys/usage", get(api_keys::get_usage))
+        .route(
+            "/respack/prepare",
+            post(respack::prepare_pack).layer((
+                DefaultBodyLimit::max(respack::MAX_PACK_SIZE),
+                // Raw RGBA compresses well, and browsers inflate it natively
+                CompressionLayer::new().gzip(true),
+            )),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            api_keys::middleware,
+        ));
+    let public_routes = Router::new()
+        .route("/user/{id}", get(users::get_user_profile))
+        .route("/user/{id}/avatar", get(users::get_user_avatar))
+        .route("/auth/login", post(auth::login))
+        .route("/ws/status", get(status::status_ws));
     let protected_routes = Router::new()
         .route("/auth/me", get(auth::get_me_profile))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             auth::auth_middleware,
         ));
+    // Logged in for the audit log, and an operator for acting as the session
+    let operator_routes = Router::new()
+        .route("/rooms", post(rooms::create_room))
+        .route("/rooms/{id}/chat", post(rooms::send_chat))
+        .route("/rooms/{id}/host", post(rooms::host_room))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            operator::middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::auth_middleware,
+        ));
 
     let app = Router::new()
+        .merge(keyed_routes)
         .merge(public_routes)
         .merge(protected_routes)
+        .merge(operator_routes)
         .fallback_service(ServeDir::new("../web/dist"))
         .with_state(state)
         .layer(cors);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    log::info!("Listening on http://{addr}");
-
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    futures::future::try_join_all(
+        addrs
+            .into_iter()
+            .map(|addr| listen::serve(addr, app.clone())),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,59 @@
+//! Operator credential for the routes that act through the proxy's own MP
+//! session, which a Phira login alone must not be enough for
+
+use crate::{json_err, AppState};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use subtle::ConstantTimeEq;
+
+pub const HEADER: &str = "x-operator-token";
+
+/// Compares tokens in constant time, so the expected one does not leak
+/// through response timing
+pub fn token_matches(expected: &str, given: Option<&str>) -> bool {
+    given.is_some_and(|given| given.as_bytes().ct_eq(expected.as_bytes()).into())
+}
+
+/// Requires the `--operator-token` in the `X-Operator-Token` header. The
+/// routes are disabled without a configured token.
+pub async fn middleware(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(token) = state
+        .args
+        .operator_token
+        .as_deref()
+        .filter(|t| !t.is_empty())
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            json_err!("room control is disabled, set HSN_OPERATOR_TOKEN to enable it"),
+        )
+            .into_response();
+    };
+    let given = req.headers().get(HEADER).and_then(|v| v.to_str().ok());
+    if !token_matches(token, given) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            json_err!("invalid operator token"),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("secret", Some("secret")));
+        assert!(!token_matches("secret", Some("secreT")));
+        assert!(!token_matches("secret", Some("secret2")));
+        assert!(!token_matches("secret", Some("")));
+        assert!(!token_matches("secret", None));
+    }
+}
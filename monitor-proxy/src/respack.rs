@@ -0,0 +1,50 @@
+use axum::{
+    body::{Body, Bytes},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use monitor_common::respack;
+use reqwest::header;
+use tokio::sync::Semaphore;
+
+/// Largest pack zip accepted
+pub const MAX_PACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Packs prepared at once. Each may take a few hundred MB while decoding,
+/// requests over the limit are turned away rather than queued.
+static PREPARING: Semaphore = Semaphore::const_new(2);
+
+/// Turns an uploaded resource pack zip into a bundle the client uploads
+/// without decoding, see [`monitor_common::respack`].
+pub async fn prepare_pack(body: Bytes) -> Response {
+    let Ok(_permit) = PREPARING.try_acquire() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "5")],
+            "Error: too many resource packs are being prepared, try again later",
+        )
+            .into_response();
+    };
+    log::info!("Preparing resource pack ({} bytes)", body.len());
+
+    let result =
+        tokio::task::spawn_blocking(move || respack::prepare(&respack::read_zip(&body)?)).await;
+    match result {
+        Ok(Ok(bytes)) => {
+            log::info!("Resource pack ready ({} bytes)", bytes.len());
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(bytes))
+                .unwrap()
+        }
+        Ok(Err(e)) => {
+            log::warn!("Rejected resource pack: {:#}", e);
+            (StatusCode::BAD_REQUEST, format!("Error: {:#}", e)).into_response()
+        }
+        Err(e) => {
+            log::error!("Error preparing resource pack: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
@@ -1,15 +1,16 @@
-use std::{convert::Infallible, time::Duration};
+use std::time::Duration;
 
-use crate::{json_err, AppState};
+use crate::{auth::AuthSession, json_err, json_msg, stats::WatchGuard, AppState};
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::{
-        sse::{Event, KeepAlive},
-        IntoResponse, Response, Sse,
-    },
-    Json,
+    response::{sse::KeepAlive, IntoResponse, Response, Sse},
+    Extension, Json,
 };
+use chrono::Utc;
+use cookie::{Cookie, CookieJar, Key};
+use futures::StreamExt;
+use serde::Deserialize;
 use serde_json::json;
 
 use phira_mp_common::RoomId;
@@ -54,15 +55,269 @@ pub async fn get_room_of_user(
         .unwrap_or_else(|e| (StatusCode::INTERNAL_SERVER_ERROR, json_err!("{e}")))
 }
 
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    /// Monitor token from `POST /rooms`
+    token: Option<String>,
+}
+
+/// Whether `token` lets its holder see `room`: rooms the proxy hosts need
+/// their monitor token, any other room is open
+fn may_watch(state: &AppState, room: &RoomId, token: Option<String>) -> bool {
+    !state.room_monitor_client.hosts(room)
+        || token
+            .and_then(|token| room_of_token(&state.cookie_key, token))
+            .is_some_and(|token_room| token_room == room.to_string())
+}
+
+pub async fn get_room_timeline(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<TokenQuery>,
+) -> (StatusCode, Response) {
+    let id = match RoomId::try_from(id) {
+        Ok(id) => id,
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("invalid room id: {e}")),
+    };
+    if !may_watch(&state, &id, query.token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            json_err!("room {id} needs its monitor token"),
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(state.room_monitor_client.get_room_timeline(id).await).into_response(),
+    )
+}
+
+pub async fn get_room_scoreboard(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<TokenQuery>,
+) -> (StatusCode, Response) {
+    let id = match RoomId::try_from(id) {
+        Ok(id) => id,
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("invalid room id: {e}")),
+    };
+    if !may_watch(&state, &id, query.token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            json_err!("room {id} needs its monitor token"),
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(state.room_monitor_client.get_room_scoreboard(id).await).into_response(),
+    )
+}
+
+/// Name the monitor token is encrypted under, binding it to this use
+const MONITOR_TOKEN: &str = "hsn_monitor";
+
+/// An opaque token naming `room`, encrypted with the cookie key
+fn monitor_token(key: &Key, room: &RoomId) -> String {
+    let mut jar = CookieJar::new();
+    jar.private_mut(key)
+        .add(Cookie::new(MONITOR_TOKEN, room.to_string()));
+    jar.get(MONITOR_TOKEN).unwrap().value().to_string()
+}
+
+fn room_of_token(key: &Key, token: String) -> Option<String> {
+    CookieJar::new()
+        .private(key)
+        .decrypt(Cookie::new(MONITOR_TOKEN, token))
+        .map(|cookie| cookie.value().to_string())
+}
+
+#[derive(Deserialize)]
+pub struct CreateRoomRequest {
+    /// Generated if left out
+    id: Option<String>,
+    /// Chart to select right away
+    chart: Option<i32>,
+}
+
+/// Creates a room hosted by the proxy's MP session, for tournament tooling.
+/// The returned token attaches a web monitor to the room through
+/// `/rooms/listen?token=`: while the proxy hosts the room, its events,
+/// timeline and scoreboard only go to holders of the token.
+pub async fn create_room(
+    State(state): State<AppState>,
+    Extension(session): Extension<AuthSession>,
+    Json(req): Json<CreateRoomRequest>,
+) -> (StatusCode, Response) {
+    let id = req
+        .id
+        .unwrap_or_else(|| format!("hsn-{:x}", Utc::now().timestamp_millis()));
+    let id = match RoomId::try_from(id) {
+        Ok(id) => id,
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("invalid room id: {e}")),
+    };
+    log::info!("User {} creates room {id}", session.id);
+    match state
+        .room_monitor_client
+        .create_room(id.clone(), req.chart)
+        .await
+    {
+        Ok(data) => (
+            StatusCode::OK,
+            Json(json!({
+                "room": id.to_string(),
+                "data": data,
+                "token": monitor_token(&state.cookie_key, &id),
+            }))
+            .into_response(),
+        ),
+        Err(e) if e.is::<AlreadyHosting>() => (StatusCode::CONFLICT, json_err!("{e}")),
+        Err(e) => (StatusCode::BAD_GATEWAY, json_err!("{e:#}")),
+    }
+}
+
+/// Longest chat message relayed to a room, in characters
+const MAX_CHAT_LEN: usize = 200;
+
+#[derive(Deserialize)]
+pub struct ChatRequest {
+    message: String,
+}
+
+/// Sends a chat message to a room created with `POST /rooms`. Players see
+/// it as sent by the proxy's MP session.
+pub async fn send_chat(
+    State(state): State<AppState>,
+    Extension(session): Extension<AuthSession>,
+    Path(id): Path<String>,
+    Json(req): Json<ChatRequest>,
+) -> (StatusCode, Response) {
+    let id = match RoomId::try_from(id) {
+        Ok(id) => id,
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("invalid room id: {e}")),
+    };
+    let message = req.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_CHAT_LEN {
+        return (
+            StatusCode::BAD_REQUEST,
+            json_err!("message must be 1 to {MAX_CHAT_LEN} characters"),
+        );
+    }
+    if !state.room_monitor_client.hosts(&id) {
+        return (
+            StatusCode::CONFLICT,
+            json_err!("room {id} is not hosted by the proxy"),
+        );
+    }
+    log::info!("User {} chats in room {id}: {message}", session.id);
+    match state
+        .room_monitor_client
+        .send_chat(&id, session.id, message.to_string())
+        .await
+    {
+        Ok(()) => (StatusCode::OK, json_msg!("sent")),
+        Err(e) => (StatusCode::BAD_GATEWAY, json_err!("{e:#}")),
+    }
+}
+
+/// Controls a room created with `POST /rooms` as its host, for running a
+/// match from the web UI.
+pub async fn host_room(
+    State(state): State<AppState>,
+    Extension(session): Extension<AuthSession>,
+    Path(id): Path<String>,
+    Json(cmd): Json<HostCommand>,
+) -> (StatusCode, Response) {
+    let id = match RoomId::try_from(id) {
+        Ok(id) => id,
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("invalid room id: {e}")),
+    };
+    if !state.room_monitor_client.hosts(&id) {
+        return (
+            StatusCode::CONFLICT,
+            json_err!("room {id} is not hosted by the proxy"),
+        );
+    }
+    log::info!("User {} controls room {id}: {cmd:?}", session.id);
+    match state.room_monitor_client.host_command(&id, cmd).await {
+        Ok(()) => (StatusCode::OK, json_msg!("sent")),
+        Err(e) => (StatusCode::BAD_GATEWAY, json_err!("{e:#}")),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListenQuery {
+    /// Room the listener is watching, counted in `presence` events
+    room: Option<String>,
+    /// Monitor token from `POST /rooms`, in place of `room`
+    token: Option<String>,
+}
+
 pub async fn listen(
     State(state): State<AppState>,
-) -> (
-    StatusCode,
-    Sse<impl futures::Stream<Item = Result<Event, Infallible>>>,
-) {
+    Query(query): Query<ListenQuery>,
+) -> (StatusCode, Response) {
+    let token_room = match query.token {
+        Some(token) => match room_of_token(&state.cookie_key, token)
+            .and_then(|room| RoomId::try_from(room).ok())
+        {
+            Some(room) => Some(room),
+            None => return (StatusCode::UNAUTHORIZED, json_err!("invalid monitor token")),
+        },
+        None => None,
+    };
+    let room = match query.room.map(RoomId::try_from).transpose() {
+        Ok(room) => room.or_else(|| token_room.clone()),
+        Err(e) => return (StatusCode::BAD_REQUEST, json_err!("invalid room id: {e}")),
+    };
+    // A hosted room's events only reach its token's holders, refuse rather
+    // than stream none of them
+    if let Some(room) = &room {
+        if state.room_monitor_client.hosts(room) && token_room.as_ref() != Some(room) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                json_err!("room {room} needs its monitor token"),
+            );
+        }
+    }
+    // Only rooms the server has, the query is up to the listener
+    let watch = match &room {
+        Some(room) if state.room_monitor_client.knows_room(room).await => {
+            Some(WatchGuard::new(state.clone(), room.to_string()))
+        }
+        _ => None,
+    };
+    let stream = state
+        .room_monitor_client
+        .listen_stream(room, token_room)
+        .await
+        .map(move |event| {
+            let _ = &watch;
+            event
+        });
     (
         StatusCode::OK,
-        Sse::new(state.room_monitor_client.listen_stream().await)
-            .keep_alive(KeepAlive::new().interval(Duration::from_secs(10))),
+        Sse::new(stream)
+            .keep_alive(KeepAlive::new().interval(Duration::from_secs(10)))
+            .into_response(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monitor_token() {
+        let key = Key::generate();
+        let room = RoomId::try_from("final-1".to_string()).unwrap();
+        let token = monitor_token(&key, &room);
+        assert_eq!(
+            room_of_token(&key, token.clone()).as_deref(),
+            Some("final-1")
+        );
+
+        assert!(room_of_token(&Key::generate(), token.clone()).is_none());
+        let mut tampered = token.into_bytes();
+        tampered[0] ^= 1;
+        assert!(room_of_token(&key, String::from_utf8(tampered).unwrap()).is_none());
+    }
+}
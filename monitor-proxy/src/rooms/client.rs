@@ -1,13 +1,15 @@
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, ensure, Context, Error, Result};
 use axum::response::sse::Event;
+use chrono::Utc;
 use futures::StreamExt;
 use phira_mp_common::{
-    generate_secret_key, ClientCommand, ClientRoomState, RoomId, ServerCommand, Stream, UserInfo,
-    HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT,
+    generate_secret_key, ClientCommand, ClientRoomState, Message, RoomId, ServerCommand, Stream,
+    UserInfo, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT,
 };
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     convert::Infallible,
     future::Future,
     sync::{
@@ -28,6 +30,65 @@ type SResult<T> = Result<T, String>;
 
 const TIMEOUT: Duration = Duration::from_secs(3);
 
+/// Number of rooms whose timeline is kept; the least recently active is dropped first
+const MAX_TIMELINES: usize = 256;
+/// Entries kept per room timeline
+const MAX_TIMELINE_ENTRIES: usize = 1024;
+
+/// How often the number of connected viewers is broadcast
+const PRESENCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Room-level state transitions, kept for post-match analysis.
+#[derive(Default)]
+struct RoomTimeline {
+    entries: VecDeque<Value>,
+    state: Option<String>,
+    last_active: Option<Instant>,
+}
+
+/// What the host of a room can do, see `RoomMonitorClient::host_command`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HostCommand {
+    SelectChart {
+        chart: i32,
+    },
+    /// Starts the round once everyone is ready
+    RequestStart,
+    /// Calls off a requested start while players are still getting ready
+    Cancel,
+    /// Keeps players from joining or leaving
+    Lock {
+        lock: bool,
+    },
+    /// Passes the host role to the next player after each round
+    Cycle {
+        cycle: bool,
+    },
+}
+
+/// Returned by `RoomMonitorClient::create_room` while our session still
+/// hosts the room it created before
+#[derive(Debug)]
+pub struct AlreadyHosting(pub RoomId);
+
+impl std::fmt::Display for AlreadyHosting {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the proxy already hosts room {}", self.0)
+    }
+}
+
+impl std::error::Error for AlreadyHosting {}
+
+/// An event for `/rooms/listen`. `scope` is the room it belongs to if only
+/// listeners holding that room's monitor token may see it, i.e. a room our
+/// session hosts.
+#[derive(Clone)]
+struct ScopedEvent {
+    scope: Option<RoomId>,
+    event: Event,
+}
+
 struct TaskResult<T> {
     lock: Mutex<()>,
     tx: Mutex<Option<oneshot::Sender<T>>>,
@@ -67,21 +128,246 @@ struct ClientState {
 
     authenticate_result: TaskResult<SResult<(UserInfo, Option<ClientRoomState>)>>,
     room_result: TaskResult<SResult<(HashMap<RoomId, Value>, HashMap<i32, RoomId>)>>,
+    chat_result: TaskResult<SResult<()>>,
 
     /// (room state, update events, next sync time)
     cached_room_state: RwLock<(HashMap<RoomId, Value>, HashMap<i32, RoomId>)>,
-    cached_events: RwLock<Vec<Event>>,
+    cached_events: RwLock<Vec<ScopedEvent>>,
     next_sync_time: Mutex<Instant>,
-    broadcast_tx: broadcast::Sender<Event>,
+    broadcast_tx: broadcast::Sender<ScopedEvent>,
+
+    timelines: RwLock<HashMap<RoomId, RoomTimeline>>,
+
+    /// (connected listeners, listeners per watched room)
+    viewers: std::sync::Mutex<(usize, HashMap<RoomId, usize>)>,
+
+    /// Rooms we asked the server to create, waiting for their create event
+    pending_rooms: std::sync::Mutex<HashMap<RoomId, oneshot::Sender<Value>>>,
+
+    /// Room our session hosts. A session is in one room at a time and host
+    /// commands act on that room, so only one is created until it is gone.
+    /// Never held across an await, the command handler reads it for chat.
+    hosted_room: std::sync::RwLock<Option<RoomId>>,
+
+    /// Held while acting as the session (creating a room, chatting, host
+    /// commands), so concurrent creates cannot both go through and no
+    /// command goes out while the session moves to a new room
+    session: Mutex<()>,
+
+    /// Our session's user, to tell our own chat apart when it comes back
+    user_id: std::sync::OnceLock<i32>,
 }
 
 impl ClientState {
-    pub async fn push_event(&self, event: Event) -> Result<()> {
+    fn new() -> Self {
+        Self {
+            delay: Mutex::default(),
+            ping_notify: Notify::new(),
+
+            authenticate_result: TaskResult::new(),
+            room_result: TaskResult::new(),
+            chat_result: TaskResult::new(),
+
+            cached_room_state: RwLock::default(),
+            cached_events: RwLock::default(),
+            next_sync_time: Mutex::new(Instant::now()),
+
+            broadcast_tx: broadcast::channel(1024).0,
+
+            timelines: RwLock::default(),
+
+            viewers: std::sync::Mutex::default(),
+
+            pending_rooms: std::sync::Mutex::default(),
+
+            hosted_room: std::sync::RwLock::default(),
+            session: Mutex::default(),
+
+            user_id: std::sync::OnceLock::new(),
+        }
+    }
+
+    fn hosted_room(&self) -> Option<RoomId> {
+        self.hosted_room.read().unwrap().clone()
+    }
+
+    /// Scope of the events of `room`: the room itself while our session
+    /// hosts or is creating it, else none
+    fn scope_of(&self, room: &RoomId) -> Option<RoomId> {
+        let hosted = self.hosted_room.read().unwrap().as_ref() == Some(room)
+            || self.pending_rooms.lock().unwrap().contains_key(room);
+        hosted.then(|| room.clone())
+    }
+
+    pub async fn push_event(&self, room: &RoomId, event: Event) -> Result<()> {
+        let event = ScopedEvent {
+            scope: self.scope_of(room),
+            event,
+        };
         let mut events = self.cached_events.write().await;
         events.push(event.clone());
         self.broadcast_tx.send(event)?;
         Ok(())
     }
+
+    /// Room events for one listener, counted as a viewer of `room` (if any)
+    /// until the stream is dropped. Events of a room our session hosts are
+    /// left out unless it is `allowed`, the room of the listener's monitor
+    /// token.
+    async fn listen_stream(
+        self: &Arc<Self>,
+        room: Option<RoomId>,
+        allowed: Option<RoomId>,
+    ) -> impl futures::Stream<Item = Result<Event, Infallible>> {
+        let guard = ViewerGuard::new(Arc::clone(self), room);
+        let visible = move |scope: &Option<RoomId>| scope.is_none() || *scope == allowed;
+        let room_state = self.cached_room_state.read().await;
+        let events = self.cached_events.read().await;
+        let mut init_events: Vec<Result<Event, Infallible>> = Vec::new();
+
+        for (id, data) in &room_state.0 {
+            if !visible(&self.scope_of(id)) {
+                continue;
+            }
+            let s = json!({"room": id.to_string(), "data": data.clone()}).to_string();
+            init_events.push(Ok(Event::default().event("create_room").data(s)));
+        }
+        for event in events.iter().filter(|event| visible(&event.scope)) {
+            init_events.push(Ok(event.event.clone()));
+        }
+        let init_stream = futures::stream::iter(init_events);
+        let update_stream =
+            BroadcastStream::new(self.broadcast_tx.subscribe()).filter_map(move |msg| {
+                futures::future::ready(match msg {
+                    Ok(event) => visible(&event.scope).then_some(Ok(event.event)),
+                    Err(_) => Some(Ok(Event::default().event("error").comment("lagged"))),
+                })
+            });
+        init_stream.chain(update_stream).map(move |event| {
+            let _ = &guard;
+            event
+        })
+    }
+
+    pub async fn record_timeline(&self, room: &RoomId, event: &str, data: Value) {
+        let mut timelines = self.timelines.write().await;
+        if !timelines.contains_key(room) && timelines.len() >= MAX_TIMELINES {
+            let oldest = timelines
+                .iter()
+                .min_by_key(|(_, t)| t.last_active)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                timelines.remove(&oldest);
+            }
+        }
+        let timeline = timelines.entry(room.clone()).or_default();
+        if event == "update_state" {
+            let new_state = data.as_str().map(str::to_owned);
+            if timeline.state.as_deref() == Some("PLAYING")
+                && new_state.as_deref() != Some("PLAYING")
+            {
+                Self::push_entry(timeline, "end_round", Value::Null);
+            }
+            let from = timeline.state.replace(new_state.unwrap_or_default());
+            Self::push_entry(timeline, event, json!({"from": from, "to": data}));
+        } else {
+            Self::push_entry(timeline, event, data);
+        }
+    }
+
+    fn presence(&self) -> Value {
+        let viewers = self.viewers.lock().unwrap();
+        let rooms: serde_json::Map<String, Value> = viewers
+            .1
+            .iter()
+            .map(|(id, count)| (id.to_string(), (*count).into()))
+            .collect();
+        json!({"viewers": viewers.0, "rooms": rooms})
+    }
+
+    /// Standings of the current (or last) round: each player's latest
+    /// record since the round started, best score first, with its
+    /// judgement counts.
+    fn scoreboard(timeline: &RoomTimeline) -> Vec<Value> {
+        let round = timeline
+            .entries
+            .iter()
+            .rposition(|entry| entry["event"] == "start_round")
+            .map_or(0, |i| i + 1);
+        let mut records: Vec<&Value> = Vec::new();
+        for entry in timeline.entries.range(round..) {
+            if entry["event"] == "player_score" {
+                let record = &entry["data"];
+                records.retain(|r| r["player"] != record["player"]);
+                records.push(record);
+            }
+        }
+        records.sort_by_key(|r| std::cmp::Reverse(r["score"].as_i64()));
+        records
+            .into_iter()
+            .enumerate()
+            .map(|(i, r)| {
+                json!({
+                    "rank": i + 1,
+                    "player": r["player"],
+                    "score": r["score"],
+                    "accuracy": r["accuracy"],
+                    "max_combo": r["max_combo"],
+                    "full_combo": r["full_combo"],
+                    "perfect": r["perfect"],
+                    "good": r["good"],
+                    "bad": r["bad"],
+                    "miss": r["miss"],
+                })
+            })
+            .collect()
+    }
+
+    fn push_entry(timeline: &mut RoomTimeline, event: &str, data: Value) {
+        if timeline.entries.len() >= MAX_TIMELINE_ENTRIES {
+            timeline.entries.pop_front();
+        }
+        timeline.entries.push_back(json!({
+            "time": Utc::now().timestamp_millis(),
+            "event": event,
+            "data": data,
+        }));
+        timeline.last_active = Some(Instant::now());
+    }
+}
+
+/// Counts a listener while its event stream is alive.
+struct ViewerGuard {
+    state: Arc<ClientState>,
+    room: Option<RoomId>,
+}
+
+impl ViewerGuard {
+    fn new(state: Arc<ClientState>, room: Option<RoomId>) -> Self {
+        {
+            let mut viewers = state.viewers.lock().unwrap();
+            viewers.0 += 1;
+            if let Some(room) = &room {
+                *viewers.1.entry(room.clone()).or_default() += 1;
+            }
+        }
+        Self { state, room }
+    }
+}
+
+impl Drop for ViewerGuard {
+    fn drop(&mut self) {
+        let mut viewers = self.state.viewers.lock().unwrap();
+        viewers.0 -= 1;
+        if let Some(room) = &self.room {
+            if let Some(count) = viewers.1.get_mut(room) {
+                *count -= 1;
+                if *count == 0 {
+                    viewers.1.remove(room);
+                }
+            }
+        }
+    }
 }
 
 pub struct RoomMonitorClient {
@@ -90,6 +376,7 @@ pub struct RoomMonitorClient {
 
     ping_fail_count: Arc<AtomicU8>,
     ping_task_handle: JoinHandle<()>,
+    presence_task_handle: JoinHandle<()>,
 }
 
 impl RoomMonitorClient {
@@ -97,19 +384,7 @@ impl RoomMonitorClient {
         let tcp_stream = TcpStream::connect(mp_server).await?;
         tcp_stream.set_nodelay(true)?;
 
-        let state = Arc::new(ClientState {
-            delay: Mutex::default(),
-            ping_notify: Notify::new(),
-
-            authenticate_result: TaskResult::new(),
-            room_result: TaskResult::new(),
-
-            cached_room_state: RwLock::default(),
-            cached_events: RwLock::default(),
-            next_sync_time: Mutex::new(Instant::now()),
-
-            broadcast_tx: broadcast::channel(1024).0,
-        });
+        let state = Arc::new(ClientState::new());
         let stream = Arc::new(
             Stream::new(
                 Some(1),
@@ -150,6 +425,21 @@ impl RoomMonitorClient {
             }
         });
 
+        let presence_task_handle = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move {
+                loop {
+                    time::sleep(PRESENCE_INTERVAL).await;
+                    // Not cached: only the latest count matters to new listeners
+                    let s = state.presence().to_string();
+                    let _ = state.broadcast_tx.send(ScopedEvent {
+                        scope: None,
+                        event: Event::default().event("presence").data(s),
+                    });
+                }
+            }
+        });
+
         // Authenticate
         let key = generate_secret_key("room_monitor", 64)
             .expect("failed to generate key for room monitor");
@@ -158,6 +448,7 @@ impl RoomMonitorClient {
             stream,
             ping_fail_count,
             ping_task_handle,
+            presence_task_handle,
         };
         this.authenticate(&key).await.map(move |_| this)
     }
@@ -172,7 +463,9 @@ impl RoomMonitorClient {
                     .await
             })
             .await?
-            .map(|_| {})
+            .map(|(user, _)| {
+                let _ = self.state.user_id.set(user.id);
+            })
             .map_err(Error::msg)
     }
 
@@ -195,22 +488,13 @@ impl RoomMonitorClient {
         self.ping_fail_count.load(Ordering::Relaxed)
     }
 
-    pub async fn listen_stream(&self) -> impl futures::Stream<Item = Result<Event, Infallible>> {
-        let room_state = self.state.cached_room_state.read().await;
-        let events = self.state.cached_events.read().await;
-        let mut init_events: Vec<Result<Event, Infallible>> = Vec::new();
-
-        for (id, data) in &room_state.0 {
-            let s = json!({"room": id.to_string(), "data": data.clone()}).to_string();
-            init_events.push(Ok(Event::default().event("create_room").data(s)));
-        }
-        for event in events.iter() {
-            init_events.push(Ok(event.clone()));
-        }
-        let init_stream = futures::stream::iter(init_events);
-        let update_stream = BroadcastStream::new(self.state.broadcast_tx.subscribe())
-            .map(|msg| msg.or_else(|_| Ok(Event::default().event("error").comment("lagged"))));
-        init_stream.chain(update_stream)
+    /// Room events for one listener, see `ClientState::listen_stream`
+    pub async fn listen_stream(
+        &self,
+        room: Option<RoomId>,
+        allowed: Option<RoomId>,
+    ) -> impl futures::Stream<Item = Result<Event, Infallible>> {
+        self.state.listen_stream(room, allowed).await
     }
 
     async fn update_room_info(&self) -> Result<()> {
@@ -253,11 +537,141 @@ impl RoomMonitorClient {
         };
         Ok(guard.0.get(&id).cloned().unwrap_or(Value::Null))
     }
+
+    /// Creates a room hosted by our own session and selects `chart` in it.
+    /// Returns the room data from the server's create event, or
+    /// [`AlreadyHosting`] while the session still hosts another room.
+    pub async fn create_room(&self, id: RoomId, chart: Option<i32>) -> Result<Value> {
+        let _session = self.state.session.lock().await;
+        if let Some(room) = self.state.hosted_room() {
+            self.update_room_info().await?;
+            if self
+                .state
+                .cached_room_state
+                .read()
+                .await
+                .0
+                .contains_key(&room)
+            {
+                return Err(AlreadyHosting(room).into());
+            }
+            *self.state.hosted_room.write().unwrap() = None;
+        }
+        let (tx, rx) = oneshot::channel();
+        self.state
+            .pending_rooms
+            .lock()
+            .unwrap()
+            .insert(id.clone(), tx);
+        let created = async {
+            self.stream
+                .send(ClientCommand::CreateRoom { id: id.clone() })
+                .await?;
+            time::timeout(TIMEOUT, rx)
+                .await
+                .with_context(|| format!("server did not create room {id}"))?
+                .map_err(Error::from)
+        }
+        .await;
+        // Hosted before it stops pending, so its events stay scoped to it
+        if created.is_ok() {
+            *self.state.hosted_room.write().unwrap() = Some(id.clone());
+        }
+        self.state.pending_rooms.lock().unwrap().remove(&id);
+        let data = created?;
+        if let Some(chart) = chart {
+            self.stream
+                .send(ClientCommand::SelectChart { id: chart })
+                .await?;
+        }
+        Ok(data)
+    }
+
+    /// Whether our session hosts `room`, see `create_room`
+    pub fn hosts(&self, room: &RoomId) -> bool {
+        self.state.hosted_room.read().unwrap().as_ref() == Some(room)
+    }
+
+    /// Sends a host command for `room`, which our session must host.
+    pub async fn host_command(&self, room: &RoomId, cmd: HostCommand) -> Result<()> {
+        let _session = self.state.session.lock().await;
+        ensure!(self.hosts(room), "room {room} is not hosted by the proxy");
+        let cmd = match cmd {
+            HostCommand::SelectChart { chart } => ClientCommand::SelectChart { id: chart },
+            HostCommand::RequestStart => ClientCommand::RequestStart,
+            // From the host, cancelling the ready cancels the game
+            HostCommand::Cancel => ClientCommand::CancelReady,
+            HostCommand::Lock { lock } => ClientCommand::LockRoom { lock },
+            HostCommand::Cycle { cycle } => ClientCommand::CycleRoom { cycle },
+        };
+        self.stream.send(cmd).await
+    }
+
+    /// Sends a chat message from our session to the room it hosts, on
+    /// behalf of Phira user `user`. Listeners get it as a `chat` event once
+    /// the server accepted it. Hosting and room chat for the monitor
+    /// session come from our phira-mp fork; a server that rejects the
+    /// message or never answers fails the send and nothing is relayed.
+    pub async fn send_chat(&self, room: &RoomId, user: i32, message: String) -> Result<()> {
+        let session = self.state.session.lock().await;
+        ensure!(self.hosts(room), "room {room} is not hosted by the proxy");
+        let stream = Arc::clone(&self.stream);
+        let content = message.clone();
+        self.state
+            .chat_result
+            .acquire(async move || stream.send(ClientCommand::Chat { message: content }).await)
+            .await
+            .context("failed to send chat message")?
+            .map_err(Error::msg)?;
+        drop(session);
+        let data = json!({"user": user, "message": message});
+        self.state.record_timeline(room, "chat", data.clone()).await;
+        let s = json!({"room": room.to_string(), "user": user, "message": message}).to_string();
+        self.state
+            .push_event(room, Event::default().event("chat").data(s))
+            .await
+    }
+
+    /// Whether the server told us about `room`, from the room list or its
+    /// events
+    pub async fn knows_room(&self, room: &RoomId) -> bool {
+        self.state
+            .cached_room_state
+            .read()
+            .await
+            .0
+            .contains_key(room)
+            || self.state.timelines.read().await.contains_key(room)
+    }
+
+    pub async fn get_room_timeline(&self, id: RoomId) -> Value {
+        let timelines = self.state.timelines.read().await;
+        match timelines.get(&id) {
+            Some(timeline) => json!({
+                "room": id.to_string(),
+                "state": timeline.state,
+                "events": timeline.entries,
+            }),
+            None => Value::Null,
+        }
+    }
+
+    pub async fn get_room_scoreboard(&self, id: RoomId) -> Value {
+        let timelines = self.state.timelines.read().await;
+        match timelines.get(&id) {
+            Some(timeline) => json!({
+                "room": id.to_string(),
+                "players": ClientState::scoreboard(timeline),
+            }),
+            None => Value::Null,
+        }
+    }
 }
 
 impl Drop for RoomMonitorClient {
     fn drop(&mut self) {
         self.ping_task_handle.abort();
+        self.presence_task_handle.abort();
     }
 }
 
@@ -273,6 +687,13 @@ async fn process(state: Arc<ClientState>, cmd: ServerCommand) {
                 .await
                 .inspect_err(|e| log::warn!("error setting authenticate result: {e}"));
         }
+        ServerCommand::Chat(res) => {
+            let _ = state
+                .chat_result
+                .put(res)
+                .await
+                .inspect_err(|e| log::warn!("error setting chat result: {e}"));
+        }
         ServerCommand::RoomResponse(value) => {
             let _ = state
                 .room_result
@@ -281,44 +702,89 @@ async fn process(state: Arc<ClientState>, cmd: ServerCommand) {
                 .inspect_err(|e| log::warn!("error setting room result: {e}"));
         }
         ServerCommand::CreateRoomEvent { room, data } => {
+            state
+                .record_timeline(&room, "create_room", json!(data))
+                .await;
+            record_room_update(&state, &room, &json!(data)).await;
             let s = json!({"room": room.to_string(), "data": data}).to_string();
             let _ = state
-                .push_event(Event::default().event("create_room").data(s))
+                .push_event(&room, Event::default().event("create_room").data(s))
                 .await
                 .inspect_err(|e| log::warn!("error sending create_room event: {e}"));
+            // Only once the event is out, it is scoped to the room while pending
+            if let Some(tx) = state.pending_rooms.lock().unwrap().remove(&room) {
+                let _ = tx.send(data);
+            }
         }
         ServerCommand::UpdateRoomEvent { room, data } => {
+            record_room_update(&state, &room, &json!(data)).await;
             let s = json!({"room": room.to_string(), "data": data}).to_string();
             let _ = state
-                .push_event(Event::default().event("update_room").data(s))
+                .push_event(&room, Event::default().event("update_room").data(s))
                 .await
                 .inspect_err(|e| log::warn!("error sending update_room event: {e}"));
         }
         ServerCommand::JoinRoomEvent { room, user } => {
+            state.record_timeline(&room, "join_room", json!(user)).await;
             let s = json!({"room": room.to_string(), "user": user}).to_string();
             let _ = state
-                .push_event(Event::default().event("join_room").data(s))
+                .push_event(&room, Event::default().event("join_room").data(s))
                 .await
                 .inspect_err(|e| log::warn!("error sending join_room event: {e}"));
         }
         ServerCommand::LeaveRoomEvent { room, user } => {
+            state
+                .record_timeline(&room, "leave_room", json!(user))
+                .await;
             let s = json!({"room": room.to_string(), "user": user}).to_string();
             let _ = state
-                .push_event(Event::default().event("leave_room").data(s))
+                .push_event(&room, Event::default().event("leave_room").data(s))
                 .await
                 .inspect_err(|e| log::warn!("error sending leave_room event: {e}"));
         }
         ServerCommand::PlayerScoreEvent { room, record } => {
+            state
+                .record_timeline(&room, "player_score", json!(record))
+                .await;
             let s = json!({"room": room.to_string(), "record": record}).to_string();
             let _ = state
-                .push_event(Event::default().event("player_score").data(s))
+                .push_event(&room, Event::default().event("player_score").data(s))
                 .await
                 .inspect_err(|e| log::warn!("error sending player_score event: {e}"));
+            let players = match state.timelines.read().await.get(&room) {
+                Some(timeline) => ClientState::scoreboard(timeline),
+                None => return,
+            };
+            let s = json!({"room": room.to_string(), "players": players}).to_string();
+            let _ = state
+                .push_event(&room, Event::default().event("scoreboard").data(s))
+                .await
+                .inspect_err(|e| log::warn!("error sending scoreboard event: {e}"));
+        }
+        ServerCommand::Message(Message::Chat { user, content }) => {
+            // Room messages only reach us in the room we host. Our own chat
+            // was already relayed by `send_chat`, with the user it was for.
+            let Some(room) = state.hosted_room() else {
+                return;
+            };
+            if state.user_id.get() == Some(&user) {
+                return;
+            }
+            let data = json!({"user": user, "message": content});
+            state.record_timeline(&room, "chat", data).await;
+            let s = json!({"room": room.to_string(), "user": user, "message": content}).to_string();
+            let _ = state
+                .push_event(&room, Event::default().event("chat").data(s))
+                .await
+                .inspect_err(|e| log::warn!("error sending chat event: {e}"));
         }
         ServerCommand::StartRoundEvent { room } => {
+            state
+                .record_timeline(&room, "start_round", Value::Null)
+                .await;
             let s = json!({"room": room.to_string()}).to_string();
             let _ = state
-                .push_event(Event::default().event("start_round").data(s))
+                .push_event(&room, Event::default().event("start_round").data(s))
                 .await
                 .inspect_err(|e| log::warn!("error sending start_round event: {e}"));
         }
@@ -327,3 +793,111 @@ async fn process(state: Arc<ClientState>, cmd: ServerCommand) {
         }
     }
 }
+
+/// Derives timeline entries (chart selection, state transitions) from a
+/// (partial) room data object.
+async fn record_room_update(state: &ClientState, room: &RoomId, data: &Value) {
+    if let Some(chart) = data.get("chart").filter(|c| !c.is_null()) {
+        state
+            .record_timeline(room, "select_chart", chart.clone())
+            .await;
+    }
+    if let Some(room_state) = data.get("state") {
+        state
+            .record_timeline(room, "update_state", room_state.clone())
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_relays_chat_while_sending() {
+        let state = Arc::new(ClientState::new());
+        let room = RoomId::try_from("final-1".to_string()).unwrap();
+        *state.hosted_room.write().unwrap() = Some(room.clone());
+        let mut events = state.broadcast_tx.subscribe();
+
+        // Held by `send_chat` until its message is out
+        let _session = state.session.lock().await;
+        let chat = Message::Chat {
+            user: 2,
+            content: "gl hf".to_string(),
+        };
+        time::timeout(
+            TIMEOUT,
+            process(Arc::clone(&state), ServerCommand::Message(chat)),
+        )
+        .await
+        .unwrap();
+
+        assert!(events.try_recv().is_ok());
+        let timelines = state.timelines.read().await;
+        let entry = &timelines[&room].entries[0];
+        assert_eq!(entry["event"], "chat");
+        assert_eq!(entry["data"], json!({"user": 2, "message": "gl hf"}));
+    }
+
+    #[tokio::test]
+    async fn test_chat_waits_for_server() {
+        let state = Arc::new(ClientState::new());
+        let sent = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move { state.chat_result.acquire(async || Ok(())).await }
+        });
+        // Let the send go out and start waiting
+        tokio::task::yield_now().await;
+        let rejected = ServerCommand::Chat(Err("chat is disabled".to_string()));
+        process(Arc::clone(&state), rejected).await;
+        let result = sent.await.unwrap().unwrap();
+        assert_eq!(result, Err("chat is disabled".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_hosted_room_events_need_token() {
+        use futures::FutureExt;
+
+        fn ready(
+            stream: &mut (impl futures::Stream<Item = Result<Event, Infallible>> + Unpin),
+        ) -> usize {
+            std::iter::from_fn(|| stream.next().now_or_never().flatten()).count()
+        }
+
+        let state = Arc::new(ClientState::new());
+        let hosted = RoomId::try_from("final-1".to_string()).unwrap();
+        let other = RoomId::try_from("casual".to_string()).unwrap();
+        *state.hosted_room.write().unwrap() = Some(hosted.clone());
+        let start = |room: &RoomId| ServerCommand::StartRoundEvent { room: room.clone() };
+
+        process(Arc::clone(&state), start(&hosted)).await;
+        let mut open = Box::pin(state.listen_stream(Some(other.clone()), None).await);
+        let mut allowed = Box::pin(state.listen_stream(None, Some(hosted.clone())).await);
+        process(Arc::clone(&state), start(&other)).await;
+        process(Arc::clone(&state), start(&hosted)).await;
+
+        // Replayed and live events of the hosted room only reach its token
+        assert_eq!(ready(&mut open), 1);
+        assert_eq!(ready(&mut allowed), 3);
+    }
+
+    #[test]
+    fn test_scoreboard_keeps_current_round() {
+        let mut timeline = RoomTimeline::default();
+        let record = |player: i32, score: i64| json!({"player": player, "score": score, "perfect": 90, "good": 8, "bad": 1, "miss": 1});
+        ClientState::push_entry(&mut timeline, "player_score", record(1, 900_000));
+        ClientState::push_entry(&mut timeline, "start_round", Value::Null);
+        ClientState::push_entry(&mut timeline, "player_score", record(2, 800_000));
+        ClientState::push_entry(&mut timeline, "player_score", record(3, 950_000));
+        ClientState::push_entry(&mut timeline, "player_score", record(2, 990_000));
+
+        let board = ClientState::scoreboard(&timeline);
+        let players: Vec<_> = board.iter().map(|p| p["player"].as_i64()).collect();
+        assert_eq!(players, [Some(2), Some(3)]);
+        assert_eq!(board[0]["rank"], 1);
+        assert_eq!(board[0]["score"], 990_000);
+        assert_eq!(board[0]["good"], 8);
+        assert_eq!(board[0]["miss"], 1);
+    }
+}
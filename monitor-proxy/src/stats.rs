@@ -0,0 +1,279 @@
+//! Chart views and room watch time, kept across restarts in a JSON file
+//! next to the chart cache
+//!
+//! A few thousand small entries rewritten once a minute don't need an
+//! embedded database such as sqlite or sled, and the file stays readable
+//! with any JSON tool. Saves run on the blocking pool, off the runtime.
+//! Growth is bounded: charts are counted once served and rooms only when
+//! the MP server knows them, capped at [`MAX_ROOMS`].
+
+use crate::AppState;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const FILE_NAME: &str = "stats.json";
+
+/// How often changed statistics are written out
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rooms nobody watched for this long are dropped, room IDs are rarely
+/// reused
+const ROOM_RETENTION: chrono::Duration = chrono::Duration::days(30);
+
+/// Rooms kept, the least recently watched are dropped first
+const MAX_ROOMS: usize = 4096;
+
+/// Entries returned by `/stats/charts` unless `limit` says otherwise
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Default, Serialize, Deserialize)]
+struct ChartStats {
+    views: u64,
+    /// Milliseconds timestamp
+    last_viewed: i64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RoomStats {
+    /// Listeners that watched the room
+    sessions: u64,
+    watch_secs: u64,
+    /// Milliseconds timestamp
+    last_watched: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StatsData {
+    /// When collection started, in milliseconds
+    since: i64,
+    charts: HashMap<String, ChartStats>,
+    rooms: HashMap<String, RoomStats>,
+}
+
+impl Default for StatsData {
+    fn default() -> Self {
+        Self {
+            since: Utc::now().timestamp_millis(),
+            charts: HashMap::new(),
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+pub struct Stats {
+    path: PathBuf,
+    data: Mutex<StatsData>,
+    /// Changed since the last save
+    dirty: AtomicBool,
+}
+
+impl Stats {
+    /// Reads the statistics saved in `cache_dir`, starting over if there are
+    /// none or they cannot be read.
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(FILE_NAME);
+        let data = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                log::warn!("Failed to read statistics from {:?}: {}", path, e);
+                StatsData::default()
+            }),
+            Err(_) => StatsData::default(),
+        };
+        Self {
+            path,
+            data: Mutex::new(data),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn chart_viewed(&self, id: &str) {
+        let mut data = self.data.lock().unwrap();
+        let chart = data.charts.entry(id.to_string()).or_default();
+        chart.views += 1;
+        chart.last_viewed = Utc::now().timestamp_millis();
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn room_watched(&self, room: &str, duration: Duration) {
+        let mut data = self.data.lock().unwrap();
+        if !data.rooms.contains_key(room) && data.rooms.len() >= MAX_ROOMS {
+            let oldest = data
+                .rooms
+                .iter()
+                .min_by_key(|(_, room)| room.last_watched)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                data.rooms.remove(&oldest);
+            }
+        }
+        let stats = data.rooms.entry(room.to_string()).or_default();
+        stats.sessions += 1;
+        stats.watch_secs += duration.as_secs();
+        stats.last_watched = Utc::now().timestamp_millis();
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes the statistics out if they changed. They stay marked as
+    /// changed if that fails, so the next save tries again.
+    fn save(&self) -> anyhow::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let result = self.write();
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Writes the statistics out atomically (write tmp, then rename).
+    fn write(&self) -> anyhow::Result<()> {
+        let bytes = {
+            let mut data = self.data.lock().unwrap();
+            let cutoff = (Utc::now() - ROOM_RETENTION).timestamp_millis();
+            data.rooms.retain(|_, room| room.last_watched >= cutoff);
+            serde_json::to_vec(&*data)?
+        };
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Saves the statistics every `SAVE_INTERVAL`, so at most that much is
+/// lost when the proxy stops.
+pub async fn save_periodically(state: AppState) {
+    loop {
+        tokio::time::sleep(SAVE_INTERVAL).await;
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || state.stats.save()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Failed to save statistics: {}", e),
+            Err(e) => log::warn!("Statistics save task failed: {}", e),
+        }
+    }
+}
+
+/// Adds the time until it is dropped to the room's watch time
+pub struct WatchGuard {
+    state: AppState,
+    room: String,
+    started: Instant,
+}
+
+impl WatchGuard {
+    pub fn new(state: AppState, room: String) -> Self {
+        Self {
+            state,
+            room,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.state
+            .stats
+            .room_watched(&self.room, self.started.elapsed());
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    /// Most viewed charts and most watched rooms to return
+    limit: Option<usize>,
+}
+
+/// The most viewed charts and most watched rooms
+pub async fn get_chart_stats(
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> (StatusCode, Response) {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let data = state.stats.data.lock().unwrap();
+
+    let mut charts: Vec<_> = data.charts.iter().collect();
+    charts.sort_by_key(|(_, chart)| std::cmp::Reverse(chart.views));
+    let charts: Vec<_> = charts
+        .into_iter()
+        .take(limit)
+        .map(|(id, chart)| {
+            json!({
+                "chart": id,
+                "views": chart.views,
+                "last_viewed": chart.last_viewed,
+            })
+        })
+        .collect();
+
+    let mut rooms: Vec<_> = data.rooms.iter().collect();
+    rooms.sort_by_key(|(_, room)| std::cmp::Reverse(room.watch_secs));
+    let rooms: Vec<_> = rooms
+        .into_iter()
+        .take(limit)
+        .map(|(id, room)| {
+            json!({
+                "room": id,
+                "sessions": room.sessions,
+                "watch_secs": room.watch_secs,
+                "last_watched": room.last_watched,
+            })
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "since": data.since,
+            "charts": charts,
+            "rooms": rooms,
+        }))
+        .into_response(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room_cap() {
+        let stats = Stats::load(Path::new("/nonexistent"));
+        for i in 0..MAX_ROOMS + 10 {
+            stats.room_watched(&i.to_string(), Duration::from_secs(1));
+        }
+        let data = stats.data.lock().unwrap();
+        assert_eq!(data.rooms.len(), MAX_ROOMS);
+        assert!(data.rooms.contains_key(&(MAX_ROOMS + 9).to_string()));
+    }
+
+    #[test]
+    fn test_failed_save_stays_dirty() {
+        // Cannot become a directory
+        let stats = Stats::load(Path::new("/dev/null/cache"));
+        stats.chart_viewed("1");
+        assert!(stats.save().is_err());
+        assert!(stats.dirty.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,174 @@
+//! Live operational status over a WebSocket, for watching a deployment
+//! without a shell on the box
+
+use crate::{json_err, operator, AppState};
+use axum::{
+    extract::{
+        ws::{rejection::WebSocketUpgradeRejection, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events kept for watchers that fall behind before they are told they
+/// missed some
+const CAPACITY: usize = 256;
+
+/// How often watchers get a summary of the active rooms
+const ROOMS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Broadcasts status events as `{"time", "event", "data"}` JSON, like the
+/// room timeline
+#[derive(Clone)]
+pub struct Status(broadcast::Sender<Arc<str>>);
+
+impl Status {
+    pub fn new() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+
+    pub fn send(&self, event: &str, data: Value) {
+        // Log lines come through here, skip the formatting when nobody watches
+        if self.0.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.0.send(format_event(event, data).into());
+    }
+
+    /// A change to the chart disk cache
+    pub fn cache(&self, action: &str, chart: &str) {
+        self.send("cache", json!({"action": action, "chart": chart}));
+    }
+}
+
+fn format_event(event: &str, data: Value) -> String {
+    json!({
+        "time": Utc::now().timestamp_millis(),
+        "event": event,
+        "data": data,
+    })
+    .to_string()
+}
+
+/// Passes records to env_logger and copies those it prints to the status
+/// stream
+struct Logger {
+    inner: env_logger::Logger,
+    status: Status,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        self.inner.log(record);
+        self.status.send(
+            "log",
+            json!({
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            }),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger, filtered by `RUST_LOG` as before
+pub fn init_logger(status: Status) {
+    let inner =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).build();
+    log::set_max_level(inner.filter());
+    log::set_boxed_logger(Box::new(Logger { inner, status })).expect("logger already set");
+}
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    token: Option<String>,
+}
+
+/// Upgrades to a WebSocket streaming log lines, cache events and room
+/// summaries. Needs the `--status-token` as the `token` query parameter,
+/// browsers cannot set headers on WebSockets.
+pub async fn status_ws(
+    State(state): State<AppState>,
+    Query(query): Query<StatusQuery>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    let Some(token) = state.args.status_token.as_deref().filter(|t| !t.is_empty()) else {
+        return (
+            StatusCode::NOT_FOUND,
+            json_err!("status stream is disabled, set HSN_STATUS_TOKEN to enable it"),
+        )
+            .into_response();
+    };
+    if !operator::token_matches(token, query.token.as_deref()) {
+        return (StatusCode::UNAUTHORIZED, json_err!("invalid status token")).into_response();
+    }
+    match ws {
+        Ok(ws) => ws.on_upgrade(move |socket| watch(state, socket)),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+async fn watch(state: AppState, ws: WebSocket) {
+    let (mut sink, mut stream) = ws.split();
+    let mut events = state.status.0.subscribe();
+    let mut rooms = tokio::time::interval(ROOMS_INTERVAL);
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(message) => message.to_string(),
+                Err(RecvError::Lagged(skipped)) => {
+                    format_event("lagged", json!({"skipped": skipped}))
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = rooms.tick() => format_event("rooms", room_summary(&state).await),
+            // Only reading for the close, pings are answered by the socket
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if sink.send(Message::Text(message.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Id, state, player count and chart of every room
+async fn room_summary(state: &AppState) -> Value {
+    let rooms = match state.room_monitor_client.get_room_list().await {
+        Ok(Value::Array(rooms)) => rooms,
+        Ok(_) => Vec::new(),
+        Err(e) => return json!({"error": e.to_string()}),
+    };
+    rooms
+        .iter()
+        .map(|room| {
+            let data = &room["data"];
+            json!({
+                "room": room["name"],
+                "state": data["state"],
+                "users": data["users"].as_array().map_or(0, Vec::len),
+                "chart": data["chart"],
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,68 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Responses from the Phira API kept for a while, so overlays polling the
+/// same data do not hit Phira on every request
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    /// Most entries kept, the oldest is evicted to make room
+    max_entries: usize,
+    entries: Mutex<HashMap<K, (Instant, Arc<V>)>>,
+}
+
+impl<K: Eq + Hash + Clone, V> TtlCache<K, V> {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        let entries = self.entries.lock().unwrap();
+        let (fetched, value) = entries.get(key)?;
+        (fetched.elapsed() < self.ttl).then(|| Arc::clone(value))
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Arc<V> {
+        let value = Arc::new(value);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        while entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (fetched, _))| *fetched)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(key, (Instant::now(), Arc::clone(&value)));
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_oldest() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        // Refreshing a key takes no room
+        cache.insert(2, "c");
+        assert_eq!(cache.get(&1).as_deref(), Some(&"a"));
+        cache.insert(3, "d");
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&2).as_deref(), Some(&"c"));
+        assert_eq!(cache.get(&3).as_deref(), Some(&"d"));
+    }
+}
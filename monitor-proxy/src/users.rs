@@ -0,0 +1,240 @@
+use crate::{json_err, ttl::TtlCache, AppState};
+use anyhow::ensure;
+use axum::body::Bytes;
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::{header, redirect, Client, Url};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+/// How long a fetched profile is served before asking Phira again
+const TTL: Duration = Duration::from_secs(600);
+
+/// Largest avatar served
+const MAX_AVATAR_SIZE: usize = 2 * 1024 * 1024;
+
+/// Most profiles kept, each may hold an avatar of up to
+/// [`MAX_AVATAR_SIZE`]
+const MAX_PROFILES: usize = 512;
+
+/// Most redirects followed for an avatar
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Deserialize, Serialize)]
+pub struct UserProfile {
+    id: i32,
+    name: String,
+    /// Avatar URL on Phira's file server, see `/user/{id}/avatar` for a
+    /// same-origin copy
+    avatar: Option<String>,
+    #[serde(default)]
+    rks: f32,
+    /// Fetched on the first `/user/{id}/avatar`, cached with the profile
+    #[serde(skip)]
+    avatar_image: OnceLock<Arc<Avatar>>,
+}
+
+struct Avatar {
+    content_type: String,
+    bytes: Bytes,
+}
+
+/// Profiles by user ID
+pub type Cache = TtlCache<i32, UserProfile>;
+
+pub fn cache() -> Cache {
+    TtlCache::new(TTL, MAX_PROFILES)
+}
+
+/// Whether `url` may be fetched as an avatar: https on the host of
+/// `api_base` or another subdomain of its parent, e.g. `files.phira.cn`
+/// next to `api.phira.cn`. Profiles are user data, anything else could
+/// point the proxy at internal services.
+fn is_phira_file(api_base: &str, url: &Url) -> bool {
+    let Some(base) = Url::parse(api_base)
+        .ok()
+        .and_then(|base| base.host_str().map(str::to_ascii_lowercase))
+    else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let parent = base
+        .split_once('.')
+        .map(|(_, parent)| parent)
+        .filter(|parent| parent.contains('.'));
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && (host == base
+            || parent.is_some_and(|parent| {
+                host.strip_suffix(parent)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+            }))
+}
+
+/// HTTP client for avatars, following redirects only as far as
+/// [`is_phira_file`] allows
+pub fn avatar_client(api_base: &str) -> Client {
+    let api_base = api_base.to_string();
+    Client::builder()
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if is_phira_file(&api_base, attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.error("avatar redirects off Phira's file server")
+            }
+        }))
+        .build()
+        .expect("failed to build avatar client")
+}
+
+/// A Phira user's name, avatar URL and rks, for scoreboards and overlays.
+pub async fn get_user_profile(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> (StatusCode, Response) {
+    match fetch_profile(&state, id).await {
+        Ok(profile) => (StatusCode::OK, Json(&*profile).into_response()),
+        Err(e) if e.status() == Some(reqwest::StatusCode::NOT_FOUND) => {
+            (StatusCode::NOT_FOUND, json_err!("user not found"))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json_err!("failed to fetch user: {e}"),
+        ),
+    }
+}
+
+async fn fetch_profile(state: &AppState, id: i32) -> reqwest::Result<Arc<UserProfile>> {
+    if let Some(profile) = state.users.get(&id) {
+        return Ok(profile);
+    }
+    let profile = state
+        .http_client
+        .get(format!("{}/user/{id}", state.args.api_base))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<UserProfile>()
+        .await?;
+    Ok(state.users.insert(id, profile))
+}
+
+/// Serves a Phira user's avatar from our origin, so the client can upload
+/// it as a texture without running into CORS.
+pub async fn get_user_avatar(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> (StatusCode, Response) {
+    match fetch_avatar(&state, id).await {
+        Ok(Some(avatar)) => (
+            StatusCode::OK,
+            Response::builder()
+                .header(header::CONTENT_TYPE, &avatar.content_type)
+                // Served from our origin, so never let it run as a page
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .header(
+                    header::CONTENT_SECURITY_POLICY,
+                    "default-src 'none'; sandbox",
+                )
+                .header(header::CACHE_CONTROL, "public, max-age=3600")
+                .body(Body::from(avatar.bytes.clone()))
+                .unwrap(),
+        ),
+        Ok(None) => (StatusCode::NOT_FOUND, json_err!("user has no avatar")),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            json_err!("failed to fetch avatar: {e:#}"),
+        ),
+    }
+}
+
+/// Fetches the avatar once per cached profile, it expires along with it.
+async fn fetch_avatar(state: &AppState, id: i32) -> anyhow::Result<Option<Arc<Avatar>>> {
+    let user = fetch_profile(state, id).await?;
+    if let Some(avatar) = user.avatar_image.get() {
+        return Ok(Some(Arc::clone(avatar)));
+    }
+    let Some(url) = &user.avatar else {
+        return Ok(None);
+    };
+    let url = Url::parse(url)?;
+    ensure!(
+        is_phira_file(&state.args.api_base, &url),
+        "avatar {url} is not on Phira's file server"
+    );
+    let mut resp = state
+        .avatar_client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    // Scripts can hide in SVG, only raster images
+    ensure!(
+        content_type.starts_with("image/") && !content_type.starts_with("image/svg"),
+        "avatar is {content_type:?}, not an image"
+    );
+    ensure!(
+        resp.content_length().unwrap_or(0) <= MAX_AVATAR_SIZE as u64,
+        "avatar is larger than {MAX_AVATAR_SIZE} bytes"
+    );
+    let mut bytes = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        ensure!(
+            bytes.len() <= MAX_AVATAR_SIZE,
+            "avatar is larger than {MAX_AVATAR_SIZE} bytes"
+        );
+    }
+    let avatar = Arc::new(Avatar {
+        content_type,
+        bytes: bytes.into(),
+    });
+    Ok(Some(Arc::clone(user.avatar_image.get_or_init(|| avatar))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_phira_file() {
+        let allowed = |base: &str, url: &str| is_phira_file(base, &Url::parse(url).unwrap());
+        let base = "https://api.phira.cn";
+        assert!(allowed(base, "https://api.phira.cn/files/a.png"));
+        assert!(allowed(base, "https://files.phira.cn/a.png"));
+        assert!(allowed(base, "https://FILES.phira.cn:443/a.png"));
+
+        assert!(!allowed(base, "http://files.phira.cn/a.png"));
+        assert!(!allowed(base, "https://files.phira.cn:8443/a.png"));
+        assert!(!allowed(base, "https://user@files.phira.cn/a.png"));
+        assert!(!allowed(base, "https://evilphira.cn/a.png"));
+        assert!(!allowed(base, "https://phira.cn.evil.com/a.png"));
+        assert!(!allowed(base, "https://127.0.0.1/a.png"));
+        assert!(!allowed(base, "https://localhost/a.png"));
+        assert!(!allowed(base, "file:///etc/passwd"));
+        // No parent domain to share with a two-label host
+        assert!(!allowed("https://phira.cn", "https://cn/a.png"));
+        assert!(!allowed("https://phira.cn", "https://files.cn/a.png"));
+        assert!(!allowed("not a url", "https://files.phira.cn/a.png"));
+    }
+}
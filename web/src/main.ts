@@ -11,14 +11,6 @@ async function main() {
     return;
   }
 
-  // Resize canvas to full screen
-  function resize() {
-    canvas.width = window.innerWidth;
-    canvas.height = window.innerHeight;
-  }
-  window.addEventListener("resize", resize);
-  resize();
-
   try {
     const player = new ChartPlayer("gl-canvas");
     console.log("ChartPlayer instance created.");
@@ -31,6 +23,13 @@ async function main() {
     // Expose for debugging
     (window as any).chartPlayer = player;
 
+    // Needed when the proxy runs with --api-keys: `?api_key=` on the page,
+    // or VITE_API_KEY at build time
+    const apiKey =
+      new URLSearchParams(location.search).get("api_key") ??
+      (import.meta as any).env?.VITE_API_KEY;
+    if (apiKey) player.set_api_key(apiKey);
+
     // Load Resource Pack
     async function loadResourcePack() {
       console.log("Loading resource pack...");
@@ -189,8 +188,6 @@ async function main() {
           alert(`Failed to load chart: ${e}`);
         } finally {
           isLoading = false;
-          // Force a resize/render after loading to ensure valid state
-          resize();
         }
       };
     }
@@ -203,7 +200,8 @@ async function main() {
         return;
       }
       try {
-        player.resize(canvas.width, canvas.height);
+        // CSS pixels; the player applies devicePixelRatio and render scale
+        player.resize(canvas.clientWidth, canvas.clientHeight);
         player.render();
       } catch (e) {
         errorCount++;